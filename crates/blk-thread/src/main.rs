@@ -3,12 +3,15 @@
 
 use core::ptr::NonNull;
 
-use common::{RootMessageLabel, VIRTIO_MMIO_BLK_VIRT_ADDR};
-use crate_consts::{DEFAULT_CUSTOM_SLOT, DEFAULT_THREAD_FAULT_EP, VIRTIO_NET_IRQ};
+use common::{get_device, register_irq, stopped};
+use crate_consts::{
+    slots::block_thread::{IRQ_HANDLER, IRQ_NTFN},
+    BLK_IRQ_BADGE, DEFAULT_THREAD_FAULT_EP, STOP_BADGE,
+};
 use sel4::{
     cap::{IrqHandler, Notification},
     cap_type::Endpoint,
-    debug_println, init_thread, Cap,
+    debug_println, Cap,
 };
 use virtio::HalImpl;
 use virtio_drivers::{
@@ -56,9 +59,10 @@ fn main() -> ! {
         .build();
     LOGGER.set().unwrap();
     debug_println!("[BlockThread] EntryPoint");
+    let ep = Cap::<Endpoint>::from_bits(DEFAULT_THREAD_FAULT_EP);
+    let (mmio_vaddr, irq_num) = get_device(ep);
     let mut virtio_blk = VirtIOBlk::<HalImpl, MmioTransport>::new(unsafe {
-        MmioTransport::new(NonNull::new(VIRTIO_MMIO_BLK_VIRT_ADDR as *mut VirtIOHeader).unwrap())
-            .unwrap()
+        MmioTransport::new(NonNull::new(mmio_vaddr as *mut VirtIOHeader).unwrap()).unwrap()
     })
     .expect("[BlockThread] failed to create blk driver");
 
@@ -68,11 +72,10 @@ fn main() -> ! {
     );
 
     // Register interrupt handler and notification
-    let ntfn = Notification::from_bits(DEFAULT_CUSTOM_SLOT);
-    let irq_handler = IrqHandler::from_bits(DEFAULT_CUSTOM_SLOT + 1);
-    let ep = Cap::<Endpoint>::from_bits(DEFAULT_THREAD_FAULT_EP);
+    let ntfn = Notification::from_bits(IRQ_NTFN);
+    let irq_handler = IrqHandler::from_bits(IRQ_HANDLER);
 
-    ep.call(RootMessageLabel::RegisterIRQ(irq_handler.bits(), VIRTIO_NET_IRQ as _).build());
+    register_irq(ep, irq_handler, irq_num).expect("[BlockThread] failed to register the irq");
     irq_handler.irq_handler_set_notification(ntfn).unwrap();
     irq_handler.irq_handler_ack().unwrap();
 
@@ -80,6 +83,8 @@ fn main() -> ! {
     let mut request = BlkReq::default();
     let mut resp = BlkResp::default();
     let mut buffer = [0u8; 512];
+    // The root task may ask to stop while a request is in flight.
+    let mut stop_requested = false;
 
     for block_id in 0..2 {
         unsafe {
@@ -88,11 +93,17 @@ fn main() -> ! {
                 .unwrap()
         };
 
-        debug_println!("[BlockThread] Waiting for VIRTIO Net IRQ notification");
-        ntfn.wait();
+        debug_println!("[BlockThread] Waiting for VIRTIO Blk IRQ notification");
+        loop {
+            let badge = ntfn.wait();
+            stop_requested |= badge & STOP_BADGE != 0;
+            if badge & BLK_IRQ_BADGE != 0 {
+                break;
+            }
+        }
         irq_handler.irq_handler_ack().unwrap();
         virtio_blk.ack_interrupt();
-        debug_println!("[BlockThread] Received for VIRTIO Net IRQ notification");
+        debug_println!("[BlockThread] Received for VIRTIO Blk IRQ notification");
 
         debug_println!(
             "[BlockThread] Get Data Len: {}, 0..4: {:?}",
@@ -101,7 +112,12 @@ fn main() -> ! {
         );
     }
 
+    while !stop_requested {
+        stop_requested = ntfn.wait() & STOP_BADGE != 0;
+    }
+    if let Err(err) = virtio_blk.flush() {
+        debug_println!("[BlockThread] failed to flush the device: {:?}", err);
+    }
     debug_println!("[BlockThread] Say Goodbye");
-    init_thread::slot::TCB.cap().tcb_suspend().unwrap();
-    unreachable!()
+    stopped(ep)
 }
@@ -0,0 +1,162 @@
+//! The terminal settings and the helpers to transfer the console data
+//! through IPC message registers.
+//!
+//! The bytes read from or written to the console are packed into the message
+//! registers following the header registers of `ConsoleMessageLabel`, so no
+//! page needs to be shared with the uart-thread.
+
+/// The console has input to read, in the reply of `ConsoleMessageLabel::Poll`.
+pub const CONSOLE_POLL_READABLE: u64 = 1 << 0;
+
+/// The maximum number of bytes transferred by a console message.
+pub const CONSOLE_MSG_BYTES: usize = 0x300;
+
+/// The number of the control characters in [Termios].
+pub const NCCS: usize = 19;
+
+/// The indexes of the control characters in [Termios::c_cc].
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VREPRINT: usize = 12;
+pub const VDISCARD: usize = 13;
+pub const VWERASE: usize = 14;
+pub const VLNEXT: usize = 15;
+pub const VEOL2: usize = 16;
+
+/// The input modes of [Termios::c_iflag].
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+pub const INLCR: u32 = 0o100;
+pub const ISTRIP: u32 = 0o40;
+pub const IXON: u32 = 0o2000;
+
+/// The output modes of [Termios::c_oflag].
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+
+/// The control modes of [Termios::c_cflag].
+pub const B38400: u32 = 0o17;
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+pub const HUPCL: u32 = 0o2000;
+
+/// The local modes of [Termios::c_lflag].
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+pub const NOFLSH: u32 = 0o200;
+pub const ECHOCTL: u32 = 0o1000;
+pub const ECHOKE: u32 = 0o4000;
+pub const IEXTEN: u32 = 0o100000;
+
+/// The same as `struct termios` of the linux kernel, used by `TCGETS` and `TCSETS`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    /// The settings of a newly opened terminal in linux, canonical with echo.
+    fn default() -> Self {
+        let mut c_cc = [0; NCCS];
+        c_cc[VINTR] = 0x03;
+        c_cc[VQUIT] = 0x1c;
+        c_cc[VERASE] = 0x7f;
+        c_cc[VKILL] = 0x15;
+        c_cc[VEOF] = 0x04;
+        c_cc[VMIN] = 1;
+        c_cc[VSTART] = 0x11;
+        c_cc[VSTOP] = 0x13;
+        c_cc[VSUSP] = 0x1a;
+        c_cc[VREPRINT] = 0x12;
+        c_cc[VDISCARD] = 0x0f;
+        c_cc[VWERASE] = 0x17;
+        c_cc[VLNEXT] = 0x16;
+        Self {
+            c_iflag: ICRNL | IXON,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: B38400 | CS8 | CREAD | HUPCL,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc,
+        }
+    }
+}
+
+/// The number of message registers used by an encoded [Termios].
+pub const TERMIOS_REGS: usize = core::mem::size_of::<Termios>().div_ceil(8);
+
+impl Termios {
+    /// Encode the settings into message registers.
+    pub fn to_regs(&self) -> [u64; TERMIOS_REGS] {
+        let mut regs = [0; TERMIOS_REGS];
+        bytes_to_regs(self.as_bytes(), &mut regs);
+        regs
+    }
+
+    /// Decode the settings from message registers.
+    pub fn from_regs(regs: &[u64]) -> Self {
+        let mut termios = Self::default();
+        let len = core::mem::size_of::<Self>();
+        // SAFETY: `Termios` is plain old data, any bytes make a valid value.
+        let bytes =
+            unsafe { core::slice::from_raw_parts_mut(&mut termios as *mut Self as *mut u8, len) };
+        regs_to_bytes(regs, bytes);
+        termios
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        let len = core::mem::size_of::<Self>();
+        // SAFETY: `Termios` is plain old data without padding.
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, len) }
+    }
+}
+
+/// The same as `struct winsize` in C, used by `TIOCGWINSZ` and `TIOCSWINSZ`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WinSize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+/// Pack the `bytes` into the message registers `regs` in native endian.
+pub fn bytes_to_regs(bytes: &[u8], regs: &mut [u64]) {
+    for (reg, chunk) in regs.iter_mut().zip(bytes.chunks(8)) {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        *reg = u64::from_ne_bytes(word);
+    }
+}
+
+/// Unpack the message registers `regs` into the `bytes`.
+pub fn regs_to_bytes(regs: &[u64], bytes: &mut [u8]) {
+    for (chunk, reg) in bytes.chunks_mut(8).zip(regs) {
+        chunk.copy_from_slice(&reg.to_ne_bytes()[..chunk.len()]);
+    }
+}
+
+/// The number of message registers holding `len` bytes.
+pub const fn bytes_regs(len: usize) -> usize {
+    len.div_ceil(8)
+}
@@ -1,27 +1,26 @@
 #![no_std]
 
+mod console;
+mod mcs;
+mod net;
 mod obj_allocator;
+mod time;
 mod uspace;
 mod utils;
 
 use core::cell::UnsafeCell;
+pub use console::*;
 use crate_consts::PAGE_SIZE;
+pub use mcs::*;
+pub use net::*;
 pub use obj_allocator::*;
-use sel4::{with_ipc_buffer, with_ipc_buffer_mut, CPtrBits, MessageInfo};
+use sel4::{
+    cap::{Endpoint, IrqHandler},
+    with_ipc_buffer, with_ipc_buffer_mut, CPtrBits, MessageInfo,
+};
+pub use time::*;
 pub use uspace::*;
 pub use utils::*;
-// FIXME: Make this variable more generic.
-pub const VIRTIO_MMIO_ADDR: usize = 0xa003e00;
-
-pub const VIRTIO_MMIO_VIRT_ADDR: usize = 0x1_2000_0000;
-
-const VIRTIO_BLK_OFFSET: usize = 0x3e00;
-
-const VIRTIO_NET_OFFSET: usize = 0x3c00;
-
-pub const VIRTIO_MMIO_BLK_VIRT_ADDR: usize = VIRTIO_MMIO_VIRT_ADDR + VIRTIO_BLK_OFFSET;
-
-pub const VIRTIO_MMIO_NET_VIRT_ADDR: usize = VIRTIO_MMIO_VIRT_ADDR + VIRTIO_NET_OFFSET;
 
 /// Impl custom message label quickly.
 macro_rules! impl_message_label {
@@ -101,12 +100,72 @@ impl CustomMessageLabel {
 
 pub type IrqNum = u64;
 
+/// The interrupt number of the devices without an interrupt.
+pub const NO_IRQ: IrqNum = IrqNum::MAX;
+
+/// The errors of claiming an interrupt from the root task, replied to
+/// `RootMessageLabel::RegisterIRQ` and `RootMessageLabel::RegisterIRQWithCap`
+/// in the first message register, 0 if the interrupt is claimed.
+#[repr(u64)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IrqError {
+    /// The interrupt doesn't belong to the device assigned to the component.
+    NotGranted = 1,
+    /// The interrupt is claimed already.
+    AlreadyClaimed,
+    /// The slot to put the handler in can't be used.
+    InvalidSlot,
+    /// The kernel refused to create the handler.
+    Kernel,
+}
+
+impl IrqError {
+    /// Convert a replied code to the result.
+    pub fn result(code: u64) -> Result<(), Self> {
+        match code {
+            0 => Ok(()),
+            1 => Err(Self::NotGranted),
+            2 => Err(Self::AlreadyClaimed),
+            3 => Err(Self::InvalidSlot),
+            _ => Err(Self::Kernel),
+        }
+    }
+
+    /// Convert the result to the code replied.
+    pub fn code(result: Result<(), Self>) -> u64 {
+        result.map_or_else(|err| err as u64, |_| 0)
+    }
+}
+
+/// How the machine ends after the components are stopped.
+#[repr(u64)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShutdownMode {
+    PowerOff = 0,
+    Reboot,
+}
+
 #[repr(usize)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum RootMessageLabel {
+    /// Claim the interrupt, the root task puts the handler in the slot of the
+    /// caller's CSpace.
     RegisterIRQ(CPtrBits, IrqNum),
     TranslateAddr(usize),
+    /// Claim the interrupt, the root task sends the handler through the IRQ
+    /// endpoint after the reply.
     RegisterIRQWithCap(IrqNum),
+    /// Get the device assigned to the caller, the root task replies the
+    /// virtual address of its registers and its interrupt number.
+    GetDevice(usize, IrqNum),
+    /// Stop the components and power off or reboot the machine, never replied.
+    Shutdown(ShutdownMode),
+    /// The caller is stopped after the stop request of the shutdown, never
+    /// replied.
+    Stopped,
+    /// The caller finished starting and waits for its requests, sent without
+    /// waiting for a reply.
+    Ready,
 }
 
 impl RootMessageLabel {
@@ -126,6 +185,13 @@ impl RootMessageLabel {
                 0x0 => Some(Self::RegisterIRQ(regs[0], regs[1] as _)),
                 0x1 => Some(Self::TranslateAddr(regs[0] as _)),
                 0x2 => Some(Self::RegisterIRQWithCap(regs[0] as _)),
+                0x3 => Some(Self::GetDevice(regs[0] as _, regs[1] as _)),
+                0x4 => Some(Self::Shutdown(match regs[0] {
+                    1 => ShutdownMode::Reboot,
+                    _ => ShutdownMode::PowerOff,
+                })),
+                0x5 => Some(Self::Stopped),
+                0x6 => Some(Self::Ready),
                 _ => None,
             }
         })
@@ -136,6 +202,10 @@ impl RootMessageLabel {
             RootMessageLabel::RegisterIRQ(_, _) => 0,
             RootMessageLabel::TranslateAddr(_) => 1,
             RootMessageLabel::RegisterIRQWithCap(_) => 2,
+            RootMessageLabel::GetDevice(_, _) => 3,
+            RootMessageLabel::Shutdown(_) => 4,
+            RootMessageLabel::Stopped => 5,
+            RootMessageLabel::Ready => 6,
         };
         Self::LABEL_START + n
     }
@@ -161,6 +231,16 @@ impl RootMessageLabel {
                     regs[0] = *irq_num;
                     msg_size = 1;
                 }
+                RootMessageLabel::GetDevice(vaddr, irq_num) => {
+                    regs[0] = *vaddr as _;
+                    regs[1] = *irq_num;
+                    msg_size = 2;
+                }
+                RootMessageLabel::Shutdown(mode) => {
+                    regs[0] = *mode as _;
+                    msg_size = 1;
+                }
+                RootMessageLabel::Stopped | RootMessageLabel::Ready => {}
             }
         });
 
@@ -168,6 +248,51 @@ impl RootMessageLabel {
     }
 }
 
+/// Get the device assigned to the current component by the root task.
+///
+/// Returns the virtual address of its registers and its interrupt number,
+/// [NO_IRQ] if it has no interrupt.
+pub fn get_device(fault_ep: Endpoint) -> (usize, IrqNum) {
+    let reply = fault_ep.call(RootMessageLabel::GetDevice(0, NO_IRQ).build());
+    match RootMessageLabel::try_from(&reply) {
+        Some(RootMessageLabel::GetDevice(vaddr, irq)) => (vaddr, irq),
+        _ => panic!("no device is assigned to the component"),
+    }
+}
+
+/// Claim the interrupt `irq` from the root task, the handler is put in the
+/// slot of `irq_handler`.
+pub fn register_irq(
+    fault_ep: Endpoint,
+    irq_handler: IrqHandler,
+    irq: IrqNum,
+) -> Result<(), IrqError> {
+    fault_ep.call(RootMessageLabel::RegisterIRQ(irq_handler.bits(), irq).build());
+    with_ipc_buffer(|buffer| IrqError::result(buffer.msg_regs()[0]))
+}
+
+/// Ask the root task to stop the components, then power off or reboot the
+/// machine.
+pub fn shutdown(fault_ep: Endpoint, mode: ShutdownMode) -> ! {
+    fault_ep.call(RootMessageLabel::Shutdown(mode).build());
+    unreachable!("the root task replied to the shutdown")
+}
+
+/// Tell the root task the current component is stopped after `STOP_BADGE`
+/// is signalled, the machine is powered off after all of them are stopped.
+pub fn stopped(fault_ep: Endpoint) -> ! {
+    fault_ep.call(RootMessageLabel::Stopped.build());
+    unreachable!("the root task replied to the stop")
+}
+
+/// Tell the root task the component waits for its requests now.
+///
+/// The root task calls a server only after this, the server may call the
+/// root task while it starts.
+pub fn ready(fault_ep: Endpoint) {
+    fault_ep.send(RootMessageLabel::Ready.build());
+}
+
 #[repr(usize)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum BlkMessageLabel {
@@ -248,10 +373,27 @@ pub enum NetRequsetabel {
     Recv(u64, u64, u64),
     RecvTimeout(u64, u64, u64, u64),
     Connect(u64, u64),
-    Listen(u64),
+    // id, backlog
+    Listen(u64, u64),
     Accept(u64),
     Shutdown(u64),
     Close(u64),
+    // protocol
+    NewRaw(u64),
+    NewIcmp,
+    // id, buf, buf_len, encoded remote address
+    SendTo(u64, u64, u64, [u64; SOCKET_ADDR_REGS]),
+    RecvFrom(u64, u64, u64),
+    IcmpStats,
+    // id, replies `NET_POLL_*` bits or the negative error code
+    Poll(u64),
+    // id, `NetSockOpt`, value
+    SetSockOpt(u64, u64, u64),
+    // id, `NetSockOpt`, replies the value or the negative error code
+    GetSockOpt(u64, u64),
+    // id, replies the result and the encoded address
+    GetSockName(u64),
+    GetPeerName(u64),
 }
 
 impl NetRequsetabel {
@@ -276,10 +418,25 @@ impl NetRequsetabel {
                 0x5 => Some(Self::Recv(regs[0], regs[1], regs[2])),
                 0x6 => Some(Self::RecvTimeout(regs[0], regs[1], regs[2], regs[3])),
                 0x7 => Some(Self::Connect(regs[0], regs[1])),
-                0x8 => Some(Self::Listen(regs[0])),
+                0x8 => Some(Self::Listen(regs[0], regs[1])),
                 0x9 => Some(Self::Accept(regs[0])),
                 0xa => Some(Self::Shutdown(regs[0])),
                 0xb => Some(Self::Close(regs[0])),
+                0xc => Some(Self::NewRaw(regs[0])),
+                0xd => Some(Self::NewIcmp),
+                0xe => Some(Self::SendTo(
+                    regs[0],
+                    regs[1],
+                    regs[2],
+                    regs[3..3 + SOCKET_ADDR_REGS].try_into().unwrap(),
+                )),
+                0xf => Some(Self::RecvFrom(regs[0], regs[1], regs[2])),
+                0x10 => Some(Self::IcmpStats),
+                0x11 => Some(Self::Poll(regs[0])),
+                0x12 => Some(Self::SetSockOpt(regs[0], regs[1], regs[2])),
+                0x13 => Some(Self::GetSockOpt(regs[0], regs[1])),
+                0x14 => Some(Self::GetSockName(regs[0])),
+                0x15 => Some(Self::GetPeerName(regs[0])),
                 _ => None,
             }
         })
@@ -295,10 +452,20 @@ impl NetRequsetabel {
             Self::Recv(_, _, _) => 5,
             Self::RecvTimeout(_, _, _, _) => 6,
             Self::Connect(_, _) => 7,
-            Self::Listen(_) => 8,
+            Self::Listen(_, _) => 8,
             Self::Accept(_) => 9,
             Self::Shutdown(_) => 10,
             Self::Close(_) => 11,
+            Self::NewRaw(_) => 12,
+            Self::NewIcmp => 13,
+            Self::SendTo(_, _, _, _) => 14,
+            Self::RecvFrom(_, _, _) => 15,
+            Self::IcmpStats => 16,
+            Self::Poll(_) => 17,
+            Self::SetSockOpt(_, _, _) => 18,
+            Self::GetSockOpt(_, _) => 19,
+            Self::GetSockName(_) => 20,
+            Self::GetPeerName(_) => 21,
         };
         Self::LABEL_START + n
     }
@@ -355,9 +522,10 @@ impl NetRequsetabel {
                     extra_caps = 1;
                     msg_size = 2;
                 }
-                Self::Listen(id) => {
+                Self::Listen(id, backlog) => {
                     regs[0] = *id;
-                    msg_size = 1;
+                    regs[1] = *backlog;
+                    msg_size = 2;
                 }
                 Self::Accept(id) => {
                     regs[0] = *id;
@@ -371,6 +539,216 @@ impl NetRequsetabel {
                     regs[0] = *id;
                     msg_size = 1;
                 }
+                Self::NewRaw(protocol) => {
+                    regs[0] = *protocol;
+                    msg_size = 1;
+                }
+                Self::NewIcmp => {}
+                Self::SendTo(id, buf, buf_len, remote_addr) => {
+                    regs[0] = *id;
+                    regs[1] = *buf;
+                    regs[2] = *buf_len;
+                    regs[3..3 + SOCKET_ADDR_REGS].copy_from_slice(remote_addr);
+                    extra_caps = 1;
+                    msg_size = 3 + SOCKET_ADDR_REGS;
+                }
+                Self::RecvFrom(id, buf, buf_len) => {
+                    regs[0] = *id;
+                    regs[1] = *buf;
+                    regs[2] = *buf_len;
+                    extra_caps = 1;
+                    msg_size = 3;
+                }
+                Self::IcmpStats => {}
+                Self::Poll(id) => {
+                    regs[0] = *id;
+                    msg_size = 1;
+                }
+                Self::SetSockOpt(id, opt, value) => {
+                    regs[0] = *id;
+                    regs[1] = *opt;
+                    regs[2] = *value;
+                    msg_size = 3;
+                }
+                Self::GetSockOpt(id, opt) => {
+                    regs[0] = *id;
+                    regs[1] = *opt;
+                    msg_size = 2;
+                }
+                Self::GetSockName(id) => {
+                    regs[0] = *id;
+                    msg_size = 1;
+                }
+                Self::GetPeerName(id) => {
+                    regs[0] = *id;
+                    msg_size = 1;
+                }
+            }
+        });
+
+        MessageInfo::new(self.to_label(), caps_unwrapped, extra_caps, msg_size)
+    }
+}
+
+#[repr(usize)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConsoleMessageLabel {
+    // max length, replies the length or the negative error code, then the bytes
+    Read(u64),
+    // length, followed by the bytes
+    Write(u64),
+    // replies the encoded `Termios`
+    GetTermios,
+    // encoded `Termios`, whether to discard the pending input
+    SetTermios([u64; TERMIOS_REGS], u64),
+    // replies the rows and the columns
+    GetWinSize,
+    // rows, columns
+    SetWinSize(u64, u64),
+    // replies `CONSOLE_POLL_*` bits
+    Poll,
+    // replies the mask of the signals generated by the input since last time
+    TakeSignals,
+}
+
+impl ConsoleMessageLabel {
+    const LABEL_START: u64 = 0x500;
+
+    /// Try to convert a MessageInfo to a ConsoleMessageLabel
+    pub fn try_from(message: &MessageInfo) -> Option<Self> {
+        // Get the true index for the ConsoleMessageLabel
+        let label = match message.label() >= Self::LABEL_START {
+            true => message.label() - Self::LABEL_START,
+            false => return None,
+        };
+        // Convert the true index to a ConsoleMessageLabel enum
+        with_ipc_buffer(|buffer| {
+            let regs = buffer.msg_regs();
+            match label {
+                0x0 => Some(Self::Read(regs[0])),
+                0x1 => Some(Self::Write(regs[0])),
+                0x2 => Some(Self::GetTermios),
+                0x3 => Some(Self::SetTermios(
+                    regs[..TERMIOS_REGS].try_into().unwrap(),
+                    regs[TERMIOS_REGS],
+                )),
+                0x4 => Some(Self::GetWinSize),
+                0x5 => Some(Self::SetWinSize(regs[0], regs[1])),
+                0x6 => Some(Self::Poll),
+                0x7 => Some(Self::TakeSignals),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn to_label(&self) -> u64 {
+        let n = match self {
+            Self::Read(_) => 0,
+            Self::Write(_) => 1,
+            Self::GetTermios => 2,
+            Self::SetTermios(_, _) => 3,
+            Self::GetWinSize => 4,
+            Self::SetWinSize(_, _) => 5,
+            Self::Poll => 6,
+            Self::TakeSignals => 7,
+        };
+        Self::LABEL_START + n
+    }
+
+    /// Build the message, the bytes of [Self::Write] should be packed into
+    /// the registers after the first one by [bytes_to_regs] before.
+    pub fn build(&self) -> MessageInfo {
+        let caps_unwrapped = 0;
+        let extra_caps = 0;
+        let mut msg_size = 0;
+
+        with_ipc_buffer_mut(|buffer| {
+            let regs = buffer.msg_regs_mut();
+            match self {
+                Self::Read(len) => {
+                    regs[0] = *len;
+                    msg_size = 1;
+                }
+                Self::Write(len) => {
+                    regs[0] = *len;
+                    msg_size = 1 + bytes_regs(*len as usize);
+                }
+                Self::GetTermios => {}
+                Self::SetTermios(termios, flush) => {
+                    regs[..TERMIOS_REGS].copy_from_slice(termios);
+                    regs[TERMIOS_REGS] = *flush;
+                    msg_size = TERMIOS_REGS + 1;
+                }
+                Self::GetWinSize => {}
+                Self::SetWinSize(rows, cols) => {
+                    regs[0] = *rows;
+                    regs[1] = *cols;
+                    msg_size = 2;
+                }
+                Self::Poll => {}
+                Self::TakeSignals => {}
+            }
+        });
+
+        MessageInfo::new(self.to_label(), caps_unwrapped, extra_caps, msg_size)
+    }
+}
+
+#[repr(usize)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimerMessageLabel {
+    // timer id, deadline in nanoseconds, the timer with the same id is replaced
+    SetTimer(u64, u64),
+    // timer id
+    CancelTimer(u64),
+}
+
+impl TimerMessageLabel {
+    const LABEL_START: u64 = 0x600;
+
+    /// Try to convert a MessageInfo to a TimerMessageLabel
+    pub fn try_from(message: &MessageInfo) -> Option<Self> {
+        // Get the true index for the TimerMessageLabel
+        let label = match message.label() >= Self::LABEL_START {
+            true => message.label() - Self::LABEL_START,
+            false => return None,
+        };
+        // Convert the true index to a TimerMessageLabel enum
+        with_ipc_buffer(|buffer| {
+            let regs = buffer.msg_regs();
+            match label {
+                0x0 => Some(Self::SetTimer(regs[0], regs[1])),
+                0x1 => Some(Self::CancelTimer(regs[0])),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn to_label(&self) -> u64 {
+        let n = match self {
+            Self::SetTimer(_, _) => 0,
+            Self::CancelTimer(_) => 1,
+        };
+        Self::LABEL_START + n
+    }
+
+    pub fn build(&self) -> MessageInfo {
+        let caps_unwrapped = 0;
+        let extra_caps = 0;
+        let mut msg_size = 0;
+
+        with_ipc_buffer_mut(|buffer| {
+            let regs = buffer.msg_regs_mut();
+            match self {
+                Self::SetTimer(id, deadline) => {
+                    regs[0] = *id;
+                    regs[1] = *deadline;
+                    msg_size = 2;
+                }
+                Self::CancelTimer(id) => {
+                    regs[0] = *id;
+                    msg_size = 1;
+                }
             }
         });
 
@@ -0,0 +1,136 @@
+//! The IPC and the scheduling which differ on a MCS kernel.
+//!
+//! Build against a kernel configured with `KernelIsMCS` to enable them. A
+//! thread runs there only with a scheduling context, which gives it a budget
+//! of time in each period on one core. A server receives with a reply object
+//! instead of the reply capability kept in its TCB, the root task puts one
+//! in [DEFAULT_THREAD_REPLY] of each component.
+
+use crate_consts::DEFAULT_THREAD_REPLY;
+use sel4::{CNodeCapData, CPtr, IpcBuffer, MessageInfo, Word};
+
+use crate::ObjectAllocator;
+
+/// The reply capability a server receives with and replies through.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub type Reply = sel4::cap::Reply;
+
+/// The reply capability a server receives with and replies through, it is
+/// kept in the TCB.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub type Reply = ();
+
+/// The reply capability of the components.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub const SERVER_REPLY: Reply = Reply::from_bits(DEFAULT_THREAD_REPLY);
+
+/// The reply capability of the components.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub const SERVER_REPLY: Reply = ();
+
+/// Create a reply object.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub fn allocate_reply(allocator: &mut ObjectAllocator) -> Reply {
+    allocator.allocate_and_retyped_fixed_sized::<sel4::cap_type::Reply>()
+}
+
+/// Create a reply object, nothing to create without MCS.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub fn allocate_reply(_allocator: &mut ObjectAllocator) -> Reply {}
+
+/// The scheduling context of a thread.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub type SchedContext = sel4::cap::SchedContext;
+
+/// The scheduling context of a thread, the threads don't have one.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub type SchedContext = ();
+
+/// Create a scheduling context, it has no budget until configured.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub fn allocate_sched_context(allocator: &mut ObjectAllocator) -> SchedContext {
+    allocator.allocate_and_retyped_variable_sized::<sel4::cap_type::SchedContext>(
+        sel4::sys::seL4_MinSchedContextBits as _,
+    )
+}
+
+/// Create a scheduling context, nothing to create without MCS.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub fn allocate_sched_context(_allocator: &mut ObjectAllocator) -> SchedContext {}
+
+/// Unbind the scheduling context from its thread and return it to the
+/// `allocator`.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub fn free_sched_context(allocator: &mut ObjectAllocator, sched_context: SchedContext) {
+    sched_context.sched_context_unbind().unwrap();
+    allocator.recycle(
+        sel4::ObjectBlueprint::SchedContext {
+            size_bits: sel4::sys::seL4_MinSchedContextBits as _,
+        },
+        sched_context.bits() as _,
+    );
+}
+
+/// Free a scheduling context, nothing to free without MCS.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub fn free_sched_context(_allocator: &mut ObjectAllocator, _sched_context: SchedContext) {}
+
+/// Reply the caller of the message received with `reply`.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub fn reply(buffer: &mut IpcBuffer, reply: Reply, info: MessageInfo) {
+    // Sending to the reply object replies, `sel4::reply` doesn't exist.
+    buffer
+        .inner_mut()
+        .seL4_Send(reply.bits(), info.into_inner());
+}
+
+/// Reply the caller of the message received with `reply`.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub fn reply(buffer: &mut IpcBuffer, _reply: Reply, info: MessageInfo) {
+    sel4::reply(buffer, info)
+}
+
+/// Configure the CSpace, the VSpace and the IPC buffer of the `tcb`.
+///
+/// The `fault_ep` in the CSpace of the `tcb` is ignored on MCS, where the
+/// fault endpoint is set with the scheduling context instead.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub fn tcb_configure(
+    tcb: sel4::cap::Tcb,
+    _fault_ep: CPtr,
+    cspace_root: sel4::cap::CNode,
+    cspace_root_data: CNodeCapData,
+    vspace_root: sel4::cap::VSpace,
+    ipc_buffer: Word,
+    ipc_buffer_frame: sel4::cap::Granule,
+) -> sel4::Result<()> {
+    tcb.tcb_configure(
+        cspace_root,
+        cspace_root_data,
+        vspace_root,
+        ipc_buffer,
+        ipc_buffer_frame,
+    )
+}
+
+/// Configure the fault endpoint, the CSpace, the VSpace and the IPC buffer of
+/// the `tcb`.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub fn tcb_configure(
+    tcb: sel4::cap::Tcb,
+    fault_ep: CPtr,
+    cspace_root: sel4::cap::CNode,
+    cspace_root_data: CNodeCapData,
+    vspace_root: sel4::cap::VSpace,
+    ipc_buffer: Word,
+    ipc_buffer_frame: sel4::cap::Granule,
+) -> sel4::Result<()> {
+    tcb.tcb_configure(
+        fault_ep,
+        cspace_root,
+        cspace_root_data,
+        vspace_root,
+        ipc_buffer,
+        ipc_buffer_frame,
+    )
+}
@@ -0,0 +1,80 @@
+//! Helpers to transfer socket addresses through IPC message registers.
+//!
+//! A [SocketAddr] takes three registers:
+//!
+//! | register | content                                      |
+//! | -------- | -------------------------------------------- |
+//! | 0        | `is_ipv6 << 16 \| port`                       |
+//! | 1        | the lower 64 bits of the address (big endian) |
+//! | 2        | the higher 64 bits of the address             |
+
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// The socket is readable, in the reply of `NetRequsetabel::Poll`.
+pub const NET_POLL_READABLE: u64 = 1 << 0;
+/// The socket is writable, in the reply of `NetRequsetabel::Poll`.
+pub const NET_POLL_WRITABLE: u64 = 1 << 1;
+
+/// The socket options transferred by `NetRequsetabel::SetSockOpt` and
+/// `NetRequsetabel::GetSockOpt`.
+///
+/// Boolean options take `0` or `1`, buffer sizes are in bytes and timeouts
+/// are in microseconds with `0` meaning no timeout. [NetSockOpt::Error] is
+/// read-only and returns the pending `AxError` code or `0`.
+#[repr(u64)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NetSockOpt {
+    ReuseAddr,
+    NoDelay,
+    RecvBuf,
+    SendBuf,
+    KeepAlive,
+    RecvTimeout,
+    SendTimeout,
+    Error,
+    ReusePort,
+}
+
+impl NetSockOpt {
+    /// Try to convert a message register to a [NetSockOpt].
+    pub const fn try_from(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(Self::ReuseAddr),
+            1 => Some(Self::NoDelay),
+            2 => Some(Self::RecvBuf),
+            3 => Some(Self::SendBuf),
+            4 => Some(Self::KeepAlive),
+            5 => Some(Self::RecvTimeout),
+            6 => Some(Self::SendTimeout),
+            7 => Some(Self::Error),
+            8 => Some(Self::ReusePort),
+            _ => None,
+        }
+    }
+}
+
+/// The number of message registers used by an encoded [SocketAddr].
+pub const SOCKET_ADDR_REGS: usize = 3;
+
+/// Encode a [SocketAddr] into message registers.
+pub fn socket_addr_to_regs(addr: SocketAddr) -> [u64; SOCKET_ADDR_REGS] {
+    match addr.ip() {
+        IpAddr::V4(ip) => [addr.port() as u64, ip.to_bits() as u64, 0],
+        IpAddr::V6(ip) => {
+            let bits = ip.to_bits();
+            [1 << 16 | addr.port() as u64, bits as u64, (bits >> 64) as u64]
+        }
+    }
+}
+
+/// Decode a [SocketAddr] from message registers.
+pub fn socket_addr_from_regs(regs: &[u64]) -> SocketAddr {
+    let port = regs[0] as u16;
+    let ip = match regs[0] >> 16 & 1 {
+        0 => IpAddr::V4(Ipv4Addr::from_bits(regs[1] as u32)),
+        _ => IpAddr::V6(Ipv6Addr::from_bits(
+            (regs[2] as u128) << 64 | regs[1] as u128,
+        )),
+    };
+    SocketAddr::new(ip, port)
+}
@@ -1,8 +1,17 @@
+use alloc::vec::Vec;
 use core::ops::Range;
 use sel4::cap::Untyped;
 
 pub struct ObjectAllocator {
     empty_slots: Range<usize>,
+    /// The slots returned by [ObjectAllocator::free_slot].
+    free_slots: Vec<usize>,
+    /// The objects returned by [ObjectAllocator::recycle] with their
+    /// blueprints. The memory of the untyped is never reclaimed, so the
+    /// objects are reused instead.
+    free_objects: Vec<(sel4::ObjectBlueprint, usize)>,
+    /// The bytes of the untyped retyped into objects.
+    retyped: usize,
     ut: Untyped,
 }
 
@@ -10,6 +19,9 @@ impl ObjectAllocator {
     pub const fn empty() -> Self {
         Self {
             empty_slots: 0..0,
+            free_slots: Vec::new(),
+            free_objects: Vec::new(),
+            retyped: 0,
             ut: sel4::cap::Untyped::from_bits(0),
         }
     }
@@ -35,6 +47,7 @@ impl ObjectAllocator {
         size_bits: usize,
     ) -> sel4::Cap<T> {
         let slot_index = self.empty_slots.next().unwrap();
+        self.retyped += 1 << T::object_blueprint(size_bits).physical_size_bits();
         self.ut
             .untyped_retype(
                 &T::object_blueprint(size_bits),
@@ -49,14 +62,20 @@ impl ObjectAllocator {
 
 impl ObjectAllocator {
     pub fn allocate_slot(&mut self) -> (usize, usize, usize) {
+        // The CNode of a freed slot exists already.
+        if let Some(raw_slot_index) = self.free_slots.pop() {
+            return (raw_slot_index & 0xfff, raw_slot_index >> 12, raw_slot_index);
+        }
         let raw_slot_index = self.empty_slots.next().unwrap();
         let slot_index = raw_slot_index & 0xfff;
         let cnode_index = raw_slot_index >> 12;
 
         if slot_index == 0 {
+            let blueprint = sel4::ObjectBlueprint::CNode { size_bits: 12 };
+            self.retyped += 1 << blueprint.physical_size_bits();
             self.ut
                 .untyped_retype(
-                    &sel4::ObjectBlueprint::CNode { size_bits: 12 },
+                    &blueprint,
                     &sel4::init_thread::slot::CNODE.cap().relative_self(),
                     cnode_index,
                     1,
@@ -66,14 +85,48 @@ impl ObjectAllocator {
 
         (slot_index, cnode_index, raw_slot_index)
     }
+
+    /// The end of the slots taken from the empty range. The freed slots and
+    /// objects are reused first, so it grows only with the objects in use.
+    pub fn watermark(&self) -> usize {
+        self.empty_slots.start
+    }
+
+    /// The bytes of the untyped retyped into objects, without the padding
+    /// aligning them. It grows only with the objects in use, the same as
+    /// [ObjectAllocator::watermark].
+    pub fn retyped(&self) -> usize {
+        self.retyped
+    }
+
+    /// Return the empty slot `raw_index`, it is allocated again by
+    /// [ObjectAllocator::allocate_slot].
+    pub fn free_slot(&mut self, raw_index: usize) {
+        self.free_slots.push(raw_index);
+    }
+
+    /// Return the object of `blueprint` in the slot `raw_index`, it is
+    /// allocated again with the slot for the same blueprint.
+    ///
+    /// The capabilities derived from it must be revoked, and the object reset
+    /// if it keeps any state, such as the data of a frame.
+    pub fn recycle(&mut self, blueprint: sel4::ObjectBlueprint, raw_index: usize) {
+        self.free_objects.push((blueprint, raw_index));
+    }
+
     /// Allocate the slot at the new cspace.
     pub fn allocate_and_retype(
         &mut self,
         blueprint: sel4::ObjectBlueprint,
     ) -> sel4::cap::Unspecified {
+        if let Some(pos) = self.free_objects.iter().position(|(b, _)| *b == blueprint) {
+            let (_, raw_index) = self.free_objects.swap_remove(pos);
+            return sel4::init_thread::Slot::from_index(raw_index).cap();
+        }
         // let slot_index = self.empty_slots.next().unwrap();
         // let cnode_index = (slot_index >> 12) as u64;
         let (slot_index, cnode_index, raw_index) = self.allocate_slot();
+        self.retyped += 1 << blueprint.physical_size_bits();
         self.ut
             .untyped_retype(
                 &blueprint,
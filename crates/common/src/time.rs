@@ -0,0 +1,48 @@
+//! The monotonic clock shared by all the components.
+//!
+//! The clock is the physical count of the ARM generic timer, which starts at
+//! zero when the machine boots. It is read directly from `CNTPCT_EL0`, so
+//! the kernel should be configured with `KernelArmExportPCNTUser`.
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+pub const NANOS_PER_MILLIS: u64 = 1_000_000;
+pub const NANOS_PER_MICROS: u64 = 1_000;
+
+/// The frequency of the generic timer in Hz.
+#[inline]
+pub fn counter_frequency() -> u64 {
+    let freq: u64;
+    unsafe {
+        core::arch::asm!("mrs {0}, cntfrq_el0", out(reg) freq);
+    }
+    freq
+}
+
+/// The current count of the generic timer.
+#[inline]
+pub fn counter() -> u64 {
+    let count: u64;
+    unsafe {
+        // Keep the read from being executed before the previous instructions.
+        core::arch::asm!("isb", "mrs {0}, cntpct_el0", out(reg) count);
+    }
+    count
+}
+
+/// Convert a count of the generic timer into nanoseconds.
+#[inline]
+pub fn ticks_to_nanos(ticks: u64) -> u64 {
+    (ticks as u128 * NANOS_PER_SEC as u128 / counter_frequency() as u128) as u64
+}
+
+/// Convert nanoseconds into a count of the generic timer, rounded up.
+#[inline]
+pub fn nanos_to_ticks(nanos: u64) -> u64 {
+    (nanos as u128 * counter_frequency() as u128).div_ceil(NANOS_PER_SEC as u128) as u64
+}
+
+/// The nanoseconds since the machine booted.
+#[inline]
+pub fn current_nanos() -> u64 {
+    ticks_to_nanos(counter())
+}
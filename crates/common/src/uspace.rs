@@ -1,6 +1,6 @@
 //! It defines all kinds of configuration about user space.
 
-use core::net::{Ipv4Addr, SocketAddr};
+use core::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
 
 pub const USPACE_HEAP_BASE: usize = 0x1_0000_0000;
 pub const USPACE_HEAP_SIZE: usize = 0x10_0000;
@@ -19,10 +19,30 @@ pub const STDERR_FD: i32 = 2;
 
 /// The lowest address of the user space
 pub const USPACE_BASE: usize = 0x1000;
+/// The end of the user space, above `seL4_UserTop` of aarch64
+pub const USPACE_TOP: usize = 0x8000_0000_0000;
+
+/// The lowest address of the IPC buffers of the tasks sharing the memory
+pub const USPACE_IPC_BUFFER_BASE: usize = 0x3_0000_0000;
+
+/// The address the position-independent executables are loaded at
+pub const USPACE_DYN_BASE: usize = 0x4_0000_0000;
+/// The address the interpreters of the dynamically linked programs are loaded at
+pub const USPACE_INTERP_BASE: usize = 0x5_0000_0000;
 
 /// A void pointer in C
 pub type CVoidPtr = usize;
 
+/// The address family of unix domain sockets, the same as `AF_UNIX` in C
+pub const AF_UNIX: u16 = 1;
+/// The address family of IPv4, the same as `AF_INET` in C
+pub const AF_INET: u16 = 2;
+/// The address family of IPv6, the same as `AF_INET6` in C
+pub const AF_INET6: u16 = 10;
+
+/// The generic socket address, the same as `sockaddr` in C.
+///
+/// It is also the layout of `sockaddr_in` when `sa_family` is [AF_INET].
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone)]
 pub struct LibcSocketAddr {
@@ -30,45 +50,211 @@ pub struct LibcSocketAddr {
     pub sa_data: [u8; 14usize],
 }
 
-impl From<SocketAddr> for LibcSocketAddr {
-    fn from(value: SocketAddr) -> Self {
-        let mut addr = LibcSocketAddr::default();
-        // FIXME: It default use AF_INET domain. And it use ipv4 address.
-        addr.sa_family = 2;
-        let ip = value.ip();
-        match ip {
-            core::net::IpAddr::V4(ip) => {
-                let ip = ip.octets();
-                for i in 0..4 {
-                    addr.sa_data[i + 2] = ip[i];
-                }
-            }
-            core::net::IpAddr::V6(ip) => {
-                let ip = ip.octets();
-                for i in 0..12 {
-                    addr.sa_data[i + 2] = ip[i];
-                }
-            }
-        }
-        addr.sa_data[0] = (value.port() >> 8) as u8;
-        addr.sa_data[1] = (value.port() & 0xff) as u8;
+impl From<SocketAddrV4> for LibcSocketAddr {
+    fn from(value: SocketAddrV4) -> Self {
+        let mut addr = LibcSocketAddr {
+            sa_family: AF_INET,
+            ..Default::default()
+        };
+        addr.sa_data[..2].copy_from_slice(&value.port().to_be_bytes());
+        addr.sa_data[2..6].copy_from_slice(&value.ip().octets());
         addr
     }
 }
 
-impl Into<SocketAddr> for LibcSocketAddr {
-    fn into(self) -> SocketAddr {
-        // FIXME: It default use AF_INET domain. And it use ipv4 address.
-        let data = self.sa_data;
+impl From<LibcSocketAddr> for SocketAddrV4 {
+    fn from(value: LibcSocketAddr) -> Self {
+        let data = value.sa_data;
         let port = u16::from_be_bytes([data[0], data[1]]);
+        SocketAddrV4::new(Ipv4Addr::new(data[2], data[3], data[4], data[5]), port)
+    }
+}
 
-        SocketAddr::new(
-            core::net::IpAddr::V4(Ipv4Addr::new(data[2], data[3], data[4], data[5])),
-            port,
+/// The IPv6 socket address, the same as `sockaddr_in6` in C.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LibcSocketAddrIn6 {
+    pub sin6_family: u16,
+    /// The port in network byte order
+    pub sin6_port: u16,
+    pub sin6_flowinfo: u32,
+    pub sin6_addr: [u8; 16],
+    pub sin6_scope_id: u32,
+}
+
+impl From<SocketAddrV6> for LibcSocketAddrIn6 {
+    fn from(value: SocketAddrV6) -> Self {
+        Self {
+            sin6_family: AF_INET6,
+            sin6_port: value.port().to_be(),
+            sin6_flowinfo: value.flowinfo(),
+            sin6_addr: value.ip().octets(),
+            sin6_scope_id: value.scope_id(),
+        }
+    }
+}
+
+impl From<LibcSocketAddrIn6> for SocketAddrV6 {
+    fn from(value: LibcSocketAddrIn6) -> Self {
+        SocketAddrV6::new(
+            Ipv6Addr::from(value.sin6_addr),
+            u16::from_be(value.sin6_port),
+            value.sin6_flowinfo,
+            value.sin6_scope_id,
         )
     }
 }
 
+/// The length of the path in [LibcSocketAddrUn], the same as `UNIX_PATH_MAX` in linux.
+pub const UNIX_PATH_MAX: usize = 108;
+
+/// The unix domain socket address, the same as `sockaddr_un` in C.
+///
+/// An abstract name starts with a NUL byte, and its length is given by the
+/// length of the address instead of a terminating NUL byte.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LibcSocketAddrUn {
+    pub sun_family: u16,
+    pub sun_path: [u8; UNIX_PATH_MAX],
+}
+
+impl Default for LibcSocketAddrUn {
+    fn default() -> Self {
+        Self {
+            sun_family: AF_UNIX,
+            sun_path: [0; UNIX_PATH_MAX],
+        }
+    }
+}
+
+/// A buffer in the user space, the same as `struct iovec` in C.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IoVec {
+    pub iov_base: usize,
+    pub iov_len: usize,
+}
+
+/// The message of `sendmsg` and `recvmsg`, the same as `struct msghdr` in linux.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MsgHdr {
+    pub msg_name: usize,
+    pub msg_namelen: u32,
+    pub msg_iov: usize,
+    pub msg_iovlen: usize,
+    pub msg_control: usize,
+    pub msg_controllen: usize,
+    pub msg_flags: i32,
+}
+
+/// The header of an ancillary message, the same as `struct cmsghdr` in linux.
+///
+/// The data follows the header, and the next header is aligned to 8 bytes.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CmsgHdr {
+    pub cmsg_len: usize,
+    pub cmsg_level: i32,
+    pub cmsg_type: i32,
+}
+
+/// The time value, the same as `struct timespec` in C.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl TimeSpec {
+    /// Whether the time value is zero.
+    pub const fn is_zero(&self) -> bool {
+        self.tv_sec == 0 && self.tv_nsec == 0
+    }
+
+    /// Whether the time value is normalized and not negative.
+    pub const fn is_valid(&self) -> bool {
+        self.tv_sec >= 0 && self.tv_nsec >= 0 && self.tv_nsec < 1_000_000_000
+    }
+
+    /// Convert the time value into nanoseconds, saturating at the maximum.
+    pub const fn as_nanos(&self) -> u64 {
+        (self.tv_sec as u64)
+            .saturating_mul(1_000_000_000)
+            .saturating_add(self.tv_nsec as u64)
+    }
+
+    /// Create a time value from nanoseconds.
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self {
+            tv_sec: (nanos / 1_000_000_000) as i64,
+            tv_nsec: (nanos % 1_000_000_000) as i64,
+        }
+    }
+}
+
+/// The time value, the same as `struct timeval` in C.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TimeVal {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+impl TimeVal {
+    /// Whether the time value is normalized and not negative.
+    pub const fn is_valid(&self) -> bool {
+        self.tv_sec >= 0 && self.tv_usec >= 0 && self.tv_usec < 1_000_000
+    }
+
+    /// Convert the time value into microseconds, saturating at the maximum.
+    pub const fn as_micros(&self) -> u64 {
+        (self.tv_sec as u64)
+            .saturating_mul(1_000_000)
+            .saturating_add(self.tv_usec as u64)
+    }
+
+    /// Convert the time value into nanoseconds, `None` if it overflows.
+    pub const fn checked_nanos(&self) -> Option<u64> {
+        match (self.tv_sec as u64).checked_mul(1_000_000_000) {
+            Some(nanos) => nanos.checked_add(self.tv_usec as u64 * 1_000),
+            None => None,
+        }
+    }
+
+    /// Create a time value from microseconds.
+    pub const fn from_micros(micros: u64) -> Self {
+        Self {
+            tv_sec: (micros / 1_000_000) as i64,
+            tv_usec: (micros % 1_000_000) as i64,
+        }
+    }
+}
+
+/// The status of a file, the same as `struct stat` of aarch64 linux.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stat {
+    pub st_dev: u64,
+    pub st_ino: u64,
+    pub st_mode: u32,
+    pub st_nlink: u32,
+    pub st_uid: u32,
+    pub st_gid: u32,
+    pub st_rdev: u64,
+    pub __pad1: u64,
+    pub st_size: i64,
+    pub st_blksize: i32,
+    pub __pad2: i32,
+    pub st_blocks: i64,
+    pub st_atime: TimeSpec,
+    pub st_mtime: TimeSpec,
+    pub st_ctime: TimeSpec,
+    pub __unused: [u32; 2],
+}
+
 bitflags::bitflags! {
     /// 用于 sys_clone 的选项
     #[derive(Debug, Clone, Copy)]
@@ -138,3 +324,58 @@ impl Default for CloneArgs {
         }
     }
 }
+
+/// The scheduling policies of linux.
+pub const SCHED_OTHER: u32 = 0;
+pub const SCHED_FIFO: u32 = 1;
+pub const SCHED_RR: u32 = 2;
+pub const SCHED_BATCH: u32 = 3;
+pub const SCHED_IDLE: u32 = 5;
+pub const SCHED_DEADLINE: u32 = 6;
+/// Or-ed into the policy of `sched_setscheduler`, the children are not
+/// given the real-time policies or a negative nice.
+pub const SCHED_RESET_ON_FORK: u32 = 0x4000_0000;
+/// The flag of [SchedAttr] for [SCHED_RESET_ON_FORK].
+pub const SCHED_FLAG_RESET_ON_FORK: u64 = 0x01;
+
+/// The priorities of `SCHED_FIFO` and `SCHED_RR`.
+pub const SCHED_RT_PRIORITY_MIN: u32 = 1;
+pub const SCHED_RT_PRIORITY_MAX: u32 = 99;
+
+/// The nice values of the other policies, a lower one runs first.
+pub const NICE_MIN: i32 = -20;
+pub const NICE_MAX: i32 = 19;
+
+/// The `which` of `getpriority` and `setpriority`.
+pub const PRIO_PROCESS: usize = 0;
+pub const PRIO_PGRP: usize = 1;
+pub const PRIO_USER: usize = 2;
+
+/// The size of the first version of [SchedAttr], the same as
+/// `SCHED_ATTR_SIZE_VER0` in linux.
+pub const SCHED_ATTR_SIZE_VER0: u32 = 48;
+
+/// The scheduling parameter of `sched_setscheduler`, the same as
+/// `struct sched_param` in C.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SchedParam {
+    pub sched_priority: i32,
+}
+
+/// The scheduling attributes of `sched_setattr`, the first version of
+/// `struct sched_attr` in linux.
+///
+/// The times of `SCHED_DEADLINE` are in nanoseconds.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SchedAttr {
+    pub size: u32,
+    pub sched_policy: u32,
+    pub sched_flags: u64,
+    pub sched_nice: i32,
+    pub sched_priority: u32,
+    pub sched_runtime: u64,
+    pub sched_deadline: u64,
+    pub sched_period: u64,
+}
@@ -0,0 +1,219 @@
+//! Generate the system composition from `system.toml`, see `src/manifest.rs`.
+
+#[path = "system.rs"]
+mod system;
+
+use std::{env, fmt::Write, fs, path::PathBuf};
+
+use system::{Component, Device, Signal, Slot, Value};
+
+/// Split `component.object` into the component and the object.
+fn object_path(path: &str) -> (Option<&str>, &str) {
+    match path.split_once('.') {
+        Some((component, object)) => (Some(component), object),
+        None => (None, path),
+    }
+}
+
+fn slot_cap(component: &Component, slot: &Slot) -> String {
+    let badge = slot.badge.as_ref().map_or("0".into(), Value::expr);
+    let check_object = |path: &str, kind: &str| {
+        if let (None, object) = object_path(path) {
+            assert_eq!(
+                component.objects.get(object).map(String::as_str),
+                Some(kind),
+                "{}: slot {} uses the undefined {kind} {object}",
+                component.name,
+                slot.name
+            );
+        }
+    };
+    if slot.untyped {
+        "SlotCap::Untyped".into()
+    } else if slot.sched_control {
+        "SlotCap::SchedControl".into()
+    } else if let Some(path) = slot.endpoint.as_ref().or(slot.notification.as_ref()) {
+        let kind = match slot.endpoint {
+            Some(_) => "endpoint",
+            None => "notification",
+        };
+        check_object(path.as_str(), kind);
+        let (target, object) = object_path(path);
+        format!("SlotCap::Object {{ component: {target:?}, object: {object:?}, badge: {badge} }}")
+    } else if let Some(path) = &slot.client {
+        let (Some(server), endpoint) = object_path(path) else {
+            panic!(
+                "{}: slot {} isn't a client of another component",
+                component.name, slot.name
+            );
+        };
+        let notify = slot
+            .notify
+            .as_deref()
+            .expect("client without the notification");
+        check_object(notify, "notification");
+        format!(
+            "SlotCap::Client {{ component: {server:?}, endpoint: {endpoint:?}, notify: {notify:?}, badge: {badge} }}"
+        )
+    } else if let Some(count) = &slot.clients {
+        format!("SlotCap::Clients {{ count: {} }}", count.expr())
+    } else {
+        "SlotCap::Empty".into()
+    }
+}
+
+/// The notification and the badge of `signal`.
+fn signal(component: &Component, signal: &Signal) -> String {
+    assert_eq!(
+        component.objects.get(&signal.notify).map(String::as_str),
+        Some("notification"),
+        "{}: the undefined notification {} is signalled",
+        component.name,
+        signal.notify
+    );
+    format!("Some(({:?}, {}))", signal.notify, signal.badge.expr())
+}
+
+fn slot_restart(component: &Component, slot: &Slot) -> String {
+    let Some(restart) = &slot.restart else {
+        return "None".into();
+    };
+    let (target, _) = slot
+        .endpoint
+        .as_deref()
+        .or(slot.notification.as_deref())
+        .or(slot.client.as_deref())
+        .map_or((None, ""), object_path);
+    assert!(
+        target.is_some(),
+        "{}: slot {} doesn't hold the capability of another component",
+        component.name,
+        slot.name
+    );
+    signal(component, restart)
+}
+
+fn restart_policy(component: &Component) -> String {
+    match component.restart.as_str() {
+        "never" => "RestartPolicy::Never".into(),
+        "always" => "RestartPolicy::Always".into(),
+        "backoff" => format!(
+            "RestartPolicy::Backoff {{ initial_ms: {}, max_ms: {} }}",
+            component.backoff_ms, component.max_backoff_ms
+        ),
+        policy => panic!("{}: unknown restart policy {policy}", component.name),
+    }
+}
+
+fn device(device: &Device) -> String {
+    let kind = match (device.kind.as_str(), device.id) {
+        ("pl011", _) => "DeviceKind::Pl011".into(),
+        ("pl031", _) => "DeviceKind::Pl031".into(),
+        ("arch-timer", _) => "DeviceKind::ArchTimer".into(),
+        ("virtio", Some(id)) => format!("DeviceKind::Virtio({id})"),
+        (kind, _) => panic!("unknown device {kind}"),
+    };
+    format!(
+        "Some(Device {{ kind: {kind}, vaddr: {:#x} }})",
+        device.vaddr
+    )
+}
+
+fn main() {
+    let manifest = system::load();
+
+    let mut out = String::new();
+    let mut slots = String::new();
+    writeln!(out, "pub static COMPONENTS: &[Component] = &[").unwrap();
+    for component in &manifest.component {
+        let objects: Vec<_> = component
+            .objects
+            .iter()
+            .map(|(name, kind)| match kind.as_str() {
+                "endpoint" => format!("({name:?}, ObjectKind::Endpoint)"),
+                "notification" => format!("({name:?}, ObjectKind::Notification)"),
+                _ => panic!("{}: unknown object kind {kind}", component.name),
+            })
+            .collect();
+
+        let module = component.name.replace('-', "_");
+        writeln!(slots, "    pub mod {module} {{").unwrap();
+        writeln!(slots, "        #[allow(unused_imports)]").unwrap();
+        writeln!(slots, "        use crate::*;").unwrap();
+        let mut offset = String::from("0");
+        let mut table = Vec::new();
+        for slot in &component.slots {
+            writeln!(
+                slots,
+                "        pub const {}: u64 = DEFAULT_CUSTOM_SLOT + {offset};",
+                slot.name
+            )
+            .unwrap();
+            table.push(format!(
+                "Slot {{ name: {:?}, offset: {offset}, cap: {}, restart: {}, first_instance: {} }}",
+                slot.name,
+                slot_cap(component, slot),
+                slot_restart(component, slot),
+                slot.first_instance
+            ));
+            let size = match &slot.clients {
+                Some(count) => count.expr(),
+                None if slot.sched_control => "NUM_CORES".into(),
+                None => "1".into(),
+            };
+            offset = format!("{offset} + {size}");
+        }
+        writeln!(slots, "    }}").unwrap();
+
+        writeln!(
+            out,
+            "    Component {{ name: {:?}, elf: {:?}, priority: {}, stack_pages: {}, core: {}, budget_us: {}, period_us: {}, dma_pages: {}, device: {}, restart: {}, max_restarts: {}, stop: {}, objects: &[{}], slots: &[{}] }},",
+            component.name,
+            component.elf,
+            component.priority,
+            component.stack_pages,
+            component.core,
+            component.budget_us.unwrap_or(component.period_us),
+            component.period_us,
+            component.dma_pages,
+            component.device.as_ref().map_or("None".into(), device),
+            restart_policy(component),
+            component.max_restarts,
+            component
+                .stop
+                .as_ref()
+                .map_or("None".into(), |stop| signal(component, stop)),
+            objects.join(", "),
+            table.join(", ")
+        )
+        .unwrap();
+    }
+    writeln!(out, "];").unwrap();
+
+    let root_timer = manifest
+        .root_timer
+        .as_deref()
+        .map(|path| match object_path(path) {
+            (Some(component), endpoint) => (component, endpoint),
+            (None, _) => panic!("root_timer {path} isn't an endpoint of a component"),
+        });
+    writeln!(
+        out,
+        "\n/// The component and the endpoint of the timer used by the root task."
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "pub static ROOT_TIMER: Option<(&str, &str)> = {root_timer:?};"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "\n/// The slots of each component, generated from `system.toml`."
+    )
+    .unwrap();
+    writeln!(out, "pub mod slots {{\n{slots}}}").unwrap();
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("system.rs");
+    fs::write(out_path, out).unwrap();
+}
@@ -1,11 +1,16 @@
 #![no_std]
 
+pub mod manifest;
+
+pub use manifest::slots;
 use sel4::cap::Endpoint;
 
 /// The default notification for thread lock.
 pub const DEFAULT_THREAD_NOTIFICATION: u64 = 17;
 /// The default endpoint for thread lock.
 pub const DEFAULT_THREAD_FAULT_EP: u64 = 18;
+/// The reply object a server receives with on a MCS kernel.
+pub const DEFAULT_THREAD_REPLY: u64 = 19;
 /// The default endpoint for thread IRQ.
 pub const DEFAULT_THREAD_IRQ_EP: u64 = 20;
 /// The default slot to store custom cap.
@@ -15,9 +20,50 @@ pub const DEFAULT_EMPTY_SLOT_INDEX: usize = 32;
 /// The default slot to store thread recv cap.
 pub const DEFAULT_THREAD_RECV_SLOT: u64 = (KERNEL_THREAD_SLOT_NUMS - 1) as _;
 
+/// The badges of the notifications bound to a thread's TCB start at this bit,
+/// so they can be told apart from the badges of the endpoints when receiving.
+pub const NOTIFICATION_BADGE_START: u64 = 1 << 32;
+/// The badge of the VIRTIO net IRQ notification bound to the net thread.
+pub const NET_IRQ_BADGE: u64 = NOTIFICATION_BADGE_START;
+/// The badge of the readiness notification bound to the kernel thread,
+/// signalled by the net thread when the readiness of its sockets changes.
+pub const NET_READY_BADGE: u64 = NOTIFICATION_BADGE_START << 1;
+/// The badge of the VIRTIO block IRQ notification of the block thread.
+pub const BLK_IRQ_BADGE: u64 = NOTIFICATION_BADGE_START;
+/// The badge of the serial IRQ notification bound to the uart thread.
+pub const UART_IRQ_BADGE: u64 = NOTIFICATION_BADGE_START;
+/// The badge of the readiness notification bound to the kernel thread,
+/// signalled by the uart thread when the console has input or a signal.
+pub const CONSOLE_READY_BADGE: u64 = NOTIFICATION_BADGE_START << 2;
+/// The badge of the timer IRQ notification bound to the timer thread.
+pub const TIMER_IRQ_BADGE: u64 = NOTIFICATION_BADGE_START;
+/// The badge of the notifications bound to the clients of the timer thread,
+/// signalled when one of their deadlines expires.
+pub const TIMER_BADGE: u64 = NOTIFICATION_BADGE_START << 3;
+/// The badge of the readiness notification bound to the kernel thread,
+/// signalled by the root task when the net thread is restarted.
+pub const NET_RESTART_BADGE: u64 = NOTIFICATION_BADGE_START << 4;
+/// The badge of the readiness notification bound to the kernel thread,
+/// signalled by the root task when the timer thread is restarted.
+pub const TIMER_RESTART_BADGE: u64 = NOTIFICATION_BADGE_START << 5;
+/// The badge of the stop request of the shutdown, signalled by the root task
+/// on the `stop` notification of the components in `system.toml`.
+pub const STOP_BADGE: u64 = NOTIFICATION_BADGE_START << 6;
+/// The number of the timer clients, the badges of their endpoints are below it.
+///
+/// The badges are given to the clients in the order of `system.toml`.
+pub const TIMER_MAX_CLIENTS: u64 = 8;
+/// The badge of the net endpoint held by the kernel thread.
+///
+/// The net thread scopes the sockets by the badge of the client creating them.
+pub const KERNEL_THREAD_NET_BADGE: u64 = 1;
+
 // Init End point, used in tasks.
 pub const INIT_EP: Endpoint = Endpoint::from_bits(DEFAULT_THREAD_FAULT_EP);
 
+/// The number of the cores the kernel is built with.
+pub const NUM_CORES: u64 = sel4::sel4_cfg_usize!(MAX_NUM_NODES) as u64;
+
 // CNode Bits
 pub const DEFAULT_CNODE_BITS: u64 = 12;
 pub const DEFAULT_CNODE_SLOT_NUMS: usize = 1 << DEFAULT_CNODE_BITS;
@@ -35,8 +81,10 @@ pub const STACK_ALIGN_SIZE: usize = 16;
 /// The size of the granule.
 pub const GRANULE_SIZE: usize = sel4::FrameObjectType::GRANULE.bytes();
 
-/// The irq number of the serial device.
+/// The irq number of the serial device, only used by the IRQ tests.
+///
+/// The driver components get their irq numbers from the root task, which
+/// reads them from the device tree.
 pub const SERIAL_DEVICE_IRQ: usize = 33;
-pub const VIRTIO_NET_IRQ: usize = 0x2f + 0x20;
 
 pub const DMA_ADDR_START: usize = 0x1_0000_3000;
@@ -0,0 +1,134 @@
+//! The system composition described by `system.toml`.
+//!
+//! The build script turns the manifest into [COMPONENTS], read by the root
+//! task to start the components, and into the [slots] modules, which name
+//! the capabilities each component finds in its CSpace. [ROOT_TIMER] is the
+//! timer the root task delays the restarts of the components with.
+
+/// A component started by the root task.
+#[derive(Debug)]
+pub struct Component {
+    pub name: &'static str,
+    /// The name of the ELF file in the build directory.
+    pub elf: &'static str,
+    pub priority: u8,
+    pub stack_pages: usize,
+    /// The core the instances run on and their interrupts are routed to.
+    pub core: usize,
+    /// The time the instances run for in each period on a MCS kernel, in
+    /// microseconds. They aren't limited if it is the whole period.
+    pub budget_us: u64,
+    pub period_us: u64,
+    /// The number of the pages mapped from `DMA_ADDR_START`.
+    pub dma_pages: usize,
+    pub device: Option<Device>,
+    /// What the root task does when an instance of the component faults.
+    pub restart: RestartPolicy,
+    /// The number of the faults in a row after which an instance isn't
+    /// restarted any more.
+    pub max_restarts: u32,
+    /// The notification of each instance signalled with the badge to stop it
+    /// when the system shuts down. The instance is stopped without being
+    /// told if it is `None`.
+    pub stop: Option<(&'static str, u64)>,
+    /// The objects created for each instance of the component.
+    pub objects: &'static [(&'static str, ObjectKind)],
+    pub slots: &'static [Slot],
+}
+
+/// The device assigned to a component.
+#[derive(Debug, Clone, Copy)]
+pub struct Device {
+    pub kind: DeviceKind,
+    /// The virtual address of the page of the registers, 0 if not mapped.
+    pub vaddr: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Pl011,
+    Pl031,
+    ArchTimer,
+    /// A virtio-mmio device with the device ID, one instance of the component
+    /// is started for each device.
+    Virtio(u32),
+}
+
+/// The restart policy of a component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// The instance stays stopped after a fault.
+    Never,
+    /// The instance is restarted at once.
+    Always,
+    /// The instance is restarted after a delay, starting from `initial_ms`
+    /// and doubled by each fault up to `max_ms`. The delay is reset when the
+    /// instance runs longer than `max_ms` without a fault.
+    Backoff { initial_ms: u64, max_ms: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Endpoint,
+    Notification,
+}
+
+/// A slot in the CSpace of a component.
+#[derive(Debug)]
+pub struct Slot {
+    pub name: &'static str,
+    /// The offset from `DEFAULT_CUSTOM_SLOT`.
+    pub offset: u64,
+    pub cap: SlotCap,
+    /// The notification of this instance signalled with the badge when the
+    /// component the capability belongs to is restarted.
+    pub restart: Option<(&'static str, u64)>,
+    /// The slot is left empty in the instances other than the first one.
+    pub first_instance: bool,
+}
+
+/// The capability put in a slot by the root task.
+#[derive(Debug)]
+pub enum SlotCap {
+    /// Left empty, filled by the component.
+    Empty,
+    /// The largest untyped memory.
+    Untyped,
+    /// The object of this instance if `component` is `None`, otherwise the
+    /// object of the first instance of `component`, minted with `badge`.
+    Object {
+        component: Option<&'static str>,
+        object: &'static str,
+        badge: u64,
+    },
+    /// The endpoint of the first instance of the server `component`, minted
+    /// with the next badge of the server. The server gets the notification
+    /// `notify` of this instance minted with `badge`.
+    Client {
+        component: &'static str,
+        endpoint: &'static str,
+        notify: &'static str,
+        badge: u64,
+    },
+    /// The notifications of the clients, `count` slots from this one.
+    Clients { count: u64 },
+    /// The scheduling controls of the cores on a MCS kernel, `NUM_CORES`
+    /// slots from this one. They are left empty on the other kernels.
+    SchedControl,
+}
+
+impl SlotCap {
+    /// The other component the capability belongs to.
+    pub fn component(&self) -> Option<&'static str> {
+        match self {
+            SlotCap::Object { component, .. } => *component,
+            SlotCap::Client { component, .. } => Some(*component),
+            _ => None,
+        }
+    }
+}
+
+#[allow(unused_imports)]
+use crate::*;
+
+include!(concat!(env!("OUT_DIR"), "/system.rs"));
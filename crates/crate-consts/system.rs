@@ -0,0 +1,129 @@
+//! The types of `system.toml`, shared by the build scripts of `crate-consts`
+//! and `root-task`.
+
+// Each build script reads only the parts of the manifest it needs.
+#![allow(dead_code)]
+
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use serde::Deserialize;
+
+/// An integer, or an expression of the constants of `crate-consts`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(u64),
+    Expr(String),
+}
+
+impl Value {
+    pub fn expr(&self) -> String {
+        match self {
+            Value::Int(value) => value.to_string(),
+            Value::Expr(expr) => format!("({expr})"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Manifest {
+    /// The endpoint of the timer as `component.endpoint`, the root task is
+    /// its client.
+    pub root_timer: Option<String>,
+    pub component: Vec<Component>,
+}
+
+#[derive(Deserialize)]
+pub struct Component {
+    pub name: String,
+    pub elf: String,
+    pub priority: u8,
+    pub stack_pages: usize,
+    #[serde(default)]
+    pub core: usize,
+    pub budget_us: Option<u64>,
+    #[serde(default = "default_period_us")]
+    pub period_us: u64,
+    #[serde(default)]
+    pub dma_pages: usize,
+    pub device: Option<Device>,
+    #[serde(default = "default_restart")]
+    pub restart: String,
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    pub stop: Option<Signal>,
+    #[serde(default)]
+    pub objects: BTreeMap<String, String>,
+    #[serde(default)]
+    pub slots: Vec<Slot>,
+}
+
+#[derive(Deserialize)]
+pub struct Device {
+    pub kind: String,
+    pub id: Option<u32>,
+    #[serde(default)]
+    pub vaddr: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Slot {
+    pub name: String,
+    #[serde(default)]
+    pub untyped: bool,
+    #[serde(default)]
+    pub sched_control: bool,
+    pub endpoint: Option<String>,
+    pub notification: Option<String>,
+    pub client: Option<String>,
+    pub notify: Option<String>,
+    pub badge: Option<Value>,
+    pub clients: Option<Value>,
+    pub restart: Option<Signal>,
+    /// Only the first instance of the component gets the capability.
+    #[serde(default)]
+    pub first_instance: bool,
+}
+
+/// A notification of the instance signalled by the root task with `badge`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Signal {
+    pub notify: String,
+    pub badge: Value,
+}
+
+fn default_restart() -> String {
+    "never".into()
+}
+
+fn default_period_us() -> u64 {
+    10_000
+}
+
+fn default_backoff_ms() -> u64 {
+    100
+}
+
+fn default_max_backoff_ms() -> u64 {
+    10_000
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+
+/// Read `system.toml` at the top of the tree, and rebuild when it changes.
+pub fn load() -> Manifest {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let path = manifest_dir.join("../../system.toml");
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    toml::from_str(&fs::read_to_string(&path).unwrap())
+        .unwrap_or_else(|err| panic!("invalid {}: {err}", path.display()))
+}
@@ -0,0 +1,51 @@
+//! Generate the table of the files in the root file system, which are built
+//! into the kernel thread from `build/rootfs`.
+
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Collect the regular files under `dir`, with their paths from the root.
+fn collect(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) {
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.map(Result::unwrap) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(root, &path, files);
+        } else if path.is_file() {
+            println!("cargo:rerun-if-changed={}", path.display());
+            let name = path.strip_prefix(root).unwrap().to_str().unwrap();
+            files.push((format!("/{name}"), path));
+        }
+    }
+}
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let root = manifest_dir.join("../../build/rootfs");
+
+    let mut files = Vec::new();
+    collect(&root, &root, &mut files);
+    files.sort();
+
+    let mut out = String::from("static ROOTFS_FILES: &[(&str, &[u8])] = &[\n");
+    for (name, path) in &files {
+        writeln!(
+            out,
+            "    ({:?}, include_bytes_aligned!(16, {:?})),",
+            name,
+            path.display().to_string()
+        )
+        .unwrap();
+    }
+    out.push_str("];\n");
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("rootfs_files.rs");
+    fs::write(out_path, out).unwrap();
+}
@@ -1,23 +1,44 @@
-use crate::{syscall::handle_ipc_call, task::Sel4Task, utils::align_bits, OBJ_ALLOCATOR};
+use crate::{loader::Program, syscall::bind_sched, task::Sel4Task, OBJ_ALLOCATOR};
 use alloc::collections::btree_map::BTreeMap;
-use common::{CustomMessageLabel, USPACE_STACK_TOP};
+use common::USPACE_STACK_TOP;
 use core::cmp;
-use crate_consts::{CNODE_RADIX_BITS, DEFAULT_THREAD_FAULT_EP, PAGE_SIZE, PAGE_SIZE_BITS};
+use crate_consts::{CNODE_RADIX_BITS, DEFAULT_THREAD_FAULT_EP, PAGE_SIZE};
 use sel4::{
-    cap::Endpoint, cap_type::Granule, debug_println, init_thread, r#yield, reply, with_ipc_buffer,
-    with_ipc_buffer_mut, CNodeCapData, CapRights, Fault, MessageInfo, Result, Word,
+    cap::Endpoint, cap_type::Granule, debug_println, init_thread, CNodeCapData, CapRights, Result,
 };
 use spin::Mutex;
 use xmas_elf::ElfFile;
 
 // TODO: Make elf file path dynamically available.
-const CHILD_ELF: &[u8] = include_bytes!("../../../build/test-thread.elf");
-
+pub(crate) const CHILD_ELF: &[u8] = include_bytes!("../../../build/test-thread.elf");
+
+/// The tasks indexed by their badges.
+///
+/// The locks of the kernel thread are taken in this order: the map, the
+/// `file_table` and then the `mem` of a task, [OBJ_ALLOCATOR] and last the
+/// page seat of [crate::page_seat], so they can be taken from several cores.
+/// The lock of the map is released before an IPC to another component and
+/// before a file is dropped, as closing a socket is such an IPC.
 pub static TASK_MAP: Mutex<BTreeMap<u64, Sel4Task>> = Mutex::new(BTreeMap::new());
 
-pub fn test_child(ep: Endpoint) -> Result<()> {
+/// Start the first process, its syscalls and faults are sent to `ep`.
+///
+/// It only starts the process, [crate::event::run] serves it and the
+/// processes it creates.
+pub fn spawn_init(ep: Endpoint) -> Result<()> {
     let args = &["busybox", "echo", "Kernel Thread's Child Says Hello!"];
     debug_println!("[KernelThread] Child Task Start, busybox args: {:?}", args);
+    let task = new_process(ep, args)?;
+    task.tcb.tcb_resume().unwrap();
+
+    TASK_MAP.lock().insert(task.id as _, task);
+
+    Ok(())
+}
+
+/// Create a process running busybox with `args`, its syscalls and faults are
+/// sent to `ep`. It isn't resumed.
+pub fn new_process(ep: Endpoint, args: &[&str]) -> Result<Sel4Task> {
     let mut task = Sel4Task::new();
 
     // Copy tcb to child
@@ -39,10 +60,18 @@ pub fn test_child(ep: Endpoint) -> Result<()> {
         )?;
 
     debug_println!("[KernelThread] Child Task Mapping ELF...");
-    task.load_elf(CHILD_ELF);
+    let program = Program::parse(CHILD_ELF).expect("[KernelThread] can't load elf file");
+    let info = task.load_elf(&program);
     let child_elf_file = ElfFile::new(CHILD_ELF).expect("[KernelThread] can't load elf file");
 
-    let sp_ptr = task.map_stack(0, USPACE_STACK_TOP - 16 * PAGE_SIZE, USPACE_STACK_TOP, args);
+    let sp_ptr = task.map_stack(
+        &info,
+        USPACE_STACK_TOP - 16 * PAGE_SIZE,
+        USPACE_STACK_TOP,
+        args[0],
+        args,
+        &["HOME=/", "TERM=linux"],
+    );
 
     let ipc_buffer_cap = OBJ_ALLOCATOR
         .lock()
@@ -52,9 +81,11 @@ pub fn test_child(ep: Endpoint) -> Result<()> {
         .fold(0, |acc, x| cmp::max(acc, x.address() + x.size()));
     let ipc_buffer_addr = (max + 4096 - 1) / 4096 * 4096;
     task.map_page(ipc_buffer_addr as _, ipc_buffer_cap);
+    task.ipc_buffer = ipc_buffer_addr as _;
 
     // Configure the child task
-    task.tcb.tcb_configure(
+    common::tcb_configure(
+        task.tcb,
         ep.cptr(),
         task.cnode,
         CNodeCapData::new(0, sel4::WORD_SIZE - CNODE_RADIX_BITS),
@@ -62,15 +93,14 @@ pub fn test_child(ep: Endpoint) -> Result<()> {
         ipc_buffer_addr,
         ipc_buffer_cap,
     )?;
-    task.tcb
-        .tcb_set_sched_params(init_thread::slot::TCB.cap(), 0, 255)?;
 
     let mut user_context = sel4::UserContext::default();
 
     // Set child task's context
-    *user_context.pc_mut() = child_elf_file.header.pt2.entry_point();
+    *user_context.pc_mut() = info.start as _;
     *user_context.sp_mut() = sp_ptr as _;
     *user_context.gpr_mut(0) = ep.cptr().bits();
+    user_context.inner_mut().tpidrro_el0 = ipc_buffer_addr;
     // Get TSS section address.
     user_context.inner_mut().tpidr_el0 = child_elf_file
         .find_section_by_name(".tbss")
@@ -80,73 +110,8 @@ pub fn test_child(ep: Endpoint) -> Result<()> {
         .tcb_write_all_registers(false, &mut user_context)
         .unwrap();
 
-    // task.tcb.tcb_set_affinity(0).unwrap();
+    bind_sched(&mut task, ep)?;
     task.tcb.debug_name(b"before name");
 
-    task.tcb.tcb_resume().unwrap();
-
-    TASK_MAP.lock().insert(task.id as _, task);
-
-    loop {
-        let (message, badge) = ep.recv(());
-
-        if message.label() < 8 {
-            let fault = with_ipc_buffer(|buffer| Fault::new(&buffer, &message));
-            debug_println!("[Kernel Thread] Received Fault: {:#x?}", fault);
-            match fault {
-                Fault::VmFault(vmfault) => {
-                    let vaddr = align_bits(vmfault.addr() as usize, PAGE_SIZE_BITS);
-                    let page_cap = OBJ_ALLOCATOR
-                        .lock()
-                        .allocate_and_retyped_fixed_sized::<Granule>();
-                    let mut task_map = TASK_MAP.lock();
-                    let task = task_map.get_mut(&badge).unwrap();
-                    task.map_page(vaddr, page_cap);
-
-                    task.tcb.tcb_resume().unwrap();
-                    drop(task_map);
-                }
-                _ => {}
-            }
-        } else {
-            match CustomMessageLabel::try_from(&message) {
-                Some(CustomMessageLabel::TestCustomMessage) => reply_with(&[]),
-                Some(CustomMessageLabel::SysCall) => {
-                    let (sys_id, args) = with_ipc_buffer(|ipc_buf| {
-                        let msgs = ipc_buf.msg_regs();
-                        let args: [Word; 6] = msgs[1..7].try_into().unwrap();
-                        (msgs[0] as _, args.map(|x| x as usize))
-                    });
-                    let res = handle_ipc_call(badge, sys_id, args, ep)
-                        .map_err(|e| -e.into_raw() as isize)
-                        .unwrap_or_else(|e| e as usize);
-                    reply_with(&[res]);
-                }
-                Some(CustomMessageLabel::Exit) => break,
-                None => {
-                    debug_println!(
-                        "[Kernel Thread] Recv unknown {} length message {:#x?} ",
-                        message.length(),
-                        message
-                    );
-                }
-            }
-        }
-        r#yield();
-    }
-
-    // TODO: Free memory from slots.
-    Ok(())
-}
-
-/// Reply a message with empty message information
-#[inline]
-pub(crate) fn reply_with(regs: &[usize]) {
-    with_ipc_buffer_mut(|buffer| {
-        let msg_regs = buffer.msg_regs_mut();
-        regs.iter()
-            .enumerate()
-            .for_each(|(i, reg)| msg_regs[i] = *reg as _);
-        reply(buffer, MessageInfo::new(0, 0, 0, 8 * regs.len()))
-    });
+    Ok(task)
 }
@@ -0,0 +1,167 @@
+//! The event loop of the kernel thread.
+//!
+//! All the processes send their syscalls and faults to one endpoint, the
+//! notifications of the servers are bound to the TCB and received on it too.
+//! A message is never waited on: a syscall which can't complete is parked
+//! with its reply capability, see [crate::syscall::wait], and the loop goes
+//! on serving the other processes until a notification completes it.
+//!
+//! The programs built for linux call the kernel by `svc` instead, which the
+//! seL4 kernel sends as the `UnknownSyscall` fault, it is served the same way
+//! and the task goes on after the `svc` when it is replied.
+
+use common::{CustomMessageLabel, SERVER_REPLY};
+use core::ops::ControlFlow;
+use crate_consts::{
+    CONSOLE_READY_BADGE, NET_RESTART_BADGE, NOTIFICATION_BADGE_START, PAGE_SIZE_BITS,
+    TIMER_RESTART_BADGE,
+};
+use sel4::{
+    cap::Endpoint, cap_type::Granule, debug_println, r#yield, with_ipc_buffer, with_ipc_buffer_mut,
+    Fault, MessageInfo, Word,
+};
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{
+        handle_console_signals, handle_ipc_call, handle_svc_call, timer_restarted,
+        wake_requested_waiters, wake_waiters, SysResult, SYS_PARKED,
+    },
+    utils::align_bits,
+    OBJ_ALLOCATOR,
+};
+
+/// Serve the processes sending to `ep` until one of them sends
+/// `CustomMessageLabel::Exit`.
+pub fn run(ep: Endpoint) {
+    while handle_next(ep).is_continue() {}
+}
+
+/// Receive and handle the next message or notification sent to `ep`, breaks
+/// if a task asks the kernel thread to exit.
+pub(crate) fn handle_next(ep: Endpoint) -> ControlFlow<()> {
+    let (message, badge) = ep.recv(SERVER_REPLY);
+
+    if badge >= NOTIFICATION_BADGE_START {
+        handle_notification(badge);
+    } else if message.label() < 8 {
+        handle_fault(badge, &message, ep);
+    } else if handle_message(badge, &message, ep).is_break() {
+        return ControlFlow::Break(());
+    }
+    r#yield();
+    ControlFlow::Continue(())
+}
+
+/// Retry the parked syscalls after the bound notification is signalled.
+fn handle_notification(badge: u64) {
+    // The net-thread signals the bound notification when sockets become ready,
+    // and the uart-thread signals it when the console has input or signals.
+    if badge & CONSOLE_READY_BADGE != 0 {
+        handle_console_signals();
+    }
+    // The root task signals it when a server is restarted. The sockets
+    // of the old net-thread are gone, the syscalls on them fail then.
+    if badge & NET_RESTART_BADGE != 0 {
+        debug_println!("[Kernel Thread] net-thread restarted, the sockets are closed");
+    }
+    if badge & TIMER_RESTART_BADGE != 0 {
+        timer_restarted();
+    }
+    wake_waiters();
+}
+
+/// Handle the fault of the task `badge`, the page faults are served by
+/// mapping a new page and the `svc` of the programs of linux as syscalls.
+fn handle_fault(badge: u64, message: &MessageInfo, ep: Endpoint) {
+    let fault = with_ipc_buffer(|buffer| Fault::new(&buffer, message));
+    debug_println!("[Kernel Thread] Received Fault: {:#x?}", fault);
+    match fault {
+        Fault::VmFault(vmfault) => {
+            let vaddr = align_bits(vmfault.addr() as usize, PAGE_SIZE_BITS);
+            let page_cap = OBJ_ALLOCATOR
+                .lock()
+                .allocate_and_retyped_fixed_sized::<Granule>();
+            let mut task_map = TASK_MAP.lock();
+            let task = task_map.get_mut(&badge).unwrap();
+            task.map_page(vaddr, page_cap);
+
+            task.tcb.tcb_resume().unwrap();
+            drop(task_map);
+        }
+        Fault::UnknownSyscall(_) => handle_svc(badge, ep),
+        _ => {}
+    }
+}
+
+/// Handle the `svc` of the task `badge`, the number of the syscall is in `x8`
+/// and its arguments in `x0` to `x5` the same as linux on aarch64.
+///
+/// The reply of the fault sets `x0` to the result, and the task restarts at
+/// its PC, which is moved past the `svc` first.
+fn handle_svc(badge: u64, ep: Endpoint) {
+    let tcb = TASK_MAP.lock().get(&badge).unwrap().tcb;
+    let mut regs = tcb.tcb_read_all_registers(false).unwrap();
+    *regs.pc_mut() += 4;
+    tcb.tcb_write_all_registers(false, &mut regs).unwrap();
+
+    let regs = regs.inner();
+    let args = [regs.x0, regs.x1, regs.x2, regs.x3, regs.x4, regs.x5];
+    let res = handle_svc_call(badge, regs.x8 as _, args.map(|x| x as usize), ep);
+    reply_result(res);
+    wake_requested_waiters();
+}
+
+/// Handle the call of the task `badge`, breaks if the task asks the kernel
+/// thread to exit.
+fn handle_message(badge: u64, message: &MessageInfo, ep: Endpoint) -> ControlFlow<()> {
+    match CustomMessageLabel::try_from(message) {
+        Some(CustomMessageLabel::TestCustomMessage) => reply_with(&[]),
+        Some(CustomMessageLabel::SysCall) => {
+            let (sys_id, args) = with_ipc_buffer(|ipc_buf| {
+                let msgs = ipc_buf.msg_regs();
+                let args: [Word; 6] = msgs[1..7].try_into().unwrap();
+                (msgs[0] as _, args.map(|x| x as usize))
+            });
+            let res = handle_ipc_call(badge, sys_id, args, ep);
+            reply_result(res);
+            wake_requested_waiters();
+        }
+        Some(CustomMessageLabel::Exit) => return ControlFlow::Break(()),
+        None => {
+            debug_println!(
+                "[Kernel Thread] Recv unknown {} length message {:#x?} ",
+                message.length(),
+                message
+            );
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// Reply the result of a syscall, the parked syscall is replied by
+/// `wake_waiters` later.
+fn reply_result(res: SysResult) {
+    if res != Err(SYS_PARKED) {
+        let res = res
+            .map_err(|e| -e.into_raw() as isize)
+            .unwrap_or_else(|e| e as usize);
+        reply_with(&[res]);
+    }
+}
+
+/// Reply a message with empty message information
+#[inline]
+pub(crate) fn reply_with(regs: &[usize]) {
+    with_ipc_buffer_mut(|buffer| {
+        let msg_regs = buffer.msg_regs_mut();
+        regs.iter()
+            .enumerate()
+            .for_each(|(i, reg)| msg_regs[i] = *reg as _);
+        common::reply(
+            buffer,
+            SERVER_REPLY,
+            MessageInfo::new(0, 0, 0, 8 * regs.len()),
+        )
+    });
+}
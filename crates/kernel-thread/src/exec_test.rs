@@ -0,0 +1,260 @@
+//! Check that the programs start the same as on linux.
+
+use alloc::{collections::btree_map::BTreeMap, string::String, vec};
+use common::{USPACE_STACK_SIZE, USPACE_STACK_TOP};
+use core::mem::size_of;
+use crate_consts::PAGE_SIZE;
+use sel4::cap::Endpoint;
+use syscalls::{Errno, Sysno};
+
+use crate::{
+    child_test::{new_process, CHILD_ELF, TASK_MAP},
+    event,
+    loader::Program,
+    syscall::mount_file,
+    task::{AuxV, Sel4Task},
+    utils::{read_c_string, read_item, syscall, write_item_list},
+};
+
+/// The bytes of an argument of [test_arg_max] with its NUL byte, it takes
+/// a KiB with its pointer.
+const ARG_LEN: usize = 1024 - size_of::<usize>();
+
+/// The arguments of [ARG_LEN] fitting in `ARG_MAX`, a quarter of the stack.
+const ARG_MAX_ARGS: usize = USPACE_STACK_SIZE / 4 / 1024;
+
+/// The images of [test_exec_dynamic], the program exits with
+/// [PROGRAM_CODE] and the interpreter with [INTERP_CODE].
+const PROGRAM_PATH: &str = "/bin/exit-pie";
+const DYNAMIC_PATH: &str = "/bin/exit-dynamic";
+const INTERP_PATH: &str = "/lib/ld-exit.so.1";
+const PROGRAM_CODE: u16 = 5;
+const INTERP_CODE: u16 = 6;
+
+/// `wait4` returns at once if no child has exited.
+const WNOHANG: usize = 1;
+
+/// The size of the images of [test_exec_dynamic].
+const IMAGE_SIZE: usize = 256;
+
+/// The sizes of the ELF header and of a program header.
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+/// The values of the ELF headers, the same as the ones of linux.
+const ET_DYN: u64 = 3;
+const EM_AARCH64: u64 = 183;
+const PT_LOAD: u64 = 1;
+const PT_INTERP: u64 = 3;
+const PF_X: u64 = 1;
+const PF_R: u64 = 4;
+
+/// An ELF image aligned to read its headers in place, the same as the files
+/// of the root file system.
+#[repr(align(16))]
+struct Image([u8; IMAGE_SIZE]);
+
+static PROGRAM: Image = exit_image(PROGRAM_CODE, "");
+static DYNAMIC: Image = exit_image(PROGRAM_CODE, INTERP_PATH);
+static INTERP: Image = exit_image(INTERP_CODE, "");
+
+/// Write the `len` lowest bytes of `value` at `offset` of `image` in little
+/// endian.
+const fn put(
+    mut image: [u8; IMAGE_SIZE],
+    offset: usize,
+    len: usize,
+    value: u64,
+) -> [u8; IMAGE_SIZE] {
+    let mut i = 0;
+    while i < len {
+        image[offset + i] = (value >> (i * 8)) as u8;
+        i += 1;
+    }
+    image
+}
+
+/// Write the program header at `offset` of `image` mapping the `size` bytes
+/// at `start` of the file to the same address.
+const fn put_phdr(
+    image: [u8; IMAGE_SIZE],
+    offset: usize,
+    (p_type, flags): (u64, u64),
+    start: usize,
+    size: usize,
+) -> [u8; IMAGE_SIZE] {
+    let image = put(image, offset, 4, p_type);
+    let image = put(image, offset + 4, 4, flags);
+    let image = put(image, offset + 8, 8, start as u64);
+    let image = put(image, offset + 16, 8, start as u64);
+    let image = put(image, offset + 24, 8, start as u64);
+    let image = put(image, offset + 32, 8, size as u64);
+    let image = put(image, offset + 40, 8, size as u64);
+    put(image, offset + 48, 8, 1)
+}
+
+/// A position-independent executable of aarch64 which calls `exit(code)` by
+/// `svc`, it names the interpreter at `interp` unless it is empty.
+///
+/// The only `PT_LOAD` segment maps the whole file to the address 0, the code
+/// follows the program headers and the path of the interpreter follows it.
+const fn exit_image(code: u16, interp: &str) -> Image {
+    let interp = interp.as_bytes();
+    let phnum = if interp.is_empty() { 1 } else { 2 };
+    let entry = EHDR_SIZE + phnum * PHDR_SIZE;
+    let interp_offset = entry + 3 * size_of::<u32>();
+
+    // "\x7fELF", 64 bits, little endian and the version 1.
+    let mut image = put([0; IMAGE_SIZE], 0, 7, 0x01_0102_464c_457f);
+    image = put(image, 16, 2, ET_DYN);
+    image = put(image, 18, 2, EM_AARCH64);
+    image = put(image, 20, 4, 1);
+    image = put(image, 24, 8, entry as u64);
+    image = put(image, 32, 8, EHDR_SIZE as u64);
+    image = put(image, 52, 2, EHDR_SIZE as u64);
+    image = put(image, 54, 2, PHDR_SIZE as u64);
+    image = put(image, 56, 2, phnum as u64);
+    image = put(image, 58, 2, 64);
+
+    let mut offset = EHDR_SIZE;
+    if !interp.is_empty() {
+        let size = interp.len() + 1;
+        image = put_phdr(image, offset, (PT_INTERP, PF_R), interp_offset, size);
+        offset += PHDR_SIZE;
+        let mut i = 0;
+        while i < interp.len() {
+            image[interp_offset + i] = interp[i];
+            i += 1;
+        }
+    }
+    image = put_phdr(image, offset, (PT_LOAD, PF_R | PF_X), 0, IMAGE_SIZE);
+
+    // mov x0, #code; mov x8, #exit; svc #0
+    let exit = Sysno::exit as u64;
+    image = put(image, entry, 4, 0xd280_0000 | ((code as u64) << 5));
+    image = put(image, entry + 4, 4, 0xd280_0008 | (exit << 5));
+    image = put(image, entry + 8, 4, 0xd400_0001);
+    Image(image)
+}
+
+/// Read the string at `addr` of the `task`.
+fn string_at(task: &Sel4Task, addr: usize) -> String {
+    read_c_string(task, addr as *const u8, PAGE_SIZE).unwrap()
+}
+
+/// The initial stack holds argc, the argv and envp arrays and the auxiliary
+/// vector from the stack pointer up, the same as `create_elf_tables` of linux.
+pub fn test_initial_stack() {
+    let program = Program::parse(CHILD_ELF).unwrap();
+    let mut task = Sel4Task::new();
+    let info = task.load_elf(&program);
+    let sp = task.map_stack(
+        &info,
+        USPACE_STACK_TOP - USPACE_STACK_SIZE,
+        USPACE_STACK_TOP,
+        "/bin/prog",
+        &["prog", "arg"],
+        &["HOME=/"],
+    );
+    assert_eq!(sp % 16, 0);
+
+    let word = |index: usize| read_item(&task, (sp as *const usize).wrapping_add(index)).unwrap();
+    assert_eq!(word(0), 2);
+    assert_eq!(string_at(&task, word(1)), "prog");
+    assert_eq!(string_at(&task, word(2)), "arg");
+    assert_eq!(word(3), 0);
+    assert_eq!(string_at(&task, word(4)), "HOME=/");
+    assert_eq!(word(5), 0);
+
+    let mut auxv = BTreeMap::new();
+    let mut index = 6;
+    while word(index) != AuxV::NULL as usize {
+        auxv.insert(word(index), word(index + 1));
+        index += 2;
+    }
+    let aux = |key: AuxV| auxv[&(key as usize)];
+    assert_eq!(aux(AuxV::PHDR), info.phdr);
+    assert_eq!(aux(AuxV::PHENT), info.phent);
+    assert_eq!(aux(AuxV::PHNUM), info.phnum);
+    assert_eq!(aux(AuxV::PAGESZ), PAGE_SIZE);
+    assert_eq!(aux(AuxV::BASE), info.base);
+    assert_eq!(aux(AuxV::ENTRY), info.entry);
+    assert_eq!(string_at(&task, aux(AuxV::EXECFN)), "/bin/prog");
+    assert_eq!(string_at(&task, aux(AuxV::PLATFORM)), "aarch64");
+    // The 16 random bytes are above the arrays in the stack.
+    let random = aux(AuxV::RANDOM);
+    assert!(random > sp && random + 16 <= USPACE_STACK_TOP);
+}
+
+/// `execve` fails with `E2BIG` before freeing the memory when the arguments
+/// with their pointers don't fit in `ARG_MAX`, and succeeds when they just do.
+pub fn test_arg_max(ep: Endpoint) {
+    let task = new_process(ep, &["busybox"]).unwrap();
+    let badge = task.id as u64;
+    // The argument and the argv array are passed in the IPC buffer.
+    let arg = task.ipc_buffer;
+    let argv = arg + 1024;
+    let mut bytes = [b'a'; ARG_LEN];
+    bytes[ARG_LEN - 1] = 0;
+    write_item_list(&task, arg as *mut u8, Some(ARG_LEN), &bytes).unwrap();
+    TASK_MAP.lock().insert(badge, task);
+
+    let exec = |count: usize| {
+        let mut ptrs = vec![arg; count];
+        ptrs.push(0);
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        write_item_list(task, argv as *mut usize, Some(ptrs.len()), &ptrs).unwrap();
+        drop(task_map);
+        syscall(badge, ep, Sysno::execve, &[0, argv, 0])
+    };
+    assert_eq!(exec(ARG_MAX_ARGS + 1), Err(Errno::E2BIG));
+    // The memory is kept after the failure.
+    let len = string_at(TASK_MAP.lock().get(&badge).unwrap(), arg).len();
+    assert_eq!(len, ARG_LEN - 1);
+    assert_eq!(exec(ARG_MAX_ARGS), Ok(0));
+    syscall(badge, ep, Sysno::exit, &[0]).unwrap();
+}
+
+/// Exec the program at `path` in a child of a process which isn't running,
+/// and serve the child until it exits, returns its exit status.
+fn exec_status(ep: Endpoint, path: &str) -> i32 {
+    let parent = new_process(ep, &["busybox"]).unwrap();
+    let mut child = new_process(ep, &["busybox"]).unwrap();
+    child.ppid = parent.pid;
+    let (parent_badge, badge) = (parent.id as u64, child.id as u64);
+    // The path and the status are passed in the IPC buffers.
+    let (path_addr, wstatus) = (child.ipc_buffer, parent.ipc_buffer);
+    let mut bytes = vec![0; path.len() + 1];
+    bytes[..path.len()].copy_from_slice(path.as_bytes());
+    write_item_list(&child, path_addr as *mut u8, Some(bytes.len()), &bytes).unwrap();
+    TASK_MAP.lock().insert(parent_badge, parent);
+    TASK_MAP.lock().insert(badge, child);
+
+    syscall(badge, ep, Sysno::execve, &[path_addr, 0, 0]).unwrap();
+    let args = [badge as usize, wstatus, WNOHANG, 0];
+    let mut res = syscall(parent_badge, ep, Sysno::wait4, &args);
+    while res == Ok(0) {
+        assert!(event::handle_next(ep).is_continue());
+        res = syscall(parent_badge, ep, Sysno::wait4, &args);
+    }
+    assert_eq!(res, Ok(badge as usize));
+    let status = read_item(
+        TASK_MAP.lock().get(&parent_badge).unwrap(),
+        wstatus as *const i32,
+    )
+    .unwrap();
+    syscall(parent_badge, ep, Sysno::exit, &[0]).unwrap();
+    status
+}
+
+/// A position-independent executable runs from its entry moved to
+/// `USPACE_DYN_BASE`, and one naming an interpreter runs the interpreter
+/// moved to `USPACE_INTERP_BASE`. Both exit by `svc`.
+pub fn test_exec_dynamic(ep: Endpoint) {
+    mount_file(PROGRAM_PATH, &PROGRAM.0);
+    mount_file(DYNAMIC_PATH, &DYNAMIC.0);
+    mount_file(INTERP_PATH, &INTERP.0);
+    assert_eq!(exec_status(ep, PROGRAM_PATH), (PROGRAM_CODE as i32) << 8);
+    assert_eq!(exec_status(ep, DYNAMIC_PATH), (INTERP_CODE as i32) << 8);
+}
@@ -0,0 +1,76 @@
+//! Check that the tasks return all their objects when they exit.
+
+use common::CloneArgs;
+use sel4::{cap::Endpoint, debug_println};
+use syscalls::Sysno;
+
+use crate::{
+    child_test::{new_process, TASK_MAP},
+    utils::{read_item, syscall, write_item},
+    OBJ_ALLOCATOR,
+};
+
+/// The cycles checked after the first one, which fills the idle objects.
+const CYCLES: usize = 8;
+
+/// The exit code of the children.
+const EXIT_CODE: i32 = 3;
+
+/// Fork a child of the `parent`, exec the shim in it, exit it and collect
+/// its exit status with `wait4`.
+///
+/// The parent isn't running, the arguments are passed in its IPC buffer.
+fn fork_exec_exit(parent: u64, ep: Endpoint) {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&parent).unwrap();
+    let clone_args = task.ipc_buffer as *const CloneArgs;
+    let wstatus = clone_args.wrapping_add(1) as *const i32;
+    write_item(task, clone_args, &CloneArgs::default()).unwrap();
+    drop(task_map);
+    let args = [clone_args as usize, core::mem::size_of::<CloneArgs>()];
+    let child = syscall(parent, ep, Sysno::clone, &args).unwrap();
+    // The null path runs the shim.
+    syscall(child as _, ep, Sysno::execve, &[0, 0, 0]).unwrap();
+    syscall(child as _, ep, Sysno::exit, &[EXIT_CODE as _]).unwrap();
+
+    let args = [child, wstatus as usize, 0, 0];
+    assert_eq!(syscall(parent, ep, Sysno::wait4, &args), Ok(child));
+    let status = read_item(TASK_MAP.lock().get(&parent).unwrap(), wstatus).unwrap();
+    assert_eq!(status, EXIT_CODE << 8);
+}
+
+/// The repeated cycles reuse the objects of the first one, the allocator
+/// doesn't take any slot or memory for them.
+pub fn test_task_lifecycle(ep: Endpoint) {
+    let parent = new_process(ep, &["busybox"]).unwrap();
+    let badge = parent.id as u64;
+    TASK_MAP.lock().insert(badge, parent);
+
+    fork_exec_exit(badge, ep);
+    let (watermark, retyped) = {
+        let allocator = OBJ_ALLOCATOR.lock();
+        (allocator.watermark(), allocator.retyped())
+    };
+    for _ in 0..CYCLES {
+        fork_exec_exit(badge, ep);
+    }
+    let allocator = OBJ_ALLOCATOR.lock();
+    let slots = allocator.watermark() - watermark;
+    let bytes = allocator.retyped() - retyped;
+    drop(allocator);
+    debug_println!(
+        "[KernelThread] {} slots and {} bytes taken by {} cycles",
+        slots,
+        bytes,
+        CYCLES
+    );
+    assert_eq!((slots, bytes), (0, 0));
+
+    // No child is left to wait for.
+    let args = [usize::MAX, 0, 0, 0];
+    assert_eq!(
+        syscall(badge, ep, Sysno::wait4, &args),
+        Err(syscalls::Errno::ECHILD)
+    );
+    syscall(badge, ep, Sysno::exit, &[0]).unwrap();
+}
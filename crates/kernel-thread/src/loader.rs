@@ -0,0 +1,340 @@
+//! The ELF loader of the tasks.
+//!
+//! The executables of type `ET_EXEC` are loaded at their own addresses, and
+//! the position-independent ones of type `ET_DYN` are moved to
+//! [USPACE_DYN_BASE]. The interpreter named by `PT_INTERP`, such as
+//! `ld-musl` or `ld-linux`, is read from the root file system and moved to
+//! [USPACE_INTERP_BASE], the task starts at its entry point then and finds
+//! the program through the auxiliary vector.
+
+use alloc::{collections::btree_map::BTreeMap, string::String};
+use common::{
+    USPACE_BASE, USPACE_DYN_BASE, USPACE_HEAP_BASE, USPACE_HEAP_SIZE, USPACE_INTERP_BASE,
+    USPACE_IPC_BUFFER_BASE, USPACE_STACK_SIZE, USPACE_STACK_TOP, USPACE_TOP,
+};
+use core::{cmp, ffi::CStr, mem, ops::Range};
+use crate_consts::PAGE_SIZE;
+use sel4::{cap_type::Granule, init_thread, CapRights, CapRightsBuilder, VmAttributes};
+use syscalls::Errno;
+use xmas_elf::{
+    header::{self, Class, Machine},
+    program::{self, ProgramHeader64},
+    ElfFile,
+};
+
+use crate::{page_seat, syscall::read_file, task::Sel4Task, OBJ_ALLOCATOR};
+
+/// The type of the program header of the stack rights, the same as
+/// `PT_GNU_STACK` in linux.
+const PT_GNU_STACK: u32 = 0x6474_e551;
+
+/// The ranges of the user space holding the heap, the stack and the IPC
+/// buffers, no segment is loaded in them.
+const USPACE_RESERVED: [Range<usize>; 3] = [
+    USPACE_HEAP_BASE..USPACE_HEAP_BASE + USPACE_HEAP_SIZE,
+    USPACE_STACK_TOP - USPACE_STACK_SIZE..USPACE_STACK_TOP,
+    USPACE_IPC_BUFFER_BASE..USPACE_DYN_BASE,
+];
+
+/// The addresses of the loaded program given in the auxiliary vector.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ElfInfo {
+    /// The entry point of the program
+    pub entry: usize,
+    /// The address of the program headers
+    pub phdr: usize,
+    /// The size of a program header
+    pub phent: usize,
+    /// The number of the program headers
+    pub phnum: usize,
+    /// The base address of the interpreter, zero without one
+    pub base: usize,
+    /// The address the task starts at, the entry point of the interpreter
+    /// if there is one
+    pub start: usize,
+    /// Whether `PT_GNU_STACK` asks for an executable stack
+    pub exec_stack: bool,
+    /// The address of the TLS image of the program in `PT_TLS`, zero
+    /// without one
+    pub tls: usize,
+}
+
+/// A program checked for loading, with its interpreter read.
+///
+/// All the errors of `execve` are found here, so the old memory of the task
+/// is only freed for a program which can be loaded.
+pub struct Program<'a> {
+    /// The program and its load bias
+    file: (ElfFile<'a>, usize),
+    /// The interpreter and its load bias
+    interp: Option<(ElfFile<'static>, usize)>,
+}
+
+impl<'a> Program<'a> {
+    /// Check the ELF file in `data` and read its interpreter.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Errno> {
+        let file = check_image(data, USPACE_DYN_BASE, USPACE_BASE..USPACE_INTERP_BASE)?;
+        let interp = match interp_path(&file.0)? {
+            Some(path) => {
+                let interp = check_image(
+                    read_file(&path)?,
+                    USPACE_INTERP_BASE,
+                    USPACE_INTERP_BASE..USPACE_TOP,
+                )?;
+                // The interpreter is loaded by itself, it can't have another one.
+                if interp_path(&interp.0)?.is_some() {
+                    return Err(Errno::ELIBBAD);
+                }
+                Some(interp)
+            }
+            None => None,
+        };
+        Ok(Self { file, interp })
+    }
+}
+
+/// Check the ELF file in `data` and its `PT_LOAD` segments moved to `base`,
+/// which stay in `space` and out of [USPACE_RESERVED], returns the file and
+/// its load bias.
+fn check_image(
+    data: &[u8],
+    base: usize,
+    space: Range<usize>,
+) -> Result<(ElfFile<'_>, usize), Errno> {
+    let file = check_elf(data)?;
+    let bias = load_bias(&file, base)?;
+    (file.header.pt2.entry_point() as usize)
+        .checked_add(bias)
+        .ok_or(Errno::ENOEXEC)?;
+    for ph in file
+        .program_iter()
+        .filter(|ph| ph.get_type() == Ok(program::Type::Load))
+    {
+        let (offset, file_size) = (ph.offset() as usize, ph.file_size() as usize);
+        let mem_size = ph.mem_size() as usize;
+        offset
+            .checked_add(file_size)
+            .filter(|&end| end <= data.len() && file_size <= mem_size)
+            .ok_or(Errno::ENOEXEC)?;
+        let start = (ph.virtual_addr() as usize)
+            .checked_add(bias)
+            .ok_or(Errno::ENOEXEC)?;
+        let end = start
+            .checked_add(mem_size)
+            .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE))
+            .ok_or(Errno::ENOEXEC)?;
+        let start = start / PAGE_SIZE * PAGE_SIZE;
+        if start < space.start
+            || end > space.end
+            || USPACE_RESERVED
+                .iter()
+                .any(|range| start < range.end && range.start < end)
+        {
+            return Err(Errno::ENOEXEC);
+        }
+    }
+    Ok((file, bias))
+}
+
+/// Check that `data` is an ELF file which can run on this machine.
+fn check_elf(data: &[u8]) -> Result<ElfFile<'_>, Errno> {
+    let file = ElfFile::new(data).map_err(|_| Errno::ENOEXEC)?;
+    if file.header.pt1.class() != Class::SixtyFour
+        || file.header.pt2.machine().as_machine() != Machine::AArch64
+    {
+        return Err(Errno::ENOEXEC);
+    }
+    // The program headers are read in place, the table is in the file and
+    // aligned to their own layout.
+    let pt2 = &file.header.pt2;
+    let (ph_offset, ph_size) = (pt2.ph_offset() as usize, pt2.ph_entry_size() as usize);
+    if ph_size != mem::size_of::<ProgramHeader64>()
+        || ph_offset % mem::align_of::<ProgramHeader64>() != 0
+    {
+        return Err(Errno::ENOEXEC);
+    }
+    (pt2.ph_count() as usize)
+        .checked_mul(ph_size)
+        .and_then(|len| len.checked_add(ph_offset))
+        .filter(|&end| end <= data.len())
+        .ok_or(Errno::ENOEXEC)?;
+    match file.header.pt2.type_().as_type() {
+        header::Type::Executable | header::Type::SharedObject => Ok(file),
+        _ => Err(Errno::ENOEXEC),
+    }
+}
+
+/// The path of the interpreter in `PT_INTERP`.
+fn interp_path(file: &ElfFile) -> Result<Option<String>, Errno> {
+    let Some(ph) = file
+        .program_iter()
+        .find(|ph| ph.get_type() == Ok(program::Type::Interp))
+    else {
+        return Ok(None);
+    };
+    let start = ph.offset() as usize;
+    let bytes = start
+        .checked_add(ph.file_size() as usize)
+        .and_then(|end| file.input.get(start..end))
+        .ok_or(Errno::ENOEXEC)?;
+    let path = CStr::from_bytes_until_nul(bytes).map_err(|_| Errno::ENOEXEC)?;
+    let path = path.to_str().map_err(|_| Errno::ENOEXEC)?;
+    Ok(Some(path.into()))
+}
+
+/// The offset added to the addresses of `file`, a position-independent one
+/// is moved to `base`.
+fn load_bias(file: &ElfFile, base: usize) -> Result<usize, Errno> {
+    if file.header.pt2.type_().as_type() != header::Type::SharedObject {
+        return Ok(0);
+    }
+    let lowest = file
+        .program_iter()
+        .filter(|ph| ph.get_type() == Ok(program::Type::Load))
+        .map(|ph| ph.virtual_addr() as usize)
+        .min()
+        .unwrap_or(0);
+    base.checked_sub(lowest / PAGE_SIZE * PAGE_SIZE)
+        .ok_or(Errno::ENOEXEC)
+}
+
+/// The address of the program headers, they are mapped by `PT_PHDR`, or by
+/// the segment containing them.
+fn phdr_vaddr(file: &ElfFile) -> Option<usize> {
+    let ph_offset = file.header.pt2.ph_offset();
+    file.program_iter()
+        .find(|ph| ph.get_type() == Ok(program::Type::Phdr))
+        .map(|ph| ph.virtual_addr())
+        .or_else(|| {
+            file.program_iter()
+                .filter(|ph| ph.get_type() == Ok(program::Type::Load))
+                .find(|ph| (ph.offset()..ph.offset() + ph.file_size()).contains(&ph_offset))
+                .map(|ph| ph.virtual_addr() + ph_offset - ph.offset())
+        })
+        .map(|vaddr| vaddr as usize)
+}
+
+/// Whether `PT_GNU_STACK` asks for an executable stack, the stack is not
+/// executable without it the same as linux on aarch64.
+fn exec_stack(file: &ElfFile) -> bool {
+    file.program_iter()
+        .find(|ph| ph.get_type() == Ok(program::Type::OsSpecific(PT_GNU_STACK)))
+        .is_some_and(|ph| ph.flags().is_execute())
+}
+
+impl Sel4Task {
+    /// Load `program` and its interpreter, returns the addresses for the
+    /// auxiliary vector and the entry of the task.
+    pub fn load_elf(&mut self, program: &Program) -> ElfInfo {
+        let (file, bias) = &program.file;
+        let bias = *bias;
+        self.map_segments(file, bias);
+
+        let entry = file.header.pt2.entry_point() as usize + bias;
+        let mut info = ElfInfo {
+            entry,
+            phdr: phdr_vaddr(file).map_or(0, |phdr| phdr.wrapping_add(bias)),
+            phent: file.header.pt2.ph_entry_size() as _,
+            phnum: file.header.pt2.ph_count() as _,
+            base: 0,
+            start: entry,
+            exec_stack: exec_stack(file),
+            tls: file
+                .program_iter()
+                .find(|ph| ph.get_type() == Ok(program::Type::Tls))
+                .map_or(0, |ph| (ph.virtual_addr() as usize).wrapping_add(bias)),
+        };
+
+        if let Some((file, bias)) = &program.interp {
+            self.map_segments(file, *bias);
+            info.base = *bias;
+            info.start = file.header.pt2.entry_point() as usize + bias;
+        }
+        info
+    }
+
+    /// Map the `PT_LOAD` segments of `file` moved by `bias`, with the rights
+    /// in their flags, the segments are checked by [check_image].
+    ///
+    /// A page shared by two segments is mapped with the rights of both.
+    /// `PT_GNU_RELRO` is left writable in its segment, the dynamic linker
+    /// makes it read-only with `mprotect` after the relocations.
+    fn map_segments(&mut self, file: &ElfFile, bias: usize) {
+        let elf_data = file.input;
+        // The pages mapped by the previous segments with their write and
+        // execute rights.
+        let mut mapped_page: BTreeMap<usize, (sel4::cap::SmallPage, bool, bool)> = BTreeMap::new();
+
+        // Load data from elf file.
+        file.program_iter()
+            .filter(|ph| ph.get_type() == Ok(program::Type::Load))
+            .for_each(|ph| {
+                let mut offset = ph.offset() as usize;
+                let mut vaddr = ph.virtual_addr() as usize + bias;
+                let end = offset + ph.file_size() as usize;
+                let vaddr_end = vaddr + ph.mem_size() as usize;
+                let flags = ph.flags();
+
+                while vaddr < vaddr_end {
+                    let page_vaddr = vaddr / PAGE_SIZE * PAGE_SIZE;
+                    let (page_cap, write, execute) = match mapped_page.remove(&page_vaddr) {
+                        Some((page_cap, write, execute)) => {
+                            page_cap.frame_unmap().unwrap();
+                            (
+                                page_cap,
+                                write || flags.is_write(),
+                                execute || flags.is_execute(),
+                            )
+                        }
+                        None => (
+                            OBJ_ALLOCATOR
+                                .lock()
+                                .allocate_and_retyped_fixed_sized::<Granule>(),
+                            flags.is_write(),
+                            flags.is_execute(),
+                        ),
+                    };
+
+                    // If need to read data from elf file.
+                    if offset < end {
+                        // Map to root task to write datas.
+                        let seat = page_seat();
+                        page_cap
+                            .frame_map(
+                                init_thread::slot::VSPACE.cap(),
+                                seat.vaddr(),
+                                CapRights::all(),
+                                VmAttributes::DEFAULT,
+                            )
+                            .unwrap();
+
+                        let rsize = cmp::min(PAGE_SIZE - vaddr % PAGE_SIZE, end - offset);
+                        // Copy data from elf file's data to the correct position.
+                        unsafe {
+                            core::ptr::copy_nonoverlapping(
+                                elf_data.as_ptr().add(offset),
+                                (seat.vaddr() + vaddr % PAGE_SIZE) as *mut u8,
+                                rsize,
+                            )
+                        }
+
+                        page_cap.frame_unmap().unwrap();
+
+                        offset += rsize;
+                    }
+
+                    let rights = CapRightsBuilder::none().read(true).write(write).build();
+                    let attrs = match execute {
+                        true => VmAttributes::DEFAULT,
+                        false => VmAttributes::DEFAULT | VmAttributes::EXECUTE_NEVER,
+                    };
+                    self.map_page_with(page_vaddr, page_cap, rights, attrs);
+
+                    mapped_page.insert(page_vaddr, (page_cap, write, execute));
+
+                    // Calculate offset
+                    vaddr += PAGE_SIZE - vaddr % PAGE_SIZE;
+                }
+            });
+    }
+}
@@ -7,8 +7,13 @@ extern crate alloc;
 extern crate sel4_panicking;
 
 mod child_test;
+mod event;
+mod exec_test;
 mod irq_test;
+mod lifecycle_test;
+mod loader;
 mod logging;
+mod pl031;
 mod runtime;
 mod syscall;
 mod task;
@@ -17,21 +22,45 @@ mod utils;
 
 use common::ObjectAllocator;
 use crate_consts::{
-    DEFAULT_CUSTOM_SLOT, DEFAULT_EMPTY_SLOT_INDEX, GRANULE_SIZE, KERNEL_THREAD_SLOT_NUMS,
+    slots::kernel_thread::{READY_NTFN, UNTYPED},
+    DEFAULT_EMPTY_SLOT_INDEX, GRANULE_SIZE, INIT_EP, KERNEL_THREAD_SLOT_NUMS,
 };
 use sel4::{cap_type::Endpoint, debug_println, Cap};
 use sel4_sys::seL4_DebugPutChar;
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 use utils::{init_free_page_addr, FreePagePlaceHolder};
 
 sel4_panicking_env::register_debug_put_char!(seL4_DebugPutChar);
 
-/// Get the virtual address of the page seat.
-pub fn page_seat_vaddr() -> usize {
-    unsafe { init_free_page_addr() }
+/// The lock of the page seat, the page where the kernel thread maps a frame
+/// to read or write it, and of the slot 0 holding the copy of the frame.
+///
+/// It is the last lock taken, no other lock is taken while it is held.
+static PAGE_SEAT: Mutex<()> = Mutex::new(());
+
+/// The page seat taken by [page_seat], it is released when dropped.
+pub struct PageSeat {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl PageSeat {
+    /// Get the virtual address of the page seat.
+    pub fn vaddr(&self) -> usize {
+        unsafe { init_free_page_addr() }
+    }
+}
+
+/// Take the page seat, the frame mapped at it must be unmapped before the
+/// seat is dropped.
+pub fn page_seat() -> PageSeat {
+    PageSeat {
+        _guard: PAGE_SEAT.lock(),
+    }
 }
 
 /// The object allocator for the kernel thread.
+///
+/// It is locked after the tasks, see [child_test::TASK_MAP] for the order.
 pub(crate) static OBJ_ALLOCATOR: Mutex<ObjectAllocator> = Mutex::new(ObjectAllocator::empty());
 
 /// free page placeholder
@@ -43,24 +72,43 @@ fn main() -> ! {
     logging::init();
     OBJ_ALLOCATOR.lock().init(
         DEFAULT_EMPTY_SLOT_INDEX..KERNEL_THREAD_SLOT_NUMS,
-        Cap::from_bits(DEFAULT_CUSTOM_SLOT as _),
+        Cap::from_bits(UNTYPED as _),
     );
     debug_println!("[KernelThread] Object Allocator initialized");
+    // The device of the kernel thread is the RTC.
+    let (rtc_vaddr, _) = common::get_device(INIT_EP);
+    syscall::init_realtime(rtc_vaddr);
+    syscall::mount_rootfs();
+    // Bind the notification signaled by the net-thread when sockets become ready,
+    // and by the uart-thread when the console becomes readable.
+    sel4::init_thread::slot::TCB
+        .cap()
+        .tcb_bind_notification(Cap::from_bits(READY_NTFN as _))
+        .unwrap();
+    // The serial IRQ is owned by the uart-thread now.
     // test_func!("Test IRQ", irq_test::test_irq());
-    test_func!(
-        "[KernelThread] Test IRQ",
-        irq_test::test_irq_with_cap_transfer()
-    );
 
     // test_func!("[KernelThread] Test thread", thread::test_threads());
 
-    test_func!("[KernelThread] Test Thread", {
-        let ep = OBJ_ALLOCATOR
-            .lock()
-            .allocate_and_retyped_fixed_sized::<Endpoint>();
-        child_test::test_child(ep).unwrap()
-    });
+    let ep = OBJ_ALLOCATOR
+        .lock()
+        .allocate_and_retyped_fixed_sized::<Endpoint>();
+    child_test::spawn_init(ep).unwrap();
+    // The first process waits for the kernel thread meanwhile.
+    test_func!(
+        "[KernelThread] Test Task Lifecycle",
+        lifecycle_test::test_task_lifecycle(ep)
+    );
+    test_func!(
+        "[KernelThread] Test Initial Stack",
+        exec_test::test_initial_stack()
+    );
+    test_func!("[KernelThread] Test ARG_MAX", exec_test::test_arg_max(ep));
+    test_func!(
+        "[KernelThread] Test Exec Dynamic",
+        exec_test::test_exec_dynamic(ep)
+    );
+    test_func!("[KernelThread] Test Thread", event::run(ep));
     debug_println!("[KernelThread] Say Goodbye");
-    sel4::cap::Tcb::from_bits(1).tcb_suspend().unwrap();
-    unreachable!()
+    common::shutdown(INIT_EP, common::ShutdownMode::PowerOff)
 }
@@ -0,0 +1,62 @@
+//! The driver of the PL031 RTC, which keeps the seconds since the epoch.
+//!
+//! Only the counter is used, the alarm interrupts are left disabled.
+//!
+//! See <https://developer.arm.com/documentation/ddi0224/latest/>
+
+use core::ptr::{read_volatile, write_volatile};
+
+/// Data register.
+const RTCDR: usize = 0x00;
+/// Load register.
+const RTCLR: usize = 0x08;
+/// Control register.
+const RTCCR: usize = 0x0c;
+/// Interrupt mask set/clear register.
+const RTCIMSC: usize = 0x10;
+
+/// Start the counter.
+const CR_START: u32 = 1 << 0;
+
+pub struct Pl031 {
+    base: usize,
+}
+
+impl Pl031 {
+    /// Create the driver of the RTC mapped at `base`.
+    ///
+    /// # Safety
+    ///
+    /// The registers of a PL031 must be mapped at `base` and owned by the driver.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    #[inline]
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+
+    #[inline]
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.base + reg) as *mut u32, value) }
+    }
+
+    /// Start the counter with the alarm interrupt masked.
+    pub fn init(&self) {
+        self.write(RTCIMSC, 0);
+        self.write(RTCCR, CR_START);
+    }
+
+    /// The seconds since the epoch.
+    pub fn seconds(&self) -> u64 {
+        self.read(RTCDR) as u64
+    }
+
+    /// Load the seconds since the epoch into the counter.
+    ///
+    /// The counter is 32 bits wide, so it can't keep the time after 2106.
+    pub fn set_seconds(&self, seconds: u32) {
+        self.write(RTCLR, seconds);
+    }
+}
@@ -0,0 +1,277 @@
+//! The `epoll` syscalls.
+//!
+//! The epoll instances are [File]s of the tasks, the readiness of the fds is
+//! queried by [fd_poll] every time the instance is polled.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{wait, Deadline, SysResult},
+    utils::{read_item, write_item_list},
+};
+
+use super::{
+    file::{file_of, File},
+    poll::{fd_poll, PollEvents},
+};
+
+const EPOLL_CTL_ADD: i32 = 1;
+const EPOLL_CTL_DEL: i32 = 2;
+const EPOLL_CTL_MOD: i32 = 3;
+
+/// The maximum depth of the nested epoll instances, the same as linux.
+const EPOLL_MAX_NESTS: usize = 4;
+
+bitflags::bitflags! {
+    /// The events of `struct epoll_event`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct EpollFlags: u32 {
+        const EPOLLIN = 0x001;
+        const EPOLLPRI = 0x002;
+        const EPOLLOUT = 0x004;
+        const EPOLLERR = 0x008;
+        const EPOLLHUP = 0x010;
+        const EPOLLONESHOT = 1 << 30;
+        const EPOLLET = 1 << 31;
+    }
+}
+
+/// The same as `struct epoll_event` in C, which is not packed on aarch64.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+struct Interest {
+    events: EpollFlags,
+    data: u64,
+    /// The events reported last time, used by the edge-triggered mode.
+    last: EpollFlags,
+    /// Disabled after reporting once in the oneshot mode, until modified.
+    disabled: bool,
+}
+
+/// An epoll instance, the interests are keyed by the fds of the owner task.
+pub(crate) struct EpollInstance {
+    interests: Mutex<BTreeMap<i32, Interest>>,
+}
+
+/// The depth of the epoll instances being polled.
+static POLL_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+fn to_epoll_flags(events: PollEvents) -> EpollFlags {
+    EpollFlags::from_bits_truncate(events.bits() as u16 as u32)
+}
+
+/// Convert the readiness of an fd into the events reported to the interest.
+fn interest_events(interest: &Interest, ready: EpollFlags) -> EpollFlags {
+    let mask = (interest.events | EpollFlags::EPOLLERR | EpollFlags::EPOLLHUP)
+        - EpollFlags::EPOLLONESHOT
+        - EpollFlags::EPOLLET;
+    let ready = ready & mask;
+    if interest.events.contains(EpollFlags::EPOLLET) {
+        ready - interest.last
+    } else {
+        ready
+    }
+}
+
+impl EpollInstance {
+    fn new() -> Self {
+        Self {
+            interests: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Query the readiness of the interested fds of the task `badge`.
+    ///
+    /// The interests are collected before polling, so the instance is not
+    /// locked while polling the nested epoll instances.
+    fn readiness(&self, badge: u64) -> Vec<(i32, EpollFlags)> {
+        let fds: Vec<i32> = self
+            .interests
+            .lock()
+            .iter()
+            .filter(|(_, interest)| !interest.disabled)
+            .map(|(fd, _)| *fd)
+            .collect();
+        if POLL_DEPTH.fetch_add(1, Ordering::AcqRel) >= EPOLL_MAX_NESTS {
+            POLL_DEPTH.fetch_sub(1, Ordering::AcqRel);
+            return Vec::new();
+        }
+        let readiness = fds
+            .into_iter()
+            .map(|fd| (fd, to_epoll_flags(fd_poll(badge, fd))))
+            .collect();
+        POLL_DEPTH.fetch_sub(1, Ordering::AcqRel);
+        readiness
+    }
+
+    /// Poll the instance, returns at most `max` ready events.
+    fn poll(&self, badge: u64, max: usize) -> Vec<EpollEvent> {
+        let readiness = self.readiness(badge);
+        let mut interests = self.interests.lock();
+        let mut events = Vec::new();
+        for (fd, ready) in readiness {
+            if events.len() >= max {
+                break;
+            }
+            let Some(interest) = interests.get_mut(&fd) else {
+                continue;
+            };
+            let reported = interest_events(interest, ready);
+            interest.last = ready;
+            if reported.is_empty() {
+                continue;
+            }
+            if interest.events.contains(EpollFlags::EPOLLONESHOT) {
+                interest.disabled = true;
+            }
+            events.push(EpollEvent {
+                events: reported.bits(),
+                data: interest.data,
+            });
+        }
+        events
+    }
+
+    /// The readiness of the instance as an fd, readable if any event is ready.
+    pub(crate) fn poll_ready(&self, badge: u64) -> PollEvents {
+        let interests: BTreeMap<i32, EpollFlags> = self
+            .interests
+            .lock()
+            .iter()
+            .map(|(fd, interest)| (*fd, interest.events))
+            .collect();
+        let ready = self.readiness(badge).into_iter().any(|(fd, ready)| {
+            let mask = interests[&fd] | EpollFlags::EPOLLERR | EpollFlags::EPOLLHUP;
+            ready.intersects(mask)
+        });
+        if ready {
+            PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+}
+
+/// Get the epoll instance of the `epfd` of the task `badge`.
+fn epoll_of(badge: u64, epfd: i32) -> Result<Arc<File>, Errno> {
+    let file = file_of(badge, epfd)?;
+    match file.as_ref() {
+        File::Epoll(_) => Ok(file),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+pub(crate) fn sys_epoll_create1(badge: u64, _flags: usize) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let fd = task
+        .file_table
+        .lock()
+        .insert(Arc::new(File::Epoll(EpollInstance::new())))?;
+    Ok(fd as _)
+}
+
+pub(crate) fn sys_epoll_ctl(
+    badge: u64,
+    epfd: i32,
+    op: i32,
+    fd: i32,
+    event: *const EpollEvent,
+) -> SysResult {
+    if fd == epfd {
+        return Err(Errno::EINVAL);
+    }
+    let epoll = epoll_of(badge, epfd)?;
+    let File::Epoll(instance) = epoll.as_ref() else {
+        unreachable!()
+    };
+    // The target fd must be opened.
+    file_of(badge, fd)?;
+    let event = match op {
+        EPOLL_CTL_DEL => EpollEvent::default(),
+        _ => {
+            let task_map = TASK_MAP.lock();
+            let task = task_map.get(&badge).unwrap();
+            read_item(task, event)?
+        }
+    };
+
+    let mut interests = instance.interests.lock();
+    let interest = Interest {
+        events: EpollFlags::from_bits_truncate(event.events),
+        data: event.data,
+        last: EpollFlags::empty(),
+        disabled: false,
+    };
+    match op {
+        EPOLL_CTL_ADD => {
+            if interests.contains_key(&fd) {
+                return Err(Errno::EEXIST);
+            }
+            interests.insert(fd, interest);
+        }
+        EPOLL_CTL_MOD => {
+            *interests.get_mut(&fd).ok_or(Errno::ENOENT)? = interest;
+        }
+        EPOLL_CTL_DEL => {
+            interests.remove(&fd).ok_or(Errno::ENOENT)?;
+        }
+        _ => return Err(Errno::EINVAL),
+    }
+    Ok(0)
+}
+
+pub(crate) fn sys_epoll_pwait(
+    badge: u64,
+    epfd: i32,
+    events: *mut EpollEvent,
+    maxevents: i32,
+    timeout: i32,
+    _sigmask: usize,
+) -> SysResult {
+    if maxevents <= 0 {
+        return Err(Errno::EINVAL);
+    }
+    let epoll = epoll_of(badge, epfd)?;
+    // The negative timeout waits forever.
+    let mut deadline = match timeout {
+        ..0 => Deadline::never(),
+        0 => Deadline::at(0),
+        ms => Deadline::after(ms as u64 * 1_000_000),
+    };
+    let events = events as usize;
+    let mut retry = move || {
+        let File::Epoll(instance) = epoll.as_ref() else {
+            unreachable!()
+        };
+        let ready = instance.poll(badge, maxevents as _);
+        if ready.is_empty() && !deadline.expired() {
+            return None;
+        }
+        let task_map = TASK_MAP.lock();
+        let Some(task) = task_map.get(&badge) else {
+            return Some(Err(Errno::ESRCH));
+        };
+        if !ready.is_empty() {
+            if let Err(err) =
+                write_item_list(task, events as *mut EpollEvent, Some(ready.len()), &ready)
+            {
+                return Some(Err(err));
+            }
+        }
+        Some(Ok(ready.len()))
+    };
+    match retry() {
+        Some(res) => res,
+        None => wait::park(badge, retry),
+    }
+}
@@ -0,0 +1,303 @@
+//! The file descriptors of the tasks.
+//!
+//! Each task holds a [FileTable] translating its fds into [File]s, so the ids
+//! of the sockets in the net-thread are never exposed to the user space. The
+//! tasks created with `CLONE_FILES` share the same table.
+//!
+//! The `O_CLOEXEC` flag belongs to the fd, while the `O_NONBLOCK` flag belongs
+//! to the [File] shared by the duplicated fds, the same as linux.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use common::{STDERR_FD, STDIN_FD, STDOUT_FD};
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{
+        net::{NetSocket, UnixSocket},
+        SysResult,
+    },
+};
+
+use super::{
+    epoll::EpollInstance,
+    pipe::PipeEnd,
+    vfs::{DirFile, RegularFile},
+};
+
+/// The maximum number of fds of a task, the same as the default `RLIMIT_NOFILE`.
+const MAX_FDS: i32 = 1024;
+
+const F_DUPFD: i32 = 0;
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+const F_GETFL: i32 = 3;
+const F_SETFL: i32 = 4;
+const F_DUPFD_CLOEXEC: i32 = 1030;
+
+/// The only fd flag of `F_GETFD` and `F_SETFD`.
+const FD_CLOEXEC: usize = 1;
+
+bitflags::bitflags! {
+    /// The flags of `open`, `pipe2`, `dup3` and `fcntl`.
+    ///
+    /// See <https://github.com/torvalds/linux/blob/master/include/uapi/asm-generic/fcntl.h>
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct OpenFlags: usize {
+        const O_RDONLY = 0;
+        const O_WRONLY = 0o1;
+        const O_RDWR = 0o2;
+        const O_CREAT = 0o100;
+        const O_EXCL = 0o200;
+        const O_NOCTTY = 0o400;
+        const O_TRUNC = 0o1000;
+        const O_APPEND = 0o2000;
+        const O_NONBLOCK = 0o4000;
+        const O_DIRECTORY = 0o40000;
+        const O_NOFOLLOW = 0o100000;
+        const O_CLOEXEC = 0o2000000;
+    }
+}
+
+impl OpenFlags {
+    /// The mask of the access mode.
+    pub(crate) const O_ACCMODE: usize = 0o3;
+}
+
+/// An open file, shared by the fds duplicated from the same one.
+pub(crate) enum File {
+    Stdin,
+    Stdout,
+    Stderr,
+    /// A socket in the net-thread, closed when the last fd referring to it is closed.
+    Socket(NetSocket),
+    /// A unix domain socket living in the kernel thread.
+    Unix(Arc<UnixSocket>),
+    Epoll(EpollInstance),
+    /// An end of a pipe or a FIFO.
+    Pipe(PipeEnd),
+    /// A file of the root file system, opened read-only.
+    Regular(RegularFile),
+    /// A directory of the namespace, opened read-only.
+    Dir(DirFile),
+}
+
+impl File {
+    /// The access mode and the status flags, as returned by `F_GETFL`.
+    fn status_flags(&self) -> OpenFlags {
+        let mode = match self {
+            File::Stdin | File::Regular(_) | File::Dir(_) => OpenFlags::O_RDONLY,
+            File::Stdout | File::Stderr => OpenFlags::O_WRONLY,
+            File::Pipe(end) => end.access_mode(),
+            File::Socket(_) | File::Unix(_) | File::Epoll(_) => OpenFlags::O_RDWR,
+        };
+        if self.is_nonblocking() {
+            mode | OpenFlags::O_NONBLOCK
+        } else {
+            mode
+        }
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        match self {
+            File::Socket(socket) => socket.is_nonblocking(),
+            File::Unix(socket) => socket.is_nonblocking(),
+            File::Pipe(end) => end.is_nonblocking(),
+            // TODO: Record the flag of the other files when they can block.
+            File::Stdin
+            | File::Stdout
+            | File::Stderr
+            | File::Epoll(_)
+            | File::Regular(_)
+            | File::Dir(_) => false,
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) {
+        match self {
+            File::Socket(socket) => socket.set_nonblocking(nonblocking),
+            File::Unix(socket) => socket.set_nonblocking(nonblocking),
+            File::Pipe(end) => end.set_nonblocking(nonblocking),
+            File::Stdin
+            | File::Stdout
+            | File::Stderr
+            | File::Epoll(_)
+            | File::Regular(_)
+            | File::Dir(_) => {}
+        }
+    }
+}
+
+/// An fd referring to a [File].
+#[derive(Clone)]
+struct FdEntry {
+    file: Arc<File>,
+    /// Whether the fd is closed by `execve`.
+    cloexec: bool,
+}
+
+impl FdEntry {
+    fn new(file: Arc<File>, cloexec: bool) -> Self {
+        Self { file, cloexec }
+    }
+}
+
+/// The fds of a task.
+#[derive(Clone)]
+pub(crate) struct FileTable {
+    files: BTreeMap<i32, FdEntry>,
+}
+
+impl FileTable {
+    /// Create a file table with the standard streams opened.
+    pub(crate) fn new() -> Self {
+        let mut files = BTreeMap::new();
+        files.insert(STDIN_FD, FdEntry::new(Arc::new(File::Stdin), false));
+        files.insert(STDOUT_FD, FdEntry::new(Arc::new(File::Stdout), false));
+        files.insert(STDERR_FD, FdEntry::new(Arc::new(File::Stderr), false));
+        Self { files }
+    }
+
+    /// Insert a file at the lowest free fd.
+    pub(crate) fn insert(&mut self, file: Arc<File>) -> Result<i32, Errno> {
+        self.insert_from(0, file, false)
+    }
+
+    /// Insert a file at the lowest free fd not less than `min_fd`.
+    pub(crate) fn insert_from(
+        &mut self,
+        min_fd: i32,
+        file: Arc<File>,
+        cloexec: bool,
+    ) -> Result<i32, Errno> {
+        let fd = (min_fd..MAX_FDS)
+            .find(|fd| !self.files.contains_key(fd))
+            .ok_or(Errno::EMFILE)?;
+        self.files.insert(fd, FdEntry::new(file, cloexec));
+        Ok(fd)
+    }
+
+    /// Insert a file at the `fd`, returns the file it referred to before.
+    ///
+    /// The old file should be dropped after unlocking the table, as closing
+    /// it may wake up the waiters.
+    pub(crate) fn insert_at(
+        &mut self,
+        fd: i32,
+        file: Arc<File>,
+        cloexec: bool,
+    ) -> Result<Option<Arc<File>>, Errno> {
+        if !(0..MAX_FDS).contains(&fd) {
+            return Err(Errno::EBADF);
+        }
+        let old = self.files.insert(fd, FdEntry::new(file, cloexec));
+        Ok(old.map(|entry| entry.file))
+    }
+
+    /// Get the file of the `fd`.
+    pub(crate) fn get(&self, fd: i32) -> Result<Arc<File>, Errno> {
+        self.entry(fd).map(|entry| entry.file.clone())
+    }
+
+    fn entry(&self, fd: i32) -> Result<&FdEntry, Errno> {
+        self.files.get(&fd).ok_or(Errno::EBADF)
+    }
+
+    /// Get the socket in the net-thread referred by the `fd`.
+    pub(crate) fn socket(&self, fd: i32) -> Result<&NetSocket, Errno> {
+        match self.files.get(&fd).map(|entry| entry.file.as_ref()) {
+            Some(File::Socket(socket)) => Ok(socket),
+            Some(_) => Err(Errno::ENOTSOCK),
+            None => Err(Errno::EBADF),
+        }
+    }
+
+    /// Remove the `fd`, the file is closed if no other fd refers to it.
+    pub(crate) fn remove(&mut self, fd: i32) -> Result<Arc<File>, Errno> {
+        self.files
+            .remove(&fd)
+            .map(|entry| entry.file)
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Close the fds with `O_CLOEXEC`, which are not inherited by the new program.
+    pub(crate) fn close_on_exec(&mut self) {
+        self.files.retain(|_, entry| !entry.cloexec);
+    }
+}
+
+/// Get the file of the `fd` of the task `badge`.
+///
+/// It must not be called with the lock of [TASK_MAP] held.
+pub(crate) fn file_of(badge: u64, fd: i32) -> Result<Arc<File>, Errno> {
+    let file_table = TASK_MAP
+        .lock()
+        .get(&badge)
+        .ok_or(Errno::ESRCH)?
+        .file_table
+        .clone();
+    let file = file_table.lock().get(fd);
+    file
+}
+
+pub(crate) fn sys_dup(badge: u64, oldfd: i32) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let mut file_table = task.file_table.lock();
+    let file = file_table.get(oldfd)?;
+    Ok(file_table.insert(file)? as usize)
+}
+
+pub(crate) fn sys_dup3(badge: u64, oldfd: i32, newfd: i32, flags: usize) -> SysResult {
+    let flags = OpenFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    if oldfd == newfd || !OpenFlags::O_CLOEXEC.contains(flags) {
+        return Err(Errno::EINVAL);
+    }
+    let old = {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        let mut file_table = task.file_table.lock();
+        let file = file_table.get(oldfd)?;
+        file_table.insert_at(newfd, file, flags.contains(OpenFlags::O_CLOEXEC))?
+    };
+    // The file previously at `newfd` is closed silently.
+    drop(old);
+    Ok(newfd as usize)
+}
+
+pub(crate) fn sys_fcntl(badge: u64, fd: i32, cmd: i32, arg: usize) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let mut file_table = task.file_table.lock();
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let min_fd = i32::try_from(arg)
+                .ok()
+                .filter(|fd| *fd < MAX_FDS)
+                .ok_or(Errno::EINVAL)?;
+            let file = file_table.get(fd)?;
+            let fd = file_table.insert_from(min_fd, file, cmd == F_DUPFD_CLOEXEC)?;
+            Ok(fd as usize)
+        }
+        F_GETFD => Ok(if file_table.entry(fd)?.cloexec {
+            FD_CLOEXEC
+        } else {
+            0
+        }),
+        F_SETFD => {
+            let entry = file_table.files.get_mut(&fd).ok_or(Errno::EBADF)?;
+            entry.cloexec = arg & FD_CLOEXEC != 0;
+            Ok(0)
+        }
+        F_GETFL => Ok(file_table.get(fd)?.status_flags().bits()),
+        F_SETFL => {
+            // Only `O_NONBLOCK` can be changed, the others are ignored as linux does.
+            let file = file_table.get(fd)?;
+            drop(file_table);
+            drop(task_map);
+            file.set_nonblocking(arg & OpenFlags::O_NONBLOCK.bits() != 0);
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
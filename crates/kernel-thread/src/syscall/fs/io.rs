@@ -1,65 +1,140 @@
-use common::{STDERR_FD, STDOUT_FD};
-use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
-use sel4::{init_thread, Cap, CapRights, VmAttributes};
-use sel4_panicking_env::debug_println;
-use sel4_sys::seL4_DebugPutChar;
+use alloc::{vec, vec::Vec};
+use common::IoVec;
+use memory_addr::PAGE_SIZE_4K;
 use syscalls::Errno;
 
-use crate::{child_test::TASK_MAP, page_seat_vaddr, syscall::SysResult, utils::align_bits};
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{net, SysResult},
+    utils::{read_iovecs, read_item_list},
+};
 
-pub(crate) fn sys_write(badge: u64, fd: i32, buf: *const u8, mut count: usize) -> SysResult {
-    let task_map = TASK_MAP.lock();
-    let task = task_map.get(&badge).unwrap();
-    if fd != STDOUT_FD && fd != STDERR_FD {
-        return Err(Errno::ENOSYS);
-    }
-    let mut buf_addr = VirtAddr::from_ptr_of(buf);
-    debug_println!("buf_addr: {:?} count: {}", buf_addr, count);
-    let mut payload_length = 0;
-    while count > 0 {
-        if let Some(cap) = task.mapped_page.get(&align_bits(buf_addr.as_usize(), 12)) {
-            let new_cap = Cap::<sel4::cap_type::SmallPage>::from_bits(0);
-            init_thread::slot::CNODE
-                .cap()
-                .relative(new_cap)
-                .copy(
-                    &init_thread::slot::CNODE.cap().relative(*cap),
-                    CapRights::all(),
-                )
-                .unwrap();
+use super::{
+    file::{file_of, File},
+    pipe::{read_pipe, write_pipe},
+    tty::{console_write, read_console},
+    vfs::read_regular,
+};
 
-            new_cap
-                .frame_map(
-                    init_thread::slot::VSPACE.cap(),
-                    page_seat_vaddr(),
-                    CapRights::all(),
-                    VmAttributes::DEFAULT,
-                )
-                .unwrap();
-            let copy_len = (PAGE_SIZE_4K - buf_addr.align_offset_4k()).min(count);
-            let bytes = unsafe {
-                core::slice::from_raw_parts(
-                    page_seat_vaddr() as *const u8,
-                    copy_len + buf_addr.align_offset_4k(),
-                )
+pub(crate) fn sys_read(badge: u64, fd: i32, buf: *mut u8, count: usize) -> SysResult {
+    let file = file_of(badge, fd)?;
+    match file.as_ref() {
+        File::Unix(socket) => {
+            let bufs = net::RecvBuffers {
+                iovs: vec![IoVec {
+                    iov_base: buf as usize,
+                    iov_len: count,
+                }],
+                name: 0,
+                name_len: 0,
+                control: 0,
+                control_len: 0,
+                reply: net::RecvReply::None,
             };
+            net::recv_from(badge, socket.clone(), bufs, 0)
+        }
+        File::Pipe(_) => read_pipe(badge, file, buf, count),
+        File::Stdin => read_console(badge, buf, count),
+        File::Regular(regular) => read_regular(badge, regular, buf, count),
+        File::Dir(_) => Err(Errno::EISDIR),
+        _ => Err(Errno::ENOSYS),
+    }
+}
+
+/// The most bytes copied from the user space at once by a console write.
+const CONSOLE_WRITE_CHUNK: usize = PAGE_SIZE_4K;
+
+/// Write `count` bytes at `buf` to the console, a chunk at a time.
+///
+/// The bytes written before a bad address are reported as a short write.
+fn write_console(badge: u64, buf: *const u8, count: usize) -> SysResult {
+    let mut data = vec![0u8; count.min(CONSOLE_WRITE_CHUNK)];
+    let mut written = 0;
+    while written < count {
+        let len = (count - written).min(CONSOLE_WRITE_CHUNK);
+        let res = {
+            let task_map = TASK_MAP.lock();
+            let task = task_map.get(&badge).unwrap();
+            read_item_list(task, buf.wrapping_add(written), Some(len), &mut data[..len])
+        };
+        match res {
+            Ok(_) => console_write(&data[..len]),
+            Err(_) if written > 0 => break,
+            Err(err) => return Err(err),
+        }
+        written += len;
+    }
+    Ok(written)
+}
 
-            // FIXME: ensure that data in the page.z
-            bytes[buf_addr.align_offset_4k()..(buf_addr.align_offset_4k() + copy_len)]
-                .iter()
-                .map(u8::clone)
-                .for_each(seL4_DebugPutChar);
+pub(crate) fn sys_write(badge: u64, fd: i32, buf: *const u8, count: usize) -> SysResult {
+    let file = file_of(badge, fd)?;
+    let socket = match file.as_ref() {
+        File::Stdout | File::Stderr => return write_console(badge, buf, count),
+        File::Unix(socket) => socket.clone(),
+        File::Pipe(_) => return write_pipe(badge, file, buf, count),
+        // They are opened read-only.
+        File::Regular(_) | File::Dir(_) => return Err(Errno::EBADF),
+        _ => return Err(Errno::ENOSYS),
+    };
+    let count = socket.send_len(count)?;
+    let mut data = vec![0u8; count];
+    {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        read_item_list(task, buf, Some(count), &mut data)?;
+    }
+    net::send_to(badge, socket, data, Vec::new(), None, 0)
+}
+
+/// Read into the first non-empty buffer only, which is a valid short read.
+pub(crate) fn sys_readv(badge: u64, fd: i32, iov: *const IoVec, iovcnt: usize) -> SysResult {
+    let iovs = {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        read_iovecs(task, iov, iovcnt)?
+    };
+    match iovs.iter().find(|iov| iov.iov_len > 0) {
+        Some(iov) => sys_read(badge, fd, iov.iov_base as _, iov.iov_len),
+        None => file_of(badge, fd).map(|_| 0),
+    }
+}
 
-            count -= copy_len;
-            buf_addr += copy_len;
-            payload_length += copy_len;
-            new_cap.frame_unmap().unwrap();
-            init_thread::slot::CNODE
-                .cap()
-                .relative(new_cap)
-                .delete()
-                .unwrap();
+/// Write all the buffers to the console in order, the other files are
+/// written from the first non-empty buffer only.
+pub(crate) fn sys_writev(badge: u64, fd: i32, iov: *const IoVec, iovcnt: usize) -> SysResult {
+    let file = file_of(badge, fd)?;
+    let iovs = {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        read_iovecs(task, iov, iovcnt)?
+    };
+    match file.as_ref() {
+        File::Stdout | File::Stderr => {
+            let mut written = 0;
+            for iov in iovs.iter().filter(|iov| iov.iov_len > 0) {
+                match write_console(badge, iov.iov_base as _, iov.iov_len) {
+                    Ok(len) if len < iov.iov_len => return Ok(written + len),
+                    Ok(len) => written += len,
+                    Err(_) if written > 0 => break,
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(written)
         }
+        _ => match iovs.iter().find(|iov| iov.iov_len > 0) {
+            Some(iov) => sys_write(badge, fd, iov.iov_base as _, iov.iov_len),
+            None => Ok(0),
+        },
     }
-    Ok(payload_length)
+}
+
+pub(crate) fn sys_close(badge: u64, fd: i32) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let file = task.file_table.lock().remove(fd)?;
+    drop(task_map);
+    // The socket is closed here if it is the last fd referring to it.
+    drop(file);
+    Ok(0)
 }
@@ -1,3 +1,18 @@
+mod epoll;
+mod file;
 mod io;
+mod pipe;
+mod poll;
+mod tty;
+pub(crate) mod vfs;
 
+pub(crate) use epoll::*;
+pub(crate) use file::*;
 pub(crate) use io::*;
+pub(crate) use pipe::sys_pipe2;
+pub(crate) use poll::*;
+pub(crate) use tty::{console_signal_seq, handle_console_signals, sys_ioctl};
+pub(crate) use vfs::{
+    mount_file, mount_rootfs, read_file, sys_fstat, sys_getcwd, sys_getdents64, sys_mknodat,
+    sys_newfstatat, sys_openat, sys_unlinkat, PATH_MAX,
+};
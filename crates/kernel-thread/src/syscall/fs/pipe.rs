@@ -0,0 +1,336 @@
+//! Pipes and FIFOs.
+//!
+//! A [Pipe] is a byte queue shared by the [PipeEnd]s opened on it. The
+//! anonymous pipes are created by `pipe2`, while the FIFOs are named in the
+//! [vfs](super::vfs) and opened by `openat`. The readers and the writers
+//! which would block are parked by [wait::block_on], and every change of a
+//! pipe calls [wait::request_wake] to retry them.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{wait, SysResult},
+    utils::{read_item_list, write_item_list},
+};
+
+use super::{
+    file::{File, OpenFlags},
+    poll::PollEvents,
+};
+
+/// The capacity of a pipe, the same as the default of linux.
+const PIPE_SIZE: usize = 0x10000;
+
+/// The writes not longer than it are atomic, the same as `PIPE_BUF` in linux.
+const PIPE_BUF: usize = 4096;
+
+#[derive(Default)]
+struct PipeInner {
+    buf: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    /// The number of the ends opened for reading ever, used by the FIFOs
+    /// waiting for a reader.
+    read_opens: usize,
+    /// The number of the ends opened for writing ever.
+    write_opens: usize,
+}
+
+/// The buffer of a pipe or a FIFO.
+#[derive(Default)]
+pub(crate) struct Pipe {
+    inner: Mutex<PipeInner>,
+}
+
+impl Pipe {
+    /// Open an end of the pipe with the access mode in `flags`.
+    pub(crate) fn open(self: &Arc<Self>, flags: OpenFlags) -> PipeEnd {
+        let (readable, writable) = match flags.bits() & OpenFlags::O_ACCMODE {
+            0 => (true, false),
+            1 => (false, true),
+            _ => (true, true),
+        };
+        let mut inner = self.inner.lock();
+        if readable {
+            inner.readers += 1;
+            inner.read_opens += 1;
+        }
+        if writable {
+            inner.writers += 1;
+            inner.write_opens += 1;
+        }
+        drop(inner);
+        wait::request_wake();
+        PipeEnd {
+            pipe: self.clone(),
+            readable,
+            writable,
+            nonblock: AtomicBool::new(flags.contains(OpenFlags::O_NONBLOCK)),
+        }
+    }
+
+    /// The numbers of the ends opened for reading and writing ever.
+    fn opens(&self) -> (usize, usize) {
+        let inner = self.inner.lock();
+        (inner.read_opens, inner.write_opens)
+    }
+}
+
+/// An end of a [Pipe], closed when the last fd referring to it is closed.
+pub(crate) struct PipeEnd {
+    pipe: Arc<Pipe>,
+    readable: bool,
+    writable: bool,
+    nonblock: AtomicBool,
+}
+
+impl PipeEnd {
+    /// Create an anonymous pipe, returns the read end and the write end.
+    pub(crate) fn pair(flags: OpenFlags) -> (Self, Self) {
+        let pipe = Arc::new(Pipe::default());
+        (
+            pipe.open(flags | OpenFlags::O_RDONLY),
+            pipe.open(flags | OpenFlags::O_WRONLY),
+        )
+    }
+
+    pub(crate) fn access_mode(&self) -> OpenFlags {
+        match (self.readable, self.writable) {
+            (true, false) => OpenFlags::O_RDONLY,
+            (false, true) => OpenFlags::O_WRONLY,
+            _ => OpenFlags::O_RDWR,
+        }
+    }
+
+    pub(crate) fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Read at most `len` bytes, an empty result means the end of file.
+    fn read(&self, len: usize) -> Result<Vec<u8>, Errno> {
+        if !self.readable {
+            return Err(Errno::EBADF);
+        }
+        let mut inner = self.pipe.inner.lock();
+        if inner.buf.is_empty() {
+            return if inner.writers == 0 || len == 0 {
+                Ok(Vec::new())
+            } else {
+                Err(Errno::EAGAIN)
+            };
+        }
+        let len = len.min(inner.buf.len());
+        let data = inner.buf.drain(..len).collect();
+        drop(inner);
+        wait::request_wake();
+        Ok(data)
+    }
+
+    /// The number of the bytes of a write of `len` bytes which fit now.
+    ///
+    /// A write not longer than [PIPE_BUF] is never interleaved with the
+    /// others, the longer ones may be written partially.
+    fn write_len(&self, len: usize) -> SysResult {
+        if !self.writable {
+            return Err(Errno::EBADF);
+        }
+        let inner = self.pipe.inner.lock();
+        if inner.readers == 0 {
+            // TODO: Send `SIGPIPE` when the signals are supported.
+            return Err(Errno::EPIPE);
+        }
+        let room = PIPE_SIZE - inner.buf.len();
+        if len == 0 {
+            return Ok(0);
+        }
+        if room == 0 || (len <= PIPE_BUF && room < len) {
+            return Err(Errno::EAGAIN);
+        }
+        Ok(len.min(room))
+    }
+
+    /// Write the `data`, returns the number of the bytes written, see
+    /// [PipeEnd::write_len].
+    fn write(&self, data: &[u8]) -> SysResult {
+        let len = self.write_len(data.len())?;
+        if len > 0 {
+            self.pipe.inner.lock().buf.extend(&data[..len]);
+            wait::request_wake();
+        }
+        Ok(len)
+    }
+
+    pub(crate) fn poll(&self) -> PollEvents {
+        let inner = self.pipe.inner.lock();
+        let mut events = PollEvents::empty();
+        if self.readable {
+            if !inner.buf.is_empty() {
+                events |= PollEvents::POLLIN;
+            }
+            if inner.writers == 0 && inner.write_opens > 0 {
+                events |= PollEvents::POLLHUP;
+            }
+        }
+        if self.writable {
+            if inner.readers == 0 {
+                events |= PollEvents::POLLERR;
+            } else if PIPE_SIZE - inner.buf.len() >= PIPE_BUF {
+                events |= PollEvents::POLLOUT;
+            }
+        }
+        events
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let mut inner = self.pipe.inner.lock();
+        if self.readable {
+            inner.readers -= 1;
+        }
+        if self.writable {
+            inner.writers -= 1;
+        }
+        drop(inner);
+        // The readers see the end of file and the writers see `EPIPE`.
+        wait::request_wake();
+    }
+}
+
+/// Open an end of the FIFO `pipe`.
+///
+/// Opening the read end blocks until a writer opens it, and opening the write
+/// end blocks until a reader opens it, unless `O_NONBLOCK` is set. The write
+/// end fails with [Errno::ENXIO] in the nonblocking mode if there is no reader.
+pub(crate) fn open_fifo(badge: u64, pipe: Arc<Pipe>, flags: OpenFlags) -> SysResult {
+    let nonblock = flags.contains(OpenFlags::O_NONBLOCK);
+    let cloexec = flags.contains(OpenFlags::O_CLOEXEC);
+    let mode = flags.bits() & OpenFlags::O_ACCMODE;
+    if mode == OpenFlags::O_WRONLY.bits() && nonblock && pipe.inner.lock().readers == 0 {
+        return Err(Errno::ENXIO);
+    }
+    // The counters are taken before opening, so the peer which opens and
+    // closes its end between the retries is never missed.
+    let (read_opens, write_opens) = pipe.opens();
+    let end = Arc::new(File::Pipe(pipe.open(flags)));
+    wait::block_on(badge, nonblock, move || {
+        let File::Pipe(pipe_end) = end.as_ref() else {
+            unreachable!()
+        };
+        let inner = pipe_end.pipe.inner.lock();
+        let ready = match (pipe_end.readable, pipe_end.writable) {
+            (true, false) => inner.writers > 0 || inner.write_opens != write_opens,
+            (false, true) => inner.readers > 0 || inner.read_opens != read_opens,
+            _ => true,
+        };
+        drop(inner);
+        if !ready && !nonblock {
+            return Err(Errno::EAGAIN);
+        }
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).ok_or(Errno::ESRCH)?;
+        let fd = task
+            .file_table
+            .lock()
+            .insert_from(0, end.clone(), cloexec)?;
+        Ok(fd as usize)
+    })
+}
+
+/// Read from the pipe end, the syscall blocks while the pipe is empty.
+pub(crate) fn read_pipe(badge: u64, file: Arc<File>, buf: *mut u8, count: usize) -> SysResult {
+    let File::Pipe(end) = file.as_ref() else {
+        return Err(Errno::EBADF);
+    };
+    let buf = buf as usize;
+    wait::block_on(badge, end.is_nonblocking(), move || {
+        let File::Pipe(end) = file.as_ref() else {
+            unreachable!()
+        };
+        let data = end.read(count)?;
+        if !data.is_empty() {
+            let task_map = TASK_MAP.lock();
+            let task = task_map.get(&badge).ok_or(Errno::ESRCH)?;
+            write_item_list(task, buf as *mut u8, Some(data.len()), &data)?;
+        }
+        Ok(data.len())
+    })
+}
+
+/// Write to the pipe end, the syscall blocks while the pipe is full.
+///
+/// A blocking write returns after all the data is written, a nonblocking one
+/// returns the bytes written until the pipe is full. The data is copied from
+/// the user space as much as fits in the pipe at a time.
+pub(crate) fn write_pipe(badge: u64, file: Arc<File>, buf: *const u8, count: usize) -> SysResult {
+    let File::Pipe(end) = file.as_ref() else {
+        return Err(Errno::EBADF);
+    };
+    let nonblock = end.is_nonblocking();
+    let buf = buf as usize;
+    let mut written = 0;
+    wait::block_on(badge, nonblock, move || {
+        let File::Pipe(end) = file.as_ref() else {
+            unreachable!()
+        };
+        loop {
+            let res = end.write_len(count - written).and_then(|len| {
+                let mut data = alloc::vec![0u8; len];
+                let task_map = TASK_MAP.lock();
+                let task = task_map.get(&badge).ok_or(Errno::ESRCH)?;
+                read_item_list(
+                    task,
+                    buf.wrapping_add(written) as *const u8,
+                    Some(len),
+                    &mut data,
+                )?;
+                drop(task_map);
+                end.write(&data)
+            });
+            match res {
+                Ok(len) => written += len,
+                // Park until there is room, keeping the bytes written.
+                Err(Errno::EAGAIN) if !nonblock => return Err(Errno::EAGAIN),
+                Err(_) if written > 0 => return Ok(written),
+                Err(err) => return Err(err),
+            }
+            if written == count {
+                return Ok(written);
+            }
+        }
+    })
+}
+
+pub(crate) fn sys_pipe2(badge: u64, fds: *mut i32, flags: usize) -> SysResult {
+    let flags = OpenFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    if !(OpenFlags::O_CLOEXEC | OpenFlags::O_NONBLOCK).contains(flags) {
+        return Err(Errno::EINVAL);
+    }
+    let cloexec = flags.contains(OpenFlags::O_CLOEXEC);
+    let (read_end, write_end) = PipeEnd::pair(flags);
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let mut file_table = task.file_table.lock();
+    let read_fd = file_table.insert_from(0, Arc::new(File::Pipe(read_end)), cloexec)?;
+    let write_fd = match file_table.insert_from(0, Arc::new(File::Pipe(write_end)), cloexec) {
+        Ok(fd) => fd,
+        Err(err) => {
+            let _ = file_table.remove(read_fd);
+            return Err(err);
+        }
+    };
+    if let Err(err) = write_item_list(task, fds, Some(2), &[read_fd, write_fd]) {
+        let _ = file_table.remove(read_fd);
+        let _ = file_table.remove(write_fd);
+        return Err(err);
+    }
+    Ok(0)
+}
@@ -0,0 +1,220 @@
+//! The `ppoll` and `pselect6` syscalls.
+
+use alloc::vec::Vec;
+use common::{TimeSpec, NET_POLL_READABLE, NET_POLL_WRITABLE};
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{net::ipc::tcp, wait, Deadline, SysResult},
+    utils::{read_item, read_item_list, write_item_list},
+};
+
+use super::{
+    file::{file_of, File},
+    tty::console_poll,
+};
+
+bitflags::bitflags! {
+    /// The events of `struct pollfd`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct PollEvents: i16 {
+        const POLLIN = 0x001;
+        const POLLPRI = 0x002;
+        const POLLOUT = 0x004;
+        const POLLERR = 0x008;
+        const POLLHUP = 0x010;
+        const POLLNVAL = 0x020;
+    }
+}
+
+/// The maximum number of fds in a `fd_set`.
+const FD_SETSIZE: usize = 1024;
+
+/// The number of bits in a word of `fd_set`.
+const FD_SET_WORD_BITS: usize = u64::BITS as usize;
+
+/// The same as `struct pollfd` in C.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+/// Get the readiness of the file descriptor `fd` of the task `badge`.
+pub(crate) fn fd_poll(badge: u64, fd: i32) -> PollEvents {
+    let Ok(file) = file_of(badge, fd) else {
+        return PollEvents::POLLNVAL;
+    };
+    match file.as_ref() {
+        File::Stdin => console_poll(),
+        File::Stdout | File::Stderr => PollEvents::POLLOUT,
+        // The same as linux, a regular file or a directory is always ready.
+        File::Regular(_) | File::Dir(_) => PollEvents::POLLIN | PollEvents::POLLOUT,
+        File::Epoll(instance) => instance.poll_ready(badge),
+        File::Unix(socket) => socket.poll(),
+        File::Pipe(end) => end.poll(),
+        File::Socket(socket) => match tcp::poll(socket.id) {
+            Ok(bits) => {
+                let mut events = PollEvents::empty();
+                if bits & NET_POLL_READABLE != 0 {
+                    events |= PollEvents::POLLIN;
+                }
+                if bits & NET_POLL_WRITABLE != 0 {
+                    events |= PollEvents::POLLOUT;
+                }
+                events
+            }
+            Err(_) => PollEvents::POLLERR,
+        },
+    }
+}
+
+/// The deadline of the timeout, the null timeout waits forever.
+fn deadline_of(badge: u64, tmo: *const TimeSpec) -> Result<Deadline, Errno> {
+    if tmo.is_null() {
+        return Ok(Deadline::never());
+    }
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let tmo = read_item(task, tmo)?;
+    if !tmo.is_valid() {
+        return Err(Errno::EINVAL);
+    }
+    match tmo.is_zero() {
+        true => Ok(Deadline::at(0)),
+        false => Ok(Deadline::after(tmo.as_nanos())),
+    }
+}
+
+/// Poll all the fds and fill the `revents`, returns the number of ready fds.
+fn poll_fds(badge: u64, fds: &mut [PollFd]) -> usize {
+    fds.iter_mut().fold(0, |count, pollfd| {
+        let events = PollEvents::from_bits_truncate(pollfd.events)
+            | PollEvents::POLLERR
+            | PollEvents::POLLHUP
+            | PollEvents::POLLNVAL;
+        pollfd.revents = (fd_poll(badge, pollfd.fd) & events).bits();
+        count + (pollfd.revents != 0) as usize
+    })
+}
+
+pub(crate) fn sys_ppoll(
+    badge: u64,
+    fds: *mut PollFd,
+    nfds: usize,
+    tmo: *const TimeSpec,
+    _sigmask: usize,
+    _sigsetsize: usize,
+) -> SysResult {
+    if nfds > FD_SETSIZE {
+        return Err(Errno::EINVAL);
+    }
+    let mut pollfds = alloc::vec![PollFd::default(); nfds];
+    if nfds > 0 {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        read_item_list(task, fds, Some(nfds), &mut pollfds)?;
+    }
+    let mut deadline = deadline_of(badge, tmo)?;
+
+    let fds = fds as usize;
+    let mut retry = move || {
+        let count = poll_fds(badge, &mut pollfds);
+        if count == 0 && !deadline.expired() {
+            return None;
+        }
+        let task_map = TASK_MAP.lock();
+        let Some(task) = task_map.get(&badge) else {
+            return Some(Err(Errno::ESRCH));
+        };
+        Some(
+            write_item_list(task, fds as *mut PollFd, Some(nfds), &pollfds)
+                .map(|_| count),
+        )
+    };
+    match retry() {
+        Some(res) => res,
+        None => wait::park(badge, retry),
+    }
+}
+
+/// Read a `fd_set` of `nfds` bits from the user space, the null set is empty.
+fn read_fd_set(badge: u64, set: *const u64, nfds: usize) -> Result<Vec<u64>, Errno> {
+    let mut words = alloc::vec![0u64; nfds.div_ceil(FD_SET_WORD_BITS)];
+    if !set.is_null() && !words.is_empty() {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        let len = words.len();
+        read_item_list(task, set, Some(len), &mut words)?;
+    }
+    Ok(words)
+}
+
+pub(crate) fn sys_pselect6(
+    badge: u64,
+    nfds: usize,
+    readfds: *mut u64,
+    writefds: *mut u64,
+    exceptfds: *mut u64,
+    tmo: *const TimeSpec,
+    _sigmask: usize,
+) -> SysResult {
+    if nfds > FD_SETSIZE {
+        return Err(Errno::EINVAL);
+    }
+    let sets = [
+        (readfds as usize, read_fd_set(badge, readfds, nfds)?),
+        (writefds as usize, read_fd_set(badge, writefds, nfds)?),
+        (exceptfds as usize, read_fd_set(badge, exceptfds, nfds)?),
+    ];
+    let mut deadline = deadline_of(badge, tmo)?;
+    let masks = [
+        PollEvents::POLLIN | PollEvents::POLLHUP | PollEvents::POLLERR,
+        PollEvents::POLLOUT | PollEvents::POLLERR,
+        PollEvents::POLLPRI,
+    ];
+
+    let mut retry = move || {
+        let mut results = sets
+            .clone()
+            .map(|(addr, words)| (addr, alloc::vec![0u64; words.len()]));
+        let mut count = 0;
+        for fd in 0..nfds {
+            let (word, bit) = (fd / FD_SET_WORD_BITS, 1 << (fd % FD_SET_WORD_BITS));
+            if sets.iter().all(|(_, words)| words[word] & bit == 0) {
+                continue;
+            }
+            let events = fd_poll(badge, fd as _);
+            if events.contains(PollEvents::POLLNVAL) {
+                return Some(Err(Errno::EBADF));
+            }
+            for (i, (_, words)) in sets.iter().enumerate() {
+                if words[word] & bit != 0 && events.intersects(masks[i]) {
+                    results[i].1[word] |= bit;
+                    count += 1;
+                }
+            }
+        }
+        if count == 0 && !deadline.expired() {
+            return None;
+        }
+        let task_map = TASK_MAP.lock();
+        let Some(task) = task_map.get(&badge) else {
+            return Some(Err(Errno::ESRCH));
+        };
+        for (addr, words) in results.iter().filter(|(addr, words)| *addr != 0 && !words.is_empty()) {
+            if let Err(err) = write_item_list(task, *addr as *mut u64, Some(words.len()), words)
+            {
+                return Some(Err(err));
+            }
+        }
+        Some(Ok(count))
+    };
+    match retry() {
+        Some(res) => res,
+        None => wait::park(badge, retry),
+    }
+}
@@ -0,0 +1,197 @@
+//! The console backed by the uart-thread.
+//!
+//! The stdin, stdout and stderr of the tasks are the console. The line
+//! discipline lives in the uart-thread, which signals the notification bound
+//! to the kernel thread with `CONSOLE_READY_BADGE` when the input becomes
+//! readable or the input generates signals, then the parked reads are retried.
+
+use alloc::{sync::Arc, vec::Vec};
+use common::{
+    bytes_to_regs, regs_to_bytes, ConsoleMessageLabel, Termios, WinSize, CONSOLE_MSG_BYTES,
+    CONSOLE_POLL_READABLE,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate_consts::slots::kernel_thread::CONSOLE_EP;
+use sel4::{cap::Endpoint, with_ipc_buffer, with_ipc_buffer_mut};
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{wait, Deadline, SysResult},
+    utils::{read_item, write_item, write_item_list},
+};
+
+use super::{
+    file::{file_of, File},
+    poll::PollEvents,
+};
+
+const TCGETS: usize = 0x5401;
+const TCSETS: usize = 0x5402;
+const TCSETSW: usize = 0x5403;
+const TCSETSF: usize = 0x5404;
+const TIOCGWINSZ: usize = 0x5413;
+const TIOCSWINSZ: usize = 0x5414;
+
+/// Increased when the console generates signals, the reads parked before
+/// are interrupted.
+static SIGNAL_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of the signal-generating inputs of the console so far, the
+/// syscalls parked before it changes are interrupted.
+pub(crate) fn console_signal_seq() -> usize {
+    SIGNAL_SEQ.load(Ordering::Acquire)
+}
+
+fn console_call(label: ConsoleMessageLabel) {
+    let ep = Endpoint::from_bits(CONSOLE_EP);
+    ep.call(label.build());
+}
+
+/// Read at most [CONSOLE_MSG_BYTES] bytes.
+///
+/// If nothing is readable, it fails with the tenths of a second the read
+/// should wait for, zero if it waits until some input arrives.
+fn console_read(len: usize) -> Result<Vec<u8>, u64> {
+    console_call(ConsoleMessageLabel::Read(len.min(CONSOLE_MSG_BYTES) as u64));
+    with_ipc_buffer(|buffer| {
+        let regs = buffer.msg_regs();
+        let len = regs[0] as i64;
+        if len < 0 {
+            return Err(regs[1]);
+        }
+        let mut data = alloc::vec![0u8; len as usize];
+        regs_to_bytes(&regs[1..], &mut data);
+        Ok(data)
+    })
+}
+
+/// Write the `data` through the line discipline.
+pub(crate) fn console_write(data: &[u8]) {
+    for chunk in data.chunks(CONSOLE_MSG_BYTES) {
+        with_ipc_buffer_mut(|buffer| bytes_to_regs(chunk, &mut buffer.msg_regs_mut()[1..]));
+        console_call(ConsoleMessageLabel::Write(chunk.len() as u64));
+    }
+}
+
+pub(crate) fn console_poll() -> PollEvents {
+    console_call(ConsoleMessageLabel::Poll);
+    let bits = with_ipc_buffer(|buffer| buffer.msg_regs()[0]);
+    if bits & CONSOLE_POLL_READABLE != 0 {
+        PollEvents::POLLIN
+    } else {
+        PollEvents::empty()
+    }
+}
+
+/// Read from the console, the syscall blocks until the input is readable.
+///
+/// The read is interrupted with [Errno::EINTR] if the console generates a
+/// signal meanwhile. In the non-canonical mode with `VMIN` zero, it returns 0
+/// after `VTIME` tenths of a second.
+pub(crate) fn read_console(badge: u64, buf: *mut u8, count: usize) -> SysResult {
+    let seq = SIGNAL_SEQ.load(Ordering::Acquire);
+    let mut deadline: Option<Deadline> = None;
+    let buf = buf as usize;
+    wait::block_on(badge, false, move || {
+        if SIGNAL_SEQ.load(Ordering::Acquire) != seq {
+            return Err(Errno::EINTR);
+        }
+        let data = match console_read(count) {
+            Ok(data) => data,
+            Err(0) => return Err(Errno::EAGAIN),
+            Err(vtime) => {
+                let deadline = deadline.get_or_insert_with(|| Deadline::after(vtime * 100_000_000));
+                return match deadline.expired() {
+                    true => Ok(0),
+                    false => Err(Errno::EAGAIN),
+                };
+            }
+        };
+        if !data.is_empty() {
+            let task_map = TASK_MAP.lock();
+            let task = task_map.get(&badge).ok_or(Errno::ESRCH)?;
+            write_item_list(task, buf as *mut u8, Some(data.len()), &data)?;
+        }
+        Ok(data.len())
+    })
+}
+
+/// Take the signals generated by the console input and deliver them.
+///
+/// It is called when the uart-thread signals the bound notification, before
+/// the parked syscalls are retried.
+pub(crate) fn handle_console_signals() {
+    console_call(ConsoleMessageLabel::TakeSignals);
+    let signals = with_ipc_buffer(|buffer| buffer.msg_regs()[0]);
+    if signals == 0 {
+        return;
+    }
+    // TODO: Send the signals to the foreground process group when the signals
+    // and the process groups are supported, only the reads are interrupted now.
+    SIGNAL_SEQ.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Check that `fd` of the task `badge` is the console.
+fn tty_of(badge: u64, fd: i32) -> Result<Arc<File>, Errno> {
+    let file = file_of(badge, fd)?;
+    match file.as_ref() {
+        File::Stdin | File::Stdout | File::Stderr => Ok(file),
+        _ => Err(Errno::ENOTTY),
+    }
+}
+
+pub(crate) fn sys_ioctl(badge: u64, fd: i32, cmd: usize, arg: usize) -> SysResult {
+    tty_of(badge, fd)?;
+    match cmd {
+        TCGETS => {
+            console_call(ConsoleMessageLabel::GetTermios);
+            let termios = with_ipc_buffer(|buffer| Termios::from_regs(buffer.msg_regs()));
+            let task_map = TASK_MAP.lock();
+            let task = task_map.get(&badge).unwrap();
+            write_item(task, arg as *const Termios, &termios)?;
+            Ok(0)
+        }
+        TCSETS | TCSETSW | TCSETSF => {
+            let termios = {
+                let task_map = TASK_MAP.lock();
+                let task = task_map.get(&badge).unwrap();
+                read_item(task, arg as *const Termios)?
+            };
+            // The output is written synchronously, so `TCSETSW` has nothing to drain.
+            console_call(ConsoleMessageLabel::SetTermios(
+                termios.to_regs(),
+                (cmd == TCSETSF) as u64,
+            ));
+            Ok(0)
+        }
+        TIOCGWINSZ => {
+            console_call(ConsoleMessageLabel::GetWinSize);
+            let winsize = with_ipc_buffer(|buffer| {
+                let regs = buffer.msg_regs();
+                WinSize {
+                    ws_row: regs[0] as u16,
+                    ws_col: regs[1] as u16,
+                    ..Default::default()
+                }
+            });
+            let task_map = TASK_MAP.lock();
+            let task = task_map.get(&badge).unwrap();
+            write_item(task, arg as *const WinSize, &winsize)?;
+            Ok(0)
+        }
+        TIOCSWINSZ => {
+            let winsize = {
+                let task_map = TASK_MAP.lock();
+                let task = task_map.get(&badge).unwrap();
+                read_item(task, arg as *const WinSize)?
+            };
+            console_call(ConsoleMessageLabel::SetWinSize(
+                winsize.ws_row as u64,
+                winsize.ws_col as u64,
+            ));
+            Ok(0)
+        }
+        _ => Err(Errno::ENOTTY),
+    }
+}
@@ -0,0 +1,541 @@
+//! A minimal in-memory namespace of the file system.
+//!
+//! There is no real file system yet. The namespace holds the read-only files
+//! of the root file system built into the kernel thread, such as the dynamic
+//! linkers, the files generated when they are opened, such as
+//! `/proc/net/snmp`, and the special files created by the syscalls, such as
+//! the paths bound by unix sockets and the FIFOs created by `mknodat`.
+//! All the paths are absolute and normalized, the relative ones are resolved
+//! from the root as the tasks have no working directory yet, or from the
+//! directory of `dirfd` with the `*at` syscalls.
+//!
+//! The directories are not kept in the namespace, they are the root and the
+//! ancestors of the nodes, so they exist while they have entries.
+
+use alloc::{
+    borrow::Cow,
+    collections::btree_map::BTreeMap,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use common::Stat;
+use core::ops::Bound;
+use include_bytes_aligned::include_bytes_aligned;
+use spin::Mutex;
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{
+        net::{icmp_snmp, UnixSocket},
+        SysResult,
+    },
+    utils::{read_c_string, write_item, write_item_list},
+};
+
+use super::{
+    file::{file_of, File, OpenFlags},
+    pipe::{open_fifo, Pipe},
+};
+
+include!(concat!(env!("OUT_DIR"), "/rootfs_files.rs"));
+
+/// The maximum length of a path, the same as `PATH_MAX` in linux.
+pub(crate) const PATH_MAX: usize = 4096;
+
+/// The `dirfd` of the `*at` syscalls for the working directory.
+const AT_FDCWD: i32 = -100;
+
+/// The flag of `unlinkat` to remove a directory.
+const AT_REMOVEDIR: i32 = 0x200;
+
+/// The flag of `newfstatat` to get the status of `dirfd` if the path is empty.
+const AT_EMPTY_PATH: i32 = 0x1000;
+
+/// The mask of the file type in the mode.
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+/// The file types of the entries returned by `getdents64`.
+const DT_FIFO: u8 = 1;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_SOCK: u8 = 12;
+
+/// The size of the fixed fields of `struct linux_dirent64`, the name follows.
+const DIRENT64_HEADER_SIZE: usize = 19;
+
+/// A node in the namespace.
+#[derive(Clone)]
+pub(crate) enum Inode {
+    /// A read-only regular file of the root file system.
+    File(&'static [u8]),
+    /// A read-only file whose data is generated when it is opened.
+    Generated(fn() -> Vec<u8>),
+    /// The path bound by a unix socket, it stays after the socket is closed.
+    Socket(Weak<UnixSocket>),
+    /// A FIFO, the data in it is kept until all the ends are closed.
+    Fifo(Arc<Pipe>),
+}
+
+impl Inode {
+    /// The file type and the permissions in the mode, and the type of the
+    /// entry returned by `getdents64`.
+    fn file_type(&self) -> (u32, u8) {
+        match self {
+            // The files of the root file system are the programs and their
+            // libraries.
+            Inode::File(_) => (S_IFREG | 0o555, DT_REG),
+            Inode::Generated(_) => (S_IFREG | 0o444, DT_REG),
+            Inode::Socket(_) => (S_IFSOCK | 0o777, DT_SOCK),
+            Inode::Fifo(_) => (S_IFIFO | 0o666, DT_FIFO),
+        }
+    }
+}
+
+static NAMESPACE: Mutex<BTreeMap<String, Inode>> = Mutex::new(BTreeMap::new());
+
+/// Add the files of the root file system and the generated files to the
+/// namespace.
+pub(crate) fn mount_rootfs() {
+    let mut namespace = NAMESPACE.lock();
+    for (path, data) in ROOTFS_FILES {
+        namespace.insert((*path).into(), Inode::File(data));
+    }
+    namespace.insert("/proc/net/snmp".into(), Inode::Generated(icmp_snmp));
+}
+
+/// Add the read-only `data` at the normalized `path`, the same as a file of
+/// the root file system.
+pub(crate) fn mount_file(path: &str, data: &'static [u8]) {
+    NAMESPACE.lock().insert(path.into(), Inode::File(data));
+}
+
+/// An open read-only regular file, read from `offset`.
+pub(crate) struct RegularFile {
+    path: String,
+    data: Cow<'static, [u8]>,
+    offset: Mutex<usize>,
+}
+
+/// An open directory, its entries are read from the `offset`th one.
+pub(crate) struct DirFile {
+    path: String,
+    offset: Mutex<usize>,
+}
+
+/// Insert the opened `file` into the fd table of the task `badge`.
+fn insert_file(badge: u64, file: File, flags: OpenFlags) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let fd = task.file_table.lock().insert_from(
+        0,
+        Arc::new(file),
+        flags.contains(OpenFlags::O_CLOEXEC),
+    )?;
+    Ok(fd as usize)
+}
+
+/// Open the regular file `data` at `path`, it can only be read.
+fn open_regular(badge: u64, path: String, data: Cow<'static, [u8]>, flags: OpenFlags) -> SysResult {
+    if flags.bits() & OpenFlags::O_ACCMODE != OpenFlags::O_RDONLY.bits()
+        || flags.contains(OpenFlags::O_TRUNC)
+    {
+        return Err(Errno::EROFS);
+    }
+    let file = File::Regular(RegularFile {
+        path,
+        data,
+        offset: Mutex::new(0),
+    });
+    insert_file(badge, file, flags)
+}
+
+/// Open the directory at `path`, it can only be read.
+fn open_dir(badge: u64, path: String, flags: OpenFlags) -> SysResult {
+    if flags.bits() & OpenFlags::O_ACCMODE != OpenFlags::O_RDONLY.bits()
+        || flags.contains(OpenFlags::O_CREAT)
+    {
+        return Err(Errno::EISDIR);
+    }
+    let file = File::Dir(DirFile {
+        path,
+        offset: Mutex::new(0),
+    });
+    insert_file(badge, file, flags)
+}
+
+/// Read the regular file into the user buffer `buf`.
+pub(crate) fn read_regular(
+    badge: u64,
+    file: &RegularFile,
+    buf: *mut u8,
+    count: usize,
+) -> SysResult {
+    let mut offset = file.offset.lock();
+    let start = (*offset).min(file.data.len());
+    let data = &file.data[start..start + count.min(file.data.len() - start)];
+    if !data.is_empty() {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        write_item_list(task, buf, Some(data.len()), data)?;
+    }
+    *offset = start + data.len();
+    Ok(data.len())
+}
+
+/// Normalize the `path` into an absolute path without `.`, `..` and repeated `/`.
+pub(crate) fn normalize_path(path: &str) -> Result<String, Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    let mut normalized = String::new();
+    for part in parts {
+        normalized.push('/');
+        normalized.push_str(part);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// Read a path from the user space, it may be empty.
+fn read_path(badge: u64, path: *const u8) -> Result<String, Errno> {
+    if path.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    read_c_string(task, path, PATH_MAX)
+}
+
+/// Resolve the `path` from `dirfd` and normalize it.
+///
+/// A relative path is resolved from the working directory, the root, if
+/// `dirfd` is [AT_FDCWD], or else from the directory `dirfd` refers to.
+fn resolve_at(badge: u64, dirfd: i32, path: &str) -> Result<String, Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if path.starts_with('/') || dirfd == AT_FDCWD {
+        return normalize_path(path);
+    }
+    match file_of(badge, dirfd)?.as_ref() {
+        File::Dir(dir) => normalize_path(&format!("{}/{}", dir.path, path)),
+        _ => Err(Errno::ENOTDIR),
+    }
+}
+
+/// Read a path from the user space, resolve it from `dirfd` and normalize it.
+fn read_path_at(badge: u64, dirfd: i32, path: *const u8) -> Result<String, Errno> {
+    resolve_at(badge, dirfd, &read_path(badge, path)?)
+}
+
+/// The `path` with a trailing `/`, the prefix of the paths in the directory.
+fn dir_prefix(path: &str) -> String {
+    match path {
+        "/" => path.into(),
+        _ => format!("{}/", path),
+    }
+}
+
+/// The nodes whose paths start with `prefix`, those in the directory of the
+/// prefix and its subdirectories.
+fn dir_nodes<'a>(
+    namespace: &'a BTreeMap<String, Inode>,
+    prefix: &'a str,
+) -> impl Iterator<Item = (&'a String, &'a Inode)> {
+    namespace
+        .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+        .take_while(move |(path, _)| path.starts_with(prefix))
+}
+
+/// Whether there is a directory at the normalized `path`.
+fn is_dir(namespace: &BTreeMap<String, Inode>, path: &str) -> bool {
+    path == "/" || dir_nodes(namespace, &dir_prefix(path)).next().is_some()
+}
+
+/// The names and the `getdents64` types of the entries of the directory at
+/// the normalized `path`, including `.` and `..`.
+fn read_dir(path: &str) -> Vec<(String, u8)> {
+    let prefix = dir_prefix(path);
+    let namespace = NAMESPACE.lock();
+    let mut entries = BTreeMap::new();
+    for (node_path, inode) in dir_nodes(&namespace, &prefix) {
+        match node_path[prefix.len()..].split_once('/') {
+            Some((name, _)) => entries.insert(String::from(name), DT_DIR),
+            None => entries.insert(node_path[prefix.len()..].into(), inode.file_type().1),
+        };
+    }
+    [(".".into(), DT_DIR), ("..".into(), DT_DIR)]
+        .into_iter()
+        .chain(entries)
+        .collect()
+}
+
+/// The inode number of the normalized `path`, hashed with FNV-1a as the
+/// nodes have no numbers.
+fn path_ino(path: &str) -> u64 {
+    path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// Create the node at the normalized `path`, fails if it exists.
+pub(crate) fn create(path: &str, inode: Inode) -> Result<(), Errno> {
+    let mut namespace = NAMESPACE.lock();
+    if namespace.contains_key(path) || is_dir(&namespace, path) {
+        return Err(Errno::EEXIST);
+    }
+    namespace.insert(path.into(), inode);
+    Ok(())
+}
+
+/// Look up the node at the normalized `path`.
+pub(crate) fn lookup(path: &str) -> Result<Inode, Errno> {
+    NAMESPACE.lock().get(path).cloned().ok_or(Errno::ENOENT)
+}
+
+/// Read the whole regular file at `path`, for loading the programs.
+pub(crate) fn read_file(path: &str) -> Result<&'static [u8], Errno> {
+    let path = normalize_path(path)?;
+    if is_dir(&NAMESPACE.lock(), &path) {
+        return Err(Errno::EACCES);
+    }
+    match lookup(&path)? {
+        Inode::File(data) => Ok(data),
+        // The same as linux, the generated and special files can not be
+        // executed.
+        Inode::Generated(_) | Inode::Socket(_) | Inode::Fifo(_) => Err(Errno::EACCES),
+    }
+}
+
+/// Remove the node at the normalized `path`, the files of the root file
+/// system and the generated ones are read-only.
+pub(crate) fn unlink(path: &str) -> Result<(), Errno> {
+    let mut namespace = NAMESPACE.lock();
+    match namespace.get(path) {
+        Some(Inode::File(_) | Inode::Generated(_)) => Err(Errno::EROFS),
+        Some(_) => {
+            namespace.remove(path);
+            Ok(())
+        }
+        None => Err(Errno::ENOENT),
+    }
+}
+
+pub(crate) fn sys_unlinkat(badge: u64, dirfd: i32, path: *const u8, flags: i32) -> SysResult {
+    let path = read_path_at(badge, dirfd, path)?;
+    let dir = is_dir(&NAMESPACE.lock(), &path);
+    if flags & AT_REMOVEDIR != 0 {
+        // A directory exists only while it has entries.
+        return Err(match lookup(&path) {
+            _ if path == "/" => Errno::EBUSY,
+            _ if dir => Errno::ENOTEMPTY,
+            Ok(_) => Errno::ENOTDIR,
+            Err(err) => err,
+        });
+    }
+    if dir {
+        return Err(Errno::EISDIR);
+    }
+    unlink(&path)?;
+    Ok(0)
+}
+
+pub(crate) fn sys_mknodat(
+    badge: u64,
+    dirfd: i32,
+    path: *const u8,
+    mode: u32,
+    _dev: u64,
+) -> SysResult {
+    let path = read_path_at(badge, dirfd, path)?;
+    match mode & S_IFMT {
+        S_IFIFO => create(&path, Inode::Fifo(Arc::new(Pipe::default())))?,
+        // TODO: Create the regular files and the devices when there is a file system.
+        _ => return Err(Errno::EPERM),
+    }
+    Ok(0)
+}
+
+pub(crate) fn sys_openat(
+    badge: u64,
+    dirfd: i32,
+    path: *const u8,
+    flags: usize,
+    _mode: u32,
+) -> SysResult {
+    let flags = OpenFlags::from_bits_truncate(flags);
+    let path = read_path_at(badge, dirfd, path)?;
+    if is_dir(&NAMESPACE.lock(), &path) {
+        if flags.contains(OpenFlags::O_CREAT | OpenFlags::O_EXCL) {
+            return Err(Errno::EEXIST);
+        }
+        return open_dir(badge, path, flags);
+    }
+    let inode = match lookup(&path) {
+        Ok(_) if flags.contains(OpenFlags::O_CREAT | OpenFlags::O_EXCL) => {
+            return Err(Errno::EEXIST)
+        }
+        Ok(inode) => inode,
+        // There is nowhere to create a regular file.
+        Err(Errno::ENOENT) if flags.contains(OpenFlags::O_CREAT) => return Err(Errno::EROFS),
+        Err(err) => return Err(err),
+    };
+    if flags.contains(OpenFlags::O_DIRECTORY) {
+        return Err(Errno::ENOTDIR);
+    }
+    match inode {
+        Inode::File(data) => open_regular(badge, path, Cow::Borrowed(data), flags),
+        Inode::Generated(generate) => open_regular(badge, path, Cow::Owned(generate()), flags),
+        Inode::Fifo(pipe) => open_fifo(badge, pipe, flags),
+        // The same as linux, a socket can not be opened.
+        Inode::Socket(_) => Err(Errno::ENXIO),
+    }
+}
+
+/// Read the entries of the directory `fd` as `struct linux_dirent64`s, as
+/// many as fit in `count` bytes.
+pub(crate) fn sys_getdents64(badge: u64, fd: i32, buf: *mut u8, count: usize) -> SysResult {
+    let file = file_of(badge, fd)?;
+    let File::Dir(dir) = file.as_ref() else {
+        return Err(Errno::ENOTDIR);
+    };
+    let entries = read_dir(&dir.path);
+    let mut offset = dir.offset.lock();
+    let mut data = Vec::new();
+    for (name, d_type) in entries.iter().skip(*offset) {
+        let reclen = (DIRENT64_HEADER_SIZE + name.len() + 1).next_multiple_of(8);
+        if data.len() + reclen > count {
+            break;
+        }
+        let start = data.len();
+        *offset += 1;
+        let ino = path_ino(&normalize_path(&format!("{}/{}", dir.path, name))?);
+        data.extend(ino.to_ne_bytes());
+        // The offset of the next entry, as `lseek` takes it.
+        data.extend((*offset as i64).to_ne_bytes());
+        data.extend((reclen as u16).to_ne_bytes());
+        data.push(*d_type);
+        data.extend(name.as_bytes());
+        data.resize(start + reclen, 0);
+    }
+    if data.is_empty() && *offset < entries.len() {
+        // The buffer is too small for the next entry.
+        return Err(Errno::EINVAL);
+    }
+    if !data.is_empty() {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        write_item_list(task, buf, Some(data.len()), &data)?;
+    }
+    Ok(data.len())
+}
+
+/// The status with the file type and the permissions in `mode`, the inode
+/// number `ino` and the size `size` in bytes.
+fn new_stat(mode: u32, ino: u64, size: usize) -> Stat {
+    Stat {
+        st_ino: ino,
+        st_mode: mode,
+        st_nlink: if mode & S_IFMT == S_IFDIR { 2 } else { 1 },
+        st_size: size as i64,
+        st_blksize: 4096,
+        st_blocks: size.div_ceil(512) as i64,
+        ..Default::default()
+    }
+}
+
+/// The status of the node or the directory at the normalized `path`.
+fn path_stat(path: &str) -> Result<Stat, Errno> {
+    if is_dir(&NAMESPACE.lock(), path) {
+        return Ok(new_stat(S_IFDIR | 0o555, path_ino(path), 0));
+    }
+    let inode = lookup(path)?;
+    let size = match &inode {
+        Inode::File(data) => data.len(),
+        // The same as linux, the generated files are empty until they are read.
+        Inode::Generated(_) | Inode::Socket(_) | Inode::Fifo(_) => 0,
+    };
+    Ok(new_stat(inode.file_type().0, path_ino(path), size))
+}
+
+/// The status of the open `file`.
+///
+/// The files outside the namespace are numbered by their addresses, which
+/// are unique while they are open.
+fn file_stat(file: &Arc<File>) -> Result<Stat, Errno> {
+    let ino = Arc::as_ptr(file) as u64;
+    Ok(match file.as_ref() {
+        File::Regular(regular) => {
+            let mode = lookup(&regular.path).map_or(S_IFREG | 0o444, |inode| inode.file_type().0);
+            new_stat(mode, path_ino(&regular.path), regular.data.len())
+        }
+        File::Dir(dir) => new_stat(S_IFDIR | 0o555, path_ino(&dir.path), 0),
+        File::Stdin | File::Stdout | File::Stderr => new_stat(S_IFCHR | 0o620, ino, 0),
+        File::Pipe(_) => new_stat(S_IFIFO | 0o600, ino, 0),
+        File::Socket(_) | File::Unix(_) => new_stat(S_IFSOCK | 0o777, ino, 0),
+        // The same as linux, an epoll instance is an anonymous inode.
+        File::Epoll(_) => new_stat(0o600, ino, 0),
+    })
+}
+
+/// Write the status `stat` to the user buffer `statbuf`.
+fn write_stat(badge: u64, statbuf: *mut Stat, stat: &Stat) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_item(task, statbuf, stat)?;
+    Ok(0)
+}
+
+pub(crate) fn sys_fstat(badge: u64, fd: i32, statbuf: *mut Stat) -> SysResult {
+    let stat = file_stat(&file_of(badge, fd)?)?;
+    write_stat(badge, statbuf, &stat)
+}
+
+/// There are no symbolic links, so `AT_SYMLINK_NOFOLLOW` is ignored.
+pub(crate) fn sys_newfstatat(
+    badge: u64,
+    dirfd: i32,
+    path: *const u8,
+    statbuf: *mut Stat,
+    flags: i32,
+) -> SysResult {
+    let path = read_path(badge, path)?;
+    let stat = match path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        true if dirfd == AT_FDCWD => path_stat("/")?,
+        true => file_stat(&file_of(badge, dirfd)?)?,
+        false => path_stat(&resolve_at(badge, dirfd, &path)?)?,
+    };
+    write_stat(badge, statbuf, &stat)
+}
+
+/// The tasks have no working directory yet, it is always the root.
+pub(crate) fn sys_getcwd(badge: u64, buf: *mut u8, size: usize) -> SysResult {
+    const CWD: &[u8] = b"/\0";
+    if size < CWD.len() {
+        return Err(Errno::ERANGE);
+    }
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_item_list(task, buf, Some(CWD.len()), CWD)?;
+    Ok(CWD.len())
+}
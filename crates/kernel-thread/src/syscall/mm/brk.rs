@@ -10,9 +10,9 @@ pub(crate) fn sys_brk(badge: u64, addr: *mut u8) -> SysResult {
     if addr < USPACE_HEAP_BASE || addr > USPACE_HEAP_BASE + USPACE_HEAP_SIZE {
         return Err(Errno::ENOMEM);
     }
-    if addr > task.heap {
+    if addr > task.mem.lock().heap {
         task.brk(addr);
     }
-    task.heap = addr;
+    task.mem.lock().heap = addr;
     Ok(0)
 }
@@ -1,5 +1,5 @@
-use memory_addr::{MemoryAddr, PageIter4K, VirtAddr};
-use sel4::{debug_println, CapRights, CapRightsBuilder};
+use memory_addr::{MemoryAddr, PageIter4K, VirtAddr, PAGE_SIZE_4K};
+use sel4::{debug_println, CapRights, CapRightsBuilder, VmAttributes};
 use syscalls::Errno;
 
 use crate::{child_test::TASK_MAP, syscall::SysResult, OBJ_ALLOCATOR};
@@ -35,6 +35,15 @@ impl From<MmapProt> for CapRights {
     }
 }
 
+impl From<MmapProt> for VmAttributes {
+    fn from(value: MmapProt) -> Self {
+        match value.contains(MmapProt::PROT_EXEC) {
+            true => VmAttributes::DEFAULT,
+            false => VmAttributes::DEFAULT | VmAttributes::EXECUTE_NEVER,
+        }
+    }
+}
+
 bitflags::bitflags! {
     #[derive(Debug)]
     /// flags for sys_mmap
@@ -79,7 +88,7 @@ pub(crate) fn sys_mmap(
     for vaddr in PageIter4K::new(start_addr.align_down_4k(), end.align_up_4k())
         .expect("Failed to create page iterator")
     {
-        if task.mapped_page.get(&(vaddr.as_usize())).is_some() {
+        if task.mem.lock().mapped_page.contains_key(&vaddr.as_usize()) {
             continue;
         }
         let page_cap = OBJ_ALLOCATOR
@@ -100,10 +109,31 @@ pub(crate) fn sys_unmap(badge: u64, addr: *mut usize, length: usize) -> SysResul
     for vaddr in PageIter4K::new(start_addr.align_down_4k(), end.align_up_4k())
         .expect("Failed to create page iterator")
     {
-        if let Some(page) = task.mapped_page.get(&(vaddr.as_usize())) {
-            task.unmap_page(vaddr.as_usize(), *page);
-        }
+        task.unmap_page(vaddr.as_usize());
     }
 
     Ok(0)
 }
+
+/// Change the rights of the mapped pages, the dynamic linker makes
+/// `PT_GNU_RELRO` read-only with it after the relocations.
+pub(crate) fn sys_mprotect(badge: u64, addr: usize, length: usize, prot: i32) -> SysResult {
+    let prot = MmapProt::from_bits(prot).ok_or(Errno::EINVAL)?;
+    let start_addr = VirtAddr::from(addr);
+    if !start_addr.is_aligned_4k() {
+        return Err(Errno::EINVAL);
+    }
+    let mut task_map = TASK_MAP.lock();
+    let task = task_map.get_mut(&badge).unwrap();
+    // The range can't wrap around the address space.
+    let end = addr
+        .checked_add(length)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+        .ok_or(Errno::ENOMEM)?;
+    for vaddr in
+        PageIter4K::new(start_addr, VirtAddr::from(end)).expect("Failed to create page iterator")
+    {
+        task.protect_page(vaddr.as_usize(), prot.clone().into(), prot.clone().into())?;
+    }
+    Ok(0)
+}
@@ -3,10 +3,42 @@ use syscalls::{Errno, Sysno};
 mod fs;
 mod mm;
 mod net;
+mod power;
 mod thread;
+mod time;
+mod wait;
+
+pub(crate) use fs::{
+    handle_console_signals, mount_file, mount_rootfs, read_file, FileTable, PATH_MAX,
+};
+pub(crate) use thread::{bind_sched, Credentials, ONLINE_CORES};
+pub(crate) use time::{init_realtime, timer_restarted, Deadline, RealTimer, USER_HZ};
+pub(crate) use wait::{wake_requested_waiters, wake_waiters, SYS_PARKED};
 
 pub type SysResult = Result<usize, Errno>;
 
+/// Handle the syscall of the task `badge` called by `svc`, the arguments are
+/// the same as the IPC call but `clone` takes the ones of linux.
+pub fn handle_svc_call(
+    badge: u64,
+    sys_id: usize,
+    args: [usize; 6],
+    fault_ep: Endpoint,
+) -> Result<usize, Errno> {
+    match Sysno::new(sys_id) {
+        Some(Sysno::clone) => thread::sys_clone_svc(
+            badge,
+            fault_ep,
+            args[0],
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+            args[4] as _,
+        ),
+        _ => handle_ipc_call(badge, sys_id, args, fault_ep),
+    }
+}
+
 pub fn handle_ipc_call(
     badge: u64,
     sys_id: usize,
@@ -16,7 +48,70 @@ pub fn handle_ipc_call(
     let sys_no = Sysno::new(sys_id).ok_or(Errno::EINVAL)?;
     debug_println!("[KernelThread] Syscall: {:?}", sys_no);
     match sys_no {
+        Sysno::read => fs::sys_read(badge, args[0] as _, args[1] as _, args[2] as _),
         Sysno::write => fs::sys_write(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::readv => fs::sys_readv(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::writev => fs::sys_writev(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::ioctl => fs::sys_ioctl(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::close => fs::sys_close(badge, args[0] as _),
+        Sysno::pipe2 => fs::sys_pipe2(badge, args[0] as _, args[1] as _),
+        Sysno::dup => fs::sys_dup(badge, args[0] as _),
+        Sysno::dup3 => fs::sys_dup3(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::fcntl => fs::sys_fcntl(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::openat => fs::sys_openat(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
+        Sysno::mknodat => fs::sys_mknodat(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
+        Sysno::unlinkat => fs::sys_unlinkat(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::getdents64 => fs::sys_getdents64(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::newfstatat => fs::sys_newfstatat(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
+        Sysno::fstat => fs::sys_fstat(badge, args[0] as _, args[1] as _),
+        Sysno::getcwd => fs::sys_getcwd(badge, args[0] as _, args[1] as _),
+        Sysno::ppoll => fs::sys_ppoll(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+            args[4] as _,
+        ),
+        Sysno::pselect6 => fs::sys_pselect6(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+            args[4] as _,
+            args[5] as _,
+        ),
+        Sysno::epoll_create1 => fs::sys_epoll_create1(badge, args[0] as _),
+        Sysno::epoll_ctl => {
+            fs::sys_epoll_ctl(badge, args[0] as _, args[1] as _, args[2] as _, args[3] as _)
+        }
+        Sysno::epoll_pwait => fs::sys_epoll_pwait(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+            args[4] as _,
+        ),
         Sysno::brk => mm::sys_brk(badge, args[0] as _),
         Sysno::mmap => mm::sys_mmap(
             badge,
@@ -28,8 +123,16 @@ pub fn handle_ipc_call(
             args[5] as _,
         ),
         Sysno::munmap => mm::sys_unmap(badge, args[0] as _, args[1] as _),
+        Sysno::mprotect => mm::sys_mprotect(badge, args[0] as _, args[1] as _, args[2] as _),
         Sysno::exit => thread::sys_exit(badge, args[0] as _),
         Sysno::exit_group => thread::sys_exit_group(badge, args[0] as _),
+        Sysno::wait4 => thread::sys_wait4(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
         Sysno::getpid => thread::sys_getpid(badge),
         Sysno::execve => {
             thread::sys_exec(badge, fault_ep, args[0] as _, args[1] as _, args[2] as _)
@@ -37,16 +140,81 @@ pub fn handle_ipc_call(
         Sysno::clone => thread::sys_clone(badge, fault_ep, args[0] as _, args[1] as _),
         Sysno::gettid => thread::sys_gettid(badge as _),
         Sysno::sched_yield => thread::sys_sched_yield(),
+        Sysno::sched_setaffinity => {
+            thread::sys_sched_setaffinity(badge, args[0] as _, args[1] as _, args[2] as _)
+        }
+        Sysno::sched_getaffinity => {
+            thread::sys_sched_getaffinity(badge, args[0] as _, args[1] as _, args[2] as _)
+        }
+        Sysno::sched_setattr => {
+            thread::sys_sched_setattr(badge, args[0] as _, args[1] as _, args[2] as _)
+        }
+        Sysno::sched_getattr => thread::sys_sched_getattr(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
+        Sysno::sched_setscheduler => {
+            thread::sys_sched_setscheduler(badge, args[0] as _, args[1] as _, args[2] as _)
+        }
+        Sysno::sched_getscheduler => thread::sys_sched_getscheduler(badge, args[0] as _),
+        Sysno::sched_setparam => thread::sys_sched_setparam(badge, args[0] as _, args[1] as _),
+        Sysno::sched_getparam => thread::sys_sched_getparam(badge, args[0] as _, args[1] as _),
+        Sysno::sched_get_priority_max => thread::sys_sched_get_priority_max(args[0] as _),
+        Sysno::sched_get_priority_min => thread::sys_sched_get_priority_min(args[0] as _),
+        Sysno::sched_rr_get_interval => {
+            thread::sys_sched_rr_get_interval(badge, args[0] as _, args[1] as _)
+        }
+        Sysno::getpriority => thread::sys_getpriority(badge, args[0] as _, args[1] as _),
+        Sysno::setpriority => {
+            thread::sys_setpriority(badge, args[0] as _, args[1] as _, args[2] as _)
+        }
         Sysno::getppid => thread::sys_getppid(badge),
         Sysno::set_tid_address => thread::sys_set_tid_address(badge, args[0] as _),
+        Sysno::futex => thread::sys_futex(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
         Sysno::getuid => thread::sys_getuid(badge),
         Sysno::geteuid => thread::sys_geteuid(badge),
+        Sysno::getresuid => thread::sys_getresuid(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::setuid => thread::sys_setuid(badge, args[0] as _),
+        Sysno::setresuid => thread::sys_setresuid(badge, args[0] as _, args[1] as _, args[2] as _),
+
+        Sysno::clock_gettime => time::sys_clock_gettime(badge, args[0] as _, args[1] as _),
+        Sysno::clock_getres => time::sys_clock_getres(badge, args[0] as _, args[1] as _),
+        Sysno::clock_settime => time::sys_clock_settime(badge, args[0] as _, args[1] as _),
+        Sysno::gettimeofday => time::sys_gettimeofday(badge, args[0] as _, args[1] as _),
+        Sysno::settimeofday => time::sys_settimeofday(badge, args[0] as _, args[1] as _),
+        Sysno::times => time::sys_times(badge, args[0] as _),
+        Sysno::nanosleep => time::sys_nanosleep(badge, args[0] as _, args[1] as _),
+        Sysno::clock_nanosleep => time::sys_clock_nanosleep(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
+        Sysno::getitimer => time::sys_getitimer(badge, args[0] as _, args[1] as _),
+        Sysno::setitimer => time::sys_setitimer(badge, args[0] as _, args[1] as _, args[2] as _),
 
         Sysno::socket => net::sys_socket(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::socketpair => net::sys_socketpair(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
         Sysno::accept => net::sys_accept(badge, args[0] as _, args[1] as _, args[2] as _),
         Sysno::bind => net::sys_bind(badge, args[0] as _, args[1] as _, args[2] as _),
         Sysno::connect => net::sys_connect(badge, args[0] as _, args[1] as _, args[2] as _),
-        Sysno::listen => net::sys_listen(badge, args[0] as _),
+        Sysno::listen => net::sys_listen(badge, args[0] as _, args[1] as _),
         Sysno::sendto => net::sys_sendto(
             badge,
             args[0] as _,
@@ -65,7 +233,38 @@ pub fn handle_ipc_call(
             args[4] as _,
             args[5] as _,
         ),
+        Sysno::sendmsg => net::sys_sendmsg(badge, args[0] as _, args[1] as _, args[2] as _),
+        Sysno::recvmsg => net::sys_recvmsg(badge, args[0] as _, args[1] as _, args[2] as _),
         Sysno::shutdown => net::sys_shutdown(badge, args[0] as _, args[1] as _),
+        Sysno::setsockopt => net::sys_setsockopt(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+            args[4] as _,
+        ),
+        Sysno::getsockopt => net::sys_getsockopt(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+            args[4] as _,
+        ),
+        Sysno::getsockname => {
+            net::sys_getsockname(badge, args[0] as _, args[1] as _, args[2] as _)
+        }
+        Sysno::getpeername => {
+            net::sys_getpeername(badge, args[0] as _, args[1] as _, args[2] as _)
+        }
+        Sysno::reboot => power::sys_reboot(
+            badge,
+            args[0] as _,
+            args[1] as _,
+            args[2] as _,
+            args[3] as _,
+        ),
         _ => Err(Errno::ENOSYS),
     }
 }
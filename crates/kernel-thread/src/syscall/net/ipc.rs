@@ -1,14 +1,15 @@
 //! IPC for net-thread
 
+use axerrno::{AxError, AxResult};
 use common::NetRequsetabel;
-use crate_consts::DEFAULT_CUSTOM_SLOT;
+use crate_consts::slots::kernel_thread::NET_EP;
 use memory_addr::PAGE_SIZE_4K;
 use sel4::{init_thread, with_ipc_buffer_mut, Cap, CapRights, VmAttributes};
 
-use crate::{page_seat_vaddr, OBJ_ALLOCATOR};
+use crate::{page_seat, PageSeat, OBJ_ALLOCATOR};
 
 fn send_net_ipc(label: NetRequsetabel, cap: Option<Cap<sel4::cap_type::SmallPage>>) {
-    let ipc_ep = Cap::<sel4::cap_type::Endpoint>::from_bits(DEFAULT_CUSTOM_SLOT + 2);
+    let ipc_ep = Cap::<sel4::cap_type::Endpoint>::from_bits(NET_EP);
     with_ipc_buffer_mut(|buffer| {
         if let Some(cap) = cap {
             buffer.caps_or_badges_mut()[0] = cap.bits() as _;
@@ -18,14 +19,20 @@ fn send_net_ipc(label: NetRequsetabel, cap: Option<Cap<sel4::cap_type::SmallPage
 }
 
 /// Generate capabilities to access item in other task
-fn gen_cap<T: Sized + Copy>(item: *const T, num: Option<usize>) -> Cap<sel4::cap_type::SmallPage> {
+///
+/// The frame stays mapped at the page seat until [release_cap].
+fn gen_cap<T: Sized + Copy>(
+    item: *const T,
+    num: Option<usize>,
+) -> (Cap<sel4::cap_type::SmallPage>, PageSeat) {
     let new_cap = OBJ_ALLOCATOR
         .lock()
         .allocate_and_retyped_fixed_sized::<sel4::cap_type::Granule>();
+    let seat = page_seat();
     new_cap
         .frame_map(
             init_thread::slot::VSPACE.cap(),
-            page_seat_vaddr(),
+            seat.vaddr(),
             CapRights::all(),
             VmAttributes::default(),
         )
@@ -38,42 +45,64 @@ fn gen_cap<T: Sized + Copy>(item: *const T, num: Option<usize>) -> Cap<sel4::cap
     unsafe {
         core::ptr::copy_nonoverlapping(
             item as *const T,
-            page_seat_vaddr() as *mut T,
+            seat.vaddr() as *mut T,
             core::mem::size_of::<T>() * copy_num,
         );
     }
-    new_cap
+    (new_cap, seat)
+}
+
+/// Release the capability generated by [gen_cap] after the IPC finished,
+/// and then the page seat.
+fn release_cap(cap: Cap<sel4::cap_type::SmallPage>, _seat: PageSeat) {
+    cap.frame_unmap().unwrap();
+    init_thread::slot::CNODE.cap().relative(cap).delete().unwrap();
+}
+
+/// Decode the result of an operation without return value.
+///
+/// An unknown error code is reported as [AxError::Io].
+fn handle_axresult(val: u64) -> AxResult<usize> {
+    let val_i32 = val as i32;
+    match val_i32 {
+        0 => Ok(val as usize),
+        _ => Err(val_i32.try_into().unwrap_or(AxError::Io)),
+    }
+}
+
+/// Decode the result of an operation which returns a length.
+///
+/// The net-thread encodes the error as the negative error code, an unknown
+/// one is reported as [AxError::Io].
+fn handle_lenresult(val: u64) -> AxResult<usize> {
+    let val_i64 = val as i64;
+    if val_i64 < 0 {
+        Err((-val_i64 as i32).try_into().unwrap_or(AxError::Io))
+    } else {
+        Ok(val as usize)
+    }
 }
 
 pub(crate) type TCPSocketId = u64;
 
+/// The sockets of the net-thread.
+///
+/// The data of a send or receive is passed in one page, a longer buffer is
+/// cut to a page and the short count is returned.
 #[allow(unused)]
 pub(crate) mod tcp {
     use core::net::SocketAddr;
 
-    use axerrno::AxResult;
+    use common::{socket_addr_from_regs, socket_addr_to_regs, NetSockOpt};
     use sel4::{debug_println, with_ipc_buffer};
 
     use super::*;
 
-    fn handle_axresult(val: u64) -> AxResult<usize> {
-        let val_i32 = val as i32;
-        match val_i32 {
-            0 => Ok(val as usize),
-            _ => Err(val_i32.try_into().unwrap()),
-        }
-    }
-
     pub(crate) fn new() -> TCPSocketId {
         send_net_ipc(NetRequsetabel::New, None);
         with_ipc_buffer(|buffer| buffer.msg_regs()[0])
     }
 
-    pub(crate) fn is_non_blocking(socket_id: TCPSocketId) -> bool {
-        send_net_ipc(NetRequsetabel::IsNonBlocking(socket_id), None);
-        with_ipc_buffer(|buffer| buffer.msg_regs()[0] != 0)
-    }
-
     pub(crate) fn set_nonblocking(socket_id: TCPSocketId, is_nonblocking: bool) {
         send_net_ipc(
             NetRequsetabel::SetNonBlocking(socket_id, is_nonblocking as u64),
@@ -82,49 +111,89 @@ pub(crate) mod tcp {
     }
 
     pub(crate) fn bind(socket_id: TCPSocketId, local_addr: SocketAddr) -> AxResult {
-        let cap = gen_cap(&local_addr, None);
+        let (cap, seat) = gen_cap(&local_addr, None);
         send_net_ipc(
             NetRequsetabel::Bind(socket_id, &local_addr as *const SocketAddr as u64),
             Some(cap),
         );
+        release_cap(cap, seat);
         with_ipc_buffer(|buffer| handle_axresult(buffer.msg_regs()[0]).map(|_| ()))
     }
 
     pub(crate) fn send(socket_id: TCPSocketId, buf: &[u8]) -> AxResult<usize> {
-        if buf.len() > PAGE_SIZE_4K {
-            panic!("The buffer is not contained in a page.");
-        }
-        let cap = gen_cap(buf.as_ptr(), Some(buf.len()));
+        let buf = &buf[..buf.len().min(PAGE_SIZE_4K)];
+        let (cap, seat) = gen_cap(buf.as_ptr(), Some(buf.len()));
         unsafe {
-            core::ptr::copy_nonoverlapping(buf.as_ptr(), page_seat_vaddr() as *mut u8, buf.len());
+            core::ptr::copy_nonoverlapping(buf.as_ptr(), seat.vaddr() as *mut u8, buf.len());
         }
         send_net_ipc(
             NetRequsetabel::Send(socket_id, buf.as_ptr() as u64, buf.len() as u64),
             Some(cap),
         );
-        with_ipc_buffer(|buffer| handle_axresult(buffer.msg_regs()[0]))
+        release_cap(cap, seat);
+        with_ipc_buffer(|buffer| handle_lenresult(buffer.msg_regs()[0]))
+    }
+
+    pub(crate) fn send_to(
+        socket_id: TCPSocketId,
+        buf: &[u8],
+        remote_addr: SocketAddr,
+    ) -> AxResult<usize> {
+        let buf = &buf[..buf.len().min(PAGE_SIZE_4K)];
+        let (cap, seat) = gen_cap(buf.as_ptr(), Some(buf.len()));
+        send_net_ipc(
+            NetRequsetabel::SendTo(
+                socket_id,
+                buf.as_ptr() as u64,
+                buf.len() as u64,
+                socket_addr_to_regs(remote_addr),
+            ),
+            Some(cap),
+        );
+        release_cap(cap, seat);
+        with_ipc_buffer(|buffer| handle_lenresult(buffer.msg_regs()[0]))
     }
 
     pub(crate) fn recv(socket_id: TCPSocketId, buf: &mut [u8]) -> AxResult<usize> {
-        if buf.len() > PAGE_SIZE_4K {
-            panic!("The buffer is not contained in a page.");
-        }
-        let cap = gen_cap(buf.as_ptr(), Some(buf.len()));
+        let len = buf.len().min(PAGE_SIZE_4K);
+        let buf = &mut buf[..len];
+        let (cap, seat) = gen_cap(buf.as_ptr(), Some(buf.len()));
         send_net_ipc(
             NetRequsetabel::Recv(socket_id, buf.as_ptr() as u64, buf.len() as u64),
             Some(cap),
         );
-        with_ipc_buffer(|buffer| {
-            let len = buffer.msg_regs()[0] as usize;
+        let ans = with_ipc_buffer(|buffer| handle_lenresult(buffer.msg_regs()[0]));
+        if let Ok(len) = ans {
             unsafe {
-                core::ptr::copy_nonoverlapping(
-                    page_seat_vaddr() as *const u8,
-                    buf.as_mut_ptr(),
-                    len,
-                );
+                core::ptr::copy_nonoverlapping(seat.vaddr() as *const u8, buf.as_mut_ptr(), len);
             }
-            handle_axresult(buffer.msg_regs()[0])
-        })
+        }
+        release_cap(cap, seat);
+        ans
+    }
+
+    pub(crate) fn recv_from(
+        socket_id: TCPSocketId,
+        buf: &mut [u8],
+    ) -> AxResult<(usize, SocketAddr)> {
+        let len = buf.len().min(PAGE_SIZE_4K);
+        let buf = &mut buf[..len];
+        let (cap, seat) = gen_cap(buf.as_ptr(), Some(buf.len()));
+        send_net_ipc(
+            NetRequsetabel::RecvFrom(socket_id, buf.as_ptr() as u64, buf.len() as u64),
+            Some(cap),
+        );
+        let ans = with_ipc_buffer(|buffer| {
+            let regs = buffer.msg_regs();
+            handle_lenresult(regs[0]).map(|len| (len, socket_addr_from_regs(&regs[1..])))
+        });
+        if let Ok((len, _)) = ans {
+            unsafe {
+                core::ptr::copy_nonoverlapping(seat.vaddr() as *const u8, buf.as_mut_ptr(), len);
+            }
+        }
+        release_cap(cap, seat);
+        ans
     }
 
     pub(crate) fn recv_timeout(
@@ -132,53 +201,43 @@ pub(crate) mod tcp {
         buf: &mut [u8],
         timeout: u64,
     ) -> AxResult<usize> {
-        if buf.len() > PAGE_SIZE_4K {
-            panic!("The buffer is not contained in a page.");
-        }
-        let cap = gen_cap(buf.as_ptr(), Some(buf.len()));
+        let len = buf.len().min(PAGE_SIZE_4K);
+        let buf = &mut buf[..len];
+        let (cap, seat) = gen_cap(buf.as_ptr(), Some(buf.len()));
         send_net_ipc(
             NetRequsetabel::RecvTimeout(socket_id, buf.as_ptr() as u64, buf.len() as u64, timeout),
             Some(cap),
         );
-        with_ipc_buffer(|buffer| {
-            let len = buffer.msg_regs()[0] as usize;
+        let ans = with_ipc_buffer(|buffer| handle_lenresult(buffer.msg_regs()[0]));
+        if let Ok(len) = ans {
             unsafe {
-                core::ptr::copy_nonoverlapping(
-                    page_seat_vaddr() as *const u8,
-                    buf.as_mut_ptr(),
-                    len,
-                );
+                core::ptr::copy_nonoverlapping(seat.vaddr() as *const u8, buf.as_mut_ptr(), len);
             }
-            handle_axresult(buffer.msg_regs()[0])
-        })
+        }
+        release_cap(cap, seat);
+        ans
     }
 
-    pub(crate) fn listen(socket_id: TCPSocketId) -> AxResult {
-        send_net_ipc(NetRequsetabel::Listen(socket_id), None);
+    /// Listen on the bound address, at most `backlog` connections are pending.
+    pub(crate) fn listen(socket_id: TCPSocketId, backlog: u64) -> AxResult {
+        send_net_ipc(NetRequsetabel::Listen(socket_id, backlog), None);
         with_ipc_buffer(|buffer| handle_axresult(buffer.msg_regs()[0]).map(|_| ()))
     }
 
     pub(crate) fn connect(socket_id: TCPSocketId, remote_addr: SocketAddr) -> AxResult {
-        let cap = gen_cap(&remote_addr, None);
+        let (cap, seat) = gen_cap(&remote_addr, None);
         send_net_ipc(NetRequsetabel::Connect(socket_id, 0), Some(cap));
+        release_cap(cap, seat);
         with_ipc_buffer(|buffer| handle_axresult(buffer.msg_regs()[0]).map(|_| ()))
     }
 
-    pub(crate) fn accept(socket_id: TCPSocketId) -> AxResult<[u64; 5]> {
+    /// Accept a new connection, returns the new socket and the peer address.
+    pub(crate) fn accept(socket_id: TCPSocketId) -> AxResult<(TCPSocketId, SocketAddr)> {
         send_net_ipc(NetRequsetabel::Accept(socket_id), None);
         with_ipc_buffer(|buffer| {
-            if (buffer.msg_regs()[0] as i64) < 0 {
-                let error_code = (buffer.msg_regs()[0] as i32).abs();
-                Err(error_code.try_into().unwrap())
-            } else {
-                Ok([
-                    buffer.msg_regs()[0],
-                    buffer.msg_regs()[1],
-                    buffer.msg_regs()[2],
-                    buffer.msg_regs()[3],
-                    buffer.msg_regs()[4],
-                ])
-            }
+            let regs = buffer.msg_regs();
+            handle_lenresult(regs[0])
+                .map(|id| (id as TCPSocketId, socket_addr_from_regs(&regs[1..])))
         })
     }
 
@@ -191,4 +250,67 @@ pub(crate) mod tcp {
         send_net_ipc(NetRequsetabel::Shutdown(socket_id), None);
         with_ipc_buffer(|buffer| handle_axresult(buffer.msg_regs()[0]).map(|_| ()))
     }
+
+    /// Set the socket option, see [NetSockOpt] for the encoding of `value`.
+    pub(crate) fn set_option(socket_id: TCPSocketId, opt: NetSockOpt, value: u64) -> AxResult {
+        send_net_ipc(NetRequsetabel::SetSockOpt(socket_id, opt as u64, value), None);
+        with_ipc_buffer(|buffer| handle_axresult(buffer.msg_regs()[0]).map(|_| ()))
+    }
+
+    /// Get the socket option, see [NetSockOpt] for the encoding of the value.
+    pub(crate) fn get_option(socket_id: TCPSocketId, opt: NetSockOpt) -> AxResult<u64> {
+        send_net_ipc(NetRequsetabel::GetSockOpt(socket_id, opt as u64), None);
+        with_ipc_buffer(|buffer| handle_lenresult(buffer.msg_regs()[0]).map(|value| value as u64))
+    }
+
+    /// Get the local address of the socket.
+    pub(crate) fn local_addr(socket_id: TCPSocketId) -> AxResult<SocketAddr> {
+        send_net_ipc(NetRequsetabel::GetSockName(socket_id), None);
+        with_ipc_buffer(|buffer| {
+            let regs = buffer.msg_regs();
+            handle_axresult(regs[0]).map(|_| socket_addr_from_regs(&regs[1..]))
+        })
+    }
+
+    /// Get the remote address of the connected socket.
+    pub(crate) fn peer_addr(socket_id: TCPSocketId) -> AxResult<SocketAddr> {
+        send_net_ipc(NetRequsetabel::GetPeerName(socket_id), None);
+        with_ipc_buffer(|buffer| {
+            let regs = buffer.msg_regs();
+            handle_axresult(regs[0]).map(|_| socket_addr_from_regs(&regs[1..]))
+        })
+    }
+
+    /// Query the readiness of the socket, returns the `NET_POLL_*` bits.
+    pub(crate) fn poll(socket_id: TCPSocketId) -> AxResult<u64> {
+        send_net_ipc(NetRequsetabel::Poll(socket_id), None);
+        with_ipc_buffer(|buffer| handle_lenresult(buffer.msg_regs()[0]).map(|bits| bits as u64))
+    }
+}
+
+pub(crate) mod icmp {
+    use sel4::with_ipc_buffer;
+
+    use super::*;
+
+    /// Create an ICMP datagram socket, the same as `SOCK_DGRAM`/`IPPROTO_ICMP`.
+    pub(crate) fn new_dgram() -> TCPSocketId {
+        send_net_ipc(NetRequsetabel::NewIcmp, None);
+        with_ipc_buffer(|buffer| buffer.msg_regs()[0])
+    }
+
+    /// Create a raw IPv4 socket for the given protocol.
+    pub(crate) fn new_raw(protocol: u8) -> TCPSocketId {
+        send_net_ipc(NetRequsetabel::NewRaw(protocol as u64), None);
+        with_ipc_buffer(|buffer| buffer.msg_regs()[0])
+    }
+
+    /// Get the ICMP echo statistics of the net-thread.
+    ///
+    /// The counters are echo requests received, echo replies sent,
+    /// echo requests sent and echo replies received, in order.
+    pub(crate) fn stats() -> [u64; 4] {
+        send_net_ipc(NetRequsetabel::IcmpStats, None);
+        with_ipc_buffer(|buffer| buffer.msg_regs()[..4].try_into().unwrap())
+    }
 }
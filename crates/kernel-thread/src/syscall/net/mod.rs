@@ -1,4 +1,6 @@
-mod ipc;
+pub(crate) mod ipc;
 
 mod net_impl;
+mod unix;
 pub(crate) use net_impl::*;
+pub(crate) use unix::{recv_from, send_to, RecvBuffers, RecvReply, UnixSocket};
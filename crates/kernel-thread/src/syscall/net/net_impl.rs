@@ -1,63 +1,312 @@
-use core::net::{Ipv4Addr, SocketAddr};
+use core::{
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use alloc::vec::Vec;
-use axerrno::AxError;
-use common::LibcSocketAddr;
+use alloc::{format, sync::Arc, vec, vec::Vec};
+use axerrno::{AxError, LinuxError};
+use common::{
+    IoVec, LibcSocketAddr, LibcSocketAddrIn6, MsgHdr, NetSockOpt, TimeVal, AF_INET, AF_INET6,
+    AF_UNIX, NET_POLL_WRITABLE,
+};
+use memory_addr::PAGE_SIZE_4K;
 use syscalls::Errno;
 
 use crate::{
     child_test::TASK_MAP,
-    syscall::SysResult,
-    utils::{read_item, read_item_list, write_item, write_item_list},
+    syscall::{fs::File, wait, Deadline, SysResult},
+    task::Sel4Task,
+    utils::{
+        gather_iovecs, iovecs_len, read_iovecs, read_item, read_item_list, scatter_iovecs,
+        truncate_iovecs, write_item, write_item_list,
+    },
 };
 
-use super::ipc::tcp;
+use super::ipc::{icmp, tcp, TCPSocketId};
+use super::unix::{self, unix_socket_of, RecvBuffers, RecvReply, UnixType};
+
+const SOCK_STREAM: usize = 1;
+const SOCK_DGRAM: usize = 2;
+const SOCK_RAW: usize = 3;
+const SOCK_TYPE_MASK: usize = 0xf;
+const SOCK_NONBLOCK: usize = 0o4000;
+const IPPROTO_ICMP: usize = 1;
+
+pub(super) const SOL_SOCKET: i32 = 1;
+const IPPROTO_TCP: i32 = 6;
+const SO_REUSEADDR: i32 = 2;
+const SO_ERROR: i32 = 4;
+const SO_SNDBUF: i32 = 7;
+const SO_RCVBUF: i32 = 8;
+const SO_KEEPALIVE: i32 = 9;
+const SO_REUSEPORT: i32 = 15;
+const SO_RCVTIMEO: i32 = 20;
+const SO_SNDTIMEO: i32 = 21;
+const TCP_NODELAY: i32 = 1;
+
+/// The most bytes of one send or receive on a net-thread socket, the data is
+/// passed to the net-thread in one page.
+const NET_IO_MAX: usize = PAGE_SIZE_4K;
+
+/// A socket in the net-thread opened by the tasks, with the `O_NONBLOCK` flag
+/// of its fds.
+///
+/// The socket is made nonblocking in the net-thread once when it is opened,
+/// since a blocking call would stall the net-thread and the kernel thread.
+/// The syscalls which would block are parked by [block_on_socket] instead.
+pub(crate) struct NetSocket {
+    pub(crate) id: TCPSocketId,
+    nonblock: AtomicBool,
+}
+
+impl NetSocket {
+    fn new(id: TCPSocketId, nonblock: bool) -> Self {
+        tcp::set_nonblocking(id, true);
+        Self {
+            id,
+            nonblock: AtomicBool::new(nonblock),
+        }
+    }
+
+    pub(crate) fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+}
+
+impl Drop for NetSocket {
+    fn drop(&mut self) {
+        // Nothing can be done if the net-thread fails to close it.
+        let _ = tcp::close(self.id);
+    }
+}
+
+/// Translate the fd of the `task` into the id of the socket in the net-thread
+/// and its `O_NONBLOCK` flag.
+fn socket_in(task: &Sel4Task, socket_fd: i32) -> Result<(TCPSocketId, bool), Errno> {
+    let file_table = task.file_table.lock();
+    let socket = file_table.socket(socket_fd)?;
+    Ok((socket.id, socket.is_nonblocking()))
+}
+
+/// Translate the fd of the task `badge` into the id of the socket in the
+/// net-thread and its `O_NONBLOCK` flag.
+fn socket_of(badge: u64, socket_fd: i32) -> Result<(TCPSocketId, bool), Errno> {
+    let task_map = TASK_MAP.lock();
+    socket_in(task_map.get(&badge).unwrap(), socket_fd)
+}
+
+/// Convert the error returned by the net-thread into [Errno].
+fn ax_to_errno(err: AxError) -> Errno {
+    Errno::new(LinuxError::from(err).code())
+}
+
+/// Read a `sockaddr_in` or `sockaddr_in6` from the user space by its family.
+fn read_sockaddr(
+    task: &Sel4Task,
+    addr: *const LibcSocketAddr,
+    addr_len: usize,
+) -> Result<SocketAddr, Errno> {
+    let generic = read_item(task, addr)?;
+    match generic.sa_family {
+        AF_INET => Ok(SocketAddrV4::from(generic).into()),
+        AF_INET6 => {
+            if addr_len < core::mem::size_of::<LibcSocketAddrIn6>() {
+                return Err(Errno::EINVAL);
+            }
+            let addr = read_item(task, addr as *const LibcSocketAddrIn6)?;
+            Ok(SocketAddrV6::from(addr).into())
+        }
+        _ => Err(Errno::EAFNOSUPPORT),
+    }
+}
+
+/// Call `f` on the net-thread socket until it does not fail with
+/// [Errno::EAGAIN], the syscall is parked between the calls unless
+/// `nonblock`.
+///
+/// The net-thread signals the kernel thread when sockets become ready. The
+/// timeout is read from the socket option `timeout` if any.
+///
+/// It must not be called with the lock of `TASK_MAP` held.
+fn block_on_socket(
+    badge: u64,
+    socket_id: TCPSocketId,
+    nonblock: bool,
+    timeout: Option<NetSockOpt>,
+    mut f: impl FnMut() -> SysResult + Send + 'static,
+) -> SysResult {
+    match f() {
+        Err(Errno::EAGAIN) if !nonblock => {}
+        res => return res,
+    }
+    let micros = match timeout {
+        Some(opt) => tcp::get_option(socket_id, opt).map_err(ax_to_errno)?,
+        None => 0,
+    };
+    let mut deadline = match micros {
+        0 => Deadline::never(),
+        micros => Deadline::after(micros.saturating_mul(1000)),
+    };
+    wait::park(badge, move || match f() {
+        Err(Errno::EAGAIN) if !deadline.expired() => None,
+        res => Some(res),
+    })
+}
+
+/// Map the `level` and `optname` of a socket option to [NetSockOpt].
+fn sockopt_of(level: i32, optname: i32) -> Result<NetSockOpt, Errno> {
+    match (level, optname) {
+        (SOL_SOCKET, SO_REUSEADDR) => Ok(NetSockOpt::ReuseAddr),
+        (SOL_SOCKET, SO_ERROR) => Ok(NetSockOpt::Error),
+        (SOL_SOCKET, SO_SNDBUF) => Ok(NetSockOpt::SendBuf),
+        (SOL_SOCKET, SO_RCVBUF) => Ok(NetSockOpt::RecvBuf),
+        (SOL_SOCKET, SO_KEEPALIVE) => Ok(NetSockOpt::KeepAlive),
+        (SOL_SOCKET, SO_REUSEPORT) => Ok(NetSockOpt::ReusePort),
+        (SOL_SOCKET, SO_RCVTIMEO) => Ok(NetSockOpt::RecvTimeout),
+        (SOL_SOCKET, SO_SNDTIMEO) => Ok(NetSockOpt::SendTimeout),
+        (IPPROTO_TCP, TCP_NODELAY) => Ok(NetSockOpt::NoDelay),
+        _ => Err(Errno::ENOPROTOOPT),
+    }
+}
 
-pub fn sys_socket(_badge: u64, _domain: usize, _type: usize, _protocol: usize) -> SysResult {
-    Ok(tcp::new() as usize)
+/// Whether the value of the socket option is a `struct timeval`, otherwise an `int`.
+fn is_timeval_opt(opt: NetSockOpt) -> bool {
+    matches!(opt, NetSockOpt::RecvTimeout | NetSockOpt::SendTimeout)
+}
+
+/// Write a [SocketAddr] to the user space as `sockaddr_in` or `sockaddr_in6`.
+fn write_sockaddr(task: &Sel4Task, addr: *mut LibcSocketAddr, sock_addr: SocketAddr) -> SysResult {
+    match sock_addr {
+        SocketAddr::V4(v4) => write_item(task, addr, &v4.into()),
+        SocketAddr::V6(v6) => write_item(task, addr as *mut LibcSocketAddrIn6, &v6.into()),
+    }
+}
+
+/// Map the type of an `AF_UNIX` socket to [UnixType].
+fn unix_type_of(type_: usize, protocol: usize) -> Result<UnixType, Errno> {
+    match (type_ & SOCK_TYPE_MASK, protocol) {
+        (SOCK_STREAM, 0) => Ok(UnixType::Stream),
+        (SOCK_DGRAM, 0) => Ok(UnixType::Dgram),
+        (SOCK_STREAM | SOCK_DGRAM, _) => Err(Errno::EPROTONOSUPPORT),
+        _ => Err(Errno::ESOCKTNOSUPPORT),
+    }
+}
+
+/// The ICMP echo counters of the net-thread in the format of
+/// `/proc/net/snmp`, only the echo columns of the `Icmp` lines are given.
+pub(crate) fn icmp_snmp() -> Vec<u8> {
+    let [in_echos, out_echo_reps, out_echos, in_echo_reps] = icmp::stats();
+    format!(
+        "Icmp: InEchos InEchoReps OutEchos OutEchoReps\n\
+         Icmp: {in_echos} {in_echo_reps} {out_echos} {out_echo_reps}\n"
+    )
+    .into_bytes()
+}
+
+pub fn sys_socket(badge: u64, domain: usize, type_: usize, protocol: usize) -> SysResult {
+    if domain as u16 == AF_UNIX {
+        let ty = unix_type_of(type_, protocol)?;
+        return unix::sys_socket(badge, ty, type_ & SOCK_NONBLOCK != 0);
+    }
+    let socket_id = match (domain as u16, type_ & SOCK_TYPE_MASK, protocol) {
+        (AF_INET | AF_INET6, SOCK_STREAM, _) => tcp::new(),
+        (AF_INET, SOCK_DGRAM, IPPROTO_ICMP) => icmp::new_dgram(),
+        (AF_INET, SOCK_RAW, protocol) if protocol <= u8::MAX as usize => {
+            icmp::new_raw(protocol as u8)
+        }
+        (AF_INET, SOCK_RAW, _) => return Err(Errno::EINVAL),
+        (AF_INET | AF_INET6, _, _) => return Err(Errno::EPROTONOSUPPORT),
+        _ => return Err(Errno::EAFNOSUPPORT),
+    };
+    let socket = NetSocket::new(socket_id, type_ & SOCK_NONBLOCK != 0);
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let fd = task
+        .file_table
+        .lock()
+        .insert(Arc::new(File::Socket(socket)))?;
+    Ok(fd as usize)
+}
+
+pub fn sys_socketpair(
+    badge: u64,
+    domain: usize,
+    type_: usize,
+    protocol: usize,
+    sv: *mut i32,
+) -> SysResult {
+    if domain as u16 != AF_UNIX {
+        return Err(Errno::EOPNOTSUPP);
+    }
+    let ty = unix_type_of(type_, protocol)?;
+    unix::sys_socketpair(badge, ty, type_ & SOCK_NONBLOCK != 0, sv)
 }
 
 pub fn sys_bind(
     badge: u64,
     socket_fd: i32,
     addr: *const LibcSocketAddr,
-    _addr_len: u32,
+    addr_len: u32,
 ) -> SysResult {
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        return unix::sys_bind(badge, &socket, addr as _, addr_len as _);
+    }
     let task_map = TASK_MAP.lock();
     let task = task_map.get(&badge).unwrap();
-    let addr = read_item(task, addr)?;
-    let socket_id = socket_fd as u64;
-    let local_addr: SocketAddr = addr.into();
-    match tcp::bind(socket_id, local_addr) {
-        Ok(()) => Ok(0),
-        Err(AxError::InvalidInput) => Err(Errno::EINVAL),
-        Err(_) => panic!("Unknown Error!"),
-    }
+    let local_addr = read_sockaddr(task, addr, addr_len as usize)?;
+    let socket_id = task.file_table.lock().socket(socket_fd)?.id;
+    drop(task_map);
+    tcp::bind(socket_id, local_addr).map_err(ax_to_errno)?;
+    Ok(0)
 }
 
 pub fn sys_connect(
     badge: u64,
     socket_fd: i32,
     addr: *const LibcSocketAddr,
-    _addr_len: u32,
+    addr_len: u32,
 ) -> SysResult {
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        return unix::sys_connect(badge, socket, addr as _, addr_len as _);
+    }
     let task_map = TASK_MAP.lock();
     let task = task_map.get(&badge).unwrap();
-    let addr = read_item(task, addr)?;
-    let socket_id = socket_fd as u64;
-    let remote_addr: SocketAddr = addr.into();
+    let remote_addr = read_sockaddr(task, addr, addr_len as usize)?;
+    let (socket_id, nonblock) = socket_in(task, socket_fd)?;
+    drop(task_map);
     match tcp::connect(socket_id, remote_addr) {
-        Ok(()) => Ok(0),
-        Err(AxError::InvalidInput) | Err(AxError::AddrInUse) => Err(Errno::EINVAL),
-        Err(_) => panic!("Unknown Error!"),
+        Ok(()) => return Ok(0),
+        Err(AxError::WouldBlock) if nonblock => return Err(Errno::EINPROGRESS),
+        Err(AxError::WouldBlock) => {}
+        Err(err) => return Err(ax_to_errno(err)),
     }
+    // Wait for the handshake, the socket becomes writable when it is done and
+    // a failure is kept as the error of the socket.
+    block_on_socket(badge, socket_id, false, None, move || {
+        if tcp::poll(socket_id).map_err(ax_to_errno)? & NET_POLL_WRITABLE == 0 {
+            return Err(Errno::EAGAIN);
+        }
+        match tcp::get_option(socket_id, NetSockOpt::Error).map_err(ax_to_errno)? {
+            0 => Ok(0),
+            code => Err(AxError::try_from(code as i32).map_or(Errno::EIO, ax_to_errno)),
+        }
+    })
 }
 
-pub fn sys_listen(_badge: u64, socket_fd: i32) -> SysResult {
-    let socket_id = socket_fd as u64;
-    match tcp::listen(socket_id) {
+pub fn sys_listen(badge: u64, socket_fd: i32, backlog: i32) -> SysResult {
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        return unix::sys_listen(&socket, backlog);
+    }
+    let (socket_id, _) = socket_of(badge, socket_fd)?;
+    // A negative backlog is taken as the maximum like linux, the net-thread clamps it.
+    match tcp::listen(socket_id, backlog as u32 as u64) {
         Ok(()) => Ok(0),
-        Err(AxError::InvalidInput) | Err(AxError::AddrInUse) => Err(Errno::EINVAL),
+        Err(AxError::InvalidInput) => Err(Errno::EINVAL),
+        Err(AxError::AddrInUse) => Err(Errno::EADDRINUSE),
         Err(_) => panic!("Unknown Error!"),
     }
 }
@@ -66,40 +315,35 @@ pub fn sys_accept(
     badge: u64,
     socket_fd: i32,
     addr: *mut LibcSocketAddr,
-    _addr_len: u32,
+    addr_len: *mut u32,
 ) -> SysResult {
-    let task_map = TASK_MAP.lock();
-    let task = task_map.get(&badge).unwrap();
-    fn parse_ipaddr(is_ipv4: bool, addr_low: u64, addr_high: u64, port: u16) -> SocketAddr {
-        if is_ipv4 {
-            let addr = addr_low.to_be_bytes();
-            SocketAddr::new(
-                core::net::IpAddr::V4(Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3])),
-                port,
-            )
-        } else {
-            let addr = (addr_high as u128) << 32 | addr_low as u128;
-            let addr = addr.to_be_bytes();
-            SocketAddr::new(core::net::IpAddr::V6(addr.into()), port)
-        }
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        return unix::sys_accept(badge, socket, addr as _, addr_len);
     }
-    let socket_id = socket_fd as u64;
-    match tcp::accept(socket_id) {
-        Ok(ans) => {
-            let socket_id = ans[0] as usize;
-            let is_ipv4 = ans[1] != 0;
-            let port = ans[2] as u16;
-            let socket_addr = parse_ipaddr(is_ipv4, ans[3], ans[4], port);
-            write_item(task, addr, &socket_addr.into());
-            Ok(socket_id)
+    let (socket_id, nonblock) = socket_of(badge, socket_fd)?;
+    let addr = addr as usize;
+    block_on_socket(badge, socket_id, nonblock, None, move || {
+        let (new_socket_id, peer_addr) = tcp::accept(socket_id).map_err(ax_to_errno)?;
+        // The new socket does not inherit `O_NONBLOCK`, the same as linux.
+        let new_socket = NetSocket::new(new_socket_id, false);
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        let fd = task
+            .file_table
+            .lock()
+            .insert(Arc::new(File::Socket(new_socket)))?;
+        if addr != 0 {
+            write_sockaddr(task, addr as *mut LibcSocketAddr, peer_addr)?;
         }
-        Err(AxError::InvalidInput) | Err(AxError::AddrInUse) => Err(Errno::EINVAL),
-        Err(_) => panic!("Unknown Error!"),
-    }
+        Ok(fd as usize)
+    })
 }
 
-pub fn sys_shutdown(_badge: u64, socket_fd: i32, _how: i32) -> SysResult {
-    let socket_id = socket_fd as u64;
+pub fn sys_shutdown(badge: u64, socket_fd: i32, how: i32) -> SysResult {
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        return unix::sys_shutdown(&socket, how);
+    }
+    let (socket_id, _) = socket_of(badge, socket_fd)?;
     match tcp::shutdown(socket_id) {
         Ok(()) => Ok(0),
         Err(AxError::InvalidInput) | Err(AxError::AddrInUse) => Err(Errno::EINVAL),
@@ -112,22 +356,59 @@ pub fn sys_sendto(
     socket_fd: i32,
     buf: *const u8,
     len: usize,
-    _flags: i32,
-    _addr: *const LibcSocketAddr,
-    _addr_len: usize,
+    flags: i32,
+    addr: *const LibcSocketAddr,
+    addr_len: usize,
 ) -> SysResult {
-    let task_map = TASK_MAP.lock();
-    let task = task_map.get(&badge).unwrap();
-    let socket_id = socket_fd as u64;
-    let _remote_addr: SocketAddr = read_item(task, _addr)?.into();
-    // TODO: copy the capabilities of the user thread and transmit it directly
-    let mut payload = Vec::with_capacity(len);
-    read_item_list(task, buf, Some(len), payload.as_mut_slice());
-    match tcp::send(socket_id, payload.as_slice()) {
-        Ok(len) => Ok(len),
-        Err(AxError::InvalidInput) | Err(AxError::AddrInUse) => Err(Errno::EINVAL),
-        Err(_) => panic!("Unknown Error!"),
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        let (data, to) = {
+            let task_map = TASK_MAP.lock();
+            let task = task_map.get(&badge).unwrap();
+            let len = socket.send_len(len)?;
+            let mut data = vec![0u8; len];
+            read_item_list(task, buf, Some(len), &mut data)?;
+            let to = if addr.is_null() {
+                None
+            } else {
+                Some(unix::read_unix_addr(task, addr as _, addr_len)?)
+            };
+            (data, to)
+        };
+        return unix::send_to(badge, socket, data, vec![], to, flags);
     }
+    let (socket_id, nonblock, payload, remote_addr) = {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        let (socket_id, nonblock) = socket_in(task, socket_fd)?;
+        // TODO: copy the capabilities of the user thread and transmit it directly
+        let len = len.min(NET_IO_MAX);
+        let mut payload = vec![0u8; len];
+        read_item_list(task, buf, Some(len), payload.as_mut_slice())?;
+        let remote_addr = match addr.is_null() {
+            true => None,
+            false => Some(read_sockaddr(task, addr, addr_len)?),
+        };
+        (socket_id, nonblock, payload, remote_addr)
+    };
+    send_socket(badge, socket_id, nonblock, payload, remote_addr)
+}
+
+/// Send `payload` on the net-thread socket, to `remote_addr` if given.
+fn send_socket(
+    badge: u64,
+    socket_id: TCPSocketId,
+    nonblock: bool,
+    payload: Vec<u8>,
+    remote_addr: Option<SocketAddr>,
+) -> SysResult {
+    let timeout = Some(NetSockOpt::SendTimeout);
+    block_on_socket(badge, socket_id, nonblock, timeout, move || {
+        match remote_addr {
+            Some(remote_addr) => tcp::send_to(socket_id, payload.as_slice(), remote_addr),
+            None => tcp::send(socket_id, payload.as_slice()),
+        }
+        .map_err(ax_to_errno)
+    })
 }
 
 pub fn sys_recvfrom(
@@ -135,17 +416,279 @@ pub fn sys_recvfrom(
     socket_fd: i32,
     buf: *mut u8,
     len: usize,
-    _flags: i32,
-    _addr: *const LibcSocketAddr,
-    _addr_len: usize,
+    flags: i32,
+    addr: *mut LibcSocketAddr,
+    addr_len: *mut u32,
+) -> SysResult {
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        let name_len = if addr.is_null() || addr_len.is_null() {
+            0
+        } else {
+            let task_map = TASK_MAP.lock();
+            read_item(task_map.get(&badge).unwrap(), addr_len)? as usize
+        };
+        let bufs = RecvBuffers {
+            iovs: vec![IoVec {
+                iov_base: buf as usize,
+                iov_len: len,
+            }],
+            name: addr as usize,
+            name_len,
+            control: 0,
+            control_len: 0,
+            reply: RecvReply::AddrLen(addr_len as usize),
+        };
+        return unix::recv_from(badge, socket, bufs, flags);
+    }
+    let (socket_id, nonblock) = socket_of(badge, socket_fd)?;
+    let (buf, addr) = (buf as usize, addr as usize);
+    let mut recv_buf = vec![0u8; len.min(NET_IO_MAX)];
+    let timeout = Some(NetSockOpt::RecvTimeout);
+    block_on_socket(badge, socket_id, nonblock, timeout, move || {
+        let (len, remote_addr) =
+            tcp::recv_from(socket_id, recv_buf.as_mut_slice()).map_err(ax_to_errno)?;
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        write_item_list(task, buf as *mut u8, Some(len), &recv_buf[..len])?;
+        if addr != 0 {
+            write_sockaddr(task, addr as *mut LibcSocketAddr, remote_addr)?;
+        }
+        Ok(len)
+    })
+}
+
+pub fn sys_setsockopt(
+    badge: u64,
+    socket_fd: i32,
+    level: i32,
+    optname: i32,
+    optval: *const u8,
+    optlen: u32,
 ) -> SysResult {
+    let opt = sockopt_of(level, optname)?;
+    if opt == NetSockOpt::Error {
+        // `SO_ERROR` is read-only.
+        return Err(Errno::ENOPROTOOPT);
+    }
+    if unix_socket_of(badge, socket_fd)?.is_some() {
+        // The options of unix sockets are accepted but take no effect for now.
+        return Ok(0);
+    }
     let task_map = TASK_MAP.lock();
     let task = task_map.get(&badge).unwrap();
-    let socket_id = socket_fd as u64;
-    let mut recv_buf = Vec::with_capacity(len);
-    match tcp::recv(socket_id, recv_buf.as_mut_slice()) {
-        Ok(len) => write_item_list(task, buf, Some(len), recv_buf.as_slice()),
-        Err(AxError::InvalidInput) | Err(AxError::AddrInUse) => Err(Errno::EINVAL),
-        Err(_) => panic!("Unknown Error!"),
+    let value = if is_timeval_opt(opt) {
+        if (optlen as usize) < core::mem::size_of::<TimeVal>() {
+            return Err(Errno::EINVAL);
+        }
+        let tv = read_item(task, optval as *const TimeVal)?;
+        if !tv.is_valid() {
+            return Err(Errno::EDOM);
+        }
+        tv.as_micros()
+    } else {
+        if (optlen as usize) < core::mem::size_of::<i32>() {
+            return Err(Errno::EINVAL);
+        }
+        let val = read_item(task, optval as *const i32)?;
+        match opt {
+            NetSockOpt::RecvBuf | NetSockOpt::SendBuf => val.max(0) as u64,
+            _ => (val != 0) as u64,
+        }
+    };
+    let socket_id = task.file_table.lock().socket(socket_fd)?.id;
+    drop(task_map);
+    tcp::set_option(socket_id, opt, value).map_err(ax_to_errno)?;
+    Ok(0)
+}
+
+pub fn sys_getsockopt(
+    badge: u64,
+    socket_fd: i32,
+    level: i32,
+    optname: i32,
+    optval: *mut u8,
+    optlen: *mut u32,
+) -> SysResult {
+    let opt = sockopt_of(level, optname)?;
+    let is_unix = unix_socket_of(badge, socket_fd)?.is_some();
+    let (len, socket_id) = {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        let len = read_item(task, optlen)? as usize;
+        let socket_id = match is_unix {
+            false => Some(task.file_table.lock().socket(socket_fd)?.id),
+            true => None,
+        };
+        (len, socket_id)
+    };
+    let value = if let Some(socket_id) = socket_id {
+        tcp::get_option(socket_id, opt).map_err(ax_to_errno)?
+    } else if opt == NetSockOpt::Error {
+        // A unix socket reports its errors by the syscalls directly.
+        0
+    } else {
+        return Err(Errno::ENOPROTOOPT);
+    };
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let written = if is_timeval_opt(opt) {
+        if len < core::mem::size_of::<TimeVal>() {
+            return Err(Errno::EINVAL);
+        }
+        write_item(task, optval as *mut TimeVal, &TimeVal::from_micros(value))?
+    } else {
+        if len < core::mem::size_of::<i32>() {
+            return Err(Errno::EINVAL);
+        }
+        let value = match opt {
+            // The net-thread reports the error as the code of `AxError`.
+            NetSockOpt::Error if value != 0 => AxError::try_from(value as i32)
+                .map_or(Errno::EIO, ax_to_errno)
+                .into_raw(),
+            _ => value as i32,
+        };
+        write_item(task, optval as *mut i32, &value)?
+    };
+    write_item(task, optlen, &(written as u32))?;
+    Ok(0)
+}
+
+/// Write the address of the socket and its length to the user space.
+fn write_sockname(
+    task: &Sel4Task,
+    addr: *mut LibcSocketAddr,
+    addr_len: *mut u32,
+    sock_addr: SocketAddr,
+) -> SysResult {
+    if addr.is_null() || addr_len.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    let len = read_item(task, addr_len)? as usize;
+    let needed = match sock_addr {
+        SocketAddr::V4(_) => core::mem::size_of::<LibcSocketAddr>(),
+        SocketAddr::V6(_) => core::mem::size_of::<LibcSocketAddrIn6>(),
+    };
+    if len < needed {
+        return Err(Errno::EINVAL);
     }
+    write_sockaddr(task, addr, sock_addr)?;
+    write_item(task, addr_len, &(needed as u32))?;
+    Ok(0)
+}
+
+pub fn sys_getsockname(
+    badge: u64,
+    socket_fd: i32,
+    addr: *mut LibcSocketAddr,
+    addr_len: *mut u32,
+) -> SysResult {
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        return unix::sys_getsockname(badge, &socket, addr as _, addr_len);
+    }
+    let socket_id = socket_of(badge, socket_fd)?.0;
+    let local_addr = tcp::local_addr(socket_id).map_err(ax_to_errno)?;
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_sockname(task, addr, addr_len, local_addr)
+}
+
+pub fn sys_getpeername(
+    badge: u64,
+    socket_fd: i32,
+    addr: *mut LibcSocketAddr,
+    addr_len: *mut u32,
+) -> SysResult {
+    if let Some(socket) = unix_socket_of(badge, socket_fd)? {
+        return unix::sys_getpeername(badge, &socket, addr as _, addr_len);
+    }
+    let socket_id = socket_of(badge, socket_fd)?.0;
+    let peer_addr = tcp::peer_addr(socket_id).map_err(ax_to_errno)?;
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_sockname(task, addr, addr_len, peer_addr)
+}
+
+pub fn sys_sendmsg(badge: u64, socket_fd: i32, msg: *const MsgHdr, flags: i32) -> SysResult {
+    let unix_socket = unix_socket_of(badge, socket_fd)?;
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let hdr = read_item(task, msg)?;
+    let iovs = read_iovecs(task, hdr.msg_iov as *const IoVec, hdr.msg_iovlen)?;
+    let has_name = hdr.msg_name != 0 && hdr.msg_namelen != 0;
+    if let Some(socket) = unix_socket {
+        let to = if has_name {
+            Some(unix::read_unix_addr(
+                task,
+                hdr.msg_name as _,
+                hdr.msg_namelen as _,
+            )?)
+        } else {
+            None
+        };
+        let len = socket.send_len(iovecs_len(&iovs)?)?;
+        let data = gather_iovecs(task, &truncate_iovecs(&iovs, len))?;
+        let files = unix::read_rights(task, hdr.msg_control, hdr.msg_controllen)?;
+        drop(task_map);
+        return unix::send_to(badge, socket, data, files, to, flags);
+    }
+    // The ancillary messages of the net-thread sockets are ignored.
+    let (socket_id, nonblock) = socket_in(task, socket_fd)?;
+    let data = gather_iovecs(task, &truncate_iovecs(&iovs, NET_IO_MAX))?;
+    let remote_addr = match has_name {
+        true => Some(read_sockaddr(
+            task,
+            hdr.msg_name as *const LibcSocketAddr,
+            hdr.msg_namelen as _,
+        )?),
+        false => None,
+    };
+    drop(task_map);
+    send_socket(badge, socket_id, nonblock, data, remote_addr)
+}
+
+pub fn sys_recvmsg(badge: u64, socket_fd: i32, msg: *mut MsgHdr, flags: i32) -> SysResult {
+    let unix_socket = unix_socket_of(badge, socket_fd)?;
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let mut hdr = read_item(task, msg)?;
+    let iovs = read_iovecs(task, hdr.msg_iov as *const IoVec, hdr.msg_iovlen)?;
+    if let Some(socket) = unix_socket {
+        drop(task_map);
+        let bufs = RecvBuffers {
+            iovs,
+            name: hdr.msg_name,
+            name_len: hdr.msg_namelen as _,
+            control: hdr.msg_control,
+            control_len: hdr.msg_controllen,
+            reply: RecvReply::MsgHdr(msg as usize, hdr),
+        };
+        return unix::recv_from(badge, socket, bufs, flags);
+    }
+    let (socket_id, nonblock) = socket_in(task, socket_fd)?;
+    drop(task_map);
+    let msg = msg as usize;
+    let iovs = truncate_iovecs(&iovs, NET_IO_MAX);
+    let mut recv_buf = vec![0u8; iovs.iter().map(|iov| iov.iov_len).sum()];
+    let timeout = Some(NetSockOpt::RecvTimeout);
+    block_on_socket(badge, socket_id, nonblock, timeout, move || {
+        let (len, remote_addr) =
+            tcp::recv_from(socket_id, recv_buf.as_mut_slice()).map_err(ax_to_errno)?;
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        scatter_iovecs(task, &iovs, &recv_buf[..len])?;
+        if hdr.msg_name != 0 {
+            let needed = match remote_addr {
+                SocketAddr::V4(_) => core::mem::size_of::<LibcSocketAddr>(),
+                SocketAddr::V6(_) => core::mem::size_of::<LibcSocketAddrIn6>(),
+            };
+            if hdr.msg_namelen as usize >= needed {
+                write_sockaddr(task, hdr.msg_name as *mut LibcSocketAddr, remote_addr)?;
+            }
+            hdr.msg_namelen = needed as u32;
+        }
+        hdr.msg_controllen = 0;
+        hdr.msg_flags = 0;
+        write_item(task, msg as *mut MsgHdr, &hdr)?;
+        Ok(len)
+    })
 }
@@ -0,0 +1,946 @@
+//! Unix domain sockets, handled by the kernel thread without the net-thread.
+//!
+//! A socket is named by a path in the [vfs] or by an abstract name starting
+//! with a NUL byte. A stream socket is connected to exactly one peer, and the
+//! data sent is queued in the peer until it is received. The files passed by
+//! `SCM_RIGHTS` travel with the data and are installed into the file table of
+//! the receiver. The syscalls which would block are parked by [wait::park].
+
+use alloc::{
+    collections::{btree_map::BTreeMap, VecDeque},
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use common::{CmsgHdr, IoVec, LibcSocketAddrUn, MsgHdr, AF_UNIX};
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use spin::Mutex;
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{
+        fs::{file_of, vfs, File, PollEvents},
+        wait, SysResult,
+    },
+    task::Sel4Task,
+    utils::{iovecs_len, read_item, read_item_list, scatter_iovecs, write_item, write_item_list},
+};
+
+use super::net_impl::SOL_SOCKET;
+
+/// The bytes can be queued in a socket before the senders block.
+const UNIX_BUF_SIZE: usize = 0x10000;
+/// The maximum backlog of a listening socket.
+const UNIX_MAX_BACKLOG: usize = 128;
+/// The maximum number of files passed in a message, the same as `SCM_MAX_FD` in linux.
+const SCM_MAX_FD: usize = 253;
+/// The maximum length of the control buffer, the same as the default `optmem_max` of linux.
+const CONTROL_MAX: usize = 20480;
+/// The offset of `sun_path` in `sockaddr_un`.
+const SUN_PATH_OFFSET: usize = 2;
+
+const SCM_RIGHTS: i32 = 1;
+
+const MSG_PEEK: i32 = 0x2;
+const MSG_CTRUNC: i32 = 0x8;
+const MSG_TRUNC: i32 = 0x20;
+const MSG_DONTWAIT: i32 = 0x40;
+
+const SHUT_RD: i32 = 0;
+const SHUT_WR: i32 = 1;
+const SHUT_RDWR: i32 = 2;
+
+/// The sockets bound to abstract names.
+static ABSTRACT_NAMES: Mutex<BTreeMap<Vec<u8>, Weak<UnixSocket>>> = Mutex::new(BTreeMap::new());
+
+/// The next abstract name assigned by autobind.
+static AUTOBIND_NEXT: AtomicU32 = AtomicU32::new(0);
+
+/// The type of a unix socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnixType {
+    Stream,
+    Dgram,
+}
+
+/// The address of a unix socket.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) enum UnixAddr {
+    #[default]
+    Unnamed,
+    /// The path given by the user, normalized when it is looked up.
+    Path(String),
+    Abstract(Vec<u8>),
+}
+
+impl UnixAddr {
+    /// Parse the bytes of a `sockaddr_un`.
+    fn parse(bytes: &[u8]) -> Result<Self, Errno> {
+        if bytes.len() < SUN_PATH_OFFSET || u16::from_ne_bytes([bytes[0], bytes[1]]) != AF_UNIX {
+            return Err(Errno::EINVAL);
+        }
+        let path = &bytes[SUN_PATH_OFFSET..];
+        match path.first() {
+            None => Ok(UnixAddr::Unnamed),
+            Some(0) => Ok(UnixAddr::Abstract(path[1..].to_vec())),
+            Some(_) => {
+                let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+                let path = core::str::from_utf8(&path[..end]).map_err(|_| Errno::EINVAL)?;
+                Ok(UnixAddr::Path(path.into()))
+            }
+        }
+    }
+
+    /// Encode the address into a `sockaddr_un`, returns it and its length.
+    fn to_libc(&self) -> (LibcSocketAddrUn, usize) {
+        let mut addr = LibcSocketAddrUn::default();
+        let len = match self {
+            UnixAddr::Unnamed => 0,
+            UnixAddr::Path(path) => {
+                let len = path.len().min(addr.sun_path.len() - 1);
+                addr.sun_path[..len].copy_from_slice(&path.as_bytes()[..len]);
+                len + 1
+            }
+            UnixAddr::Abstract(name) => {
+                let len = name.len().min(addr.sun_path.len() - 1);
+                addr.sun_path[1..=len].copy_from_slice(&name[..len]);
+                len + 1
+            }
+        };
+        (addr, SUN_PATH_OFFSET + len)
+    }
+}
+
+/// A message queued in a socket.
+#[derive(Clone)]
+struct UnixMessage {
+    data: Vec<u8>,
+    /// The bytes already received from a stream.
+    offset: usize,
+    files: Vec<Arc<File>>,
+    from: UnixAddr,
+}
+
+#[derive(Default)]
+enum UnixState {
+    #[default]
+    Unconnected,
+    Listening {
+        backlog: usize,
+        /// The connected sockets waiting to be accepted.
+        pending: VecDeque<Arc<UnixSocket>>,
+    },
+    Connected(Weak<UnixSocket>),
+}
+
+#[derive(Default)]
+struct UnixInner {
+    local: UnixAddr,
+    state: UnixState,
+    recv_queue: VecDeque<UnixMessage>,
+    /// The bytes of the messages in `recv_queue`.
+    queued: usize,
+    read_shutdown: bool,
+    write_shutdown: bool,
+    /// The peer shuts down writing, nothing more will arrive.
+    peer_shutdown: bool,
+}
+
+/// A message received from a socket.
+pub(crate) struct UnixReceived {
+    data: Vec<u8>,
+    files: Vec<Arc<File>>,
+    from: UnixAddr,
+    /// The datagram is longer than the buffer.
+    truncated: bool,
+}
+
+/// A unix domain socket.
+///
+/// The peers and the names refer to it weakly, so it is closed when the last
+/// fd referring to it is closed.
+pub(crate) struct UnixSocket {
+    ty: UnixType,
+    nonblock: AtomicBool,
+    inner: Mutex<UnixInner>,
+}
+
+impl UnixSocket {
+    pub(crate) fn new(ty: UnixType) -> Self {
+        Self {
+            ty,
+            nonblock: AtomicBool::new(false),
+            inner: Mutex::new(UnixInner::default()),
+        }
+    }
+
+    /// Create a pair of sockets connected to each other.
+    pub(crate) fn pair(ty: UnixType) -> (Arc<Self>, Arc<Self>) {
+        let (a, b) = (Arc::new(Self::new(ty)), Arc::new(Self::new(ty)));
+        a.inner.lock().state = UnixState::Connected(Arc::downgrade(&b));
+        b.inner.lock().state = UnixState::Connected(Arc::downgrade(&a));
+        (a, b)
+    }
+
+    pub(crate) fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    pub(crate) fn local_addr(&self) -> UnixAddr {
+        self.inner.lock().local.clone()
+    }
+
+    pub(crate) fn peer_addr(&self) -> Result<UnixAddr, Errno> {
+        let peer = self.peer().map_err(|_| Errno::ENOTCONN)?;
+        Ok(peer.local_addr())
+    }
+
+    /// Get the connected peer.
+    fn peer(&self) -> Result<Arc<UnixSocket>, Errno> {
+        match &self.inner.lock().state {
+            UnixState::Connected(peer) => peer.upgrade().ok_or(match self.ty {
+                UnixType::Stream => Errno::EPIPE,
+                UnixType::Dgram => Errno::ECONNREFUSED,
+            }),
+            _ => Err(Errno::ENOTCONN),
+        }
+    }
+
+    /// Whether more bytes can be queued in the socket.
+    fn has_room(&self) -> bool {
+        self.inner.lock().queued < UNIX_BUF_SIZE
+    }
+
+    pub(crate) fn bind(self: &Arc<Self>, addr: UnixAddr) -> Result<(), Errno> {
+        if self.inner.lock().local != UnixAddr::Unnamed {
+            return Err(Errno::EINVAL);
+        }
+        let addr = match addr {
+            UnixAddr::Unnamed => self.autobind(),
+            UnixAddr::Path(path) => {
+                let inode = vfs::Inode::Socket(Arc::downgrade(self));
+                vfs::create(&vfs::normalize_path(&path)?, inode).map_err(|err| match err {
+                    Errno::EEXIST => Errno::EADDRINUSE,
+                    err => err,
+                })?;
+                UnixAddr::Path(path)
+            }
+            UnixAddr::Abstract(name) => {
+                self.register_abstract(&name)?;
+                UnixAddr::Abstract(name)
+            }
+        };
+        self.inner.lock().local = addr;
+        Ok(())
+    }
+
+    /// Bind to an unused abstract name of 5 hex digits, the same as linux.
+    fn autobind(self: &Arc<Self>) -> UnixAddr {
+        loop {
+            let next = AUTOBIND_NEXT.fetch_add(1, Ordering::Relaxed) & 0xfffff;
+            let name = format!("{:05x}", next).into_bytes();
+            if self.register_abstract(&name).is_ok() {
+                return UnixAddr::Abstract(name);
+            }
+        }
+    }
+
+    fn register_abstract(self: &Arc<Self>, name: &[u8]) -> Result<(), Errno> {
+        let mut names = ABSTRACT_NAMES.lock();
+        if names
+            .get(name)
+            .is_some_and(|socket| socket.strong_count() > 0)
+        {
+            return Err(Errno::EADDRINUSE);
+        }
+        names.insert(name.to_vec(), Arc::downgrade(self));
+        Ok(())
+    }
+
+    pub(crate) fn listen(&self, backlog: usize) -> Result<(), Errno> {
+        if self.ty != UnixType::Stream {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        let backlog = backlog.clamp(1, UNIX_MAX_BACKLOG);
+        let mut inner = self.inner.lock();
+        if inner.local == UnixAddr::Unnamed {
+            return Err(Errno::EINVAL);
+        }
+        match &mut inner.state {
+            UnixState::Unconnected => {
+                inner.state = UnixState::Listening {
+                    backlog,
+                    pending: VecDeque::new(),
+                }
+            }
+            UnixState::Listening { backlog: old, .. } => *old = backlog,
+            UnixState::Connected(_) => return Err(Errno::EINVAL),
+        }
+        Ok(())
+    }
+
+    /// Connect to the socket named `addr`.
+    ///
+    /// A stream socket is connected once the listener has room in its
+    /// backlog, otherwise [Errno::EAGAIN] is returned.
+    pub(crate) fn connect(self: &Arc<Self>, addr: &UnixAddr) -> Result<(), Errno> {
+        let target = lookup(addr)?;
+        if target.ty != self.ty {
+            return Err(Errno::EPROTOTYPE);
+        }
+        if self.ty == UnixType::Dgram {
+            self.inner.lock().state = UnixState::Connected(Arc::downgrade(&target));
+            return Ok(());
+        }
+        match self.inner.lock().state {
+            UnixState::Unconnected => {}
+            UnixState::Connected(_) => return Err(Errno::EISCONN),
+            UnixState::Listening { .. } => return Err(Errno::EINVAL),
+        }
+        let server = Arc::new(UnixSocket::new(UnixType::Stream));
+        {
+            let mut target_inner = target.inner.lock();
+            let local = target_inner.local.clone();
+            let UnixState::Listening { backlog, pending } = &mut target_inner.state else {
+                return Err(Errno::ECONNREFUSED);
+            };
+            if pending.len() >= *backlog {
+                return Err(Errno::EAGAIN);
+            }
+            let mut server_inner = server.inner.lock();
+            server_inner.local = local;
+            server_inner.state = UnixState::Connected(Arc::downgrade(self));
+            drop(server_inner);
+            pending.push_back(server.clone());
+        }
+        self.inner.lock().state = UnixState::Connected(Arc::downgrade(&server));
+        wait::request_wake();
+        Ok(())
+    }
+
+    /// Accept a pending connection.
+    pub(crate) fn accept(&self) -> Result<Arc<UnixSocket>, Errno> {
+        let socket = match &mut self.inner.lock().state {
+            UnixState::Listening { pending, .. } => pending.pop_front().ok_or(Errno::EAGAIN)?,
+            _ => return Err(Errno::EINVAL),
+        };
+        // The connects may be waiting for the room in the backlog.
+        wait::request_wake();
+        Ok(socket)
+    }
+
+    /// The bytes taken from the user space for a send of `len` bytes.
+    ///
+    /// A stream takes at most the size of the buffer, the rest is left to the
+    /// next send. A datagram larger than the buffer can never be queued.
+    pub(crate) fn send_len(&self, len: usize) -> Result<usize, Errno> {
+        match self.ty {
+            UnixType::Stream => Ok(len.min(UNIX_BUF_SIZE)),
+            UnixType::Dgram if len > UNIX_BUF_SIZE => Err(Errno::EMSGSIZE),
+            UnixType::Dgram => Ok(len),
+        }
+    }
+
+    /// Send `data` and `files` to `to` or the connected peer.
+    ///
+    /// A stream may send a part of the data, the files are taken only if
+    /// something is sent.
+    pub(crate) fn send(
+        &self,
+        data: &[u8],
+        files: &mut Vec<Arc<File>>,
+        to: Option<&UnixAddr>,
+    ) -> Result<usize, Errno> {
+        let from = {
+            let inner = self.inner.lock();
+            if inner.write_shutdown {
+                return Err(Errno::EPIPE);
+            }
+            inner.local.clone()
+        };
+        let target = match (self.ty, to) {
+            (UnixType::Stream, Some(_)) => return Err(Errno::EISCONN),
+            (_, Some(addr)) => lookup(addr)?,
+            (_, None) => self.peer()?,
+        };
+        if target.ty != self.ty {
+            return Err(Errno::EPROTOTYPE);
+        }
+        let mut inner = target.inner.lock();
+        if inner.read_shutdown {
+            return Err(Errno::EPIPE);
+        }
+        let room = UNIX_BUF_SIZE.saturating_sub(inner.queued);
+        let len = match self.ty {
+            UnixType::Stream if room == 0 => return Err(Errno::EAGAIN),
+            UnixType::Stream => data.len().min(room),
+            UnixType::Dgram if data.len() > UNIX_BUF_SIZE => return Err(Errno::EMSGSIZE),
+            UnixType::Dgram if data.len() > room => return Err(Errno::EAGAIN),
+            UnixType::Dgram => data.len(),
+        };
+        if self.ty == UnixType::Stream && len == 0 && files.is_empty() {
+            return Ok(0);
+        }
+        inner.queued += len;
+        inner.recv_queue.push_back(UnixMessage {
+            data: data[..len].to_vec(),
+            offset: 0,
+            files: core::mem::take(files),
+            from,
+        });
+        drop(inner);
+        wait::request_wake();
+        Ok(len)
+    }
+
+    /// Receive at most `len` bytes, the data is left in the socket if `peek`.
+    ///
+    /// A stream does not merge the data across the messages carrying files,
+    /// so the files are received with the data sent along with them.
+    pub(crate) fn recv(&self, len: usize, peek: bool) -> Result<UnixReceived, Errno> {
+        let mut inner = self.inner.lock();
+        let peer_closed = match &inner.state {
+            UnixState::Connected(peer) => peer.strong_count() == 0,
+            UnixState::Listening { .. } => return Err(Errno::EINVAL),
+            UnixState::Unconnected if self.ty == UnixType::Stream => return Err(Errno::EINVAL),
+            UnixState::Unconnected => false,
+        };
+        if inner.recv_queue.is_empty() {
+            let eof = inner.read_shutdown
+                || (self.ty == UnixType::Stream && (inner.peer_shutdown || peer_closed));
+            if !eof {
+                return Err(Errno::EAGAIN);
+            }
+            return Ok(UnixReceived {
+                data: Vec::new(),
+                files: Vec::new(),
+                from: UnixAddr::Unnamed,
+                truncated: false,
+            });
+        }
+        let received = match self.ty {
+            UnixType::Dgram => {
+                let msg = if peek {
+                    inner.recv_queue[0].clone()
+                } else {
+                    let msg = inner.recv_queue.pop_front().unwrap();
+                    inner.queued -= msg.data.len();
+                    msg
+                };
+                UnixReceived {
+                    truncated: msg.data.len() > len,
+                    data: msg.data[..msg.data.len().min(len)].to_vec(),
+                    files: msg.files,
+                    from: msg.from,
+                }
+            }
+            UnixType::Stream => {
+                let mut data = Vec::new();
+                let mut files = Vec::new();
+                let from = inner.recv_queue[0].from.clone();
+                for msg in inner.recv_queue.iter_mut() {
+                    if data.len() >= len {
+                        break;
+                    }
+                    if !msg.files.is_empty() {
+                        if !data.is_empty() || !files.is_empty() {
+                            break;
+                        }
+                        files = if peek {
+                            msg.files.clone()
+                        } else {
+                            core::mem::take(&mut msg.files)
+                        };
+                    }
+                    let count = (msg.data.len() - msg.offset).min(len - data.len());
+                    data.extend_from_slice(&msg.data[msg.offset..msg.offset + count]);
+                    if !peek {
+                        msg.offset += count;
+                    }
+                }
+                if !peek {
+                    inner.queued -= data.len();
+                    while inner
+                        .recv_queue
+                        .front()
+                        .is_some_and(|msg| msg.offset == msg.data.len() && msg.files.is_empty())
+                    {
+                        inner.recv_queue.pop_front();
+                    }
+                }
+                UnixReceived {
+                    data,
+                    files,
+                    from,
+                    truncated: false,
+                }
+            }
+        };
+        drop(inner);
+        if !peek {
+            // The senders may be waiting for the room.
+            wait::request_wake();
+        }
+        Ok(received)
+    }
+
+    pub(crate) fn shutdown(&self, how: i32) -> Result<(), Errno> {
+        let (read, write) = match how {
+            SHUT_RD => (true, false),
+            SHUT_WR => (false, true),
+            SHUT_RDWR => (true, true),
+            _ => return Err(Errno::EINVAL),
+        };
+        let peer = {
+            let mut inner = self.inner.lock();
+            let UnixState::Connected(peer) = &inner.state else {
+                return Err(Errno::ENOTCONN);
+            };
+            let peer = peer.clone();
+            inner.read_shutdown |= read;
+            inner.write_shutdown |= write;
+            peer
+        };
+        if let Some(peer) = peer.upgrade().filter(|_| write) {
+            peer.inner.lock().peer_shutdown = true;
+        }
+        wait::request_wake();
+        Ok(())
+    }
+
+    /// Get the readiness of the socket.
+    pub(crate) fn poll(&self) -> PollEvents {
+        let (mut events, peer) = {
+            let inner = self.inner.lock();
+            let peer = match &inner.state {
+                UnixState::Listening { pending, .. } if pending.is_empty() => {
+                    return PollEvents::empty()
+                }
+                UnixState::Listening { .. } => return PollEvents::POLLIN,
+                UnixState::Connected(peer) => Some(peer.clone()),
+                UnixState::Unconnected => None,
+            };
+            let mut events = PollEvents::empty();
+            if !inner.recv_queue.is_empty() || inner.read_shutdown || inner.peer_shutdown {
+                events |= PollEvents::POLLIN;
+            }
+            (events, peer)
+        };
+        match (self.ty, peer.map(|peer| peer.upgrade())) {
+            (UnixType::Stream, None) => events |= PollEvents::POLLOUT | PollEvents::POLLHUP,
+            (UnixType::Stream, Some(None)) => {
+                events |= PollEvents::POLLIN | PollEvents::POLLOUT | PollEvents::POLLHUP
+            }
+            (_, Some(Some(peer))) if !peer.has_room() => {}
+            (_, _) => events |= PollEvents::POLLOUT,
+        }
+        events
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let this = self as *const Self;
+        if let UnixAddr::Abstract(name) = &self.inner.get_mut().local {
+            let mut names = ABSTRACT_NAMES.lock();
+            if names
+                .get(name)
+                .is_some_and(|socket| socket.as_ptr() == this)
+            {
+                names.remove(name);
+            }
+        }
+        // The peer sees the end of the stream.
+        wait::request_wake();
+    }
+}
+
+/// Find the socket named `addr`.
+fn lookup(addr: &UnixAddr) -> Result<Arc<UnixSocket>, Errno> {
+    match addr {
+        UnixAddr::Unnamed => Err(Errno::EINVAL),
+        UnixAddr::Path(path) => match vfs::lookup(&vfs::normalize_path(path)?)? {
+            vfs::Inode::Socket(socket) => socket.upgrade().ok_or(Errno::ECONNREFUSED),
+            _ => Err(Errno::ECONNREFUSED),
+        },
+        UnixAddr::Abstract(name) => ABSTRACT_NAMES
+            .lock()
+            .get(name)
+            .and_then(Weak::upgrade)
+            .ok_or(Errno::ECONNREFUSED),
+    }
+}
+
+/// Get the unix socket of the `fd` of the task `badge`, `None` if it is
+/// another kind of file.
+pub(crate) fn unix_socket_of(badge: u64, fd: i32) -> Result<Option<Arc<UnixSocket>>, Errno> {
+    Ok(match file_of(badge, fd)?.as_ref() {
+        File::Unix(socket) => Some(socket.clone()),
+        _ => None,
+    })
+}
+
+/// Read a `sockaddr_un` of `addr_len` bytes from the user space.
+pub(crate) fn read_unix_addr(
+    task: &Sel4Task,
+    addr: *const u8,
+    addr_len: usize,
+) -> Result<UnixAddr, Errno> {
+    if addr.is_null() || !(SUN_PATH_OFFSET..=size_of::<LibcSocketAddrUn>()).contains(&addr_len) {
+        return Err(Errno::EINVAL);
+    }
+    let mut bytes = vec![0u8; addr_len];
+    read_item_list(task, addr, Some(addr_len), &mut bytes)?;
+    UnixAddr::parse(&bytes)
+}
+
+/// Write the `sockaddr_un` of `unix_addr` into the buffer of `buf_len` bytes
+/// at `addr`, returns the length of the whole address.
+///
+/// The address is truncated if the buffer is too small, the same as linux.
+fn write_unix_addr(
+    task: &Sel4Task,
+    addr: usize,
+    buf_len: usize,
+    unix_addr: &UnixAddr,
+) -> Result<u32, Errno> {
+    let (libc_addr, len) = unix_addr.to_libc();
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&libc_addr as *const LibcSocketAddrUn as *const u8, len)
+    };
+    let copy_len = len.min(buf_len);
+    if addr != 0 && copy_len > 0 {
+        write_item_list(task, addr as *mut u8, Some(copy_len), &bytes[..copy_len])?;
+    }
+    Ok(len as u32)
+}
+
+/// Write the address to the buffer whose length is at `addr_len`, and write
+/// back the length, as `accept`, `getsockname` and `recvfrom` do.
+fn write_unix_addr_len(
+    task: &Sel4Task,
+    addr: usize,
+    addr_len: usize,
+    unix_addr: &UnixAddr,
+) -> Result<(), Errno> {
+    if addr == 0 || addr_len == 0 {
+        return Ok(());
+    }
+    let buf_len = read_item(task, addr_len as *const u32)? as usize;
+    let len = write_unix_addr(task, addr, buf_len, unix_addr)?;
+    write_item(task, addr_len as *mut u32, &len)?;
+    Ok(())
+}
+
+#[inline]
+const fn cmsg_align(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+/// Read the files passed by `SCM_RIGHTS` in the control buffer of `sendmsg`.
+///
+/// The other kinds of ancillary messages are ignored.
+pub(crate) fn read_rights(
+    task: &Sel4Task,
+    control: usize,
+    control_len: usize,
+) -> Result<Vec<Arc<File>>, Errno> {
+    if control == 0 || control_len == 0 {
+        return Ok(Vec::new());
+    }
+    if control_len > CONTROL_MAX {
+        return Err(Errno::ENOBUFS);
+    }
+    let mut buf = vec![0u8; control_len];
+    read_item_list(task, control as *const u8, Some(control_len), &mut buf)?;
+    let hdr_len = size_of::<CmsgHdr>();
+    let mut files = Vec::new();
+    let mut offset = 0;
+    while offset + hdr_len <= control_len {
+        let hdr = unsafe { core::ptr::read_unaligned(buf[offset..].as_ptr() as *const CmsgHdr) };
+        if hdr.cmsg_len < hdr_len || offset + hdr.cmsg_len > control_len {
+            return Err(Errno::EINVAL);
+        }
+        if hdr.cmsg_level == SOL_SOCKET && hdr.cmsg_type == SCM_RIGHTS {
+            let file_table = task.file_table.lock();
+            for fd in buf[offset + hdr_len..offset + hdr.cmsg_len].chunks_exact(4) {
+                files.push(file_table.get(i32::from_ne_bytes(fd.try_into().unwrap()))?);
+            }
+            if files.len() > SCM_MAX_FD {
+                return Err(Errno::EINVAL);
+            }
+        }
+        offset += cmsg_align(hdr.cmsg_len);
+    }
+    Ok(files)
+}
+
+/// Install the received `files` into the task, and write their fds into the
+/// control buffer as `SCM_RIGHTS`.
+///
+/// Returns the length of the control buffer used and whether it is too small
+/// for all the files. The files which do not fit are closed.
+fn write_rights(
+    task: &Sel4Task,
+    control: usize,
+    control_len: usize,
+    files: Vec<Arc<File>>,
+) -> Result<(usize, bool), Errno> {
+    if files.is_empty() {
+        return Ok((0, false));
+    }
+    let hdr_len = size_of::<CmsgHdr>();
+    let room = match control {
+        0 => 0,
+        _ => control_len.saturating_sub(hdr_len) / size_of::<i32>(),
+    };
+    let count = files.len().min(room);
+    let truncated = count < files.len();
+    if count == 0 {
+        return Ok((0, truncated));
+    }
+    let hdr = CmsgHdr {
+        cmsg_len: hdr_len + count * size_of::<i32>(),
+        cmsg_level: SOL_SOCKET,
+        cmsg_type: SCM_RIGHTS,
+    };
+    let mut bytes = Vec::with_capacity(hdr.cmsg_len);
+    bytes.extend_from_slice(unsafe {
+        core::slice::from_raw_parts(&hdr as *const CmsgHdr as *const u8, hdr_len)
+    });
+    let mut file_table = task.file_table.lock();
+    for file in files.into_iter().take(count) {
+        bytes.extend_from_slice(&file_table.insert(file)?.to_ne_bytes());
+    }
+    drop(file_table);
+    write_item_list(task, control as *mut u8, Some(bytes.len()), &bytes)?;
+    Ok((cmsg_align(hdr.cmsg_len).min(control_len), truncated))
+}
+
+/// Where a received message is written in the user space.
+pub(crate) struct RecvBuffers {
+    pub(crate) iovs: Vec<IoVec>,
+    /// The buffer of the sender address, `0` if it is not wanted.
+    pub(crate) name: usize,
+    pub(crate) name_len: usize,
+    /// The buffer of the ancillary messages, `0` if it is not wanted.
+    pub(crate) control: usize,
+    pub(crate) control_len: usize,
+    pub(crate) reply: RecvReply,
+}
+
+/// How the lengths and flags of a received message are reported.
+pub(crate) enum RecvReply {
+    /// Nothing is reported, as `read` does.
+    None,
+    /// The length of the address is written to the `socklen_t` at the address,
+    /// as `recvfrom` does.
+    AddrLen(usize),
+    /// The `msghdr` at the address is written back with the lengths and the
+    /// flags, as `recvmsg` does.
+    MsgHdr(usize, MsgHdr),
+}
+
+/// Write the received message into the buffers of the task `badge`.
+fn deliver(badge: u64, received: UnixReceived, bufs: &RecvBuffers) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let Some(task) = task_map.get(&badge) else {
+        return Err(Errno::ESRCH);
+    };
+    let len = scatter_iovecs(task, &bufs.iovs, &received.data)?;
+    let name_len = match bufs.name {
+        0 => 0,
+        name => write_unix_addr(task, name, bufs.name_len, &received.from)?,
+    };
+    let (control_len, ctrunc) = write_rights(task, bufs.control, bufs.control_len, received.files)?;
+    match bufs.reply {
+        RecvReply::None => {}
+        RecvReply::AddrLen(0) => {}
+        RecvReply::AddrLen(addr_len) => {
+            write_item(task, addr_len as *mut u32, &name_len)?;
+        }
+        RecvReply::MsgHdr(msg, mut hdr) => {
+            hdr.msg_namelen = name_len;
+            hdr.msg_controllen = control_len;
+            hdr.msg_flags = 0;
+            if received.truncated {
+                hdr.msg_flags |= MSG_TRUNC;
+            }
+            if ctrunc {
+                hdr.msg_flags |= MSG_CTRUNC;
+            }
+            write_item(task, msg as *mut MsgHdr, &hdr)?;
+        }
+    }
+    Ok(len)
+}
+
+/// Insert a new file into the file table of the task `badge`.
+fn insert_file(badge: u64, file: File) -> Result<i32, Errno> {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).ok_or(Errno::ESRCH)?;
+    let fd = task.file_table.lock().insert(Arc::new(file));
+    fd
+}
+
+pub(crate) fn sys_socket(badge: u64, ty: UnixType, nonblock: bool) -> SysResult {
+    let socket = UnixSocket::new(ty);
+    socket.set_nonblocking(nonblock);
+    Ok(insert_file(badge, File::Unix(Arc::new(socket)))? as usize)
+}
+
+pub(crate) fn sys_socketpair(badge: u64, ty: UnixType, nonblock: bool, sv: *mut i32) -> SysResult {
+    let (a, b) = UnixSocket::pair(ty);
+    a.set_nonblocking(nonblock);
+    b.set_nonblocking(nonblock);
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let mut file_table = task.file_table.lock();
+    let fds = [
+        file_table.insert(Arc::new(File::Unix(a)))?,
+        file_table.insert(Arc::new(File::Unix(b)))?,
+    ];
+    drop(file_table);
+    if let Err(err) = write_item_list(task, sv, Some(2), &fds) {
+        let mut file_table = task.file_table.lock();
+        for fd in fds {
+            let _ = file_table.remove(fd);
+        }
+        return Err(err);
+    }
+    Ok(0)
+}
+
+pub(crate) fn sys_bind(
+    badge: u64,
+    socket: &Arc<UnixSocket>,
+    addr: *const u8,
+    addr_len: usize,
+) -> SysResult {
+    let addr = {
+        let task_map = TASK_MAP.lock();
+        read_unix_addr(task_map.get(&badge).unwrap(), addr, addr_len)?
+    };
+    socket.bind(addr)?;
+    Ok(0)
+}
+
+pub(crate) fn sys_connect(
+    badge: u64,
+    socket: Arc<UnixSocket>,
+    addr: *const u8,
+    addr_len: usize,
+) -> SysResult {
+    let addr = {
+        let task_map = TASK_MAP.lock();
+        read_unix_addr(task_map.get(&badge).unwrap(), addr, addr_len)?
+    };
+    wait::block_on(badge, socket.is_nonblocking(), move || {
+        socket.connect(&addr).map(|_| 0)
+    })
+}
+
+pub(crate) fn sys_listen(socket: &UnixSocket, backlog: i32) -> SysResult {
+    // A negative backlog is taken as the maximum like linux.
+    socket.listen(backlog as u32 as usize)?;
+    Ok(0)
+}
+
+pub(crate) fn sys_accept(
+    badge: u64,
+    socket: Arc<UnixSocket>,
+    addr: *mut u8,
+    addr_len: *mut u32,
+) -> SysResult {
+    let (addr, addr_len) = (addr as usize, addr_len as usize);
+    wait::block_on(badge, socket.is_nonblocking(), move || {
+        let new_socket = socket.accept()?;
+        let peer_addr = new_socket.peer_addr().unwrap_or_default();
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).ok_or(Errno::ESRCH)?;
+        write_unix_addr_len(task, addr, addr_len, &peer_addr)?;
+        let fd = task
+            .file_table
+            .lock()
+            .insert(Arc::new(File::Unix(new_socket)))?;
+        Ok(fd as usize)
+    })
+}
+
+/// Send `data` and `files` to `to` or the connected peer.
+pub(crate) fn send_to(
+    badge: u64,
+    socket: Arc<UnixSocket>,
+    data: Vec<u8>,
+    mut files: Vec<Arc<File>>,
+    to: Option<UnixAddr>,
+    flags: i32,
+) -> SysResult {
+    let nonblock = socket.is_nonblocking() || flags & MSG_DONTWAIT != 0;
+    wait::block_on(badge, nonblock, move || {
+        socket.send(&data, &mut files, to.as_ref())
+    })
+}
+
+/// Receive a message into the buffers.
+pub(crate) fn recv_from(
+    badge: u64,
+    socket: Arc<UnixSocket>,
+    bufs: RecvBuffers,
+    flags: i32,
+) -> SysResult {
+    let nonblock = socket.is_nonblocking() || flags & MSG_DONTWAIT != 0;
+    let peek = flags & MSG_PEEK != 0;
+    // No message is longer than the buffer.
+    let len = iovecs_len(&bufs.iovs)?.min(UNIX_BUF_SIZE);
+    wait::block_on(badge, nonblock, move || {
+        let received = socket.recv(len, peek)?;
+        deliver(badge, received, &bufs)
+    })
+}
+
+pub(crate) fn sys_shutdown(socket: &UnixSocket, how: i32) -> SysResult {
+    socket.shutdown(how)?;
+    Ok(0)
+}
+
+pub(crate) fn sys_getsockname(
+    badge: u64,
+    socket: &UnixSocket,
+    addr: *mut u8,
+    addr_len: *mut u32,
+) -> SysResult {
+    if addr.is_null() || addr_len.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_unix_addr_len(task, addr as _, addr_len as _, &socket.local_addr())?;
+    Ok(0)
+}
+
+pub(crate) fn sys_getpeername(
+    badge: u64,
+    socket: &UnixSocket,
+    addr: *mut u8,
+    addr_len: *mut u32,
+) -> SysResult {
+    if addr.is_null() || addr_len.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    let peer_addr = socket.peer_addr()?;
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_unix_addr_len(task, addr as _, addr_len as _, &peer_addr)?;
+    Ok(0)
+}
@@ -0,0 +1,38 @@
+//! `reboot`.
+
+use common::ShutdownMode;
+use crate_consts::INIT_EP;
+use syscalls::Errno;
+
+use super::{thread::capable, SysResult};
+
+const LINUX_REBOOT_MAGIC1: u32 = 0xfee1_dead;
+const LINUX_REBOOT_MAGIC2: [u32; 4] = [672274793, 85072278, 369367448, 537993216];
+
+const LINUX_REBOOT_CMD_RESTART: u32 = 0x0123_4567;
+const LINUX_REBOOT_CMD_HALT: u32 = 0xcdef_0123;
+const LINUX_REBOOT_CMD_CAD_ON: u32 = 0x89ab_cdef;
+const LINUX_REBOOT_CMD_CAD_OFF: u32 = 0x0000_0000;
+const LINUX_REBOOT_CMD_POWER_OFF: u32 = 0x4321_fedc;
+
+/// Power off or restart the machine through the root task.
+///
+/// `RESTART` resets the machine, `HALT` and `POWER_OFF` both power it off.
+/// Ctrl-Alt-Del isn't delivered, so `CAD_ON` and `CAD_OFF` do nothing. Only
+/// a task with `CAP_SYS_BOOT` may call it.
+pub(crate) fn sys_reboot(badge: u64, magic1: u32, magic2: u32, cmd: u32, _arg: usize) -> SysResult {
+    if !capable(badge) {
+        return Err(Errno::EPERM);
+    }
+    if magic1 != LINUX_REBOOT_MAGIC1 || !LINUX_REBOOT_MAGIC2.contains(&magic2) {
+        return Err(Errno::EINVAL);
+    }
+    match cmd {
+        LINUX_REBOOT_CMD_CAD_ON | LINUX_REBOOT_CMD_CAD_OFF => Ok(0),
+        LINUX_REBOOT_CMD_RESTART => common::shutdown(INIT_EP, ShutdownMode::Reboot),
+        LINUX_REBOOT_CMD_HALT | LINUX_REBOOT_CMD_POWER_OFF => {
+            common::shutdown(INIT_EP, ShutdownMode::PowerOff)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
@@ -0,0 +1,109 @@
+//! The user ids of the tasks and the privileges they grant.
+//!
+//! There are no capability sets, a task whose effective user id is 0 has all
+//! the capabilities, such as `CAP_SYS_TIME` and `CAP_SYS_BOOT`, and the others
+//! have none, the same as linux without the file capabilities.
+
+use syscalls::Errno;
+
+use crate::{child_test::TASK_MAP, syscall::SysResult, utils::write_item};
+
+/// The id passed to `setresuid` to keep an id unchanged.
+const KEEP_ID: u32 = u32::MAX;
+
+/// The user ids of a task, inherited by the cloned tasks and kept by `execve`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Credentials {
+    pub uid: u32,
+    pub euid: u32,
+    /// The saved user id, which an unprivileged task may switch back to.
+    pub suid: u32,
+}
+
+impl Credentials {
+    /// Whether the task has all the capabilities.
+    pub(crate) const fn is_privileged(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// Whether an unprivileged task may switch to the user id `id`.
+    fn may_switch_to(&self, id: u32) -> bool {
+        id == self.uid || id == self.euid || id == self.suid
+    }
+}
+
+/// Whether the task `badge` has all the capabilities, the check of
+/// `capable` in linux.
+pub(crate) fn capable(badge: u64) -> bool {
+    TASK_MAP
+        .lock()
+        .get(&badge)
+        .is_some_and(|task| task.cred.is_privileged())
+}
+
+pub(crate) fn sys_getuid(badge: u64) -> SysResult {
+    Ok(TASK_MAP.lock().get(&badge).unwrap().cred.uid as usize)
+}
+
+pub(crate) fn sys_geteuid(badge: u64) -> SysResult {
+    Ok(TASK_MAP.lock().get(&badge).unwrap().cred.euid as usize)
+}
+
+pub(crate) fn sys_getresuid(
+    badge: u64,
+    ruid: *mut u32,
+    euid: *mut u32,
+    suid: *mut u32,
+) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    let cred = task.cred;
+    write_item(task, ruid, &cred.uid)?;
+    write_item(task, euid, &cred.euid)?;
+    write_item(task, suid, &cred.suid)?;
+    Ok(0)
+}
+
+/// Set all the user ids if the task is privileged, or else only the
+/// effective one to the real or the saved one.
+pub(crate) fn sys_setuid(badge: u64, uid: u32) -> SysResult {
+    if uid == KEEP_ID {
+        return Err(Errno::EINVAL);
+    }
+    let mut task_map = TASK_MAP.lock();
+    let cred = &mut task_map.get_mut(&badge).unwrap().cred;
+    if cred.is_privileged() {
+        *cred = Credentials {
+            uid,
+            euid: uid,
+            suid: uid,
+        };
+    } else if uid == cred.uid || uid == cred.suid {
+        cred.euid = uid;
+    } else {
+        return Err(Errno::EPERM);
+    }
+    Ok(0)
+}
+
+/// Set the user ids other than [KEEP_ID], an unprivileged task may only set
+/// them to its current ones.
+pub(crate) fn sys_setresuid(badge: u64, ruid: u32, euid: u32, suid: u32) -> SysResult {
+    let mut task_map = TASK_MAP.lock();
+    let cred = &mut task_map.get_mut(&badge).unwrap().cred;
+    let ids = [ruid, euid, suid];
+    if !cred.is_privileged()
+        && ids
+            .iter()
+            .any(|id| *id != KEEP_ID && !cred.may_switch_to(*id))
+    {
+        return Err(Errno::EPERM);
+    }
+    let keep = |id: u32, old: u32| if id == KEEP_ID { old } else { id };
+    *cred = Credentials {
+        uid: keep(ruid, cred.uid),
+        euid: keep(euid, cred.euid),
+        suid: keep(suid, cred.suid),
+    };
+    Ok(0)
+}
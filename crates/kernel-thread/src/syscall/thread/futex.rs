@@ -0,0 +1,129 @@
+//! The `futex` syscall, only `FUTEX_WAIT` and `FUTEX_WAKE`.
+//!
+//! A waiter is parked by [wait::park] with a ticket queued on the futex word,
+//! [futex_wake] marks the tickets of the woken waiters and requests a wake so
+//! their syscalls are retried and return. The futexes are keyed by the
+//! memory of the task and the address, so the private and the shared futexes
+//! are the same, the tasks share memory only with `CLONE_VM`.
+
+use alloc::{
+    collections::{btree_map::BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+};
+use common::TimeSpec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{wait, Deadline, SysResult},
+    task::Sel4Task,
+    utils::read_item,
+};
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+const FUTEX_PRIVATE_FLAG: usize = 128;
+const FUTEX_CLOCK_REALTIME: usize = 256;
+
+/// The memory of the task and the address of the futex word.
+type FutexKey = (usize, usize);
+
+/// The tickets of the waiters of each futex in the order they wait, a ticket
+/// is set when its waiter is woken.
+///
+/// The ticket is owned by the parked syscall, so the waiters dropped by
+/// [wait::cancel_waiters] are skipped by [futex_wake].
+static FUTEX_QUEUES: Mutex<BTreeMap<FutexKey, VecDeque<Weak<AtomicBool>>>> =
+    Mutex::new(BTreeMap::new());
+
+fn futex_key(task: &Sel4Task, uaddr: usize) -> FutexKey {
+    (Arc::as_ptr(&task.mem) as usize, uaddr)
+}
+
+/// Wake at most `count` waiters of the futex `uaddr` in the memory of the
+/// `task`, returns the number of the woken ones.
+pub(crate) fn futex_wake(task: &Sel4Task, uaddr: usize, count: usize) -> usize {
+    let key = futex_key(task, uaddr);
+    let mut queues = FUTEX_QUEUES.lock();
+    let Some(queue) = queues.get_mut(&key) else {
+        return 0;
+    };
+    let mut woken = 0;
+    while woken < count {
+        let Some(ticket) = queue.pop_front() else {
+            break;
+        };
+        if let Some(ticket) = ticket.upgrade() {
+            ticket.store(true, Ordering::Release);
+            woken += 1;
+        }
+    }
+    if queue.is_empty() {
+        queues.remove(&key);
+    }
+    if woken > 0 {
+        wait::request_wake();
+    }
+    woken
+}
+
+/// Wait on the futex `uaddr` while it holds `val`, until it is woken or the
+/// relative `timeout` passes.
+fn futex_wait(badge: u64, uaddr: usize, val: u32, timeout: *const TimeSpec) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    if uaddr % core::mem::align_of::<u32>() != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut deadline = match timeout.is_null() {
+        true => Deadline::never(),
+        false => {
+            let timeout = read_item(task, timeout)?;
+            if !timeout.is_valid() {
+                return Err(Errno::EINVAL);
+            }
+            Deadline::after(timeout.as_nanos())
+        }
+    };
+    if read_item(task, uaddr as *const u32)? != val {
+        return Err(Errno::EAGAIN);
+    }
+    // The syscalls are served one at a time, so no wake is missed between
+    // the check and the queueing.
+    let ticket = Arc::new(AtomicBool::new(false));
+    FUTEX_QUEUES
+        .lock()
+        .entry(futex_key(task, uaddr))
+        .or_default()
+        .push_back(Arc::downgrade(&ticket));
+    drop(task_map);
+    wait::park(badge, move || {
+        if ticket.load(Ordering::Acquire) {
+            Some(Ok(0))
+        } else if deadline.expired() {
+            Some(Err(Errno::ETIMEDOUT))
+        } else {
+            None
+        }
+    })
+}
+
+pub(crate) fn sys_futex(
+    badge: u64,
+    uaddr: usize,
+    futex_op: usize,
+    val: u32,
+    timeout: *const TimeSpec,
+) -> SysResult {
+    match futex_op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+        FUTEX_WAIT => futex_wait(badge, uaddr, val, timeout),
+        FUTEX_WAKE => {
+            let task_map = TASK_MAP.lock();
+            let task = task_map.get(&badge).unwrap();
+            Ok(futex_wake(task, uaddr, val as usize))
+        }
+        _ => Err(Errno::ENOSYS),
+    }
+}
@@ -1,5 +1,11 @@
+mod cred;
+mod futex;
+mod policy;
 mod schedule;
 mod task;
 
+pub(crate) use cred::*;
+pub(crate) use futex::*;
+pub(crate) use policy::*;
 pub(crate) use schedule::*;
 pub(crate) use task::*;
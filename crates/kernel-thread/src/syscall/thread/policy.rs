@@ -0,0 +1,319 @@
+//! The scheduling policies of linux on the priorities of seL4.
+//!
+//! The tasks run below the components, within the priorities controlled by
+//! the kernel thread. `SCHED_DEADLINE` runs above the real-time policies,
+//! which run above the others. The nice values of the other policies are
+//! spread around [NICE_0_PRIORITY] and `SCHED_IDLE` runs below all of them.
+//!
+//! seL4 runs the threads of a priority in turn, so `SCHED_FIFO` is the same
+//! as `SCHED_RR`, and `SCHED_BATCH` the same as `SCHED_OTHER`.
+
+use common::{
+    SchedAttr, SchedParam, TimeSpec, NICE_MAX, NICE_MIN, PRIO_PGRP, PRIO_PROCESS, PRIO_USER,
+    SCHED_ATTR_SIZE_VER0, SCHED_BATCH, SCHED_DEADLINE, SCHED_FIFO, SCHED_FLAG_RESET_ON_FORK,
+    SCHED_IDLE, SCHED_OTHER, SCHED_RESET_ON_FORK, SCHED_RR, SCHED_RT_PRIORITY_MAX,
+    SCHED_RT_PRIORITY_MIN,
+};
+use syscalls::Errno;
+
+use super::schedule::{target_task, update_sched};
+use crate::{
+    child_test::TASK_MAP,
+    syscall::SysResult,
+    task::Sel4Task,
+    utils::{read_item, write_item},
+};
+
+/// The priority of `SCHED_IDLE`.
+const IDLE_PRIORITY: u64 = 1;
+/// The priority of the nice value 0, each nice value above lowers it by one.
+const NICE_0_PRIORITY: u64 = 100;
+/// The priority below the real-time priority 1, the same as the nice value
+/// -20.
+const RT_PRIORITY_BASE: u64 = NICE_0_PRIORITY - NICE_MIN as u64;
+/// The priority of `SCHED_DEADLINE`.
+const DEADLINE_PRIORITY: u64 = RT_PRIORITY_BASE + SCHED_RT_PRIORITY_MAX as u64 + 1;
+
+/// The seL4 priority of a task with the attributes `sched`.
+pub(crate) fn sel4_priority(sched: &SchedAttr) -> u64 {
+    match sched.sched_policy {
+        SCHED_FIFO | SCHED_RR => RT_PRIORITY_BASE + sched.sched_priority as u64,
+        SCHED_DEADLINE => DEADLINE_PRIORITY,
+        SCHED_IDLE => IDLE_PRIORITY,
+        _ => (NICE_0_PRIORITY as i64 - sched.sched_nice as i64) as u64,
+    }
+}
+
+/// The scheduling attributes of a child of the task with `sched`.
+///
+/// With `SCHED_FLAG_RESET_ON_FORK` the child runs with `SCHED_OTHER` instead
+/// of the real-time policies, and without a negative nice value. A
+/// `SCHED_DEADLINE` task without it can't fork, its budget is not shared.
+pub(crate) fn fork_sched(sched: &SchedAttr) -> Result<SchedAttr, Errno> {
+    if sched.sched_flags & SCHED_FLAG_RESET_ON_FORK == 0 {
+        return match sched.sched_policy {
+            SCHED_DEADLINE => Err(Errno::EAGAIN),
+            _ => Ok(*sched),
+        };
+    }
+    let mut child = match sched.sched_policy {
+        SCHED_FIFO | SCHED_RR | SCHED_DEADLINE => SchedAttr {
+            size: SCHED_ATTR_SIZE_VER0,
+            ..Default::default()
+        },
+        _ => *sched,
+    };
+    child.sched_flags = 0;
+    child.sched_nice = child.sched_nice.max(0);
+    Ok(child)
+}
+
+/// Check the scheduling attributes `sched`, the nice value is clamped and
+/// the period of `SCHED_DEADLINE` defaults to its deadline.
+fn check_sched(sched: &mut SchedAttr) -> Result<(), Errno> {
+    sched.sched_nice = sched.sched_nice.clamp(NICE_MIN, NICE_MAX);
+    let rt_priorities = SCHED_RT_PRIORITY_MIN..=SCHED_RT_PRIORITY_MAX;
+    match sched.sched_policy {
+        SCHED_OTHER | SCHED_BATCH | SCHED_IDLE if sched.sched_priority == 0 => {}
+        SCHED_FIFO | SCHED_RR if rt_priorities.contains(&sched.sched_priority) => {}
+        SCHED_DEADLINE => return check_deadline(sched),
+        _ => return Err(Errno::EINVAL),
+    }
+    sched.sched_runtime = 0;
+    sched.sched_deadline = 0;
+    sched.sched_period = 0;
+    Ok(())
+}
+
+/// The budget is given in microseconds to seL4, the runtime is at least one.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+fn check_deadline(sched: &mut SchedAttr) -> Result<(), Errno> {
+    if sched.sched_period == 0 {
+        sched.sched_period = sched.sched_deadline;
+    }
+    if sched.sched_runtime < 1000
+        || sched.sched_runtime > sched.sched_deadline
+        || sched.sched_deadline > sched.sched_period
+        || sched.sched_priority != 0
+    {
+        return Err(Errno::EINVAL);
+    }
+    Ok(())
+}
+
+/// Only a MCS kernel limits the time a task runs.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+fn check_deadline(_sched: &mut SchedAttr) -> Result<(), Errno> {
+    Err(Errno::EINVAL)
+}
+
+/// Schedule the `task` with the attributes `sched`.
+///
+/// Fails with [Errno::EBUSY] if the kernel refuses the budget, as linux does
+/// when it can't admit a `SCHED_DEADLINE` task.
+fn set_sched(task: &mut Sel4Task, mut sched: SchedAttr) -> SysResult {
+    check_sched(&mut sched)?;
+    let old = core::mem::replace(&mut task.sched, sched);
+    update_sched(task).map_err(|_| {
+        task.sched = old;
+        Errno::EBUSY
+    })?;
+    Ok(0)
+}
+
+/// The time slice of the round robin, the default budget on a MCS kernel.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+fn time_slice_nanos(sched: &SchedAttr) -> u64 {
+    super::schedule::budget(sched).0 * 1000
+}
+
+/// The time slice of the round robin.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+fn time_slice_nanos(_sched: &SchedAttr) -> u64 {
+    let ticks = sel4::sel4_cfg_usize!(TIME_SLICE) * sel4::sel4_cfg_usize!(TIMER_TICK_MS);
+    ticks as u64 * 1_000_000
+}
+
+pub(crate) fn sys_sched_setattr(
+    badge: u64,
+    pid: usize,
+    attr: *const SchedAttr,
+    flags: usize,
+) -> SysResult {
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut task_map = TASK_MAP.lock();
+    let mut sched = read_item(task_map.get(&badge).unwrap(), attr)?;
+    // The fields added by the later versions are ignored.
+    match sched.size {
+        size if size != 0 && size < SCHED_ATTR_SIZE_VER0 => return Err(Errno::E2BIG),
+        _ => sched.size = SCHED_ATTR_SIZE_VER0,
+    }
+    // TODO: Support the other flags, such as `SCHED_FLAG_KEEP_POLICY`.
+    if sched.sched_flags & !SCHED_FLAG_RESET_ON_FORK != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let task = task_map
+        .get_mut(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?;
+    set_sched(task, sched)
+}
+
+pub(crate) fn sys_sched_getattr(
+    badge: u64,
+    pid: usize,
+    attr: *mut SchedAttr,
+    size: usize,
+    flags: usize,
+) -> SysResult {
+    if flags != 0 || size < SCHED_ATTR_SIZE_VER0 as usize {
+        return Err(Errno::EINVAL);
+    }
+    let task_map = TASK_MAP.lock();
+    let sched = task_map
+        .get(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?
+        .sched;
+    write_item(task_map.get(&badge).unwrap(), attr, &sched)?;
+    Ok(0)
+}
+
+/// `SCHED_DEADLINE` is only set by `sched_setattr`.
+pub(crate) fn sys_sched_setscheduler(
+    badge: u64,
+    pid: usize,
+    policy: usize,
+    param: *const SchedParam,
+) -> SysResult {
+    let mut task_map = TASK_MAP.lock();
+    let param = read_item(task_map.get(&badge).unwrap(), param)?;
+    let policy = u32::try_from(policy).map_err(|_| Errno::EINVAL)?;
+    let sched_policy = policy & !SCHED_RESET_ON_FORK;
+    if sched_policy == SCHED_DEADLINE || param.sched_priority < 0 {
+        return Err(Errno::EINVAL);
+    }
+    let task = task_map
+        .get_mut(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?;
+    let sched = SchedAttr {
+        size: SCHED_ATTR_SIZE_VER0,
+        sched_policy,
+        sched_flags: match policy & SCHED_RESET_ON_FORK {
+            0 => 0,
+            _ => SCHED_FLAG_RESET_ON_FORK,
+        },
+        sched_nice: task.sched.sched_nice,
+        sched_priority: param.sched_priority as _,
+        ..Default::default()
+    };
+    set_sched(task, sched)
+}
+
+/// The policy is or-ed with `SCHED_RESET_ON_FORK` if it is set.
+pub(crate) fn sys_sched_getscheduler(badge: u64, pid: usize) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let sched = task_map
+        .get(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?
+        .sched;
+    match sched.sched_flags & SCHED_FLAG_RESET_ON_FORK {
+        0 => Ok(sched.sched_policy as _),
+        _ => Ok((sched.sched_policy | SCHED_RESET_ON_FORK) as _),
+    }
+}
+
+pub(crate) fn sys_sched_setparam(badge: u64, pid: usize, param: *const SchedParam) -> SysResult {
+    let mut task_map = TASK_MAP.lock();
+    let param = read_item(task_map.get(&badge).unwrap(), param)?;
+    if param.sched_priority < 0 {
+        return Err(Errno::EINVAL);
+    }
+    let task = task_map
+        .get_mut(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?;
+    let sched = SchedAttr {
+        sched_priority: param.sched_priority as _,
+        ..task.sched
+    };
+    set_sched(task, sched)
+}
+
+pub(crate) fn sys_sched_getparam(badge: u64, pid: usize, param: *mut SchedParam) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let sched_priority = task_map
+        .get(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?
+        .sched
+        .sched_priority as _;
+    write_item(
+        task_map.get(&badge).unwrap(),
+        param,
+        &SchedParam { sched_priority },
+    )?;
+    Ok(0)
+}
+
+pub(crate) fn sys_sched_get_priority_max(policy: usize) -> SysResult {
+    match policy as u32 {
+        SCHED_FIFO | SCHED_RR => Ok(SCHED_RT_PRIORITY_MAX as _),
+        SCHED_OTHER | SCHED_BATCH | SCHED_IDLE | SCHED_DEADLINE => Ok(0),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+pub(crate) fn sys_sched_get_priority_min(policy: usize) -> SysResult {
+    match policy as u32 {
+        SCHED_FIFO | SCHED_RR => Ok(SCHED_RT_PRIORITY_MIN as _),
+        SCHED_OTHER | SCHED_BATCH | SCHED_IDLE | SCHED_DEADLINE => Ok(0),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+pub(crate) fn sys_sched_rr_get_interval(badge: u64, pid: usize, tp: *mut TimeSpec) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let sched = task_map
+        .get(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?
+        .sched;
+    let interval = TimeSpec::from_nanos(time_slice_nanos(&sched));
+    write_item(task_map.get(&badge).unwrap(), tp, &interval)?;
+    Ok(0)
+}
+
+/// Returns `20 - nice` as the syscall of linux does, the C library turns it
+/// back into the nice value.
+pub(crate) fn sys_getpriority(badge: u64, which: usize, who: usize) -> SysResult {
+    match which {
+        PRIO_PROCESS => {}
+        // TODO: Support the process groups and the users.
+        PRIO_PGRP | PRIO_USER => return Err(Errno::EINVAL),
+        _ => return Err(Errno::EINVAL),
+    }
+    let task_map = TASK_MAP.lock();
+    let nice = task_map
+        .get(&target_task(badge, who))
+        .ok_or(Errno::ESRCH)?
+        .sched
+        .sched_nice;
+    Ok((20 - nice) as _)
+}
+
+/// The nice value is clamped to the range of linux.
+pub(crate) fn sys_setpriority(badge: u64, which: usize, who: usize, nice: i32) -> SysResult {
+    match which {
+        PRIO_PROCESS => {}
+        // TODO: Support the process groups and the users.
+        PRIO_PGRP | PRIO_USER => return Err(Errno::EINVAL),
+        _ => return Err(Errno::EINVAL),
+    }
+    let mut task_map = TASK_MAP.lock();
+    let task = task_map
+        .get_mut(&target_task(badge, who))
+        .ok_or(Errno::ESRCH)?;
+    let sched = SchedAttr {
+        sched_nice: nice,
+        ..task.sched
+    };
+    set_sched(task, sched)
+}
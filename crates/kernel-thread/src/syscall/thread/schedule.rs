@@ -1,22 +1,327 @@
-use crate::{child_test::TASK_MAP, syscall::SysResult};
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sel4::{cap::Endpoint, init_thread};
+use spin::Mutex;
+use syscalls::Errno;
 
-pub(crate) fn sys_exit(badge: u64, exit_code: i32) -> SysResult {
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{wait, SysResult},
+    task::Sel4Task,
+    utils::{read_item, write_item, write_item_list},
+};
+
+use super::{futex::futex_wake, policy::sel4_priority};
+
+/// The cores the kernel is built with, as a `cpu_set_t` mask.
+pub(crate) const ONLINE_CORES: u64 = u64::MAX >> (64 - sel4::sel4_cfg_usize!(MAX_NUM_NODES));
+
+/// Pick a core of `affinity` for a task, the cores are taken in turn so the
+/// tasks are spread over them.
+fn next_core(affinity: u64) -> usize {
+    static NEXT_CORE: AtomicUsize = AtomicUsize::new(0);
+    let start = NEXT_CORE.fetch_add(1, Ordering::Relaxed) % sel4::sel4_cfg_usize!(MAX_NUM_NODES);
+    // The first core of `affinity` from `start`, wrapping around.
+    (start + affinity.rotate_right(start as u32).trailing_zeros() as usize) % 64
+}
+
+/// `wait4` returns at once if no child has exited.
+const WNOHANG: u32 = 1;
+/// The stopped and the continued children are accepted but never reported,
+/// as the tasks are never stopped.
+const WUNTRACED: u32 = 2;
+const WCONTINUED: u32 = 8;
+/// `__WNOTHREAD`, `__WALL` and `__WCLONE`, the children are the same to them.
+const WAIT_THREAD_OPTIONS: u32 = 0xe000_0000;
+
+/// The size of `struct rusage`, the usage isn't accounted and it is filled
+/// with zeros.
+const RUSAGE_SIZE: usize = 144;
+
+/// A thread group whose tasks have all exited, until its parent collects the
+/// exit status with `wait4`.
+struct Zombie {
+    pid: usize,
+    ppid: usize,
+    /// The exit status in the format of `wstatus`.
+    status: i32,
+}
+
+static ZOMBIES: Mutex<Vec<Zombie>> = Mutex::new(Vec::new());
+
+/// Remove the exited task and release its resources.
+///
+/// The last task of a thread group leaves the `exit_code` to its parent. The
+/// files and the memory are freed with the last task sharing them, the IPC
+/// buffer is unmapped if other tasks share the memory so its address can be
+/// reused.
+fn reap(badge: u64, exit_code: i32) {
+    wait::cancel_waiters(badge);
     let mut task_map = TASK_MAP.lock();
-    let task = task_map.get_mut(&badge).unwrap();
-    task.exit = Some(exit_code);
+    let mut task = task_map.remove(&badge).unwrap();
+    if !task_map.values().any(|other| other.pid == task.pid) {
+        let mut zombies = ZOMBIES.lock();
+        // Nothing waits for the children of the group anymore.
+        zombies.retain(|zombie| zombie.ppid != task.pid);
+        if task_map.values().any(|other| other.pid == task.ppid) {
+            zombies.push(Zombie {
+                pid: task.pid,
+                ppid: task.ppid,
+                status: (exit_code & 0xff) << 8,
+            });
+            wait::request_wake();
+        }
+    }
+    drop(task_map);
     task.tcb.tcb_suspend().unwrap();
+    // `CLONE_CHILD_CLEARTID`, the threads joining it wait on the futex.
+    if let Some(tidptr) = task.clear_child_tid.take() {
+        if write_item(&task, tidptr as *const i32, &0).is_ok() {
+            futex_wake(&task, tidptr, 1);
+        }
+    }
+    if Arc::strong_count(&task.mem) > 1 {
+        task.unmap_page(task.ipc_buffer);
+    }
+    // The files are dropped without holding the lock of the task map.
+    drop(task);
+}
+
+pub(crate) fn sys_exit(badge: u64, exit_code: i32) -> SysResult {
+    reap(badge, exit_code);
     Ok(0)
 }
 
+/// Exit all the threads in the thread group of the task `badge`.
 pub(crate) fn sys_exit_group(badge: u64, exit_code: i32) -> SysResult {
-    let mut task_map = TASK_MAP.lock();
-    let task = task_map.get_mut(&badge).unwrap();
-    task.exit = Some(exit_code);
-    task.tcb.tcb_suspend().unwrap();
+    let task_map = TASK_MAP.lock();
+    let pid = task_map.get(&badge).unwrap().pid;
+    let threads: Vec<u64> = task_map
+        .iter()
+        .filter(|(_, task)| task.pid == pid)
+        .map(|(badge, _)| *badge)
+        .collect();
+    drop(task_map);
+    threads
+        .into_iter()
+        .for_each(|thread| reap(thread, exit_code));
     Ok(0)
 }
 
+/// Wait for a child of the task `badge` matching `pid` to exit.
+///
+/// The process groups aren't kept, a `pid` of zero or below matches any
+/// child.
+pub(crate) fn sys_wait4(
+    badge: u64,
+    pid: i32,
+    wstatus: *mut i32,
+    options: u32,
+    rusage: *mut u8,
+) -> SysResult {
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED | WAIT_THREAD_OPTIONS) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (wstatus, rusage) = (wstatus as usize, rusage as usize);
+    let nohang = options & WNOHANG != 0;
+    match wait::block_on(badge, nohang, move || {
+        collect_zombie(badge, pid, wstatus, rusage)
+    }) {
+        // Only returned without waiting.
+        Err(Errno::EAGAIN) => Ok(0),
+        res => res,
+    }
+}
+
+/// Collect the exit status of a child of the task `badge` matching `pid`,
+/// fails with [Errno::EAGAIN] if the matching children are all running.
+fn collect_zombie(badge: u64, pid: i32, wstatus: usize, rusage: usize) -> SysResult {
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).ok_or(Errno::ESRCH)?;
+    let matches = |child: usize| pid <= 0 || child == pid as usize;
+    let mut zombies = ZOMBIES.lock();
+    let Some(index) = zombies
+        .iter()
+        .position(|zombie| zombie.ppid == task.pid && matches(zombie.pid))
+    else {
+        let running = task_map
+            .values()
+            .any(|child| child.ppid == task.pid && matches(child.pid));
+        return Err(match running {
+            true => Errno::EAGAIN,
+            false => Errno::ECHILD,
+        });
+    };
+    if wstatus != 0 {
+        write_item(task, wstatus as *const i32, &zombies[index].status)?;
+    }
+    if rusage != 0 {
+        write_item_list(
+            task,
+            rusage as *mut u8,
+            Some(RUSAGE_SIZE),
+            &[0; RUSAGE_SIZE],
+        )?;
+    }
+    Ok(zombies.swap_remove(index).pid)
+}
+
+/// The task is put behind the others of its priority when the syscall is
+/// replied, the kernel thread runs above them and doesn't need to yield.
 pub(crate) fn sys_sched_yield() -> SysResult {
-    sel4::r#yield();
     Ok(0)
 }
+
+/// The budget of the tasks in each period on a MCS kernel, in microseconds,
+/// unless they are `SCHED_DEADLINE`. The rest is left to the components.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+const DEFAULT_BUDGET_US: u64 = 5_000;
+#[sel4::sel4_cfg(KERNEL_MCS)]
+const DEFAULT_PERIOD_US: u64 = 10_000;
+
+/// The slot the badged fault endpoint is minted into to bind the scheduling
+/// context, it is looked up in the CSpace of the kernel thread.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+static FAULT_EP_SLOT: spin::Lazy<Endpoint> = spin::Lazy::new(|| {
+    let (_, _, index) = crate::OBJ_ALLOCATOR.lock().allocate_slot();
+    Endpoint::from_bits(index as _)
+});
+
+/// Give the TCB of the `task` its priority, a core of its affinity and, on a
+/// MCS kernel, its scheduling context and the `fault_ep` minted with its
+/// badge.
+///
+/// It is called after the TCB is configured, before it is resumed.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub(crate) fn bind_sched(task: &mut Sel4Task, fault_ep: Endpoint) -> sel4::Result<()> {
+    task.core = next_core(task.affinity);
+    place(task)?;
+    let cnode = init_thread::slot::CNODE.cap();
+    let slot = *FAULT_EP_SLOT;
+    cnode.relative(slot).mint(
+        &cnode.relative(fault_ep),
+        sel4::CapRights::all(),
+        task.id as _,
+    )?;
+    let result = task.tcb.tcb_set_sched_params(
+        init_thread::slot::TCB.cap(),
+        0,
+        sel4_priority(&task.sched),
+        task.sched_context,
+        slot,
+    );
+    cnode.relative(slot).delete()?;
+    result
+}
+
+/// Give the TCB of the `task` its priority and a core of its affinity, the
+/// fault endpoint is set by `tcb_configure`.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub(crate) fn bind_sched(task: &mut Sel4Task, _fault_ep: Endpoint) -> sel4::Result<()> {
+    task.core = next_core(task.affinity);
+    update_sched(task)
+}
+
+/// Apply the scheduling attributes and the affinity of the `task` to its TCB.
+///
+/// The task stays on its core if the core is still in its affinity.
+pub(crate) fn update_sched(task: &mut Sel4Task) -> sel4::Result<()> {
+    if task.affinity & (1 << task.core) == 0 {
+        task.core = next_core(task.affinity);
+    }
+    task.tcb
+        .tcb_set_priority(init_thread::slot::TCB.cap(), sel4_priority(&task.sched))?;
+    place(task)
+}
+
+/// The budget and the period of the `sched` on a MCS kernel, in
+/// microseconds.
+///
+/// `SCHED_DEADLINE` runs for `sched_runtime` in each `sched_period`, the
+/// deadline of seL4 is the end of the period.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub(super) fn budget(sched: &common::SchedAttr) -> (u64, u64) {
+    match sched.sched_policy {
+        common::SCHED_DEADLINE => (sched.sched_runtime / 1000, sched.sched_period / 1000),
+        _ => (DEFAULT_BUDGET_US, DEFAULT_PERIOD_US),
+    }
+}
+
+/// Configure the scheduling context of the `task` with its budget on its
+/// core, the task moves to the core with it.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+fn place(task: &Sel4Task) -> sel4::Result<()> {
+    use crate_consts::slots::kernel_thread::SCHED_CONTROL;
+
+    let (budget, period) = budget(&task.sched);
+    sel4::cap::SchedControl::from_bits(SCHED_CONTROL + task.core as u64)
+        .sched_control_configure_flags(task.sched_context, budget, period, 0, 0, 0)
+}
+
+/// Run the `task` on its core.
+///
+/// The TCB of seL4 runs on one core, the task isn't moved between the cores
+/// of its mask once placed.
+#[sel4::sel4_cfg(all(not(KERNEL_MCS), not(MAX_NUM_NODES = "1")))]
+fn place(task: &Sel4Task) -> sel4::Result<()> {
+    task.tcb.tcb_set_affinity(task.core as _)
+}
+
+/// Run the `task` on its core, the kernel is built with only one.
+#[sel4::sel4_cfg(all(not(KERNEL_MCS), MAX_NUM_NODES = "1"))]
+fn place(_task: &Sel4Task) -> sel4::Result<()> {
+    Ok(())
+}
+
+/// The badge of the task `pid`, 0 is the calling task.
+pub(super) fn target_task(badge: u64, pid: usize) -> u64 {
+    match pid {
+        0 => badge,
+        pid => pid as u64,
+    }
+}
+
+pub(crate) fn sys_sched_setaffinity(
+    badge: u64,
+    pid: usize,
+    cpusetsize: usize,
+    mask: *const u64,
+) -> SysResult {
+    if cpusetsize < core::mem::size_of::<u64>() {
+        return Err(Errno::EINVAL);
+    }
+    let mut task_map = TASK_MAP.lock();
+    let affinity = read_item(task_map.get(&badge).unwrap(), mask)? & ONLINE_CORES;
+    if affinity == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let task = task_map
+        .get_mut(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?;
+    let old = (task.affinity, task.core);
+    task.affinity = affinity;
+    update_sched(task).map_err(|_| {
+        (task.affinity, task.core) = old;
+        Errno::EINVAL
+    })?;
+    Ok(0)
+}
+
+/// Returns the size of the mask written, the tasks run on at most 64 cores.
+pub(crate) fn sys_sched_getaffinity(
+    badge: u64,
+    pid: usize,
+    cpusetsize: usize,
+    mask: *mut u64,
+) -> SysResult {
+    if cpusetsize < core::mem::size_of::<u64>() {
+        return Err(Errno::EINVAL);
+    }
+    let task_map = TASK_MAP.lock();
+    let affinity = task_map
+        .get(&target_task(badge, pid))
+        .ok_or(Errno::ESRCH)?
+        .affinity;
+    write_item(task_map.get(&badge).unwrap(), mask, &affinity)?;
+    Ok(core::mem::size_of::<u64>())
+}
@@ -1,39 +1,33 @@
-use core::{cmp, ops::DerefMut};
+use alloc::{sync::Arc, vec};
 
-use common::{footprint, map_image, CloneArgs, CloneFlags, USPACE_STACK_SIZE, USPACE_STACK_TOP};
+use common::{CloneArgs, CloneFlags, USPACE_STACK_SIZE, USPACE_STACK_TOP};
 use crate_consts::{CNODE_RADIX_BITS, DEFAULT_THREAD_FAULT_EP, GRANULE_SIZE};
-use object::File;
 use sel4::{
     cap::Endpoint,
     cap_type::{self},
     init_thread, CNodeCapData, Cap, CapRights, VmAttributes,
 };
+use spin::Mutex;
 use syscalls::Errno;
-use xmas_elf::ElfFile;
 
 use crate::{
     child_test::TASK_MAP,
-    page_seat_vaddr,
-    syscall::SysResult,
+    loader::Program,
+    page_seat,
+    syscall::{bind_sched, read_file, SysResult, PATH_MAX},
     task::Sel4Task,
-    utils::{init_free_page_addr, read_item, FreePagePlaceHolder},
+    utils::{read_c_string, read_c_string_array, read_item, write_item, FreePagePlaceHolder},
     OBJ_ALLOCATOR,
 };
 
+use super::policy::fork_sched;
+
 pub(crate) fn sys_getpid(badge: u64) -> SysResult {
     Ok(TASK_MAP.lock().get(&badge).unwrap().pid as usize)
 }
 
 pub(crate) fn sys_getppid(badge: u64) -> SysResult {
-    Ok(TASK_MAP.lock().get(&badge).unwrap().pid as usize)
-}
-
-pub(crate) fn sys_getuid(badge: u64) -> SysResult {
-    Ok(TASK_MAP.lock().get(&badge).unwrap().id as usize)
-}
-
-pub(crate) fn sys_geteuid(badge: u64) -> SysResult {
-    Ok(TASK_MAP.lock().get(&badge).unwrap().id as usize)
+    Ok(TASK_MAP.lock().get(&badge).unwrap().ppid as usize)
 }
 
 pub(crate) fn sys_gettid(badge: usize) -> SysResult {
@@ -45,81 +39,91 @@ pub(crate) fn sys_set_tid_address(badge: u64, tidptr: *mut i32) -> SysResult {
     Ok(badge as usize)
 }
 
+/// The bits of the flags of `clone` holding the exit signal, the same as
+/// `CSIGNAL` in linux.
+const CSIGNAL: usize = 0xff;
+
+/// The program run for the paths outside the root file system.
 const CHILD_ELF: &[u8] = include_bytes!("../../../../../build/shim.elf");
 
+/// The most bytes of the strings of `argv` and `envp` with their pointers, so
+/// the initial stack fits in the stack.
+const ARG_MAX: usize = USPACE_STACK_SIZE / 4;
+
 pub(crate) fn sys_exec(
     badge: u64,
     fault_ep: Endpoint,
-    _path: *const u8,
-    _argv: *const u8,
-    _envp: *const u8,
+    path: *const u8,
+    argv: *const usize,
+    envp: *const usize,
 ) -> SysResult {
     let mut task_map = TASK_MAP.lock();
     let task = task_map.get_mut(&badge).unwrap();
-    let args = &["busybox", "--help"];
 
-    task.mapped_page.clear();
-    task.mapped_pt.clear();
-
-    let child_image = File::parse(CHILD_ELF).unwrap();
-    let mut allocator = OBJ_ALLOCATOR.lock();
-    map_image(
-        allocator.deref_mut(),
-        &mut task.mapped_page,
-        task.vspace,
-        footprint(&child_image),
-        &child_image,
-        init_thread::slot::VSPACE.cap(),
-        unsafe { init_free_page_addr() },
-    );
+    // Read the strings before the memory holding them is freed.
+    let path = match path.is_null() {
+        true => None,
+        false => Some(read_c_string(task, path, PATH_MAX)?),
+    };
+    let data = match &path {
+        None => CHILD_ELF,
+        Some(path) => match read_file(path) {
+            Err(Errno::ENOENT) => CHILD_ELF,
+            data => data?,
+        },
+    };
+    let mut args = read_c_string_array(task, argv, ARG_MAX)?;
+    let args_len = args
+        .iter()
+        .map(|arg| arg.len() + 1 + core::mem::size_of::<usize>())
+        .sum::<usize>();
+    let envs = read_c_string_array(task, envp, ARG_MAX - args_len)?;
+    if args.is_empty() {
+        args = vec!["busybox".into(), "--help".into()];
+    }
+    // The shim run for the null path is named by `argv[0]`.
+    let execfn = path.unwrap_or_else(|| args[0].clone());
+    let program = Program::parse(data)?;
 
-    drop(allocator);
+    // The old memory is freed unless other tasks share it.
+    task.reset_memory();
+    task.file_table.lock().close_on_exec();
+    let info = task.load_elf(&program);
 
     let sp_ptr = task.map_stack(
-        0,
+        &info,
         USPACE_STACK_TOP - USPACE_STACK_SIZE,
         USPACE_STACK_TOP,
-        args,
+        &execfn,
+        &args,
+        &envs,
     );
 
-    let file = ElfFile::new(CHILD_ELF).expect("can't load elf file");
-    let ipc_buffer_cap = OBJ_ALLOCATOR
-        .lock()
-        .allocate_and_retyped_fixed_sized::<cap_type::Granule>();
-    let max = file
-        .section_iter()
-        .fold(0, |acc, x| cmp::max(acc, x.address() + x.size()));
-
-    let ipc_buffer_addr = (max + 4096 - 1) / 4096 * 4096;
-
-    task.map_page(ipc_buffer_addr as _, ipc_buffer_cap);
+    let ipc_buffer_cap = task.map_ipc_buffer();
+    let ipc_buffer_addr = task.ipc_buffer as u64;
 
     // Configure the child task
-    task.tcb
-        .tcb_configure(
-            fault_ep.cptr(),
-            task.cnode,
-            CNodeCapData::new(0, sel4::WORD_SIZE - 12),
-            task.vspace,
-            ipc_buffer_addr,
-            ipc_buffer_cap,
-        )
-        .unwrap();
+    common::tcb_configure(
+        task.tcb,
+        fault_ep.cptr(),
+        task.cnode,
+        CNodeCapData::new(0, sel4::WORD_SIZE - 12),
+        task.vspace,
+        ipc_buffer_addr,
+        ipc_buffer_cap,
+    )
+    .unwrap();
 
-    task.tcb
-        .tcb_set_sched_params(init_thread::slot::TCB.cap(), 0, 255)
-        .unwrap();
+    bind_sched(task, fault_ep).unwrap();
 
     let mut user_context = sel4::UserContext::default();
 
     // Set child task's context
-    *user_context.pc_mut() = file.header.pt2.entry_point();
+    *user_context.pc_mut() = info.start as _;
     *user_context.sp_mut() = sp_ptr as _;
     *user_context.gpr_mut(1) = ipc_buffer_addr;
-    // Get TSS section address.
-    user_context.inner_mut().tpidr_el0 = file
-        .find_section_by_name(".tbss")
-        .map_or(0, |tls| tls.address());
+    user_context.inner_mut().tpidrro_el0 = ipc_buffer_addr;
+    user_context.inner_mut().tpidr_el0 = info.tls as _;
 
     task.tcb
         .tcb_write_all_registers(false, &mut user_context)
@@ -139,15 +143,55 @@ pub(crate) fn sys_clone(
     clone_args: *const CloneArgs,
     _size: usize,
 ) -> SysResult {
-    let mut task_map = TASK_MAP.lock();
-    let task = task_map.get_mut(&badge).unwrap();
-
     if clone_args.is_null() {
         return Err(Errno::EINVAL);
     }
-    let clone_args: CloneArgs = read_item(task, clone_args)?;
+    let clone_args: CloneArgs = read_item(TASK_MAP.lock().get(&badge).unwrap(), clone_args)?;
+    clone_task(badge, fault_ep, clone_args, false)
+}
+
+/// `clone` called by `svc` with the arguments of linux on aarch64, the
+/// signal sent to the parent at the exit is in the lowest byte of `flags`.
+pub(crate) fn sys_clone_svc(
+    badge: u64,
+    fault_ep: Endpoint,
+    flags: usize,
+    stack: *const u8,
+    parent_tid: *const u8,
+    tls: *const u8,
+    child_tid: *const u8,
+) -> SysResult {
+    let clone_args = CloneArgs {
+        stack,
+        flags: (flags & !CSIGNAL) as i32,
+        parent_tid,
+        child_tid,
+        tls,
+        ..Default::default()
+    };
+    clone_task(badge, fault_ep, clone_args, true)
+}
+
+/// Create the child of the task `badge` described by `clone_args`.
+///
+/// The child starts at `init_fn` if it is given, or returns from the
+/// syscall: the parent called by `svc` is moved past it already and the
+/// child returns 0, the one called by IPC skips the call and the shim tells
+/// the child by its tid.
+fn clone_task(badge: u64, fault_ep: Endpoint, clone_args: CloneArgs, svc: bool) -> SysResult {
+    let mut task_map = TASK_MAP.lock();
+    let task = task_map.get_mut(&badge).unwrap();
 
     let clone_flags = CloneFlags::from_bits(clone_args.flags).ok_or(Errno::EINVAL)?;
+    // The threads of a group share the signal handlers, which need the memory.
+    if (clone_flags.contains(CloneFlags::CLONE_THREAD)
+        && !clone_flags.contains(CloneFlags::CLONE_SIGHAND))
+        || (clone_flags.contains(CloneFlags::CLONE_SIGHAND)
+            && !clone_flags.contains(CloneFlags::CLONE_VM))
+    {
+        return Err(Errno::EINVAL);
+    }
+    let sched = fork_sched(&task.sched)?;
 
     // Default to clone without any flags
     let mut new_task = Sel4Task::new();
@@ -171,64 +215,79 @@ pub(crate) fn sys_clone(
             badge,
         )
         .map_err(|_| Errno::ENOMEM)?;
-    if !clone_flags.contains(CloneFlags::CLONE_VM) {
+    if clone_flags.contains(CloneFlags::CLONE_THREAD) {
+        new_task.pid = task.pid;
+        new_task.ppid = task.ppid;
+    } else {
+        new_task.ppid = task.pid;
+    }
+    new_task.file_table = if clone_flags.contains(CloneFlags::CLONE_FILES) {
+        task.file_table.clone()
+    } else {
+        Arc::new(Mutex::new(task.file_table.lock().clone()))
+    };
+    let ipc_buffer_cap = if !clone_flags.contains(CloneFlags::CLONE_VM) {
         // Copy vspace to child
         clone_vspace(&mut new_task, &task);
+        // The child has the copy of the IPC buffer of the parent.
+        new_task.ipc_buffer = task.ipc_buffer;
+        new_task.mem.lock().mapped_page[&task.ipc_buffer]
     } else {
-        let (_, _, new_vspace_index) = OBJ_ALLOCATOR.lock().allocate_slot();
-        let new_vspace = Cap::<cap_type::VSpace>::from_bits(new_vspace_index as u64);
-        init_thread::slot::CNODE
-            .cap()
-            .relative(new_vspace)
-            .copy(
-                &init_thread::slot::CNODE.cap().relative(task.vspace),
-                CapRights::all(),
-            )
-            .unwrap();
-
-        new_task.vspace = new_vspace;
-    }
-    let ipc_buffer_cap = OBJ_ALLOCATOR
-        .lock()
-        .allocate_and_retyped_fixed_sized::<cap_type::Granule>();
-
-    let ipc_buffer_addr = 0x4_0000;
-    new_task.map_page(ipc_buffer_addr as _, ipc_buffer_cap);
+        // The memory of the child is freed as it is replaced.
+        new_task.vspace = task.vspace;
+        new_task.mem = task.mem.clone();
+        new_task.map_ipc_buffer()
+    };
     // Configure the child task
-    new_task
-        .tcb
-        .tcb_configure(
-            fault_ep.cptr(),
-            new_task.cnode,
-            CNodeCapData::new(0, sel4::WORD_SIZE - CNODE_RADIX_BITS),
-            new_task.vspace,
-            ipc_buffer_addr,
-            ipc_buffer_cap,
-        )
-        .map_err(|_| Errno::ENOMEM)?;
-    new_task
-        .tcb
-        .tcb_set_sched_params(init_thread::slot::TCB.cap(), 0, 255)
-        .map_err(|_| Errno::ENOMEM)?;
+    common::tcb_configure(
+        new_task.tcb,
+        fault_ep.cptr(),
+        new_task.cnode,
+        CNodeCapData::new(0, sel4::WORD_SIZE - CNODE_RADIX_BITS),
+        new_task.vspace,
+        new_task.ipc_buffer as _,
+        ipc_buffer_cap,
+    )
+    .map_err(|_| Errno::ENOMEM)?;
 
     let mut regs = task.tcb.tcb_read_all_registers(false).unwrap();
-    if clone_args.init_fn.is_null() {
-        *regs.pc_mut() += 4;
-    } else {
+    if !clone_args.init_fn.is_null() {
         *regs.pc_mut() = clone_args.init_fn as _;
         *regs.gpr_mut(0) = clone_args.init_argv as _;
+    } else if svc {
+        *regs.gpr_mut(0) = 0;
+    } else {
+        *regs.pc_mut() += 4;
     }
 
     if !clone_args.stack.is_null() {
         *regs.sp_mut() = clone_args.stack as _;
     }
+    regs.inner_mut().tpidrro_el0 = new_task.ipc_buffer as _;
+    if clone_flags.contains(CloneFlags::CLONE_SETTLS) {
+        regs.inner_mut().tpidr_el0 = clone_args.tls as _;
+    }
 
     new_task
         .tcb
         .tcb_write_all_registers(false, &mut regs)
         .unwrap();
 
-    // task.tcb.tcb_set_affinity(0).unwrap();
+    let tid = badge as i32;
+    if clone_flags.contains(CloneFlags::CLONE_PARENT_SETTID) {
+        write_item(task, clone_args.parent_tid as *const i32, &tid)?;
+    }
+    if clone_flags.contains(CloneFlags::CLONE_CHILD_SETTID) {
+        write_item(&new_task, clone_args.child_tid as *const i32, &tid)?;
+    }
+    if clone_flags.contains(CloneFlags::CLONE_CHILD_CLEARTID) {
+        new_task.clear_child_tid = Some(clone_args.child_tid as usize);
+    }
+
+    new_task.cred = task.cred;
+    new_task.affinity = task.affinity;
+    new_task.sched = sched;
+    bind_sched(&mut new_task, fault_ep).map_err(|_| Errno::ENOMEM)?;
     new_task.tcb.debug_name(b"before name");
 
     new_task.tcb.tcb_resume().unwrap();
@@ -237,15 +296,19 @@ pub(crate) fn sys_clone(
     Ok(badge as usize)
 }
 
-fn clone_vspace(dst: &mut Sel4Task, src: &Sel4Task) {
+/// Copy the pages of `src` to `dst` for `fork`.
+pub(crate) fn clone_vspace(dst: &mut Sel4Task, src: &Sel4Task) {
     /// free page placeholder
     static mut EXT_FREE_PAGE_PLACEHOLDER: FreePagePlaceHolder =
         FreePagePlaceHolder([0; GRANULE_SIZE]);
 
-    for (vaddr, page_cap) in src.mapped_page.iter() {
+    let mapped_page = src.mem.lock().mapped_page.clone();
+    for (vaddr, page_cap) in mapped_page.iter() {
         let new_page_cap = OBJ_ALLOCATOR
             .lock()
             .allocate_and_retyped_fixed_sized::<cap_type::Granule>();
+        // The placeholder is guarded by the page seat as well.
+        let seat = page_seat();
 
         // READ data from src page to new_page
         new_page_cap
@@ -270,7 +333,7 @@ fn clone_vspace(dst: &mut Sel4Task, src: &Sel4Task) {
         temp_cap
             .frame_map(
                 init_thread::slot::VSPACE.cap(),
-                page_seat_vaddr(),
+                seat.vaddr(),
                 CapRights::all(),
                 VmAttributes::DEFAULT,
             )
@@ -278,7 +341,7 @@ fn clone_vspace(dst: &mut Sel4Task, src: &Sel4Task) {
 
         unsafe {
             core::ptr::copy(
-                page_seat_vaddr() as *const u8,
+                seat.vaddr() as *const u8,
                 core::ptr::addr_of!(EXT_FREE_PAGE_PLACEHOLDER) as *mut u8,
                 GRANULE_SIZE,
             );
@@ -293,6 +356,7 @@ fn clone_vspace(dst: &mut Sel4Task, src: &Sel4Task) {
             .unwrap();
 
         new_page_cap.frame_unmap().unwrap();
+        drop(seat);
 
         dst.map_page(*vaddr, new_page_cap);
     }
@@ -0,0 +1,176 @@
+//! The clocks, `clock_gettime`, `gettimeofday` and `times`.
+//!
+//! The realtime clock is the monotonic clock plus an offset, which is seeded
+//! from the PL031 RTC at boot and changed by `clock_settime`.
+
+use common::{
+    counter_frequency, current_nanos, TimeSpec, TimeVal, NANOS_PER_MICROS, NANOS_PER_SEC,
+};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    pl031::Pl031,
+    syscall::{thread::capable, SysResult},
+    utils::{read_item, write_item},
+};
+
+pub(crate) const CLOCK_REALTIME: usize = 0;
+pub(crate) const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_REALTIME_COARSE: usize = 5;
+const CLOCK_MONOTONIC_COARSE: usize = 6;
+pub(crate) const CLOCK_BOOTTIME: usize = 7;
+const CLOCK_TAI: usize = 11;
+
+/// The clock ticks per second of `times`, the same as `USER_HZ` in linux.
+pub(crate) const USER_HZ: u64 = 100;
+
+/// The same as `struct tms` in C.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Tms {
+    tms_utime: i64,
+    tms_stime: i64,
+    tms_cutime: i64,
+    tms_cstime: i64,
+}
+
+/// The realtime clock minus the monotonic clock in nanoseconds, wrapping.
+static REALTIME_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// The virtual address of the registers of the PL031.
+static RTC_VADDR: AtomicUsize = AtomicUsize::new(0);
+
+fn rtc() -> Pl031 {
+    // SAFETY: The root task maps the registers of the PL031 there, and only
+    // the kernel thread owns them.
+    unsafe { Pl031::new(RTC_VADDR.load(Ordering::Acquire)) }
+}
+
+/// Seed the realtime clock from the RTC mapped at `rtc_vaddr`, it is called
+/// once at boot.
+pub(crate) fn init_realtime(rtc_vaddr: usize) {
+    RTC_VADDR.store(rtc_vaddr, Ordering::Release);
+    let rtc = rtc();
+    rtc.init();
+    let nanos = rtc.seconds() * NANOS_PER_SEC;
+    REALTIME_OFFSET.store(nanos.wrapping_sub(current_nanos()), Ordering::Release);
+}
+
+/// The nanoseconds since the epoch.
+pub(crate) fn realtime_nanos() -> u64 {
+    current_nanos().wrapping_add(REALTIME_OFFSET.load(Ordering::Acquire))
+}
+
+/// Set the realtime clock, the RTC keeps the seconds across the reboots.
+///
+/// It fails with [Errno::EINVAL] if the RTC can't keep the time.
+fn set_realtime_nanos(nanos: u64) -> Result<(), Errno> {
+    let seconds = u32::try_from(nanos / NANOS_PER_SEC).map_err(|_| Errno::EINVAL)?;
+    REALTIME_OFFSET.store(nanos.wrapping_sub(current_nanos()), Ordering::Release);
+    rtc().set_seconds(seconds);
+    Ok(())
+}
+
+/// The current time of the clock `clock_id` in nanoseconds.
+pub(crate) fn clock_nanos(clock_id: usize) -> Result<u64, Errno> {
+    match clock_id {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE | CLOCK_TAI => Ok(realtime_nanos()),
+        // The machine never suspends, so the boot time is the monotonic time.
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+            Ok(current_nanos())
+        }
+        // TODO: Report the CPU time when it is accounted for the tasks, the
+        // clocks are unsupported until then instead of running all the time.
+        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => Err(Errno::EINVAL),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+pub(crate) fn sys_clock_gettime(badge: u64, clock_id: usize, tp: *mut TimeSpec) -> SysResult {
+    let now = TimeSpec::from_nanos(clock_nanos(clock_id)?);
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_item(task, tp, &now)?;
+    Ok(0)
+}
+
+pub(crate) fn sys_clock_settime(badge: u64, clock_id: usize, tp: *const TimeSpec) -> SysResult {
+    // Only the realtime clock is settable.
+    if clock_id != CLOCK_REALTIME {
+        return Err(Errno::EINVAL);
+    }
+    let time = {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        read_item(task, tp)?
+    };
+    if !time.is_valid() {
+        return Err(Errno::EINVAL);
+    }
+    // Setting the clock needs `CAP_SYS_TIME`.
+    if !capable(badge) {
+        return Err(Errno::EPERM);
+    }
+    set_realtime_nanos(time.as_nanos())?;
+    Ok(0)
+}
+
+pub(crate) fn sys_clock_getres(badge: u64, clock_id: usize, res: *mut TimeSpec) -> SysResult {
+    clock_nanos(clock_id)?;
+    if res.is_null() {
+        return Ok(0);
+    }
+    let resolution = TimeSpec::from_nanos(NANOS_PER_SEC.div_ceil(counter_frequency()));
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_item(task, res, &resolution)?;
+    Ok(0)
+}
+
+pub(crate) fn sys_gettimeofday(badge: u64, tv: *mut TimeVal, _tz: usize) -> SysResult {
+    if tv.is_null() {
+        return Ok(0);
+    }
+    let now = TimeVal::from_micros(realtime_nanos() / NANOS_PER_MICROS);
+    let task_map = TASK_MAP.lock();
+    let task = task_map.get(&badge).unwrap();
+    write_item(task, tv, &now)?;
+    Ok(0)
+}
+
+/// Set the realtime clock, the obsolete timezone is ignored.
+pub(crate) fn sys_settimeofday(badge: u64, tv: *const TimeVal, _tz: usize) -> SysResult {
+    if tv.is_null() {
+        return Ok(0);
+    }
+    let time = {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        read_item(task, tv)?
+    };
+    if !time.is_valid() {
+        return Err(Errno::EINVAL);
+    }
+    // Setting the clock needs `CAP_SYS_TIME`.
+    if !capable(badge) {
+        return Err(Errno::EPERM);
+    }
+    set_realtime_nanos(time.checked_nanos().ok_or(Errno::EINVAL)?)?;
+    Ok(0)
+}
+
+/// Returns the clock ticks since the boot.
+// TODO: Fill the CPU times when they are accounted for the tasks.
+pub(crate) fn sys_times(badge: u64, buf: *mut Tms) -> SysResult {
+    if !buf.is_null() {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        write_item(task, buf, &Tms::default())?;
+    }
+    Ok((current_nanos() / (NANOS_PER_SEC / USER_HZ)) as usize)
+}
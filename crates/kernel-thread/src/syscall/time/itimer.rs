@@ -0,0 +1,109 @@
+//! The interval timers of `setitimer` and `getitimer`.
+
+use common::{current_nanos, TimeVal};
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::SysResult,
+    utils::{read_item, write_item},
+};
+
+const ITIMER_REAL: usize = 0;
+
+/// The same as `struct itimerval` in C.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ITimerVal {
+    it_interval: TimeVal,
+    it_value: TimeVal,
+}
+
+/// The `ITIMER_REAL` timer of a task.
+///
+/// The expirations are computed lazily from the monotonic clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct RealTimer {
+    /// The monotonic time of the next expiration in nanoseconds.
+    deadline: Option<u64>,
+    /// The interval to reload the timer in nanoseconds, zero if one-shot.
+    interval: u64,
+}
+
+impl RealTimer {
+    /// Reload the timer for the expirations passed.
+    // TODO: Send `SIGALRM` at the expirations when the signals are supported.
+    fn update(&mut self, now: u64) {
+        let Some(deadline) = self.deadline else {
+            return;
+        };
+        if deadline > now {
+            return;
+        }
+        self.deadline = match self.interval {
+            0 => None,
+            interval => {
+                let periods = (now - deadline) / interval + 1;
+                Some(deadline.saturating_add(periods.saturating_mul(interval)))
+            }
+        };
+    }
+
+    fn value(&self, now: u64) -> ITimerVal {
+        let remaining = self.deadline.map_or(0, |deadline| deadline - now);
+        ITimerVal {
+            it_interval: TimeVal::from_micros(self.interval / 1000),
+            it_value: TimeVal::from_micros(remaining.div_ceil(1000)),
+        }
+    }
+}
+
+fn check_timeval(tv: &TimeVal) -> Result<(), Errno> {
+    match tv.is_valid() {
+        true => Ok(()),
+        false => Err(Errno::EINVAL),
+    }
+}
+
+pub(crate) fn sys_getitimer(badge: u64, which: usize, curr: *mut ITimerVal) -> SysResult {
+    if which != ITIMER_REAL {
+        return Err(Errno::EINVAL);
+    }
+    let now = current_nanos();
+    let mut task_map = TASK_MAP.lock();
+    let task = task_map.get_mut(&badge).unwrap();
+    task.real_timer.update(now);
+    let value = task.real_timer.value(now);
+    write_item(task, curr, &value)?;
+    Ok(0)
+}
+
+pub(crate) fn sys_setitimer(
+    badge: u64,
+    which: usize,
+    new: *const ITimerVal,
+    old: *mut ITimerVal,
+) -> SysResult {
+    if which != ITIMER_REAL {
+        return Err(Errno::EINVAL);
+    }
+    let now = current_nanos();
+    let mut task_map = TASK_MAP.lock();
+    let task = task_map.get_mut(&badge).unwrap();
+    let new = read_item(task, new)?;
+    check_timeval(&new.it_interval)?;
+    check_timeval(&new.it_value)?;
+
+    task.real_timer.update(now);
+    if !old.is_null() {
+        let value = task.real_timer.value(now);
+        write_item(task, old, &value)?;
+    }
+    // The timers too long to expire are never fired.
+    let value = new.it_value.checked_nanos().unwrap_or(u64::MAX);
+    task.real_timer = RealTimer {
+        deadline: (value != 0).then_some(now.saturating_add(value)),
+        interval: new.it_interval.checked_nanos().unwrap_or(u64::MAX),
+    };
+    Ok(0)
+}
@@ -0,0 +1,9 @@
+mod clock;
+mod itimer;
+mod sleep;
+mod timer;
+
+pub(crate) use clock::*;
+pub(crate) use itimer::*;
+pub(crate) use sleep::*;
+pub(crate) use timer::{timer_restarted, Deadline};
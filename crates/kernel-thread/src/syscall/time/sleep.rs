@@ -0,0 +1,83 @@
+//! `nanosleep` and `clock_nanosleep`.
+
+use common::TimeSpec;
+use syscalls::Errno;
+
+use crate::{
+    child_test::TASK_MAP,
+    syscall::{fs::console_signal_seq, wait, SysResult},
+    utils::{read_item, write_item},
+};
+
+use super::{clock::clock_nanos, timer::Deadline};
+
+/// Sleep until `clock_nanosleep` reaches the absolute time.
+const TIMER_ABSTIME: usize = 1;
+
+fn read_request(badge: u64, req: *const TimeSpec) -> Result<TimeSpec, Errno> {
+    let req = {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).unwrap();
+        read_item(task, req)?
+    };
+    match req.is_valid() {
+        true => Ok(req),
+        false => Err(Errno::EINVAL),
+    }
+}
+
+/// Write the `nanos` left of an interrupted sleep to `rem` unless it is null.
+fn write_remaining(badge: u64, rem: *mut TimeSpec, nanos: u64) -> Result<(), Errno> {
+    if !rem.is_null() {
+        let task_map = TASK_MAP.lock();
+        let task = task_map.get(&badge).ok_or(Errno::ESRCH)?;
+        write_item(task, rem, &TimeSpec::from_nanos(nanos))?;
+    }
+    Ok(())
+}
+
+/// Park the syscall until the `deadline`.
+///
+/// The sleep is interrupted with [Errno::EINTR] if the console generates a
+/// signal meanwhile, and the remaining time is written to `rem` then.
+// TODO: Interrupt the sleeps with the other signals when they are supported.
+fn sleep_until(badge: u64, mut deadline: Deadline, rem: *mut TimeSpec) -> SysResult {
+    if deadline.expired() {
+        return Ok(0);
+    }
+    let seq = console_signal_seq();
+    let rem = rem as usize;
+    wait::park(badge, move || {
+        if console_signal_seq() != seq {
+            let res = write_remaining(badge, rem as *mut TimeSpec, deadline.remaining());
+            return Some(res.and(Err(Errno::EINTR)));
+        }
+        deadline.expired().then_some(Ok(0))
+    })
+}
+
+pub(crate) fn sys_nanosleep(badge: u64, req: *const TimeSpec, rem: *mut TimeSpec) -> SysResult {
+    let req = read_request(badge, req)?;
+    sleep_until(badge, Deadline::after(req.as_nanos()), rem)
+}
+
+pub(crate) fn sys_clock_nanosleep(
+    badge: u64,
+    clock_id: usize,
+    flags: usize,
+    req: *const TimeSpec,
+    rem: *mut TimeSpec,
+) -> SysResult {
+    let now = clock_nanos(clock_id)?;
+    let req = read_request(badge, req)?;
+    match flags & TIMER_ABSTIME != 0 {
+        // The deadline of the clock is converted into the monotonic time, an
+        // absolute sleep has nothing remaining.
+        true => sleep_until(
+            badge,
+            Deadline::after(req.as_nanos().saturating_sub(now)),
+            core::ptr::null_mut(),
+        ),
+        false => sleep_until(badge, Deadline::after(req.as_nanos()), rem),
+    }
+}
@@ -0,0 +1,89 @@
+//! The client of the timer thread.
+//!
+//! A parked syscall with a timeout holds a [Deadline], which arms a timer in
+//! the timer thread when the syscall starts to wait. The timer thread signals
+//! the notification bound to the kernel thread with `TIMER_BADGE` when it
+//! expires, then the parked syscalls are retried and see the deadline passed.
+//!
+//! The timers are lost when the timer thread is restarted, the root task
+//! signals `TIMER_RESTART_BADGE` then and they are armed again.
+
+use common::{current_nanos, TimerMessageLabel};
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate_consts::slots::kernel_thread::TIMER_EP;
+use sel4::cap::Endpoint;
+
+/// The id of the next timer armed in the timer thread.
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The number of the restarts of the timer thread.
+static TIMER_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Forget the timers armed in the timer thread before it was restarted.
+pub(crate) fn timer_restarted() {
+    TIMER_EPOCH.fetch_add(1, Ordering::Relaxed);
+}
+
+fn timer_call(label: TimerMessageLabel) {
+    let ep = Endpoint::from_bits(TIMER_EP);
+    ep.call(label.build());
+}
+
+/// A point of the monotonic clock which a parked syscall waits until.
+///
+/// The timer is cancelled when the deadline is dropped with the syscall.
+pub(crate) struct Deadline {
+    /// The monotonic time in nanoseconds, [u64::MAX] never expires.
+    at: u64,
+    /// The id of the timer armed for it and the epoch of the timer thread.
+    timer: Option<(u64, u64)>,
+}
+
+impl Deadline {
+    /// The deadline at the monotonic time `at` in nanoseconds.
+    pub(crate) const fn at(at: u64) -> Self {
+        Self { at, timer: None }
+    }
+
+    /// The deadline `nanos` nanoseconds later.
+    pub(crate) fn after(nanos: u64) -> Self {
+        Self::at(current_nanos().saturating_add(nanos))
+    }
+
+    /// The deadline which never expires.
+    pub(crate) const fn never() -> Self {
+        Self::at(u64::MAX)
+    }
+
+    /// The nanoseconds until the deadline, zero if it has passed.
+    pub(crate) fn remaining(&self) -> u64 {
+        self.at.saturating_sub(current_nanos())
+    }
+
+    /// Whether the deadline has passed.
+    ///
+    /// Otherwise the timer is armed, so the parked syscall is retried when
+    /// the deadline passes.
+    pub(crate) fn expired(&mut self) -> bool {
+        if current_nanos() >= self.at {
+            return true;
+        }
+        let epoch = TIMER_EPOCH.load(Ordering::Relaxed);
+        let armed = self.timer.is_some_and(|(_, armed)| armed == epoch);
+        if !armed && self.at != u64::MAX {
+            let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+            timer_call(TimerMessageLabel::SetTimer(id, self.at));
+            self.timer = Some((id, epoch));
+        }
+        false
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        let epoch = TIMER_EPOCH.load(Ordering::Relaxed);
+        if let Some((id, _)) = self.timer.filter(|(_, armed)| *armed == epoch) {
+            timer_call(TimerMessageLabel::CancelTimer(id));
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Parking of the syscalls which can not complete now.
+//!
+//! A syscall which has to wait calls [park] with a retry function and
+//! returns its result. The reply capability of the caller is saved in a slot
+//! and the event loop does not reply. When a notification bound to the kernel
+//! thread arrives, [wake_waiters] retries all the parked syscalls and replies
+//! the ones which complete. The objects living in the kernel thread, such as
+//! unix sockets, call [request_wake] instead as nothing signals them.
+//!
+//! On a MCS kernel the reply capability is the reply object the kernel thread
+//! receives with, it is moved to the slot and another one takes its place.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use sel4::{cap::Endpoint, init_thread, with_ipc_buffer_mut, MessageInfo};
+use spin::Mutex;
+use syscalls::Errno;
+
+use crate::{syscall::SysResult, OBJ_ALLOCATOR};
+
+/// The internal errno returned by a parked syscall.
+///
+/// It is never seen by the user space, the same as `ERESTARTSYS` in linux.
+pub(crate) const SYS_PARKED: Errno = Errno::new(512);
+
+/// Retry a parked syscall, returns `None` if it still has to wait.
+type RetryFn = Box<dyn FnMut() -> Option<SysResult> + Send>;
+
+struct Waiter {
+    /// The badge of the task calling the syscall.
+    badge: u64,
+    /// The slot saving the reply capability of the caller.
+    reply: Endpoint,
+    retry: RetryFn,
+}
+
+static WAITERS: Mutex<Vec<Waiter>> = Mutex::new(Vec::new());
+
+/// Whether the parked syscalls should be retried, see [request_wake].
+static WAKE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// The slots of the reply objects freed after replying, reused to receive.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+static IDLE_REPLIES: Mutex<Vec<Endpoint>> = Mutex::new(Vec::new());
+
+/// Park the current syscall of the task `badge`.
+///
+/// It must be called while handling the syscall, before the kernel thread
+/// receives the next message, as the reply capability is saved here.
+pub(crate) fn park(
+    badge: u64,
+    retry: impl FnMut() -> Option<SysResult> + Send + 'static,
+) -> SysResult {
+    WAITERS.lock().push(Waiter {
+        badge,
+        reply: save_reply(),
+        retry: Box::new(retry),
+    });
+    Err(SYS_PARKED)
+}
+
+/// Call `f` until it does not fail with [Errno::EAGAIN], the syscall is
+/// parked between the calls unless `nonblock`.
+///
+/// It must not be called with the lock of `TASK_MAP` held.
+pub(crate) fn block_on(
+    badge: u64,
+    nonblock: bool,
+    mut f: impl FnMut() -> SysResult + Send + 'static,
+) -> SysResult {
+    match f() {
+        Err(Errno::EAGAIN) if !nonblock => park(badge, move || match f() {
+            Err(Errno::EAGAIN) => None,
+            res => Some(res),
+        }),
+        res => res,
+    }
+}
+
+/// Retry all the parked syscalls and reply the completed ones.
+pub(crate) fn wake_waiters() {
+    // Retry without holding the lock, so the retry function can use it.
+    let mut waiters = core::mem::take(&mut *WAITERS.lock());
+    waiters.retain_mut(|waiter| match (waiter.retry)() {
+        Some(res) => {
+            reply_to(waiter.reply, res);
+            false
+        }
+        None => true,
+    });
+    WAITERS.lock().extend(waiters);
+}
+
+/// Request to retry the parked syscalls after the current syscall is replied.
+pub(crate) fn request_wake() {
+    WAKE_REQUESTED.store(true, Ordering::Release);
+}
+
+/// Retry the parked syscalls if [request_wake] has been called.
+///
+/// The completed syscalls may request again, so it loops until nothing changes.
+pub(crate) fn wake_requested_waiters() {
+    while WAKE_REQUESTED.swap(false, Ordering::AcqRel) {
+        wake_waiters();
+    }
+}
+
+/// Drop the parked syscalls of the task `badge` without replying.
+pub(crate) fn cancel_waiters(badge: u64) {
+    WAITERS.lock().retain(|waiter| {
+        if waiter.badge != badge {
+            return true;
+        }
+        release_reply(waiter.reply);
+        false
+    });
+}
+
+/// Reply the result of a parked syscall through the saved reply capability.
+fn reply_to(reply: Endpoint, res: SysResult) {
+    let res = res
+        .map_err(|e| -e.into_raw() as isize)
+        .unwrap_or_else(|e| e as usize);
+    with_ipc_buffer_mut(|buffer| buffer.msg_regs_mut()[0] = res as _);
+    reply.send(MessageInfo::new(0, 0, 0, 1));
+    release_reply(reply);
+}
+
+/// A free slot to save a reply capability.
+fn free_reply_slot() -> Endpoint {
+    let (_, _, index) = OBJ_ALLOCATOR.lock().allocate_slot();
+    Endpoint::from_bits(index as _)
+}
+
+/// Save the reply capability of the current caller in a slot.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+fn save_reply() -> Endpoint {
+    let reply = free_reply_slot();
+    init_thread::slot::CNODE
+        .cap()
+        .relative(reply)
+        .save_caller()
+        .unwrap();
+    reply
+}
+
+/// Move the reply object of the current caller to a slot.
+///
+/// The kernel thread receives the next message with an idle reply object, a
+/// new one is created if none is left.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+fn save_reply() -> Endpoint {
+    let cnode = init_thread::slot::CNODE.cap();
+    let reply = free_reply_slot();
+    cnode
+        .relative(reply)
+        .move_(&cnode.relative(common::SERVER_REPLY))
+        .unwrap();
+    let idle = IDLE_REPLIES
+        .lock()
+        .pop()
+        .unwrap_or_else(|| common::allocate_reply(&mut OBJ_ALLOCATOR.lock()).cast());
+    cnode
+        .relative(common::SERVER_REPLY)
+        .move_(&cnode.relative(idle))
+        .unwrap();
+    OBJ_ALLOCATOR.lock().free_slot(idle.bits() as _);
+    reply
+}
+
+/// Free the slot saving the reply capability, it is deleted if not used.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+fn release_reply(reply: Endpoint) {
+    init_thread::slot::CNODE
+        .cap()
+        .relative(reply)
+        .delete()
+        .unwrap();
+    OBJ_ALLOCATOR.lock().free_slot(reply.bits() as _);
+}
+
+/// Keep the reply object for the next parked syscall.
+///
+/// A reply object still waited on by a cancelled caller is reset when it is
+/// received with again.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+fn release_reply(reply: Endpoint) {
+    IDLE_REPLIES.lock().push(reply);
+}
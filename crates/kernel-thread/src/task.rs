@@ -1,13 +1,23 @@
-use crate::{page_seat_vaddr, OBJ_ALLOCATOR};
-use alloc::{collections::btree_map::BTreeMap, vec::Vec};
-use common::USPACE_BASE;
-use core::{cmp, sync::atomic::AtomicU64};
-use crate_consts::{CNODE_RADIX_BITS, PAGE_SIZE, STACK_ALIGN_SIZE};
+use crate::{
+    loader::ElfInfo,
+    page_seat,
+    syscall::{Credentials, FileTable, RealTimer, ONLINE_CORES, USER_HZ},
+    OBJ_ALLOCATOR,
+};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use common::{
+    allocate_sched_context, counter, free_sched_context, SchedAttr, SchedContext,
+    SCHED_ATTR_SIZE_VER0, USPACE_BASE, USPACE_HEAP_BASE, USPACE_IPC_BUFFER_BASE,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate_consts::{CNODE_RADIX_BITS, DEFAULT_THREAD_FAULT_EP, PAGE_SIZE, STACK_ALIGN_SIZE};
 use sel4::{
-    cap_type::{CNode, Granule, Tcb, VSpace, PT},
-    init_thread, CapRights, Error, VmAttributes,
+    cap_type::{CNode, Granule, Tcb, Untyped, VSpace, PT},
+    init_thread, CapRights, CapTypeForObjectOfFixedSize, CapTypeForObjectOfVariableSize, Error,
+    VmAttributes,
 };
-use xmas_elf::{program, ElfFile};
+use spin::Mutex;
+use syscalls::Errno;
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 #[allow(non_camel_case_types, dead_code)]
@@ -62,15 +72,200 @@ pub enum AuxV {
     EXECFN = 31,
 }
 
+/// The hardware capabilities in [AuxV::HWCAP], the same as `HWCAP_FP` and
+/// `HWCAP_ASIMD` of aarch64 linux.
+const HWCAP_FP: usize = 1 << 0;
+const HWCAP_ASIMD: usize = 1 << 1;
+
+/// The platform string pointed by [AuxV::PLATFORM].
+const PLATFORM: &str = "aarch64";
+
+/// The initial stack of a task, built downwards from `top` before it is
+/// copied into the stack pages.
+struct InitStack {
+    top: usize,
+    /// The bytes in the reverse order, the last one is at the stack pointer.
+    rev_bytes: Vec<u8>,
+}
+
+impl InitStack {
+    fn new(top: usize) -> Self {
+        Self {
+            top,
+            rev_bytes: Vec::new(),
+        }
+    }
+
+    /// Push `bytes` and returns their address.
+    fn push_bytes(&mut self, bytes: &[u8]) -> usize {
+        self.rev_bytes.extend(bytes.iter().rev());
+        self.top - self.rev_bytes.len()
+    }
+
+    /// Push `s` with a NUL byte and returns its address.
+    fn push_str(&mut self, s: &str) -> usize {
+        self.push_bytes(&[0]);
+        self.push_bytes(s.as_bytes())
+    }
+
+    fn push_usize(&mut self, num: usize) -> usize {
+        self.push_bytes(&num.to_ne_bytes())
+    }
+
+    /// Pad the stack pointer down to a multiple of `align`.
+    fn align(&mut self, align: usize) {
+        let sp = self.top - self.rev_bytes.len();
+        self.rev_bytes.resize(self.rev_bytes.len() + sp % align, 0);
+    }
+
+    /// The bytes at the addresses in `range`.
+    fn bytes(&self, range: core::ops::Range<usize>) -> Vec<u8> {
+        self.rev_bytes[self.top - range.end..self.top - range.start]
+            .iter()
+            .rev()
+            .copied()
+            .collect()
+    }
+}
+
+/// The bytes pointed by [AuxV::RANDOM], mixed from the count of the generic
+/// timer with splitmix64.
+///
+/// They seed the stack protector and the pointer guard of libc, so they only
+/// need to differ between the tasks.
+fn random_bytes() -> [u8; 16] {
+    static SEED: AtomicU64 = AtomicU64::new(0);
+    let mut next = || {
+        let seed = SEED.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed) ^ counter();
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&next().to_ne_bytes());
+    bytes[8..].copy_from_slice(&next().to_ne_bytes());
+    bytes
+}
+
+/// The untyped objects of the freed memory with the empty slots of their
+/// VSpaces, reused by [TaskMemory::new].
+///
+/// seL4 releases the ASID of a VSpace only when the VSpace is deleted, and the
+/// allocator can't retype a deleted object again. So each VSpace is retyped
+/// from an untyped of its own, which is revoked to delete the VSpace and
+/// retyped again for the next one.
+static IDLE_VSPACES: Mutex<Vec<(sel4::cap::Untyped, sel4::cap::VSpace)>> = Mutex::new(Vec::new());
+
+/// Unmap the `page` from the task, zero it and return it to the allocator.
+fn free_page(page: sel4::cap::SmallPage) {
+    init_thread::slot::CNODE
+        .cap()
+        .relative(page)
+        .revoke()
+        .unwrap();
+    page.frame_unmap().unwrap();
+    let seat = page_seat();
+    page.frame_map(
+        init_thread::slot::VSPACE.cap(),
+        seat.vaddr(),
+        CapRights::all(),
+        VmAttributes::DEFAULT,
+    )
+    .unwrap();
+    unsafe {
+        core::ptr::write_bytes(seat.vaddr() as *mut u8, 0, PAGE_SIZE);
+    }
+    page.frame_unmap().unwrap();
+    drop(seat);
+    OBJ_ALLOCATOR
+        .lock()
+        .recycle(Granule::object_blueprint(), page.bits() as _);
+}
+
+/// The memory of a task, shared by the tasks cloned with `CLONE_VM`.
+///
+/// The pages and the page tables are freed with the last task using them.
+pub struct TaskMemory {
+    pub vspace: sel4::cap::VSpace,
+    /// The untyped the VSpace is retyped from.
+    untyped: sel4::cap::Untyped,
+    pub mapped_pt: Vec<sel4::cap::PT>,
+    pub mapped_page: BTreeMap<usize, sel4::cap::SmallPage>,
+    pub heap: usize,
+}
+
+impl TaskMemory {
+    /// An empty memory with a new VSpace given an ASID.
+    pub fn new() -> Self {
+        let blueprint = VSpace::object_blueprint();
+        let (untyped, vspace) = IDLE_VSPACES.lock().pop().unwrap_or_else(|| {
+            let mut allocator = OBJ_ALLOCATOR.lock();
+            let untyped = allocator
+                .allocate_and_retyped_variable_sized::<Untyped>(blueprint.physical_size_bits());
+            let vspace = sel4::cap::VSpace::from_bits(allocator.allocate_slot().2 as _);
+            (untyped, vspace)
+        });
+        let raw_index = vspace.bits() as usize;
+        untyped
+            .untyped_retype(
+                &blueprint,
+                &init_thread::slot::CNODE
+                    .cap()
+                    .relative_bits_with_depth((raw_index >> 12) as u64, 52),
+                raw_index & 0xfff,
+                1,
+            )
+            .unwrap();
+        init_thread::slot::ASID_POOL
+            .cap()
+            .asid_pool_assign(vspace)
+            .unwrap();
+        TaskMemory {
+            vspace,
+            untyped,
+            mapped_pt: Vec::new(),
+            mapped_page: BTreeMap::new(),
+            heap: USPACE_HEAP_BASE,
+        }
+    }
+}
+
+impl Drop for TaskMemory {
+    fn drop(&mut self) {
+        let root_cnode = init_thread::slot::CNODE.cap();
+        self.mapped_page.values().for_each(|page| free_page(*page));
+        // The page tables are empty after the pages are unmapped.
+        let mut allocator = OBJ_ALLOCATOR.lock();
+        self.mapped_pt.iter().for_each(|pt| {
+            root_cnode.relative(*pt).revoke().unwrap();
+            pt.pt_unmap().unwrap();
+            allocator.recycle(PT::object_blueprint(), pt.bits() as _);
+        });
+        drop(allocator);
+        // Delete the VSpace and the copies in the TCBs, it is empty now and
+        // its ASID is released.
+        root_cnode.relative(self.untyped).revoke().unwrap();
+        IDLE_VSPACES.lock().push((self.untyped, self.vspace));
+    }
+}
+
 pub struct Sel4Task {
+    /// The id of the thread group, the same as the id of its first task.
     pub pid: usize,
+    /// The thread group of the parent, the parent of the thread group for the
+    /// tasks cloned with `CLONE_THREAD`.
+    pub ppid: usize,
+    /// The id of the task, the badge of its fault endpoint and the tid.
     pub id: usize,
     pub tcb: sel4::cap::Tcb,
     pub cnode: sel4::cap::CNode,
+    /// The VSpace of `mem`.
     pub vspace: sel4::cap::VSpace,
-    pub mapped_pt: Vec<sel4::cap::PT>,
-    pub mapped_page: BTreeMap<usize, sel4::cap::SmallPage>,
-    pub heap: usize,
+    /// The address of the IPC buffer, unique among the tasks sharing the
+    /// memory. It is passed to the task in `tpidrro_el0`.
+    pub ipc_buffer: usize,
+    pub mem: Arc<Mutex<TaskMemory>>,
     pub exit: Option<i32>,
     /// The clear thread tid field
     ///
@@ -78,39 +273,51 @@ pub struct Sel4Task {
     ///
     /// When the thread exits, the kernel clears the word at this address if it is not NULL.
     pub clear_child_tid: Option<usize>,
+    /// The user ids of the task.
+    pub cred: Credentials,
+    /// The fds of the task, shared by the tasks cloned with `CLONE_FILES`.
+    pub file_table: Arc<Mutex<FileTable>>,
+    /// The `ITIMER_REAL` interval timer, not inherited by the cloned tasks.
+    pub real_timer: RealTimer,
+    /// The cores the task may run on, inherited by the cloned tasks.
+    pub affinity: u64,
+    /// The core of `affinity` the task runs on.
+    pub core: usize,
+    /// The scheduling context of the task on a MCS kernel, configured with
+    /// the budget of `sched` on `core`.
+    pub sched_context: SchedContext,
+    /// The scheduling attributes, inherited by the cloned tasks.
+    pub sched: SchedAttr,
 }
 
+/// The objects of the task are returned to the allocator, the memory is
+/// freed with the last task sharing it.
 impl Drop for Sel4Task {
     fn drop(&mut self) {
         let root_cnode = init_thread::slot::CNODE.cap();
+        self.tcb.tcb_suspend().unwrap();
+        // The copy of the TCB in the CSpace of the task is revoked with it.
         root_cnode.relative(self.tcb).revoke().unwrap();
-        root_cnode.relative(self.tcb).delete().unwrap();
+        self.cnode
+            .relative_bits_with_depth(DEFAULT_THREAD_FAULT_EP, CNODE_RADIX_BITS)
+            .delete()
+            .unwrap();
         root_cnode.relative(self.cnode).revoke().unwrap();
-        root_cnode.relative(self.cnode).delete().unwrap();
-        root_cnode.relative(self.vspace).revoke().unwrap();
-        root_cnode.relative(self.vspace).delete().unwrap();
 
-        self.mapped_pt.iter().for_each(|cap| {
-            root_cnode.relative(*cap).revoke().unwrap();
-            root_cnode.relative(*cap).delete().unwrap();
-        });
-        self.mapped_page.values().for_each(|cap| {
-            root_cnode.relative(*cap).revoke().unwrap();
-            root_cnode.relative(*cap).delete().unwrap();
-        });
+        let mut allocator = OBJ_ALLOCATOR.lock();
+        free_sched_context(&mut allocator, self.sched_context);
+        allocator.recycle(Tcb::object_blueprint(), self.tcb.bits() as _);
+        allocator.recycle(
+            CNode::object_blueprint(CNODE_RADIX_BITS),
+            self.cnode.bits() as _,
+        );
     }
 }
 
 impl Sel4Task {
     pub fn new() -> Sel4Task {
         static ID_COUNTER: AtomicU64 = AtomicU64::new(1);
-        let vspace = OBJ_ALLOCATOR
-            .lock()
-            .allocate_and_retyped_fixed_sized::<VSpace>();
-        init_thread::slot::ASID_POOL
-            .cap()
-            .asid_pool_assign(vspace)
-            .unwrap();
+        let mem = TaskMemory::new();
 
         let tcb = OBJ_ALLOCATOR
             .lock()
@@ -119,17 +326,28 @@ impl Sel4Task {
             .lock()
             .allocate_and_retyped_variable_sized::<CNode>(CNODE_RADIX_BITS);
 
+        let id = ID_COUNTER.fetch_add(1, Ordering::SeqCst) as usize;
         Sel4Task {
-            id: ID_COUNTER.fetch_add(1, core::sync::atomic::Ordering::SeqCst) as usize,
-            pid: 0,
+            id,
+            pid: id,
+            ppid: 0,
             tcb,
             cnode,
-            vspace,
-            mapped_pt: Vec::new(),
-            mapped_page: BTreeMap::new(),
-            heap: 0x2_0000_0000,
+            vspace: mem.vspace,
+            ipc_buffer: 0,
+            mem: Arc::new(Mutex::new(mem)),
             exit: None,
             clear_child_tid: None,
+            cred: Credentials::default(),
+            file_table: Arc::new(Mutex::new(FileTable::new())),
+            real_timer: RealTimer::default(),
+            affinity: ONLINE_CORES,
+            core: 0,
+            sched_context: allocate_sched_context(&mut OBJ_ALLOCATOR.lock()),
+            sched: SchedAttr {
+                size: SCHED_ATTR_SIZE_VER0,
+                ..Default::default()
+            },
         }
     }
 
@@ -138,28 +356,49 @@ impl Sel4Task {
     /// The area starts from `start` and the size is `size`.
     pub fn find_free_area(&self, start: usize, size: usize) -> Option<usize> {
         let mut last_addr = USPACE_BASE.max(start);
-        for (vaddr, _page) in &self.mapped_page {
+        for vaddr in self.mem.lock().mapped_page.keys() {
             if last_addr + size <= *vaddr {
                 return Some(last_addr);
             }
-            last_addr = *vaddr + PAGE_SIZE;
+            last_addr = last_addr.max(*vaddr + PAGE_SIZE);
         }
         // TODO: Set the limit of the top of the user space.
         Some(last_addr)
     }
 
+    /// Map a new IPC buffer at a free address from [USPACE_IPC_BUFFER_BASE],
+    /// for the task sharing the memory of another one.
+    pub fn map_ipc_buffer(&mut self) -> sel4::cap::Granule {
+        let vaddr = self
+            .find_free_area(USPACE_IPC_BUFFER_BASE, PAGE_SIZE)
+            .unwrap();
+        let page_cap = OBJ_ALLOCATOR
+            .lock()
+            .allocate_and_retyped_fixed_sized::<Granule>();
+        self.map_page(vaddr, page_cap);
+        self.ipc_buffer = vaddr;
+        page_cap
+    }
+
     pub fn map_page(&mut self, vaddr: usize, page: sel4::cap::SmallPage) {
+        self.map_page_with(vaddr, page, CapRights::all(), VmAttributes::DEFAULT);
+    }
+
+    /// Map the `page` at `vaddr` with the `rights` and `attrs`.
+    pub fn map_page_with(
+        &mut self,
+        vaddr: usize,
+        page: sel4::cap::SmallPage,
+        rights: CapRights,
+        attrs: VmAttributes,
+    ) {
         assert_eq!(vaddr % PAGE_SIZE, 0);
         for _ in 0..sel4::vspace_levels::NUM_LEVELS {
-            let res: core::result::Result<(), sel4::Error> = page.frame_map(
-                self.vspace,
-                vaddr as _,
-                CapRights::all(),
-                VmAttributes::DEFAULT,
-            );
+            let res: core::result::Result<(), sel4::Error> =
+                page.frame_map(self.vspace, vaddr as _, rights.clone(), attrs);
             match res {
                 Ok(_) => {
-                    self.mapped_page.insert(vaddr, page);
+                    self.mem.lock().mapped_page.insert(vaddr, page);
                     return;
                 }
                 Err(Error::FailedLookup) => {
@@ -169,177 +408,175 @@ impl Sel4Task {
                     pt_cap
                         .pt_map(self.vspace, vaddr, VmAttributes::DEFAULT)
                         .unwrap();
-                    self.mapped_pt.push(pt_cap);
+                    self.mem.lock().mapped_pt.push(pt_cap);
                 }
                 _ => res.unwrap(),
             }
         }
     }
 
-    pub fn unmap_page(&mut self, vaddr: usize, page: sel4::cap::SmallPage) {
+    /// Change the rights of the page mapped at `vaddr`, the frame is mapped
+    /// again at the same address.
+    pub fn protect_page(
+        &mut self,
+        vaddr: usize,
+        rights: CapRights,
+        attrs: VmAttributes,
+    ) -> Result<(), Errno> {
+        let page = self.mem.lock().mapped_page.get(&vaddr).copied();
+        let page = page.ok_or(Errno::ENOMEM)?;
+        page.frame_map(self.vspace, vaddr as _, rights, attrs)
+            .map_err(|err| match err {
+                Error::InvalidArgument | Error::RangeError => Errno::EINVAL,
+                _ => Errno::ENOMEM,
+            })
+    }
+
+    /// Unmap the page at `vaddr` and free it.
+    pub fn unmap_page(&mut self, vaddr: usize) {
         assert_eq!(vaddr % PAGE_SIZE, 0);
-        let res = page.frame_unmap();
-        match res {
-            Ok(_) => {
-                self.mapped_page.remove(&vaddr);
-            }
-            _ => res.unwrap(),
+        let page = self.mem.lock().mapped_page.remove(&vaddr);
+        if let Some(page) = page {
+            free_page(page);
         }
     }
 
-    pub fn map_stack(
+    /// Give the task a new empty memory for `execve`, the old one is freed
+    /// with the last task sharing it.
+    pub fn reset_memory(&mut self) {
+        let mem = TaskMemory::new();
+        self.vspace = mem.vspace;
+        self.mem = Arc::new(Mutex::new(mem));
+    }
+
+    /// Map the stack pages in `start..end` and build the initial stack of
+    /// linux at the top of them, returns the stack pointer.
+    ///
+    /// From the top, the stack holds the `execfn` naming the program, the
+    /// strings of `envs` and `args`, the platform string and the random bytes,
+    /// then the auxiliary vector, the envp and argv arrays and argc at the
+    /// stack pointer.
+    pub fn map_stack<S: AsRef<str>>(
         &mut self,
-        entry_point: usize,
+        info: &ElfInfo,
         start: usize,
         end: usize,
-        args: &[&str],
+        execfn: &str,
+        args: &[S],
+        envs: &[S],
     ) -> usize {
         assert!(end % 0x1000 == 0);
         assert!(start % 0x1000 == 0);
-        let mut stack_ptr = end;
+        let mut stack = InitStack::new(end);
+        // The stack is executable only if `PT_GNU_STACK` asks for it.
+        let stack_attrs = match info.exec_stack {
+            true => VmAttributes::DEFAULT,
+            false => VmAttributes::DEFAULT | VmAttributes::EXECUTE_NEVER,
+        };
+
+        let execfn_ptr = stack.push_str(execfn);
+        let envs_ptr: Vec<_> = envs
+            .iter()
+            .map(|env| stack.push_str(env.as_ref()))
+            .collect();
+        let args_ptr: Vec<_> = args
+            .iter()
+            .map(|arg| stack.push_str(arg.as_ref()))
+            .collect();
+        let platform_ptr = stack.push_str(PLATFORM);
+        let random_ptr = stack.push_bytes(&random_bytes());
+        stack.align(STACK_ALIGN_SIZE);
+
+        let mut auxv = BTreeMap::new();
+        auxv.insert(AuxV::PHDR, info.phdr);
+        auxv.insert(AuxV::PHENT, info.phent);
+        auxv.insert(AuxV::PHNUM, info.phnum);
+        auxv.insert(AuxV::PAGESZ, PAGE_SIZE);
+        auxv.insert(AuxV::BASE, info.base);
+        auxv.insert(AuxV::FLAGS, 0);
+        auxv.insert(AuxV::ENTRY, info.entry);
+        auxv.insert(AuxV::UID, 0);
+        auxv.insert(AuxV::EUID, 0);
+        auxv.insert(AuxV::GID, 0);
+        auxv.insert(AuxV::EGID, 0);
+        auxv.insert(AuxV::PLATFORM, platform_ptr);
+        auxv.insert(AuxV::HWCAP, HWCAP_FP | HWCAP_ASIMD);
+        auxv.insert(AuxV::CLKTCK, USER_HZ as usize);
+        auxv.insert(AuxV::SECURE, 0);
+        auxv.insert(AuxV::RANDOM, random_ptr);
+        auxv.insert(AuxV::HWCAP2, 0);
+        auxv.insert(AuxV::EXECFN, execfn_ptr);
+        auxv.insert(AuxV::NULL, 0);
+
+        // The stack pointer is aligned after argc, argv, envp and auxv.
+        let words = 1 + (args_ptr.len() + 1) + (envs_ptr.len() + 1) + auxv.len() * 2;
+        if words % 2 != 0 {
+            stack.push_usize(0);
+        }
+
+        // push auxiliary vector, the null entry ends it
+        auxv.into_iter().for_each(|(key, v)| {
+            stack.push_usize(v);
+            stack.push_usize(key as usize);
+        });
+        // push environment pointers
+        stack.push_usize(0);
+        envs_ptr.iter().rev().for_each(|x| {
+            stack.push_usize(*x);
+        });
+        // push args pointers
+        stack.push_usize(0);
+        args_ptr.iter().rev().for_each(|x| {
+            stack.push_usize(*x);
+        });
+        // push argc
+        let stack_ptr = stack.push_usize(args_ptr.len());
+        assert!(
+            stack_ptr >= start,
+            "the initial stack is larger than the stack"
+        );
+        assert_eq!(stack_ptr % STACK_ALIGN_SIZE, 0);
 
         for vaddr in (start..end).step_by(PAGE_SIZE) {
             let page_cap = OBJ_ALLOCATOR
                 .lock()
                 .allocate_and_retyped_fixed_sized::<Granule>();
-            if vaddr == end - PAGE_SIZE {
+            // Copy the part of the initial stack in the page.
+            if vaddr + PAGE_SIZE > stack_ptr {
+                let seat = page_seat();
                 page_cap
                     .frame_map(
                         init_thread::slot::VSPACE.cap(),
-                        page_seat_vaddr(),
+                        seat.vaddr(),
                         CapRights::all(),
                         VmAttributes::DEFAULT,
                     )
                     .unwrap();
 
-                let args_ptr: Vec<_> = args
-                    .iter()
-                    .map(|arg| {
-                        // TODO: set end bit was zeroed manually.
-                        stack_ptr = (stack_ptr - arg.bytes().len() - 1) / STACK_ALIGN_SIZE
-                            * STACK_ALIGN_SIZE;
-                        unsafe {
-                            core::ptr::copy_nonoverlapping(
-                                arg.as_bytes().as_ptr(),
-                                (page_seat_vaddr() + stack_ptr % PAGE_SIZE) as *mut u8,
-                                arg.bytes().len(),
-                            );
-                        }
-                        stack_ptr
-                    })
-                    .collect();
-
-                let mut push_num = |num: usize| {
-                    stack_ptr = stack_ptr - core::mem::size_of::<usize>();
-
-                    unsafe {
-                        ((page_seat_vaddr() + stack_ptr % PAGE_SIZE) as *mut usize)
-                            .write_volatile(num);
-                    }
-
-                    stack_ptr
-                };
-
-                let mut auxv = BTreeMap::new();
-                auxv.insert(AuxV::EXECFN, args_ptr[0]);
-                auxv.insert(AuxV::PAGESZ, PAGE_SIZE);
-                auxv.insert(AuxV::ENTRY, entry_point);
-                auxv.insert(AuxV::GID, 0);
-                auxv.insert(AuxV::EGID, 0);
-                auxv.insert(AuxV::UID, 0);
-                auxv.insert(AuxV::EUID, 0);
-                auxv.insert(AuxV::NULL, 0);
-
-                // push auxiliary vector
-                auxv.into_iter().for_each(|(key, v)| {
-                    push_num(v);
-                    push_num(key as usize);
-                });
-                // push environment
-                push_num(0);
-                // push args pointer
-                push_num(0);
-                args_ptr.iter().rev().for_each(|x| {
-                    push_num(*x);
-                });
-                // push argv
-                push_num(args_ptr.len());
+                let copy_start = vaddr.max(stack_ptr);
+                let data = stack.bytes(copy_start..vaddr + PAGE_SIZE);
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        data.as_ptr(),
+                        (seat.vaddr() + copy_start % PAGE_SIZE) as *mut u8,
+                        data.len(),
+                    );
+                }
 
                 // Unmap Frame
                 page_cap.frame_unmap().unwrap();
             }
-            self.map_page(vaddr, page_cap);
+            self.map_page_with(vaddr, page_cap, CapRights::all(), stack_attrs);
         }
         stack_ptr
     }
 
-    pub fn load_elf(&mut self, elf_data: &[u8]) {
-        let file = ElfFile::new(elf_data).expect("This is not a valid elf file");
-
-        let mut mapped_page: BTreeMap<usize, sel4::cap::SmallPage> = BTreeMap::new();
-
-        // Load data from elf file.
-        file.program_iter()
-            .filter(|ph| ph.get_type() == Ok(program::Type::Load))
-            .for_each(|ph| {
-                let mut offset = ph.offset() as usize;
-                let mut vaddr = ph.virtual_addr() as usize;
-                let end = offset + ph.file_size() as usize;
-                let vaddr_end = vaddr + ph.mem_size() as usize;
-
-                while vaddr < vaddr_end {
-                    let page_cap = match mapped_page.remove(&(vaddr / PAGE_SIZE * PAGE_SIZE)) {
-                        Some(page_cap) => {
-                            page_cap.frame_unmap().unwrap();
-                            page_cap
-                        }
-                        None => OBJ_ALLOCATOR
-                            .lock()
-                            .allocate_and_retyped_fixed_sized::<Granule>(),
-                    };
-
-                    // If need to read data from elf file.
-                    if offset < end {
-                        // Map to root task to write datas.
-                        page_cap
-                            .frame_map(
-                                init_thread::slot::VSPACE.cap(),
-                                page_seat_vaddr(),
-                                CapRights::all(),
-                                VmAttributes::DEFAULT,
-                            )
-                            .unwrap();
-
-                        let rsize = cmp::min(PAGE_SIZE - vaddr % PAGE_SIZE, end - offset);
-                        // Copy data from elf file's data to the correct position.
-                        unsafe {
-                            core::ptr::copy_nonoverlapping(
-                                elf_data.as_ptr().add(offset),
-                                (page_seat_vaddr() + offset % PAGE_SIZE) as *mut u8,
-                                rsize,
-                            )
-                        }
-
-                        page_cap.frame_unmap().unwrap();
-
-                        offset += rsize;
-                    }
-
-                    self.map_page(vaddr / PAGE_SIZE * PAGE_SIZE, page_cap);
-
-                    mapped_page.insert(vaddr / PAGE_SIZE * PAGE_SIZE, page_cap);
-
-                    // Calculate offset
-                    vaddr += PAGE_SIZE - vaddr % PAGE_SIZE;
-                }
-            });
-    }
-
     pub fn brk(&mut self, value: usize) -> usize {
+        let heap = self.mem.lock().heap;
         if value == 0 {
-            return self.heap;
+            return heap;
         }
-        for vaddr in (self.heap..value).step_by(PAGE_SIZE) {
+        for vaddr in (heap..value).step_by(PAGE_SIZE) {
             let page_cap = OBJ_ALLOCATOR
                 .lock()
                 .allocate_and_retyped_fixed_sized::<Granule>();
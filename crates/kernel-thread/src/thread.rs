@@ -55,16 +55,16 @@ pub fn test_threads() {
         }
     }
 
-    thread_tcb
-        .tcb_configure(
-            init_thread::slot::NULL.cptr(),
-            init_thread::slot::CNODE.cap(),
-            CNodeCapData::new(0, 0),
-            init_thread::slot::VSPACE.cap(),
-            SECONDARY_THREAD_IPC_BUFFER_ADDR as u64,
-            secondary_thread_ipc_buffer_cap,
-        )
-        .unwrap();
+    common::tcb_configure(
+        thread_tcb,
+        init_thread::slot::NULL.cptr(),
+        init_thread::slot::CNODE.cap(),
+        CNodeCapData::new(0, 0),
+        init_thread::slot::VSPACE.cap(),
+        SECONDARY_THREAD_IPC_BUFFER_ADDR as u64,
+        secondary_thread_ipc_buffer_cap,
+    )
+    .unwrap();
 
     let thread_fn: ThreadFn = ThreadFn::new(move || {
         let ipc_buffer = {
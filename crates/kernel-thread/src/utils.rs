@@ -1,10 +1,16 @@
-use alloc::format;
+use alloc::{format, string::String, vec, vec::Vec};
+use common::IoVec;
 use crate_consts::GRANULE_SIZE;
 use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
-use sel4::{debug_println, init_thread, Cap, CapRights, VmAttributes};
-use syscalls::Errno;
+use sel4::{cap::Endpoint, debug_println, init_thread, Cap, CapRights, VmAttributes};
+use syscalls::{Errno, Sysno};
 
-use crate::{page_seat_vaddr, syscall::SysResult, task::Sel4Task, FREE_PAGE_PLACEHOLDER};
+use crate::{
+    page_seat,
+    syscall::{handle_ipc_call, SysResult},
+    task::Sel4Task,
+    FREE_PAGE_PLACEHOLDER,
+};
 
 pub fn print_test(title: &str) {
     debug_println!("{:=^60}", format!(" {} BEGIN", title));
@@ -14,6 +20,14 @@ pub fn print_test_end(title: &str) {
     debug_println!("{:=^60}", format!(" {} PASSED ", title));
 }
 
+/// Call the syscall `sysno` for the task `badge` in a test, the same as its
+/// IPC does.
+pub(crate) fn syscall(badge: u64, ep: Endpoint, sysno: Sysno, args: &[usize]) -> SysResult {
+    let mut regs = [0; 6];
+    regs[..args.len()].copy_from_slice(args);
+    handle_ipc_call(badge, sysno.id() as _, regs, ep)
+}
+
 #[macro_export]
 macro_rules! test_func {
     ($title: literal, $test:block) => {{
@@ -54,13 +68,20 @@ where
     let number = number.unwrap_or(1);
     let mut len = core::mem::size_of::<T>() * number;
     while len > 0 {
-        if let Some(cap) = task.mapped_page.get(&align_bits(buf_addr.as_usize(), 12)) {
+        let page = task
+            .mem
+            .lock()
+            .mapped_page
+            .get(&align_bits(buf_addr.as_usize(), 12))
+            .copied();
+        if let Some(cap) = page {
+            let seat = page_seat();
             let new_cap = Cap::<sel4::cap_type::SmallPage>::from_bits(0);
             init_thread::slot::CNODE
                 .cap()
                 .relative(new_cap)
                 .copy(
-                    &init_thread::slot::CNODE.cap().relative(*cap),
+                    &init_thread::slot::CNODE.cap().relative(cap),
                     CapRights::all(),
                 )
                 .unwrap();
@@ -68,14 +89,14 @@ where
             new_cap
                 .frame_map(
                     init_thread::slot::VSPACE.cap(),
-                    page_seat_vaddr(),
+                    seat.vaddr(),
                     CapRights::all(),
                     VmAttributes::DEFAULT,
                 )
                 .unwrap();
             let copy_len = (PAGE_SIZE_4K - buf_addr.align_offset_4k()).min(len);
             f(
-                VirtAddr::from_usize(page_seat_vaddr() + buf_addr.align_offset_4k()),
+                VirtAddr::from_usize(seat.vaddr() + buf_addr.align_offset_4k()),
                 buf_addr - addr,
                 copy_len,
             );
@@ -110,7 +131,7 @@ pub(crate) fn write_item<T: Sized + Copy>(task: &Sel4Task, addr: *const T, item:
         VirtAddr::from_ptr_of(addr),
         None,
         |dst, _, copy_len| unsafe {
-            core::ptr::copy_nonoverlapping(item as *const T, dst.as_mut_ptr() as *mut T, copy_len);
+            core::ptr::copy_nonoverlapping(item as *const T as *const u8, dst.as_mut_ptr(), copy_len);
         },
     )
 }
@@ -130,8 +151,8 @@ pub(crate) fn read_item_list<T: Sized + Copy>(
             //     unsafe { core::slice::from_raw_parts(buf_addr.as_ptr(), offset + copy_len) };
             unsafe {
                 core::ptr::copy_nonoverlapping(
-                    src.as_ptr() as *const T,
-                    buf.as_mut_ptr().add(offset),
+                    src.as_ptr(),
+                    (buf.as_mut_ptr() as *mut u8).add(offset),
                     copy_len,
                 );
             }
@@ -141,6 +162,8 @@ pub(crate) fn read_item_list<T: Sized + Copy>(
 
 /// Write items to the given address.
 ///
+/// The offset and length passed to the copy function are in bytes.
+///
 /// # Arguments
 ///
 /// - buf: The buffer to write.
@@ -151,16 +174,150 @@ pub(crate) fn write_item_list<T: Sized + Copy>(
     num: Option<usize>,
     buf: &[T],
 ) -> SysResult {
-    process_item_list::<u8, _>(
+    process_item_list::<T, _>(
         task,
         VirtAddr::from_ptr_of(addr),
         num,
         |dst, offset, copy_len| unsafe {
             core::ptr::copy_nonoverlapping(
-                buf.as_ptr().add(offset),
-                dst.as_mut_ptr() as *mut T,
+                (buf.as_ptr() as *const u8).add(offset),
+                dst.as_mut_ptr(),
                 copy_len,
             );
         },
     )
 }
+
+/// Read a NUL-terminated string of at most `max_len` bytes from the user space.
+///
+/// The string is read page by page, so it can end at the last mapped page.
+pub(crate) fn read_c_string(
+    task: &Sel4Task,
+    addr: *const u8,
+    max_len: usize,
+) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+    let mut addr = VirtAddr::from_ptr_of(addr);
+    while bytes.len() < max_len {
+        let chunk_len = (PAGE_SIZE_4K - addr.align_offset_4k()).min(max_len - bytes.len());
+        let mut chunk = vec![0u8; chunk_len];
+        read_item_list(task, addr.as_ptr(), Some(chunk_len), &mut chunk)?;
+        if let Some(end) = chunk.iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&chunk[..end]);
+            return String::from_utf8(bytes).map_err(|_| Errno::EINVAL);
+        }
+        bytes.extend_from_slice(&chunk);
+        addr += chunk_len;
+    }
+    Err(Errno::ENAMETOOLONG)
+}
+
+/// Read a NULL-terminated array of strings from the user space, like the
+/// `argv` and `envp` of `execve`.
+///
+/// The strings take at most `max_len` bytes with their NUL bytes and their
+/// pointers, the same as they take in the initial stack. A NULL `addr` is an
+/// empty array.
+pub(crate) fn read_c_string_array(
+    task: &Sel4Task,
+    addr: *const usize,
+    max_len: usize,
+) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
+    if addr.is_null() {
+        return Ok(strings);
+    }
+    let mut len = 0;
+    loop {
+        let ptr = read_item(task, addr.wrapping_add(strings.len()))?;
+        if ptr == 0 {
+            return Ok(strings);
+        }
+        let ptr_len = core::mem::size_of::<usize>();
+        let max_len = max_len.checked_sub(len + ptr_len).ok_or(Errno::E2BIG)?;
+        let string = read_c_string(task, ptr as *const u8, max_len).map_err(|e| match e {
+            Errno::ENAMETOOLONG => Errno::E2BIG,
+            e => e,
+        })?;
+        len += string.len() + 1 + ptr_len;
+        strings.push(string);
+    }
+}
+
+/// The maximum number of buffers in an `iovec` array, the same as `IOV_MAX` in linux.
+pub(crate) const IOV_MAX: usize = 1024;
+
+/// Read an `iovec` array of `iovcnt` buffers from the user space.
+pub(crate) fn read_iovecs(
+    task: &Sel4Task,
+    iov: *const IoVec,
+    iovcnt: usize,
+) -> Result<Vec<IoVec>, Errno> {
+    if iovcnt > IOV_MAX {
+        return Err(Errno::EINVAL);
+    }
+    let mut iovs = vec![IoVec::default(); iovcnt];
+    if iovcnt > 0 {
+        read_item_list(task, iov, Some(iovcnt), &mut iovs)?;
+    }
+    iovecs_len(&iovs)?;
+    Ok(iovs)
+}
+
+/// The total length of the buffers, it fails with [Errno::EINVAL] if it
+/// doesn't fit in a `ssize_t` as linux does.
+pub(crate) fn iovecs_len(iovs: &[IoVec]) -> Result<usize, Errno> {
+    iovs.iter()
+        .try_fold(0usize, |total, iov| total.checked_add(iov.iov_len))
+        .filter(|&total| total <= isize::MAX as usize)
+        .ok_or(Errno::EINVAL)
+}
+
+/// Cut the user buffers to at most `max` bytes in total, in order.
+pub(crate) fn truncate_iovecs(iovs: &[IoVec], max: usize) -> Vec<IoVec> {
+    let mut left = max;
+    iovs.iter()
+        .map(|iov| {
+            let len = iov.iov_len.min(left);
+            left -= len;
+            IoVec {
+                iov_base: iov.iov_base,
+                iov_len: len,
+            }
+        })
+        .collect()
+}
+
+/// Copy the data in the user buffers into a vector, in order.
+///
+/// All the data is copied at once, the caller cuts the buffers by
+/// [truncate_iovecs] to bound the copy.
+pub(crate) fn gather_iovecs(task: &Sel4Task, iovs: &[IoVec]) -> Result<Vec<u8>, Errno> {
+    let mut data = vec![0u8; iovecs_len(iovs)?];
+    let mut offset = 0;
+    for iov in iovs.iter().filter(|iov| iov.iov_len > 0) {
+        let buf = &mut data[offset..offset + iov.iov_len];
+        read_item_list(task, iov.iov_base as *const u8, Some(iov.iov_len), buf)?;
+        offset += iov.iov_len;
+    }
+    Ok(data)
+}
+
+/// Copy the `data` into the user buffers in order, returns the bytes copied.
+pub(crate) fn scatter_iovecs(task: &Sel4Task, iovs: &[IoVec], data: &[u8]) -> SysResult {
+    let mut offset = 0;
+    for iov in iovs {
+        let len = iov.iov_len.min(data.len() - offset);
+        if len == 0 {
+            continue;
+        }
+        write_item_list(
+            task,
+            iov.iov_base as *mut u8,
+            Some(len),
+            &data[offset..offset + len],
+        )?;
+        offset += len;
+    }
+    Ok(offset)
+}
@@ -5,19 +5,26 @@
 //! Related IPC messages are defined in the [`common::NetRequsetabel`].
 
 use alloc::vec::Vec;
-use axerrno::AxResult;
-use common::NetRequsetabel;
-use core::net::{IpAddr, SocketAddr};
-use crate_consts::DEFAULT_CUSTOM_SLOT;
+use axerrno::{AxError, AxResult};
+use common::{
+    current_nanos, socket_addr_from_regs, socket_addr_to_regs, stopped, NetRequsetabel, NetSockOpt,
+    NET_POLL_READABLE, NET_POLL_WRITABLE, SERVER_REPLY, SOCKET_ADDR_REGS,
+};
+use core::net::SocketAddr;
+use crate_consts::{
+    slots::net_thread::{EP, IRQ_HANDLER, READY_NTFN, RECV_SLOT},
+    DEFAULT_THREAD_FAULT_EP, NET_IRQ_BADGE, NOTIFICATION_BADGE_START, STOP_BADGE, TIMER_BADGE,
+};
 use lazyinit::LazyInit;
 use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
 use sel4::{
-    cap::Endpoint, debug_println, init_thread, reply, with_ipc_buffer_mut, Cap, CapRights,
-    MessageInfo, VmAttributes,
+    cap::{Endpoint, IrqHandler, Notification},
+    debug_println, init_thread, with_ipc_buffer_mut, Cap, CapRights, MessageInfo, VmAttributes,
 };
 use spin::Mutex;
 
 use crate::smoltcp_impl::{self, *};
+use crate::virtio_impl::ack_net_interrupt;
 
 /// Reply a message with empty message information
 ///
@@ -29,18 +36,64 @@ fn reply_with(regs: &[u64]) {
         regs.iter()
             .enumerate()
             .for_each(|(i, reg)| msg_regs[i] = *reg as _);
-        reply(buffer, MessageInfo::new(0, 0, 0, 8 * regs.len()))
+        common::reply(
+            buffer,
+            SERVER_REPLY,
+            MessageInfo::new(0, 0, 0, 8 * regs.len()),
+        )
     });
 }
 
-static SOCKET_VEC: LazyInit<Mutex<Vec<Option<TcpSocket>>>> = LazyInit::new();
+/// The sockets of the kernel thread, its only client.
+///
+/// The sockets are not owned by the processes here. The kernel thread maps
+/// the fds of every process to the socket ids in its file table, so a
+/// process only reaches the sockets it opened or was given, and they are
+/// closed with the last fd referring to them.
+static SOCKET_VEC: LazyInit<Mutex<Vec<Option<NetSocket>>>> = LazyInit::new();
+
+/// The high bits of the socket ids given by this run of the net-thread.
+///
+/// The net-thread may be restarted by the root task, then the ids given by
+/// the old one are rejected instead of referring to the new sockets.
+static SESSION: LazyInit<u64> = LazyInit::new();
+
+/// The socket id of the index in [SOCKET_VEC].
+fn socket_id(index: usize) -> u64 {
+    *SESSION | index as u64
+}
 
-fn alloc_socket_id() -> u64 {
-    let mut sockets = SOCKET_VEC.lock();
+/// The index in [SOCKET_VEC] of the socket id given by this run.
+fn socket_index(id: u64) -> Option<usize> {
+    (id & !0xffff_ffff == *SESSION).then_some((id & 0xffff_ffff) as usize)
+}
+
+/// Allocate a free index in `sockets`.
+///
+/// The caller should hold the lock of [SOCKET_VEC], then the new index can
+/// be filled in place.
+fn alloc_socket_index(sockets: &mut Vec<Option<NetSocket>>) -> usize {
     sockets.iter().position(|x| x.is_none()).unwrap_or_else(|| {
         sockets.push(None);
         sockets.len() - 1
-    }) as u64
+    })
+}
+
+/// Insert the new socket, returns the socket id.
+fn insert_socket(socket: NetSocket) -> u64 {
+    let mut socket_vec = SOCKET_VEC.lock();
+    let index = alloc_socket_index(&mut socket_vec);
+    socket_vec[index] = Some(socket);
+    socket_id(index)
+}
+
+/// Get the socket `id`, the ids of the closed sockets and of an old run of
+/// the net-thread are rejected.
+fn get_socket(sockets: &[Option<NetSocket>], id: u64) -> AxResult<&NetSocket> {
+    match socket_index(id).and_then(|index| sockets.get(index)) {
+        Some(Some(socket)) => Ok(socket),
+        _ => Err(AxError::InvalidInput),
+    }
 }
 
 /// Get the virtual address of the page seat.
@@ -48,6 +101,52 @@ fn page_seat_vaddr() -> usize {
     0x1_0000_2000
 }
 
+/// Map the received page at the page seat and get the buffer in it.
+///
+/// The data is placed at the start of the page by the kernel thread, `buf`
+/// is only used to check whether the buffer is contained in a page.
+fn map_buffer(
+    cap: Cap<sel4::cap_type::SmallPage>,
+    buf: u64,
+    buf_len: u64,
+) -> &'static mut [u8] {
+    cap.frame_map(
+        init_thread::slot::VSPACE.cap(),
+        page_seat_vaddr(),
+        CapRights::all(),
+        VmAttributes::DEFAULT,
+    )
+    .unwrap();
+    if VirtAddr::from(buf as usize).align_offset_4k() + buf_len as usize > PAGE_SIZE_4K {
+        panic!("The buffer is not contained in a page.");
+    }
+    unsafe { core::slice::from_raw_parts_mut(page_seat_vaddr() as *mut u8, buf_len as usize) }
+}
+
+/// Encode the result of a length returned operation into a message register.
+///
+/// The error is encoded as the negative error code, so it can not be
+/// confused with the length.
+fn handle_lenresult(res: AxResult<usize>) -> u64 {
+    match res {
+        Ok(len) => len as u64,
+        Err(e) => -(e.code() as i64) as u64,
+    }
+}
+
+/// Convert the result with an address into the registers of the reply.
+///
+/// The first register is the result, followed by the encoded address.
+fn handle_addrresult(res: AxResult<SocketAddr>) -> [u64; 1 + SOCKET_ADDR_REGS] {
+    match res {
+        Ok(addr) => {
+            let [r0, r1, r2] = socket_addr_to_regs(addr);
+            [0, r0, r1, r2]
+        }
+        Err(e) => [e.code() as u64, 0, 0, 0],
+    }
+}
+
 /// Read an item from the given pointer.
 ///
 /// # Arguments
@@ -81,171 +180,196 @@ pub(crate) fn handle_ipc(recv_ep: &Endpoint) {
         }
     }
 
-    let new_cap = Cap::<sel4::cap_type::SmallPage>::from_bits(DEFAULT_CUSTOM_SLOT + 3);
+    let new_cap = Cap::<sel4::cap_type::SmallPage>::from_bits(RECV_SLOT);
     with_ipc_buffer_mut(|buf| {
         buf.set_recv_slot(&sel4::init_thread::slot::CNODE.cap().relative(new_cap));
     });
 
-    let (message, _bade) = recv_ep.recv(());
-    if message.label() < 0x8 {
+    let (message, badge) = recv_ep.recv(SERVER_REPLY);
+    if badge >= NOTIFICATION_BADGE_START {
+        if badge & NET_IRQ_BADGE != 0 {
+            handle_irq();
+        }
+        if badge & TIMER_BADGE != 0 {
+            crate::timer::poll_timer_expired();
+            smoltcp_impl::poll_interfaces();
+        }
+        if badge & STOP_BADGE != 0 {
+            stop();
+        }
+    } else if message.label() < 0x8 {
         // Handle fault
         unimplemented!();
     } else {
         match NetRequsetabel::try_from(&message) {
             Some(NetRequsetabel::New) => {
-                let socket = smoltcp_impl::TcpSocket::new();
-                let id = alloc_socket_id();
-                SOCKET_VEC.lock()[id as usize] = Some(socket);
-                reply_with(&[id]);
+                let socket = NetSocket::Tcp(smoltcp_impl::TcpSocket::new());
+                reply_with(&[insert_socket(socket)]);
+            }
+            Some(NetRequsetabel::NewIcmp) => {
+                let socket = NetSocket::Icmp(smoltcp_impl::IcmpSocket::new());
+                reply_with(&[insert_socket(socket)]);
+            }
+            Some(NetRequsetabel::NewRaw(protocol)) => {
+                let socket = NetSocket::Raw(smoltcp_impl::RawSocket::new(protocol as u8));
+                reply_with(&[insert_socket(socket)]);
             }
             Some(NetRequsetabel::IsNonBlocking(id)) => {
                 let socket_vec = SOCKET_VEC.lock();
-                let ans = socket_vec[id as usize]
-                    .as_ref()
-                    .map_or(0, |socket| socket.is_nonblocking() as i32);
-                reply_with(&[ans as u64]);
+                let ans =
+                    get_socket(&socket_vec, id).map_or(0, |socket| socket.is_nonblocking() as u64);
+                reply_with(&[ans]);
+            }
+            Some(NetRequsetabel::SetNonBlocking(id, is_nonblocking)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let ans = get_socket(&socket_vec, id)
+                    .map(|socket| socket.set_nonblocking(is_nonblocking != 0));
+                reply_with(&[handle_axresult(ans)]);
             }
             Some(NetRequsetabel::Bind(id, local_addr)) => {
-                let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
+                let socket_vec = SOCKET_VEC.lock();
                 let local_addr = read_item(local_addr as *const SocketAddr, new_cap);
-                let ans = socket.bind(local_addr);
+                let ans = get_socket(&socket_vec, id)
+                    .and_then(NetSocket::as_tcp)
+                    .and_then(|socket| socket.bind(local_addr));
                 reply_with(&[handle_axresult(ans)]);
-                new_cap.frame_unmap().unwrap();
             }
             Some(NetRequsetabel::Send(id, buf, buf_len)) => {
-                let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
-
-                new_cap
-                    .frame_map(
-                        init_thread::slot::VSPACE.cap(),
-                        page_seat_vaddr(),
-                        CapRights::all(),
-                        VmAttributes::DEFAULT,
-                    )
-                    .unwrap();
-                if VirtAddr::from(buf as usize).align_offset_4k() + buf_len as usize > PAGE_SIZE_4K
-                {
-                    panic!("The buffer is not contained in a page.");
-                }
-                let buf = unsafe {
-                    core::slice::from_raw_parts(page_seat_vaddr() as *const u8, buf_len as usize)
-                };
-                let ans = socket.send(buf);
-                reply_with(&[ans.unwrap_or_else(|err| err.code() as usize) as u64]);
+                let socket_vec = SOCKET_VEC.lock();
+                let buf = map_buffer(new_cap, buf, buf_len);
+                let ans = get_socket(&socket_vec, id).and_then(|socket| socket.send(buf));
+                reply_with(&[handle_lenresult(ans)]);
 
                 new_cap.frame_unmap().unwrap();
             }
+            Some(NetRequsetabel::SendTo(id, buf, buf_len, remote_addr)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let buf = map_buffer(new_cap, buf, buf_len);
+                let ans = get_socket(&socket_vec, id)
+                    .and_then(|socket| socket.send_to(buf, socket_addr_from_regs(&remote_addr)));
+                reply_with(&[handle_lenresult(ans)]);
 
+                new_cap.frame_unmap().unwrap();
+            }
             Some(NetRequsetabel::Recv(id, buf, buf_len)) => {
-                let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
-                new_cap
-                    .frame_map(
-                        init_thread::slot::VSPACE.cap(),
-                        page_seat_vaddr(),
-                        CapRights::all(),
-                        VmAttributes::DEFAULT,
-                    )
-                    .unwrap();
-                if VirtAddr::from(buf as usize).align_offset_4k() + buf_len as usize > PAGE_SIZE_4K
-                {
-                    panic!("The buffer is not contained in a page.");
+                let socket_vec = SOCKET_VEC.lock();
+                let buf = map_buffer(new_cap, buf, buf_len);
+                let ans = get_socket(&socket_vec, id).and_then(|socket| socket.recv(buf));
+                reply_with(&[handle_lenresult(ans)]);
+
+                new_cap.frame_unmap().unwrap();
+            }
+            Some(NetRequsetabel::RecvFrom(id, buf, buf_len)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let buf = map_buffer(new_cap, buf, buf_len);
+                match get_socket(&socket_vec, id).and_then(|socket| socket.recv_from(buf)) {
+                    Ok((len, remote_addr)) => {
+                        let [r0, r1, r2] = socket_addr_to_regs(remote_addr);
+                        reply_with(&[len as u64, r0, r1, r2]);
+                    }
+                    Err(err) => reply_with(&[handle_lenresult(Err(err))]),
                 }
-                let buf = unsafe {
-                    core::slice::from_raw_parts_mut(page_seat_vaddr() as *mut u8, buf_len as usize)
-                };
-                let ans = socket.recv(buf);
-                reply_with(&[ans.unwrap_or_else(|err| err.code() as usize) as u64]);
 
                 new_cap.frame_unmap().unwrap();
             }
             Some(NetRequsetabel::RecvTimeout(id, buf, buf_len, timeout)) => {
-                let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
-
-                new_cap
-                    .frame_map(
-                        init_thread::slot::VSPACE.cap(),
-                        page_seat_vaddr(),
-                        CapRights::all(),
-                        VmAttributes::DEFAULT,
-                    )
-                    .unwrap();
-                if VirtAddr::from(buf as usize).align_offset_4k() + buf_len as usize > PAGE_SIZE_4K
-                {
-                    panic!("The buffer is not contained in a page.");
-                }
-                let buf = unsafe {
-                    core::slice::from_raw_parts_mut(page_seat_vaddr() as *mut u8, buf_len as usize)
-                };
-                let ans = socket.recv_timeout(buf, timeout);
-                reply_with(&[ans.unwrap_or_else(|err| err.code() as usize) as u64]);
+                let socket_vec = SOCKET_VEC.lock();
+                let buf = map_buffer(new_cap, buf, buf_len);
+                let ans = get_socket(&socket_vec, id)
+                    .and_then(NetSocket::as_tcp)
+                    .and_then(|socket| socket.recv_timeout(buf, timeout));
+                reply_with(&[handle_lenresult(ans)]);
 
                 new_cap.frame_unmap().unwrap();
             }
+            Some(NetRequsetabel::IcmpStats) => {
+                reply_with(&smoltcp_impl::ICMP_STATS.snapshot());
+            }
+            Some(NetRequsetabel::Poll(id)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let ans = get_socket(&socket_vec, id)
+                    .and_then(|socket| socket.poll())
+                    .map(|state| readiness_bits(state) as usize);
+                reply_with(&[handle_lenresult(ans)]);
+            }
+            Some(NetRequsetabel::SetSockOpt(id, opt, value)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let ans = NetSockOpt::try_from(opt)
+                    .ok_or(AxError::InvalidInput)
+                    .and_then(|opt| {
+                        get_socket(&socket_vec, id).and_then(|socket| socket.set_option(opt, value))
+                    });
+                reply_with(&[handle_axresult(ans)]);
+            }
+            Some(NetRequsetabel::GetSockOpt(id, opt)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let ans = NetSockOpt::try_from(opt)
+                    .ok_or(AxError::InvalidInput)
+                    .and_then(|opt| {
+                        get_socket(&socket_vec, id).and_then(|socket| socket.get_option(opt))
+                    })
+                    .map(|value| value as usize);
+                reply_with(&[handle_lenresult(ans)]);
+            }
+            Some(NetRequsetabel::GetSockName(id)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let ans = get_socket(&socket_vec, id).and_then(|socket| socket.local_addr());
+                reply_with(&handle_addrresult(ans));
+            }
+            Some(NetRequsetabel::GetPeerName(id)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let ans = get_socket(&socket_vec, id).and_then(|socket| socket.peer_addr());
+                reply_with(&handle_addrresult(ans));
+            }
             Some(NetRequsetabel::Connect(id, remote_addr)) => {
-                let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
+                let socket_vec = SOCKET_VEC.lock();
                 let remote_addr = read_item(remote_addr as *const SocketAddr, new_cap);
-                let ans = socket.connect(remote_addr);
+                let ans = get_socket(&socket_vec, id)
+                    .and_then(NetSocket::as_tcp)
+                    .and_then(|socket| socket.connect(remote_addr));
 
                 reply_with(&[handle_axresult(ans)]);
             }
-            Some(NetRequsetabel::Listen(id)) => {
-                let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
-                let ans = socket.listen();
+            Some(NetRequsetabel::Listen(id, backlog)) => {
+                let socket_vec = SOCKET_VEC.lock();
+                let ans = get_socket(&socket_vec, id)
+                    .and_then(NetSocket::as_tcp)
+                    .and_then(|socket| socket.listen(backlog as usize));
 
                 reply_with(&[handle_axresult(ans)]);
             }
             Some(NetRequsetabel::Accept(id)) => {
                 let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
-                let ans = socket
-                    .accept()
+                let ans = get_socket(&socket_vec, id)
+                    .and_then(NetSocket::as_tcp)
+                    .and_then(|socket| socket.accept())
                     .map(|new_socket| {
-                        let new_id = alloc_socket_id();
-                        let socket_addr = new_socket.local_addr().unwrap();
-                        // 将 IpAddr 的类型转换为 u64 类型
-                        let (addr_low, addr_high) = match socket_addr.ip() {
-                            IpAddr::V4(ipv4) => (ipv4.to_bits() as u64, 0),
-                            IpAddr::V6(ipv6) => {
-                                let addr: u128 = ipv6.to_bits();
-                                (addr as u64, (addr >> 32) as u64)
-                            }
-                        };
-                        socket_vec[new_id as usize] = Some(new_socket);
-                        [
-                            new_id,
-                            socket_addr.is_ipv4() as u64,
-                            socket_addr.port() as u64,
-                            addr_low,
-                            addr_high,
-                        ]
+                        let [r0, r1, r2] = socket_addr_to_regs(new_socket.peer_addr().unwrap());
+                        let index = alloc_socket_index(&mut socket_vec);
+                        socket_vec[index] = Some(NetSocket::Tcp(new_socket));
+                        [socket_id(index), r0, r1, r2]
                     })
-                    .unwrap_or_else(|err| [err.code() as u64, 0, 0, 0, 0]);
+                    .unwrap_or_else(|err| [handle_lenresult(Err(err)), 0, 0, 0]);
 
                 reply_with(&ans);
             }
             Some(NetRequsetabel::Close(id)) => {
                 let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].take().unwrap();
-                socket.close();
-                reply_with(&[]);
-            }
-
-            Some(NetRequsetabel::SetNonBlocking(id, is_nonblocking)) => {
-                let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
-                socket.set_nonblocking(is_nonblocking != 0);
-                reply_with(&[]);
+                let ans = get_socket(&socket_vec, id).map(|_| ());
+                // Dropping the socket shuts it down and removes it from the socket set.
+                if let Some(index) = ans.ok().and_then(|_| socket_index(id)) {
+                    if let Some(NetSocket::Tcp(socket)) = socket_vec[index].take() {
+                        socket.close();
+                    }
+                }
+                reply_with(&[handle_axresult(ans)]);
             }
             Some(NetRequsetabel::Shutdown(id)) => {
-                let mut socket_vec = SOCKET_VEC.lock();
-                let socket = socket_vec[id as usize].as_mut().unwrap();
-                let ans = socket.shutdown();
+                let socket_vec = SOCKET_VEC.lock();
+                let ans = get_socket(&socket_vec, id).and_then(|socket| match socket {
+                    NetSocket::Tcp(socket) => socket.shutdown(),
+                    _ => Ok(()),
+                });
 
                 reply_with(&[handle_axresult(ans)]);
             }
@@ -263,6 +387,68 @@ pub(crate) fn handle_ipc(recv_ep: &Endpoint) {
         .relative(new_cap)
         .delete()
         .unwrap();
+    notify_readiness();
+}
+
+/// Close all the sockets for the shutdown, then tell the root task.
+fn stop() -> ! {
+    debug_println!("[Net Thread] Closing the sockets");
+    let sockets = core::mem::take(&mut *SOCKET_VEC.lock());
+    for socket in sockets.into_iter().flatten() {
+        if let NetSocket::Tcp(socket) = socket {
+            socket.close();
+        }
+    }
+    // Send the FINs of the closed connections.
+    smoltcp_impl::poll_interfaces();
+    stopped(Endpoint::from_bits(DEFAULT_THREAD_FAULT_EP))
+}
+
+/// Handle the interrupt of the VIRTIO net device.
+fn handle_irq() {
+    let irq_handler = IrqHandler::from_bits(IRQ_HANDLER);
+    ack_net_interrupt();
+    smoltcp_impl::poll_interfaces();
+    irq_handler.irq_handler_ack().unwrap();
+}
+
+/// Convert a [PollState] into the `NET_POLL_*` bits.
+fn readiness_bits(state: PollState) -> u64 {
+    let mut bits = 0;
+    if state.readable {
+        bits |= NET_POLL_READABLE;
+    }
+    if state.writable {
+        bits |= NET_POLL_WRITABLE;
+    }
+    bits
+}
+
+/// The readiness of the sockets when [notify_readiness] was called last time.
+static LAST_READINESS: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+/// Signal the kernel thread if any socket becomes readable or writable.
+///
+/// Only the bits newly set are reported, the kernel thread queries the
+/// sockets it waits on with [NetRequsetabel::Poll] after the signal.
+fn notify_readiness() {
+    let sockets = SOCKET_VEC.lock();
+    let mut last = LAST_READINESS.lock();
+    last.resize(sockets.len(), 0);
+    let mut changed = false;
+    for (id, socket) in sockets.iter().enumerate() {
+        let now = socket
+            .as_ref()
+            .and_then(|socket| socket.poll().ok())
+            .map_or(0, readiness_bits);
+        changed |= now & !last[id] != 0;
+        last[id] = now;
+    }
+    // The slot is empty in the instances other than the first one, the
+    // kernel ignores the signal then.
+    if changed {
+        Notification::from_bits(READY_NTFN).signal();
+    }
 }
 
 /// Initialize IPC
@@ -271,8 +457,10 @@ pub(crate) fn handle_ipc(recv_ep: &Endpoint) {
 #[allow(unused)]
 pub(crate) fn run_ipc() {
     SOCKET_VEC.init_once(Mutex::new(Vec::new()));
+    // Keep the ids positive, the errors are replied as the negative codes.
+    SESSION.init_once((current_nanos() & 0x7fff_ffff) << 32);
 
-    let ipc_ep = Cap::<sel4::cap_type::Endpoint>::from_bits(DEFAULT_CUSTOM_SLOT + 2);
+    let ipc_ep = Cap::<sel4::cap_type::Endpoint>::from_bits(EP);
 
     loop {
         handle_ipc(&ipc_ep);
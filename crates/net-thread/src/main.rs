@@ -8,22 +8,27 @@ extern crate sel4_panicking;
 mod ipc;
 mod runtime;
 mod smoltcp_impl;
+mod timer;
 mod virtio_impl;
 use core::ptr::NonNull;
 
 use axdriver_net::NetDriverOps;
 use axdriver_virtio::{MmioTransport, VirtIoNetDev};
 
-use common::VIRTIO_MMIO_NET_VIRT_ADDR;
-use sel4::debug_println;
+use common::{get_device, register_irq};
+use crate_consts::{
+    slots::net_thread::{IRQ_HANDLER, IRQ_NTFN},
+    DEFAULT_THREAD_FAULT_EP,
+};
+use sel4::{
+    cap::{Endpoint, IrqHandler, Notification},
+    debug_println, init_thread,
+};
 
 use virtio_drivers::transport::mmio::VirtIOHeader;
 use virtio_impl::VirtIoHalImpl;
 sel4_panicking_env::register_debug_put_char!(sel4::sys::seL4_DebugPutChar);
 
-#[allow(unused)]
-const VIRTIO_NET_IRQ: usize = 0x2e + 0x20;
-
 pub fn fmt_with_module(record: &log::Record, f: &mut core::fmt::Formatter) -> core::fmt::Result {
     let target = match record.target().is_empty() {
         true => record.module_path().unwrap_or_default(),
@@ -57,9 +62,11 @@ fn main() -> ! {
         .build();
     LOGGER.set().unwrap();
     debug_println!("[Net Thread] EntryPoint");
+    let ep = Endpoint::from_bits(DEFAULT_THREAD_FAULT_EP);
+    let (mmio_vaddr, irq_num) = get_device(ep);
+    virtio_impl::set_net_mmio_vaddr(mmio_vaddr);
     let virtio_net = VirtIoNetDev::<VirtIoHalImpl, MmioTransport, 32>::try_new(unsafe {
-        MmioTransport::new(NonNull::new(VIRTIO_MMIO_NET_VIRT_ADDR as *mut VirtIOHeader).unwrap())
-            .unwrap()
+        MmioTransport::new(NonNull::new(mmio_vaddr as *mut VirtIOHeader).unwrap()).unwrap()
     })
     .expect("failed to create net driver");
 
@@ -69,9 +76,23 @@ fn main() -> ! {
     );
 
     smoltcp_impl::init(virtio_net);
-    // ipc::run_ipc();
-
+    #[cfg(feature = "test-client")]
     smoltcp_impl::test::test_client();
 
+    // Register interrupt handler and bind the notification to the TCB, so the
+    // interrupts are received from the same endpoint as the IPC requests.
+    let ntfn = Notification::from_bits(IRQ_NTFN);
+    let irq_handler = IrqHandler::from_bits(IRQ_HANDLER);
+
+    register_irq(ep, irq_handler, irq_num).expect("[Net Thread] failed to register the irq");
+    irq_handler.irq_handler_set_notification(ntfn).unwrap();
+    irq_handler.irq_handler_ack().unwrap();
+    init_thread::slot::TCB
+        .cap()
+        .tcb_bind_notification(ntfn)
+        .unwrap();
+
+    ipc::run_ipc();
+
     unreachable!()
 }
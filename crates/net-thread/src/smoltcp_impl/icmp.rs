@@ -0,0 +1,237 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use axerrno::{ax_err, AxError, AxResult};
+use log::debug;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::icmp::{self, BindError, Endpoint, SendError};
+use smoltcp::wire::{
+    EthernetFrame, EthernetProtocol, Icmpv4Message, Icmpv4Packet, IpAddress, IpProtocol,
+    Ipv4Packet,
+};
+use spin::Mutex;
+
+use super::{block_on, PollState, SocketSetWrapper, SOCKET_SET};
+
+/// ICMP counters of the network thread.
+///
+/// Echo requests are answered by smoltcp itself, so the requests and replies
+/// of the remote peers are counted by snooping the packets passing the device.
+#[derive(Default)]
+pub struct IcmpStats {
+    /// Echo requests received from the remote peers.
+    pub echo_requests_received: AtomicU64,
+    /// Echo replies answered to the remote peers.
+    pub echo_replies_sent: AtomicU64,
+    /// Echo requests sent through the ICMP and raw sockets.
+    pub echo_requests_sent: AtomicU64,
+    /// Echo replies received by the ICMP and raw sockets.
+    pub echo_replies_received: AtomicU64,
+}
+
+impl IcmpStats {
+    const fn new() -> Self {
+        Self {
+            echo_requests_received: AtomicU64::new(0),
+            echo_replies_sent: AtomicU64::new(0),
+            echo_requests_sent: AtomicU64::new(0),
+            echo_replies_received: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a snapshot of the counters, in the order of the fields.
+    pub fn snapshot(&self) -> [u64; 4] {
+        [
+            self.echo_requests_received.load(Ordering::Acquire),
+            self.echo_replies_sent.load(Ordering::Acquire),
+            self.echo_requests_sent.load(Ordering::Acquire),
+            self.echo_replies_received.load(Ordering::Acquire),
+        ]
+    }
+}
+
+pub static ICMP_STATS: IcmpStats = IcmpStats::new();
+
+/// The direction of a snooped packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Rx,
+    Tx,
+}
+
+/// Account the ICMP echo messages in an IPv4 packet passing the device.
+pub(crate) fn snoop_ipv4_packet(buf: &[u8], dir: Direction) -> Result<(), smoltcp::wire::Error> {
+    let ipv4_packet = Ipv4Packet::new_checked(buf)?;
+    if ipv4_packet.next_header() != IpProtocol::Icmp {
+        return Ok(());
+    }
+    let icmp_packet = Icmpv4Packet::new_checked(ipv4_packet.payload())?;
+    let counter = match (icmp_packet.msg_type(), dir) {
+        (Icmpv4Message::EchoRequest, Direction::Rx) => &ICMP_STATS.echo_requests_received,
+        (Icmpv4Message::EchoReply, Direction::Tx) => &ICMP_STATS.echo_replies_sent,
+        _ => return Ok(()),
+    };
+    counter.fetch_add(1, Ordering::AcqRel);
+    Ok(())
+}
+
+/// Account the ICMP echo messages in an ethernet frame passing the device.
+pub(crate) fn snoop_ethernet_frame(buf: &[u8], dir: Direction) -> Result<(), smoltcp::wire::Error> {
+    let ether_frame = EthernetFrame::new_checked(buf)?;
+    if ether_frame.ethertype() == EthernetProtocol::Ipv4 {
+        snoop_ipv4_packet(ether_frame.payload(), dir)?;
+    }
+    Ok(())
+}
+
+/// Account an ICMP message sent or received by a socket.
+pub(crate) fn account_socket_message(icmp: &[u8], dir: Direction) {
+    let Ok(icmp_packet) = Icmpv4Packet::new_checked(icmp) else {
+        return;
+    };
+    let counter = match (icmp_packet.msg_type(), dir) {
+        (Icmpv4Message::EchoRequest, Direction::Tx) => &ICMP_STATS.echo_requests_sent,
+        (Icmpv4Message::EchoReply, Direction::Rx) => &ICMP_STATS.echo_replies_received,
+        _ => return,
+    };
+    counter.fetch_add(1, Ordering::AcqRel);
+}
+
+/// An ICMP datagram socket, the same as the `SOCK_DGRAM`/`IPPROTO_ICMP`
+/// socket of linux.
+///
+/// The message sent and received by the socket is the ICMP header with
+/// its payload. The socket is bound to the identifier of the first echo
+/// request if it was not bound before.
+pub struct IcmpSocket {
+    handle: SocketHandle,
+    ident: Mutex<Option<u16>>,
+    peer_addr: Mutex<Option<IpAddress>>,
+    nonblock: AtomicBool,
+}
+
+#[allow(unused)]
+impl IcmpSocket {
+    /// Creates a new ICMP socket.
+    pub fn new() -> Self {
+        Self {
+            handle: SOCKET_SET.add(SocketSetWrapper::new_icmp_socket()),
+            ident: Mutex::new(None),
+            peer_addr: Mutex::new(None),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns whether this socket is in nonblocking mode.
+    #[inline]
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    /// Moves this ICMP socket into or out of nonblocking mode.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Binds the socket to the given echo identifier.
+    pub fn bind(&self, ident: u16) -> AxResult {
+        let mut bound = self.ident.lock();
+        if bound.is_some() {
+            return ax_err!(InvalidInput, "socket bind() failed: already bound");
+        }
+        SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+            socket
+                .bind(Endpoint::Ident(ident))
+                .or_else(|e| match e {
+                    BindError::InvalidState => ax_err!(AlreadyExists, "socket bind() failed"),
+                    BindError::Unaddressable => ax_err!(InvalidInput, "socket bind() failed"),
+                })
+        })?;
+        *bound = Some(ident);
+        Ok(())
+    }
+
+    /// Returns the bound echo identifier, or `0` if not bound.
+    pub fn ident(&self) -> u16 {
+        self.ident.lock().unwrap_or(0)
+    }
+
+    /// Returns the connected address, or
+    /// [`Err(NotConnected)`](AxError::NotConnected) if not connected.
+    pub fn peer_addr(&self) -> AxResult<IpAddress> {
+        self.peer_addr.lock().ok_or(AxError::NotConnected)
+    }
+
+    /// Sets the default destination of [`send`](Self::send).
+    pub fn connect(&self, addr: IpAddress) -> AxResult {
+        *self.peer_addr.lock() = Some(addr);
+        Ok(())
+    }
+
+    /// Transmits an ICMP message to the connected address.
+    pub fn send(&self, buf: &[u8]) -> AxResult<usize> {
+        let addr = self.peer_addr.lock().ok_or(AxError::NotConnected)?;
+        self.send_to(buf, addr)
+    }
+
+    /// Transmits an ICMP message to the given address.
+    pub fn send_to(&self, buf: &[u8], addr: IpAddress) -> AxResult<usize> {
+        let icmp_packet = Icmpv4Packet::new_checked(buf)
+            .map_err(|_| AxError::InvalidInput)?;
+        if self.ident.lock().is_none() {
+            self.bind(icmp_packet.echo_ident())?;
+        }
+        block_on(self.is_nonblocking(), || {
+            SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+                match socket.send_slice(buf, addr) {
+                    Ok(()) => Ok(buf.len()),
+                    Err(SendError::BufferFull) => Err(AxError::WouldBlock),
+                    Err(SendError::Unaddressable) => {
+                        ax_err!(ConnectionRefused, "socket send_to() failed")
+                    }
+                }
+            })
+        })
+        .inspect(|_| account_socket_message(buf, Direction::Tx))
+    }
+
+    /// Receives an ICMP message and the address it comes from.
+    pub fn recv_from(&self, buf: &mut [u8]) -> AxResult<(usize, IpAddress)> {
+        if self.ident.lock().is_none() {
+            return ax_err!(NotConnected, "socket recv_from() failed: not bound");
+        }
+        let (len, addr) = block_on(self.is_nonblocking(), || {
+            SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+                if socket.can_recv() {
+                    socket.recv_slice(buf).map_err(|_| AxError::BadState)
+                } else {
+                    Err(AxError::WouldBlock)
+                }
+            })
+        })?;
+        account_socket_message(&buf[..len], Direction::Rx);
+        debug!("ICMP socket {}: received {} bytes from {}", self.handle, len, addr);
+        Ok((len, addr))
+    }
+
+    /// Receives an ICMP message.
+    pub fn recv(&self, buf: &mut [u8]) -> AxResult<usize> {
+        self.recv_from(buf).map(|(len, _)| len)
+    }
+
+    /// Whether the socket is readable or writable.
+    pub fn poll(&self) -> AxResult<PollState> {
+        SOCKET_SET.with_socket::<icmp::Socket, _, _>(self.handle, |socket| {
+            Ok(PollState {
+                readable: socket.can_recv(),
+                writable: socket.can_send(),
+            })
+        })
+    }
+}
+
+impl Drop for IcmpSocket {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
@@ -0,0 +1,99 @@
+//! IPv6 address configuration of the interfaces.
+//!
+//! The link-local address and the SLAAC addresses use the modified EUI-64
+//! interface identifier generated from the MAC address (RFC 4291). SLAAC
+//! prefixes are learnt by snooping the router advertisements (RFC 4862).
+
+use smoltcp::wire::{
+    EthernetAddress, EthernetFrame, EthernetProtocol, IpProtocol, Ipv6Address, Ipv6Packet,
+};
+use spin::Mutex;
+
+/// The prefix length of the link-local and SLAAC addresses.
+pub(crate) const IPV6_SLAAC_PREFIX_LEN: u8 = 64;
+
+const ICMPV6_ROUTER_ADVERT: u8 = 134;
+/// The offset of the options in a router advertisement message.
+const ROUTER_ADVERT_OPTIONS: usize = 16;
+const NDISC_OPTION_PREFIX_INFO: u8 = 3;
+/// The autonomous address-configuration flag of a prefix information option.
+const PREFIX_INFO_FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// A prefix advertised by a router which can be used by SLAAC.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SlaacPrefix {
+    /// The address generated from the prefix.
+    pub addr: Ipv6Address,
+    /// The link-local address of the router, set if it can be the default router.
+    pub router: Option<Ipv6Address>,
+}
+
+/// The prefix learnt from the last router advertisement, not applied yet.
+static PENDING_PREFIX: Mutex<Option<SlaacPrefix>> = Mutex::new(None);
+
+/// Generate an address from a 64 bits prefix and the modified EUI-64
+/// interface identifier of `mac`.
+pub(crate) fn eui64_addr(prefix: Ipv6Address, mac: EthernetAddress) -> Ipv6Address {
+    let mut bytes = prefix.0;
+    let mac = mac.0;
+    bytes[8..].copy_from_slice(&[mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]]);
+    Ipv6Address(bytes)
+}
+
+/// Generate the link-local address (`fe80::/64`) of `mac`.
+pub(crate) fn link_local_addr(mac: EthernetAddress) -> Ipv6Address {
+    eui64_addr(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac)
+}
+
+/// Snoop an ethernet frame, and record the SLAAC prefix if it is a router
+/// advertisement.
+pub(crate) fn snoop_router_advert(
+    buf: &[u8],
+    mac: EthernetAddress,
+) -> Result<(), smoltcp::wire::Error> {
+    let ether_frame = EthernetFrame::new_checked(buf)?;
+    if ether_frame.ethertype() != EthernetProtocol::Ipv6 {
+        return Ok(());
+    }
+    let ipv6_packet = Ipv6Packet::new_checked(ether_frame.payload())?;
+    if ipv6_packet.next_header() != IpProtocol::Icmpv6 {
+        return Ok(());
+    }
+    let icmp = ipv6_packet.payload();
+    if icmp.len() < ROUTER_ADVERT_OPTIONS || icmp[0] != ICMPV6_ROUTER_ADVERT {
+        return Ok(());
+    }
+    let router_lifetime = u16::from_be_bytes([icmp[6], icmp[7]]);
+    let router = (router_lifetime != 0).then_some(ipv6_packet.src_addr());
+
+    let mut options = &icmp[ROUTER_ADVERT_OPTIONS..];
+    while options.len() >= 2 {
+        let option_len = options[1] as usize * 8;
+        if option_len == 0 || option_len > options.len() {
+            return Err(smoltcp::wire::Error);
+        }
+        let option = &options[..option_len];
+        // prefix information: type, length, prefix length, flags,
+        // valid lifetime, preferred lifetime, reserved and the prefix.
+        if option[0] == NDISC_OPTION_PREFIX_INFO
+            && option_len == 32
+            && option[2] == IPV6_SLAAC_PREFIX_LEN
+            && option[3] & PREFIX_INFO_FLAG_AUTONOMOUS != 0
+        {
+            let prefix = Ipv6Address::from_bytes(&option[16..32]);
+            if !prefix.is_link_local() {
+                *PENDING_PREFIX.lock() = Some(SlaacPrefix {
+                    addr: eui64_addr(prefix, mac),
+                    router,
+                });
+            }
+        }
+        options = &options[option_len..];
+    }
+    Ok(())
+}
+
+/// Take the SLAAC prefix which is not applied to the interface yet.
+pub(crate) fn take_pending_prefix() -> Option<SlaacPrefix> {
+    PENDING_PREFIX.lock().take()
+}
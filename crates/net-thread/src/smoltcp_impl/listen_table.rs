@@ -1,118 +1,244 @@
-use alloc::{boxed::Box, collections::VecDeque};
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use axerrno::{ax_err, AxError, AxResult};
-use core::ops::{Deref, DerefMut};
 use log::{debug, warn};
 use smoltcp::iface::{SocketHandle, SocketSet};
 use smoltcp::socket::tcp::{self, State};
 use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
 use spin::mutex::SpinMutex;
 
-use super::{SocketSetWrapper, LISTEN_QUEUE_SIZE, SOCKET_SET};
+use super::{SocketSetWrapper, SOCKET_SET};
+
+/// The number of buckets in the [`ListenTable`], ports are hashed into them.
+const BUCKET_NUM: usize = 256;
+
+/// The maximum backlog of a listener.
+///
+/// Linux clamps the backlog to `SOMAXCONN` (4096), but every pending
+/// connection here holds the buffers of a whole TCP socket.
+pub const MAX_LISTEN_BACKLOG: usize = 128;
 
-const PORT_NUM: usize = 0x2000;
+/// The id of a listener in the [`ListenTable`], `0` is never used.
+pub type ListenerId = u64;
 
 struct ListenTableEntry {
+    id: ListenerId,
     listen_endpoint: IpListenEndpoint,
+    /// Whether the listener is created with `SO_REUSEPORT`.
+    reuse_port: bool,
+    backlog: usize,
+    /// The connections which are still in the handshake.
     syn_queue: VecDeque<SocketHandle>,
+    /// The established connections waiting to be accepted.
+    accept_queue: VecDeque<SocketHandle>,
 }
 
 impl ListenTableEntry {
-    pub fn new(listen_endpoint: IpListenEndpoint) -> Self {
+    pub fn new(
+        id: ListenerId,
+        listen_endpoint: IpListenEndpoint,
+        backlog: usize,
+        reuse_port: bool,
+    ) -> Self {
         Self {
+            id,
             listen_endpoint,
-            syn_queue: VecDeque::with_capacity(LISTEN_QUEUE_SIZE),
+            reuse_port,
+            backlog,
+            syn_queue: VecDeque::new(),
+            accept_queue: VecDeque::new(),
         }
     }
 
     #[inline]
-    fn can_accept(&self, dst: IpAddress) -> bool {
-        match self.listen_endpoint.addr {
-            Some(addr) => addr == dst,
-            None => true,
+    fn can_accept(&self, dst: IpEndpoint) -> bool {
+        self.listen_endpoint.port == dst.port
+            && match self.listen_endpoint.addr {
+                Some(addr) => addr == dst.addr,
+                None => true,
+            }
+    }
+
+    /// Whether the entry listens on the given address exactly.
+    #[inline]
+    fn is_bound_to(&self, addr: Option<IpAddress>) -> bool {
+        self.listen_endpoint.addr == addr
+    }
+
+    /// Whether the entry conflicts with a listener on `endpoint`.
+    ///
+    /// The unspecified addresses `0.0.0.0` and `::` are both kept as no
+    /// address, so they conflict with each other and with every address on
+    /// the port. Two specific addresses conflict only if they are the same,
+    /// so an IPv4 and an IPv6 listener on specific addresses can share the
+    /// port. Listeners on the same address never conflict if all of them set
+    /// `SO_REUSEPORT`.
+    #[inline]
+    fn conflicts_with(&self, endpoint: IpListenEndpoint, reuse_port: bool) -> bool {
+        if self.listen_endpoint.port != endpoint.port {
+            return false;
+        }
+        if self.reuse_port && reuse_port && self.is_bound_to(endpoint.addr) {
+            return false;
+        }
+        match (self.listen_endpoint.addr, endpoint.addr) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
         }
     }
+
+    /// The number of the connections which are not accepted yet.
+    #[inline]
+    fn pending(&self) -> usize {
+        self.syn_queue.len() + self.accept_queue.len()
+    }
+
+    /// Moves the established connections from the SYN queue to the accept
+    /// queue, and drops the connections reset in the handshake.
+    fn update_queues(&mut self) {
+        let accept_queue = &mut self.accept_queue;
+        self.syn_queue.retain(|&handle| {
+            if is_closed(handle) {
+                debug!("TCP socket {}: reset in the handshake", handle);
+                SOCKET_SET.remove(handle);
+                false
+            } else if is_connected(handle) {
+                accept_queue.push_back(handle);
+                false
+            } else {
+                true
+            }
+        });
+    }
 }
 
 impl Drop for ListenTableEntry {
     fn drop(&mut self) {
-        for &handle in &self.syn_queue {
+        for &handle in self.syn_queue.iter().chain(self.accept_queue.iter()) {
             SOCKET_SET.remove(handle);
         }
     }
 }
 
+/// The listeners whose ports are hashed into the same bucket.
+type ListenTableBucket = Vec<Box<ListenTableEntry>>;
+
+/// The table of the TCP listeners, covering the whole port space.
 pub struct ListenTable {
-    tcp: Box<[SpinMutex<Option<Box<ListenTableEntry>>>]>,
+    tcp: Box<[SpinMutex<ListenTableBucket>]>,
+    next_id: AtomicU64,
 }
 
 #[allow(unused)]
 impl ListenTable {
     pub fn new() -> Self {
-        let tcp = unsafe {
-            let mut buf = Box::new_uninit_slice(PORT_NUM);
-            for i in 0..PORT_NUM {
-                buf[i].write(SpinMutex::new(None));
-            }
-            buf.assume_init()
-        };
-        Self { tcp }
+        let tcp = (0..BUCKET_NUM)
+            .map(|_| SpinMutex::new(Vec::new()))
+            .collect();
+        Self {
+            tcp,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    #[inline]
+    fn bucket(&self, port: u16) -> &SpinMutex<ListenTableBucket> {
+        &self.tcp[port as usize % BUCKET_NUM]
     }
 
     pub fn can_listen(&self, port: u16) -> bool {
-        self.tcp[port as usize].lock().is_none()
+        !self
+            .bucket(port)
+            .lock()
+            .iter()
+            .any(|entry| entry.listen_endpoint.port == port)
     }
 
-    pub fn listen(&self, listen_endpoint: IpListenEndpoint) -> AxResult {
+    /// Whether a socket with `SO_REUSEPORT` set to `reuse_port` can bind to
+    /// `endpoint` without conflicting with the listeners.
+    pub fn can_bind(&self, endpoint: IpListenEndpoint, reuse_port: bool) -> bool {
+        !self
+            .bucket(endpoint.port)
+            .lock()
+            .iter()
+            .any(|entry| entry.conflicts_with(endpoint, reuse_port))
+    }
+
+    /// Adds a listener on `listen_endpoint` and returns its id.
+    ///
+    /// The `backlog` is clamped to `1..=MAX_LISTEN_BACKLOG`.
+    pub fn listen(
+        &self,
+        listen_endpoint: IpListenEndpoint,
+        backlog: usize,
+        reuse_port: bool,
+    ) -> AxResult<ListenerId> {
         let port = listen_endpoint.port;
         assert_ne!(port, 0);
-        let mut entry = self.tcp[port as usize].lock();
-        if entry.is_none() {
-            *entry = Some(Box::new(ListenTableEntry::new(listen_endpoint)));
-            Ok(())
-        } else {
-            ax_err!(AddrInUse, "socket listen() failed")
-            // sel4::Error::InvalidArgument
+        let mut bucket = self.bucket(port).lock();
+        if bucket
+            .iter()
+            .any(|entry| entry.conflicts_with(listen_endpoint, reuse_port))
+        {
+            return ax_err!(AddrInUse, "socket listen() failed");
         }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        bucket.push(Box::new(ListenTableEntry::new(
+            id,
+            listen_endpoint,
+            backlog.clamp(1, MAX_LISTEN_BACKLOG),
+            reuse_port,
+        )));
+        Ok(id)
+    }
+
+    /// Changes the backlog of a listener, as calling `listen` again does.
+    pub fn set_backlog(&self, port: u16, id: ListenerId, backlog: usize) -> AxResult {
+        let mut bucket = self.bucket(port).lock();
+        let entry = find_entry(&mut bucket, id)?;
+        entry.backlog = backlog.clamp(1, MAX_LISTEN_BACKLOG);
+        Ok(())
+    }
+
+    pub fn unlisten(&self, port: u16, id: ListenerId) {
+        debug!("TCP socket unlisten on port {}", port);
+        self.bucket(port).lock().retain(|entry| entry.id != id);
     }
 
-    pub fn unlisten(&self, port: u16) {
-        debug!("TCP socket unlisten on {}", port);
-        *self.tcp[port as usize].lock() = None;
+    pub fn can_accept(&self, port: u16, id: ListenerId) -> AxResult<bool> {
+        let mut bucket = self.bucket(port).lock();
+        Ok(!find_entry(&mut bucket, id)?.accept_queue.is_empty())
     }
 
-    pub fn can_accept(&self, port: u16) -> AxResult<bool> {
-        if let Some(entry) = self.tcp[port as usize].lock().deref() {
-            Ok(entry.syn_queue.iter().any(|&handle| is_connected(handle)))
+    pub fn accept(
+        &self,
+        port: u16,
+        id: ListenerId,
+    ) -> AxResult<(SocketHandle, (IpEndpoint, IpEndpoint))> {
+        let mut bucket = self.bucket(port).lock();
+        let entry = find_entry(&mut bucket, id)?;
+        // wait for connection
+        let handle = entry.accept_queue.pop_front().ok_or(AxError::WouldBlock)?;
+        // If the connection is reset, return ConnectionReset error
+        // Otherwise, return the handle and the address tuple
+        if is_closed(handle) {
+            SOCKET_SET.remove(handle);
+            ax_err!(ConnectionReset, "socket accept() failed: connection reset")
         } else {
-            ax_err!(InvalidInput, "socket accept() failed: not listen")
+            Ok((handle, get_addr_tuple(handle)))
         }
     }
 
-    pub fn accept(&self, port: u16) -> AxResult<(SocketHandle, (IpEndpoint, IpEndpoint))> {
-        if let Some(entry) = self.tcp[port as usize].lock().deref_mut() {
-            let syn_queue: &mut VecDeque<SocketHandle> = &mut entry.syn_queue;
-            let idx = syn_queue
-                .iter()
-                .enumerate()
-                .find_map(|(idx, &handle)| is_connected(handle).then_some(idx))
-                .ok_or(AxError::WouldBlock)?; // wait for connection
-            if idx > 0 {
-                warn!(
-                    "slow SYN queue enumeration: index = {}, len = {}!",
-                    idx,
-                    syn_queue.len()
-                );
-            }
-            let handle = syn_queue.swap_remove_front(idx).unwrap();
-            // If the connection is reset, return ConnectionReset error
-            // Otherwise, return the handle and the address tuple
-            if is_closed(handle) {
-                ax_err!(ConnectionReset, "socket accept() failed: connection reset")
-            } else {
-                Ok((handle, get_addr_tuple(handle)))
+    /// Moves the established connections of all the listeners to their
+    /// accept queues, called after polling the interfaces.
+    pub fn update_queues(&self) {
+        for bucket in self.tcp.iter() {
+            for entry in bucket.lock().iter_mut() {
+                if !entry.syn_queue.is_empty() {
+                    entry.update_queues();
+                }
             }
-        } else {
-            ax_err!(InvalidInput, "socket accept() failed: not listen")
         }
     }
 
@@ -122,14 +248,20 @@ impl ListenTable {
         dst: IpEndpoint,
         sockets: &mut SocketSet<'_>,
     ) {
-        if let Some(entry) = self.tcp[dst.port as usize].lock().deref_mut() {
-            if !entry.can_accept(dst.addr) {
-                // not listening on this address
-                return;
-            }
-            if entry.syn_queue.len() >= LISTEN_QUEUE_SIZE {
-                // SYN queue is full, drop the packet
-                warn!("SYN queue overflow!");
+        let mut bucket = self.bucket(dst.port).lock();
+        // Prefer the listeners on the exact address to the wildcard ones, and
+        // balance the connections among the listeners sharing the address.
+        let exact = bucket
+            .iter()
+            .any(|entry| entry.can_accept(dst) && entry.is_bound_to(Some(dst.addr)));
+        let entry = bucket
+            .iter_mut()
+            .filter(|entry| entry.can_accept(dst) && (!exact || entry.is_bound_to(Some(dst.addr))))
+            .min_by_key(|entry| entry.pending());
+        if let Some(entry) = entry {
+            if entry.syn_queue.len() >= entry.backlog || entry.accept_queue.len() >= entry.backlog {
+                // The backlog is full, drop the packet
+                warn!("TCP listener on {} overflows!", entry.listen_endpoint);
                 return;
             }
             let mut socket = SocketSetWrapper::new_tcp_socket();
@@ -145,6 +277,13 @@ impl ListenTable {
     }
 }
 
+fn find_entry(bucket: &mut ListenTableBucket, id: ListenerId) -> AxResult<&mut ListenTableEntry> {
+    match bucket.iter_mut().find(|entry| entry.id == id) {
+        Some(entry) => Ok(entry),
+        None => ax_err!(InvalidInput, "socket accept() failed: not listen"),
+    }
+}
+
 fn is_connected(handle: SocketHandle) -> bool {
     SOCKET_SET.with_socket::<tcp::Socket, _, _>(handle, |socket| {
         !matches!(socket.state(), State::Listen | State::SynReceived)
@@ -5,7 +5,8 @@ use smoltcp::{
     time::Instant,
 };
 
-use crate::smoltcp_impl::LISTEN_TABLE;
+use crate::smoltcp_impl::icmp::{snoop_ipv4_packet, Direction};
+use crate::smoltcp_impl::snoop_tcp_from_ip;
 
 pub(crate) struct LoopbackDev {
     pub(crate) queue: VecDeque<Vec<u8>>,
@@ -21,25 +22,6 @@ impl LoopbackDev {
     }
 }
 
-fn snoop_tcp_from_ip(buffer: &[u8], sockets: &mut SocketSet) -> Result<(), smoltcp::wire::Error> {
-    use smoltcp::wire::IpEndpoint as SocketAddr;
-    use smoltcp::wire::{IpProtocol, Ipv4Packet, TcpPacket};
-
-    let ipv4_packet = Ipv4Packet::new_checked(buffer)?;
-
-    if ipv4_packet.next_header() == IpProtocol::Tcp {
-        let tcp_packet = TcpPacket::new_checked(ipv4_packet.payload())?;
-        let src_addr = SocketAddr::new(ipv4_packet.src_addr().into(), tcp_packet.src_port());
-        let dst_addr = SocketAddr::new(ipv4_packet.dst_addr().into(), tcp_packet.dst_port());
-        let is_first = tcp_packet.syn() && !tcp_packet.ack();
-        if is_first {
-            // create a socket for the first incoming TCP packet, as the later accept() returns.
-            LISTEN_TABLE.incoming_tcp_packet(src_addr, dst_addr, sockets);
-        }
-    }
-    Ok(())
-}
-
 pub(crate) struct RxTokenScoop {
     buffer: Vec<u8>,
 }
@@ -58,6 +40,7 @@ impl smoltcp::phy::RxToken for RxTokenScoop {
 
     fn preprocess(&self, sockets: &mut SocketSet<'_>) {
         snoop_tcp_from_ip(&self.buffer, sockets).ok();
+        snoop_ipv4_packet(&self.buffer, Direction::Rx).ok();
     }
 }
 
@@ -68,6 +51,7 @@ impl<'a> smoltcp::phy::TxToken for TxToken<'a> {
     {
         let mut buffer = vec![0; len];
         let result = f(&mut buffer);
+        snoop_ipv4_packet(&buffer, Direction::Tx).ok();
         self.queue.push_back(buffer);
         result
     }
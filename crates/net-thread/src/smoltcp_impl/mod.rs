@@ -5,28 +5,37 @@
 
 mod addr;
 mod bench;
+mod icmp;
+mod ipv6;
 mod listen_table;
 mod loopback;
+mod raw;
 mod tcp;
 pub mod test;
 
 use axdriver_net::{NetBufPtr, NetDriverOps};
 use axdriver_virtio::{MmioTransport, VirtIoNetDev};
+use addr::{from_core_ipaddr, into_core_ipaddr};
 use axerrno::{AxError, AxResult};
+use common::{current_nanos, NetSockOpt, NANOS_PER_MICROS};
 use core::cell::RefCell;
 use core::ops::DerefMut;
-use core::sync::atomic::AtomicU64;
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazyinit::LazyInit;
 use listen_table::ListenTable;
-use log::{debug, trace, warn};
+use log::{debug, info, trace, warn};
 use sel4::debug_println;
 
 use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
 use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
 use smoltcp::socket::{self, AnySocket, Socket};
 use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr};
+use smoltcp::wire::{
+    EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpProtocol, IpVersion, Ipv6Address,
+};
 use spin::Mutex;
+pub(crate) use icmp::*;
+pub(crate) use raw::*;
 pub(crate) use tcp::*;
 // Qemu IP
 const IP: &str = "10.0.2.15";
@@ -40,7 +49,10 @@ const TCP_RX_BUF_LEN: usize = 64 * 64;
 const TCP_TX_BUF_LEN: usize = 64 * 64;
 const UDP_RX_BUF_LEN: usize = 64 * 64;
 const UDP_TX_BUF_LEN: usize = 64 * 64;
-const LISTEN_QUEUE_SIZE: usize = 32;
+const ICMP_RX_BUF_LEN: usize = 64 * 64;
+const ICMP_TX_BUF_LEN: usize = 64 * 64;
+const RAW_RX_BUF_LEN: usize = 64 * 64;
+const RAW_TX_BUF_LEN: usize = 64 * 64;
 
 /// I/O poll results.
 #[derive(Debug, Default, Clone, Copy)]
@@ -60,6 +72,8 @@ struct AxNetTxToken<'a>(&'a RefCell<NetDevice>);
 impl<'a> RxToken for AxNetRxToken<'a> {
     fn preprocess(&self, sockets: &mut SocketSet<'_>) {
         snoop_tcp_packet(self.1.packet(), sockets).ok();
+        icmp::snoop_ethernet_frame(self.1.packet(), icmp::Direction::Rx).ok();
+        ipv6::snoop_router_advert(self.1.packet(), ETH0.ethernet_address()).ok();
     }
 
     fn consume<R, F>(self, f: F) -> R
@@ -87,21 +101,60 @@ impl<'a> TxToken for AxNetTxToken<'a> {
         let mut tx_buf = dev.alloc_tx_buffer(len).unwrap();
         let ret = f(tx_buf.packet_mut());
         trace!("SEND {} bytes: {:02X?}", len, tx_buf.packet());
+        icmp::snoop_ethernet_frame(tx_buf.packet(), icmp::Direction::Tx).ok();
         dev.transmit(tx_buf).unwrap();
         ret
     }
 }
 
 fn snoop_tcp_packet(buf: &[u8], sockets: &mut SocketSet<'_>) -> Result<(), smoltcp::wire::Error> {
-    use smoltcp::wire::{EthernetFrame, IpProtocol, Ipv4Packet, TcpPacket};
+    use smoltcp::wire::{EthernetFrame, EthernetProtocol};
 
     let ether_frame = EthernetFrame::new_checked(buf)?;
-    let ipv4_packet = Ipv4Packet::new_checked(ether_frame.payload())?;
+    match ether_frame.ethertype() {
+        EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6 => {
+            snoop_tcp_from_ip(ether_frame.payload(), sockets)
+        }
+        _ => Ok(()),
+    }
+}
 
-    if ipv4_packet.next_header() == IpProtocol::Tcp {
-        let tcp_packet = TcpPacket::new_checked(ipv4_packet.payload())?;
-        let src_addr = (ipv4_packet.src_addr(), tcp_packet.src_port()).into();
-        let dst_addr = (ipv4_packet.dst_addr(), tcp_packet.dst_port()).into();
+/// Snoop an IPv4 or IPv6 packet, and prepare a socket in the [ListenTable]
+/// for the first incoming TCP packet of a connection.
+pub(crate) fn snoop_tcp_from_ip(
+    buf: &[u8],
+    sockets: &mut SocketSet<'_>,
+) -> Result<(), smoltcp::wire::Error> {
+    use smoltcp::wire::{IpEndpoint, Ipv4Packet, Ipv6Packet, TcpPacket};
+
+    let (src_addr, dst_addr, next_header, payload): (IpAddress, IpAddress, _, _) =
+        match IpVersion::of_packet(buf)? {
+            IpVersion::Ipv4 => {
+                let packet = Ipv4Packet::new_checked(buf)?;
+                let payload = packet.payload();
+                (
+                    packet.src_addr().into(),
+                    packet.dst_addr().into(),
+                    packet.next_header(),
+                    payload,
+                )
+            }
+            IpVersion::Ipv6 => {
+                let packet = Ipv6Packet::new_checked(buf)?;
+                let payload = packet.payload();
+                (
+                    packet.src_addr().into(),
+                    packet.dst_addr().into(),
+                    packet.next_header(),
+                    payload,
+                )
+            }
+        };
+
+    if next_header == IpProtocol::Tcp {
+        let tcp_packet = TcpPacket::new_checked(payload)?;
+        let src_addr = IpEndpoint::new(src_addr, tcp_packet.src_port());
+        let dst_addr = IpEndpoint::new(dst_addr, tcp_packet.dst_port());
         let is_first = tcp_packet.syn() && !tcp_packet.ack();
         if is_first {
             // create a socket for the first incoming TCP packet, as the later accept() returns.
@@ -180,6 +233,8 @@ pub(crate) struct InterfaceWrapper {
     ether_addr: EthernetAddress,
     dev: Mutex<DeviceWrapper>,
     iface: Mutex<Interface>,
+    /// Whether to configure IPv6 addresses from router advertisements.
+    slaac: AtomicBool,
 }
 
 #[allow(unused)]
@@ -189,8 +244,13 @@ impl<'a> SocketSetWrapper<'a> {
     }
 
     pub fn new_tcp_socket() -> socket::tcp::Socket<'a> {
-        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_RX_BUF_LEN]);
-        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_TX_BUF_LEN]);
+        Self::new_tcp_socket_with_buffers(TCP_RX_BUF_LEN, TCP_TX_BUF_LEN)
+    }
+
+    /// Create a TCP socket with the given sizes of the receive and transmit buffers.
+    pub fn new_tcp_socket_with_buffers(rx_len: usize, tx_len: usize) -> socket::tcp::Socket<'a> {
+        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; rx_len]);
+        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; tx_len]);
         socket::tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer)
     }
 
@@ -206,6 +266,30 @@ impl<'a> SocketSetWrapper<'a> {
         socket::udp::Socket::new(udp_rx_buffer, udp_tx_buffer)
     }
 
+    pub fn new_icmp_socket() -> socket::icmp::Socket<'a> {
+        let icmp_rx_buffer = socket::icmp::PacketBuffer::new(
+            vec![socket::icmp::PacketMetadata::EMPTY; 32],
+            vec![0; ICMP_RX_BUF_LEN],
+        );
+        let icmp_tx_buffer = socket::icmp::PacketBuffer::new(
+            vec![socket::icmp::PacketMetadata::EMPTY; 32],
+            vec![0; ICMP_TX_BUF_LEN],
+        );
+        socket::icmp::Socket::new(icmp_rx_buffer, icmp_tx_buffer)
+    }
+
+    pub fn new_raw_socket(version: IpVersion, protocol: IpProtocol) -> socket::raw::Socket<'a> {
+        let raw_rx_buffer = socket::raw::PacketBuffer::new(
+            vec![socket::raw::PacketMetadata::EMPTY; 32],
+            vec![0; RAW_RX_BUF_LEN],
+        );
+        let raw_tx_buffer = socket::raw::PacketBuffer::new(
+            vec![socket::raw::PacketMetadata::EMPTY; 32],
+            vec![0; RAW_TX_BUF_LEN],
+        );
+        socket::raw::Socket::new(version, protocol, raw_rx_buffer, raw_tx_buffer)
+    }
+
     pub fn new_dns_socket() -> socket::dns::Socket<'a> {
         let server_addr = DNS_SEVER.parse().expect("invalid DNS server address");
         socket::dns::Socket::new(&[server_addr], vec![])
@@ -236,18 +320,18 @@ impl<'a> SocketSetWrapper<'a> {
         f(socket)
     }
 
-    pub fn bind_check(&self, addr: IpAddress, _port: u16) -> AxResult {
+    pub fn bind_check(&self, addr: IpAddress, port: u16) -> AxResult {
         let mut sockets = self.0.lock();
         for item in sockets.iter_mut() {
             match item.1 {
                 Socket::Tcp(s) => {
                     let local_addr = s.get_bound_endpoint();
-                    if local_addr.addr == Some(addr) {
+                    if local_addr.addr == Some(addr) && local_addr.port == port {
                         return Err(AxError::AddrInUse);
                     }
                 }
                 Socket::Udp(s) => {
-                    if s.endpoint().addr == Some(addr) {
+                    if s.endpoint().addr == Some(addr) && s.endpoint().port == port {
                         return Err(AxError::AddrInUse);
                     }
                 }
@@ -265,6 +349,20 @@ impl<'a> SocketSetWrapper<'a> {
         );
 
         ETH0.poll(&self.0);
+        LISTEN_TABLE.update_queues();
+
+        // Poll again when the interfaces ask for, the earliest of them.
+        let timestamp = InterfaceWrapper::current_time();
+        let poll_at = [
+            LOOPBACK.lock().poll_at(timestamp, &self.0.lock()),
+            ETH0.poll_at(timestamp, &self.0),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        crate::timer::set_poll_timer(
+            poll_at.map(|at| at.total_micros().max(0) as u64 * NANOS_PER_MICROS),
+        );
     }
 
     pub fn remove(&self, handle: SocketHandle) {
@@ -272,7 +370,6 @@ impl<'a> SocketSetWrapper<'a> {
         debug!("socket {}: destroyed", handle);
     }
 }
-static TIME_TICKS: AtomicU64 = AtomicU64::new(0);
 #[allow(unused)]
 impl InterfaceWrapper {
     fn new(name: &'static str, dev: NetDevice, ether_addr: EthernetAddress) -> Self {
@@ -286,12 +383,12 @@ impl InterfaceWrapper {
             ether_addr,
             dev: Mutex::new(dev),
             iface,
+            slaac: AtomicBool::new(false),
         }
     }
 
     pub fn current_time() -> Instant {
-        TIME_TICKS.fetch_add(1, core::sync::atomic::Ordering::Release);
-        Instant::from_micros(TIME_TICKS.load(core::sync::atomic::Ordering::Acquire) as i64)
+        Instant::from_micros((current_nanos() / NANOS_PER_MICROS) as i64)
     }
 
     pub fn name(&self) -> &str {
@@ -309,6 +406,49 @@ impl InterfaceWrapper {
         });
     }
 
+    /// Set up the IPv6 link-local address generated from the MAC address.
+    pub fn setup_ipv6_link_local(&self) {
+        let addr = ipv6::link_local_addr(self.ether_addr);
+        self.setup_ip_addr(IpAddress::Ipv6(addr), ipv6::IPV6_SLAAC_PREFIX_LEN);
+    }
+
+    /// Set up a static IPv6 address.
+    pub fn setup_ipv6_static(&self, addr: Ipv6Address, prefix_len: u8) {
+        self.setup_ip_addr(IpAddress::Ipv6(addr), prefix_len);
+    }
+
+    /// Enable or disable the stateless address autoconfiguration.
+    ///
+    /// The address generated from the advertised prefix is added when the
+    /// interface is polled, and the advertising router becomes the default
+    /// IPv6 gateway.
+    pub fn set_slaac_enabled(&self, enabled: bool) {
+        self.slaac.store(enabled, Ordering::Release);
+    }
+
+    /// Apply the SLAAC prefix learnt from the router advertisements.
+    fn apply_slaac(&self, iface: &mut Interface) {
+        let Some(prefix) = ipv6::take_pending_prefix() else {
+            return;
+        };
+        if !self.slaac.load(Ordering::Acquire) {
+            return;
+        }
+        let cidr = IpCidr::new(IpAddress::Ipv6(prefix.addr), ipv6::IPV6_SLAAC_PREFIX_LEN);
+        if !iface.has_ip_addr(prefix.addr) {
+            iface.update_ip_addrs(|ip_addrs| {
+                if ip_addrs.push(cidr).is_err() {
+                    warn!("{}: no room for the SLAAC address {}", self.name, cidr);
+                } else {
+                    info!("{}: SLAAC address {}", self.name, cidr);
+                }
+            });
+        }
+        if let Some(router) = prefix.router {
+            iface.routes_mut().add_default_ipv6_route(router).ok();
+        }
+    }
+
     pub fn setup_gateway(&self, gateway: IpAddress) {
         let mut iface = self.iface.lock();
         match gateway {
@@ -323,6 +463,12 @@ impl InterfaceWrapper {
         let mut sockets = sockets.lock();
         let timestamp = Self::current_time();
         iface.poll(timestamp, dev.deref_mut(), &mut sockets);
+        self.apply_slaac(&mut iface);
+    }
+
+    /// The time when the interface should be polled next time.
+    pub fn poll_at(&self, timestamp: Instant, sockets: &Mutex<SocketSet>) -> Option<Instant> {
+        self.iface.lock().poll_at(timestamp, &sockets.lock())
     }
 }
 
@@ -343,6 +489,176 @@ pub fn poll_interfaces() {
     SOCKET_SET.poll_interfaces();
 }
 
+/// Block the current thread until the given function completes or fails.
+///
+/// If `nonblocking` is set, it calls the function once and returns
+/// immediately. Otherwise, it polls the interfaces and calls the function
+/// again while it returns [`Err(WouldBlock)`](AxError::WouldBlock).
+pub(crate) fn block_on<F, T>(nonblocking: bool, mut f: F) -> AxResult<T>
+where
+    F: FnMut() -> AxResult<T>,
+{
+    if nonblocking {
+        return f();
+    }
+    loop {
+        SOCKET_SET.poll_interfaces();
+        match f() {
+            Ok(t) => return Ok(t),
+            Err(AxError::WouldBlock) => sel4::r#yield(),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A socket managed by the network thread.
+pub(crate) enum NetSocket {
+    Tcp(TcpSocket),
+    Icmp(IcmpSocket),
+    Raw(RawSocket),
+}
+
+#[allow(unused)]
+impl NetSocket {
+    /// Get the TCP socket, or [`Err(InvalidInput)`](AxError::InvalidInput)
+    /// if it is not a TCP socket.
+    pub fn as_tcp(&self) -> AxResult<&TcpSocket> {
+        match self {
+            NetSocket::Tcp(socket) => Ok(socket),
+            _ => Err(AxError::InvalidInput),
+        }
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        match self {
+            NetSocket::Tcp(socket) => socket.is_nonblocking(),
+            NetSocket::Icmp(socket) => socket.is_nonblocking(),
+            NetSocket::Raw(socket) => socket.is_nonblocking(),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        match self {
+            NetSocket::Tcp(socket) => socket.set_nonblocking(nonblocking),
+            NetSocket::Icmp(socket) => socket.set_nonblocking(nonblocking),
+            NetSocket::Raw(socket) => socket.set_nonblocking(nonblocking),
+        }
+    }
+
+    pub fn send(&self, buf: &[u8]) -> AxResult<usize> {
+        match self {
+            NetSocket::Tcp(socket) => socket.send(buf),
+            NetSocket::Icmp(socket) => socket.send(buf),
+            NetSocket::Raw(socket) => socket.send(buf),
+        }
+    }
+
+    /// Transmits data to the given address. TCP sockets ignore the address
+    /// as linux does for connected streams.
+    pub fn send_to(&self, buf: &[u8], addr: core::net::SocketAddr) -> AxResult<usize> {
+        match self {
+            NetSocket::Tcp(socket) => socket.send(buf),
+            NetSocket::Icmp(socket) => socket.send_to(buf, from_core_ipaddr(addr.ip())),
+            NetSocket::Raw(socket) => socket.send_to(buf, from_core_ipaddr(addr.ip())),
+        }
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> AxResult<usize> {
+        match self {
+            NetSocket::Tcp(socket) => socket.recv(buf),
+            NetSocket::Icmp(socket) => socket.recv(buf),
+            NetSocket::Raw(socket) => socket.recv(buf),
+        }
+    }
+
+    /// Receives data and the address it comes from.
+    pub fn recv_from(&self, buf: &mut [u8]) -> AxResult<(usize, core::net::SocketAddr)> {
+        match self {
+            NetSocket::Tcp(socket) => Ok((socket.recv(buf)?, socket.peer_addr()?)),
+            NetSocket::Icmp(socket) => socket
+                .recv_from(buf)
+                .map(|(len, addr)| (len, core::net::SocketAddr::new(into_core_ipaddr(addr), 0))),
+            NetSocket::Raw(socket) => socket
+                .recv_from(buf)
+                .map(|(len, addr)| (len, core::net::SocketAddr::new(into_core_ipaddr(addr), 0))),
+        }
+    }
+
+    pub fn poll(&self) -> AxResult<PollState> {
+        match self {
+            NetSocket::Tcp(socket) => socket.poll(),
+            NetSocket::Icmp(socket) => socket.poll(),
+            NetSocket::Raw(socket) => socket.poll(),
+        }
+    }
+
+    /// Returns the local address. The port of ICMP sockets is the echo identifier.
+    pub fn local_addr(&self) -> AxResult<core::net::SocketAddr> {
+        let unspecified = into_core_ipaddr(addr::UNSPECIFIED_IP);
+        match self {
+            NetSocket::Tcp(socket) => socket.local_addr(),
+            NetSocket::Icmp(socket) => Ok(core::net::SocketAddr::new(unspecified, socket.ident())),
+            NetSocket::Raw(_) => Ok(core::net::SocketAddr::new(unspecified, 0)),
+        }
+    }
+
+    /// Returns the remote address of the connected socket.
+    pub fn peer_addr(&self) -> AxResult<core::net::SocketAddr> {
+        match self {
+            NetSocket::Tcp(socket) => socket.peer_addr(),
+            NetSocket::Icmp(socket) => socket
+                .peer_addr()
+                .map(|addr| core::net::SocketAddr::new(into_core_ipaddr(addr), 0)),
+            NetSocket::Raw(socket) => socket
+                .peer_addr()
+                .map(|addr| core::net::SocketAddr::new(into_core_ipaddr(addr), 0)),
+        }
+    }
+
+    /// Sets the socket option, see [`NetSockOpt`] for the encoding of `value`.
+    ///
+    /// Only TCP sockets support the options for now.
+    pub fn set_option(&self, opt: NetSockOpt, value: u64) -> AxResult {
+        let socket = self.as_tcp().map_err(|_| AxError::Unsupported)?;
+        let timeout = (value != 0).then(|| core::time::Duration::from_micros(value));
+        match opt {
+            NetSockOpt::ReuseAddr => socket.set_reuse_addr(value != 0),
+            NetSockOpt::ReusePort => socket.set_reuse_port(value != 0),
+            NetSockOpt::NoDelay => socket.set_nagle_enabled(value == 0)?,
+            NetSockOpt::RecvBuf => socket.set_buffer_size(Some(value as usize), None)?,
+            NetSockOpt::SendBuf => socket.set_buffer_size(None, Some(value as usize))?,
+            NetSockOpt::KeepAlive => socket.set_keep_alive(value != 0),
+            NetSockOpt::RecvTimeout => socket.set_read_timeout(timeout),
+            NetSockOpt::SendTimeout => socket.set_write_timeout(timeout),
+            NetSockOpt::Error => return Err(AxError::InvalidInput),
+        }
+        Ok(())
+    }
+
+    /// Gets the socket option, see [`NetSockOpt`] for the encoding of the value.
+    pub fn get_option(&self, opt: NetSockOpt) -> AxResult<u64> {
+        let socket = match (self, opt) {
+            (NetSocket::Tcp(socket), _) => socket,
+            (_, NetSockOpt::Error) => return Ok(0),
+            _ => return Err(AxError::Unsupported),
+        };
+        let micros = |timeout: Option<core::time::Duration>| {
+            timeout.map_or(0, |timeout| timeout.as_micros() as u64)
+        };
+        Ok(match opt {
+            NetSockOpt::ReuseAddr => socket.is_reuse_addr() as u64,
+            NetSockOpt::ReusePort => socket.is_reuse_port() as u64,
+            NetSockOpt::NoDelay => !socket.nagle_enabled() as u64,
+            NetSockOpt::RecvBuf => socket.buffer_size().0 as u64,
+            NetSockOpt::SendBuf => socket.buffer_size().1 as u64,
+            NetSockOpt::KeepAlive => socket.keep_alive() as u64,
+            NetSockOpt::RecvTimeout => micros(socket.read_timeout()),
+            NetSockOpt::SendTimeout => micros(socket.write_timeout()),
+            NetSockOpt::Error => socket.take_error().map_or(0, |err| err.code() as u64),
+        })
+    }
+}
+
 /// Benchmark raw socket transmit bandwidth.
 #[allow(unused)]
 pub fn bench_transmit() {
@@ -365,6 +681,8 @@ pub(crate) fn init(net_dev: NetDevice) {
 
     eth0.setup_ip_addr(ip, IP_PREFIX);
     eth0.setup_gateway(gateway);
+    eth0.setup_ipv6_link_local();
+    eth0.set_slaac_enabled(true);
 
     ETH0.init_once(eth0);
     debug_println!("[Net thread] Init the ethrnet device!");
@@ -384,7 +702,10 @@ pub(crate) fn init(net_dev: NetDevice) {
     iface.update_ip_addrs(|ipaddr| {
         ipaddr
             .push(IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8))
-            .unwrap()
+            .unwrap();
+        ipaddr
+            .push(IpCidr::new(IpAddress::Ipv6(Ipv6Address::LOOPBACK), 128))
+            .unwrap();
     });
 
     LOOPBACK.init_once(Mutex::new(iface));
@@ -0,0 +1,153 @@
+use alloc::vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::{ax_err, AxError, AxResult};
+use smoltcp::iface::SocketHandle;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::raw::{self, SendError};
+use smoltcp::wire::{IpAddress, IpProtocol, IpVersion, Ipv4Address, Ipv4Packet, Ipv4Repr};
+use spin::Mutex;
+
+use super::icmp::{account_socket_message, Direction};
+use super::{block_on, PollState, SocketSetWrapper, SOCKET_SET};
+
+/// The default hop limit of the packets sent by raw sockets.
+const DEFAULT_HOP_LIMIT: u8 = 64;
+
+/// A raw IPv4 socket, the same as the `SOCK_RAW` socket of linux.
+///
+/// The IP header is built by the socket when sending, and the packet
+/// received contains the IP header.
+pub struct RawSocket {
+    handle: SocketHandle,
+    protocol: IpProtocol,
+    peer_addr: Mutex<Option<IpAddress>>,
+    nonblock: AtomicBool,
+}
+
+#[allow(unused)]
+impl RawSocket {
+    /// Creates a new raw socket for the given IP protocol.
+    pub fn new(protocol: u8) -> Self {
+        let protocol = IpProtocol::from(protocol);
+        Self {
+            handle: SOCKET_SET.add(SocketSetWrapper::new_raw_socket(IpVersion::Ipv4, protocol)),
+            protocol,
+            peer_addr: Mutex::new(None),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns whether this socket is in nonblocking mode.
+    #[inline]
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    /// Moves this raw socket into or out of nonblocking mode.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Returns the connected address, or
+    /// [`Err(NotConnected)`](AxError::NotConnected) if not connected.
+    pub fn peer_addr(&self) -> AxResult<IpAddress> {
+        self.peer_addr.lock().ok_or(AxError::NotConnected)
+    }
+
+    /// Sets the default destination of [`send`](Self::send).
+    pub fn connect(&self, addr: IpAddress) -> AxResult {
+        *self.peer_addr.lock() = Some(addr);
+        Ok(())
+    }
+
+    /// Transmits the payload to the connected address.
+    pub fn send(&self, buf: &[u8]) -> AxResult<usize> {
+        let addr = self.peer_addr.lock().ok_or(AxError::NotConnected)?;
+        self.send_to(buf, addr)
+    }
+
+    /// Transmits the payload to the given address.
+    pub fn send_to(&self, buf: &[u8], addr: IpAddress) -> AxResult<usize> {
+        let IpAddress::Ipv4(dst_addr) = addr else {
+            return ax_err!(InvalidInput, "socket send_to() failed: not an IPv4 address");
+        };
+        let repr = Ipv4Repr {
+            src_addr: source_addr(dst_addr)?,
+            dst_addr,
+            next_header: self.protocol,
+            payload_len: buf.len(),
+            hop_limit: DEFAULT_HOP_LIMIT,
+        };
+        let mut packet_buf = vec![0; repr.buffer_len() + buf.len()];
+        let mut packet = Ipv4Packet::new_unchecked(&mut packet_buf);
+        repr.emit(&mut packet, &ChecksumCapabilities::default());
+        packet.payload_mut().copy_from_slice(buf);
+
+        block_on(self.is_nonblocking(), || {
+            SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(self.handle, |socket| {
+                match socket.send_slice(&packet_buf) {
+                    Ok(()) => Ok(buf.len()),
+                    Err(SendError::BufferFull) => Err(AxError::WouldBlock),
+                }
+            })
+        })
+        .inspect(|_| {
+            if self.protocol == IpProtocol::Icmp {
+                account_socket_message(buf, Direction::Tx);
+            }
+        })
+    }
+
+    /// Receives an IP packet and the address it comes from.
+    pub fn recv_from(&self, buf: &mut [u8]) -> AxResult<(usize, IpAddress)> {
+        let len = block_on(self.is_nonblocking(), || {
+            SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(self.handle, |socket| {
+                if socket.can_recv() {
+                    socket.recv_slice(buf).map_err(|_| AxError::BadState)
+                } else {
+                    Err(AxError::WouldBlock)
+                }
+            })
+        })?;
+        let packet = Ipv4Packet::new_checked(&buf[..len]).map_err(|_| AxError::BadState)?;
+        if self.protocol == IpProtocol::Icmp {
+            account_socket_message(packet.payload(), Direction::Rx);
+        }
+        Ok((len, IpAddress::Ipv4(packet.src_addr())))
+    }
+
+    /// Receives an IP packet.
+    pub fn recv(&self, buf: &mut [u8]) -> AxResult<usize> {
+        self.recv_from(buf).map(|(len, _)| len)
+    }
+
+    /// Whether the socket is readable or writable.
+    pub fn poll(&self) -> AxResult<PollState> {
+        SOCKET_SET.with_socket::<raw::Socket, _, _>(self.handle, |socket| {
+            Ok(PollState {
+                readable: socket.can_recv(),
+                writable: socket.can_send(),
+            })
+        })
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
+
+/// Select the source address of the interface which can reach `dst_addr`.
+fn source_addr(dst_addr: Ipv4Address) -> AxResult<Ipv4Address> {
+    if dst_addr.is_loopback() {
+        return Ok(Ipv4Address::new(127, 0, 0, 1));
+    }
+    super::ETH0
+        .iface
+        .lock()
+        .ipv4_addr()
+        .ok_or(AxError::AddrNotAvailable)
+}
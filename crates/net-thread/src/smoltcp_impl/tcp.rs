@@ -1,20 +1,23 @@
 use core::cell::UnsafeCell;
 use core::net::SocketAddr;
-use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
 
 use axerrno::{ax_err, ax_err_type, AxError, AxResult};
+use common::current_nanos;
 
 use log::{debug, info, warn};
-use sel4::debug_println;
 use smoltcp::iface::SocketHandle;
 use smoltcp::socket::tcp::{self, ConnectError, State};
+use smoltcp::time::Duration as SmolDuration;
 use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
 use spin::Mutex;
 
 use crate::smoltcp_impl::SOCKET_SET;
 
 use super::addr::{from_core_sockaddr, into_core_sockaddr, is_unspecified, UNSPECIFIED_ENDPOINT};
-use super::{PollState, SocketSetWrapper, LISTEN_TABLE};
+use super::listen_table::ListenerId;
+use super::{PollState, SocketSetWrapper, LISTEN_TABLE, TCP_RX_BUF_LEN, TCP_TX_BUF_LEN};
 
 // State transitions:
 // CLOSED -(connect)-> BUSY -> CONNECTING -> CONNECTED -(shutdown)-> BUSY -> CLOSED
@@ -28,6 +31,13 @@ const STATE_CONNECTING: u8 = 2;
 const STATE_CONNECTED: u8 = 3;
 const STATE_LISTENING: u8 = 4;
 
+/// The minimum size of the buffers set by [`TcpSocket::set_buffer_size`].
+const TCP_MIN_BUF_LEN: usize = 256;
+/// The maximum size of the buffers set by [`TcpSocket::set_buffer_size`].
+const TCP_MAX_BUF_LEN: usize = 256 * 1024;
+/// The interval of the keep-alive packets, the same as `tcp_keepalive_intvl` of linux.
+const TCP_KEEP_ALIVE_INTERVAL: SmolDuration = SmolDuration::from_secs(75);
+
 /// A TCP socket that provides POSIX-like APIs.
 ///
 /// - [`connect`] is for TCP clients.
@@ -45,6 +55,15 @@ pub struct TcpSocket {
     peer_addr: UnsafeCell<IpEndpoint>,
     nonblock: AtomicBool,
     reuse_addr: AtomicBool,
+    reuse_port: AtomicBool,
+    /// The id in the [`ListenTable`](super::listen_table::ListenTable) of a listening socket.
+    listener: AtomicU64,
+    /// The timeout of receiving in microseconds, `0` means no timeout.
+    read_timeout: AtomicU64,
+    /// The timeout of sending in microseconds, `0` means no timeout.
+    write_timeout: AtomicU64,
+    /// The error reported by `SO_ERROR`, set when a connection fails.
+    pending_error: Mutex<Option<AxError>>,
 }
 
 unsafe impl Sync for TcpSocket {}
@@ -60,6 +79,11 @@ impl TcpSocket {
             peer_addr: UnsafeCell::new(UNSPECIFIED_ENDPOINT),
             nonblock: AtomicBool::new(false),
             reuse_addr: AtomicBool::new(false),
+            reuse_port: AtomicBool::new(false),
+            listener: AtomicU64::new(0),
+            read_timeout: AtomicU64::new(0),
+            write_timeout: AtomicU64::new(0),
+            pending_error: Mutex::new(None),
         }
     }
 
@@ -72,6 +96,11 @@ impl TcpSocket {
             peer_addr: UnsafeCell::new(peer_addr),
             nonblock: AtomicBool::new(false),
             reuse_addr: AtomicBool::new(false),
+            reuse_port: AtomicBool::new(false),
+            listener: AtomicU64::new(0),
+            read_timeout: AtomicU64::new(0),
+            write_timeout: AtomicU64::new(0),
+            pending_error: Mutex::new(None),
         }
     }
 
@@ -135,6 +164,117 @@ impl TcpSocket {
         self.reuse_addr.store(reuse_addr, Ordering::Release);
     }
 
+    /// Returns whether this socket is in reuse port mode.
+    #[inline]
+    pub fn is_reuse_port(&self) -> bool {
+        self.reuse_port.load(Ordering::Acquire)
+    }
+
+    /// Moves this TCP socket into or out of reuse port mode.
+    ///
+    /// The `SO_REUSEPORT` option allows multiple sockets to listen on the same
+    /// address and port if all of them set it, the incoming connections are
+    /// balanced among them. This option must be set before calling `bind`.
+    #[inline]
+    pub fn set_reuse_port(&self, reuse_port: bool) {
+        self.reuse_port.store(reuse_port, Ordering::Release);
+    }
+
+    /// Returns the timeout of [`recv`](Self::recv), `None` means blocking forever.
+    #[inline]
+    pub fn read_timeout(&self) -> Option<Duration> {
+        match self.read_timeout.load(Ordering::Acquire) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    /// Sets the timeout of [`recv`](Self::recv), the zero duration is the same as `None`.
+    #[inline]
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+        let micros = timeout.map_or(0, |timeout| timeout.as_micros() as u64);
+        self.read_timeout.store(micros, Ordering::Release);
+    }
+
+    /// Returns the timeout of [`send`](Self::send), `None` means blocking forever.
+    #[inline]
+    pub fn write_timeout(&self) -> Option<Duration> {
+        match self.write_timeout.load(Ordering::Acquire) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    /// Sets the timeout of [`send`](Self::send), the zero duration is the same as `None`.
+    #[inline]
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) {
+        let micros = timeout.map_or(0, |timeout| timeout.as_micros() as u64);
+        self.write_timeout.store(micros, Ordering::Release);
+    }
+
+    /// Returns and clears the pending error of the socket, the same as `SO_ERROR`.
+    pub fn take_error(&self) -> Option<AxError> {
+        self.pending_error.lock().take()
+    }
+
+    /// Returns whether the keep-alive packets are enabled.
+    pub fn keep_alive(&self) -> bool {
+        self.with_socket(|socket| socket.is_some_and(|socket| socket.keep_alive().is_some()))
+    }
+
+    /// Enables or disables the keep-alive packets, the same as `SO_KEEPALIVE`.
+    pub fn set_keep_alive(&self, enabled: bool) {
+        self.with_socket_mut(|socket| {
+            if let Some(socket) = socket {
+                socket.set_keep_alive(enabled.then_some(TCP_KEEP_ALIVE_INTERVAL));
+            }
+        });
+    }
+
+    /// Returns the capacity of the receive and transmit buffers in bytes.
+    pub fn buffer_size(&self) -> (usize, usize) {
+        self.with_socket(|socket| match socket {
+            Some(socket) => (socket.recv_capacity(), socket.send_capacity()),
+            None => (TCP_RX_BUF_LEN, TCP_TX_BUF_LEN),
+        })
+    }
+
+    /// Resizes the receive or transmit buffer, the other one is kept if `None`.
+    ///
+    /// The buffers of smoltcp can not grow in place, so the unconnected socket
+    /// is replaced by a new one with the other options kept. The sizes are
+    /// clamped as linux does, and the request is ignored silently once the
+    /// socket is connecting, connected or listening.
+    pub fn set_buffer_size(&self, recv: Option<usize>, send: Option<usize>) -> AxResult {
+        self.update_state(STATE_CLOSED, STATE_CLOSED, || {
+            // SAFETY: no other threads can read or write these fields as we
+            // have changed the state to `BUSY`.
+            let Some(handle) = (unsafe { self.handle.get().read() }) else {
+                return Ok(());
+            };
+            let local_addr = unsafe { self.local_addr.get().read() };
+            let bound_endpoint = match local_addr != UNSPECIFIED_ENDPOINT {
+                true => Some(self.bound_endpoint()?),
+                false => None,
+            };
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+                let clamp = |len: usize| len.clamp(TCP_MIN_BUF_LEN, TCP_MAX_BUF_LEN);
+                let mut new_socket = SocketSetWrapper::new_tcp_socket_with_buffers(
+                    clamp(recv.unwrap_or(socket.recv_capacity())),
+                    clamp(send.unwrap_or(socket.send_capacity())),
+                );
+                new_socket.set_nagle_enabled(socket.nagle_enabled());
+                new_socket.set_keep_alive(socket.keep_alive());
+                if let Some(bound_endpoint) = bound_endpoint {
+                    new_socket.set_bound_endpoint(bound_endpoint);
+                }
+                *socket = new_socket;
+            });
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+    }
+
     /// To get the address pair of the socket.
     ///
     /// Returns the local and remote endpoint pair.
@@ -176,7 +316,7 @@ impl TcpSocket {
             info!("bound endpoint: {:?}", bound_endpoint);
             info!("remote endpoint: {:?}", remote_endpoint);
             warn!("Temporarily net bridge used");
-            let iface = if remote_endpoint.addr.as_bytes()[0] == 127 {
+            let iface = if remote_endpoint.addr.is_loopback() {
                 super::LOOPBACK.get().unwrap()
             } else {
                 info!("Use eth net");
@@ -226,6 +366,8 @@ impl TcpSocket {
                 } else if self.get_state() == STATE_CONNECTED {
                     Ok(())
                 } else {
+                    // The error is reported here, not by `SO_ERROR` later.
+                    self.take_error();
                     ax_err!(ConnectionRefused, "socket connect() failed")
                 }
             })
@@ -255,10 +397,14 @@ impl TcpSocket {
             }
             let local_endpoint = from_core_sockaddr(local_addr);
 
-            if !self.is_reuse_addr() {
+            if !self.is_reuse_addr() && !self.is_reuse_port() {
                 SOCKET_SET.bind_check(local_endpoint.addr, local_endpoint.port)?;
             }
             let bound_endpoint = self.bound_endpoint()?;
+            // `SO_REUSEADDR` does not allow to bind to the address of a listener.
+            if !LISTEN_TABLE.can_bind(bound_endpoint, self.is_reuse_port()) {
+                return ax_err!(AddrInUse, "socket bind() failed");
+            }
             let handle = unsafe { self.handle.get().read() }
                 .unwrap_or_else(|| SOCKET_SET.add(SocketSetWrapper::new_tcp_socket()));
             SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
@@ -273,14 +419,20 @@ impl TcpSocket {
     /// Starts listening on the bound address and port.
     ///
     /// It's must be called after [`bind`](Self::bind) and before
-    /// [`accept`](Self::accept).
-    pub fn listen(&self) -> AxResult {
+    /// [`accept`](Self::accept). At most `backlog` connections can wait for
+    /// being accepted, calling it again on a listening socket changes the
+    /// backlog.
+    pub fn listen(&self, backlog: usize) -> AxResult {
+        if self.is_listening() {
+            return LISTEN_TABLE.set_backlog(self.listen_endpoint().port, self.listener(), backlog);
+        }
         self.update_state(STATE_CLOSED, STATE_LISTENING, || {
             let bound_endpoint = self.bound_endpoint()?;
             unsafe {
                 (*self.local_addr.get()).port = bound_endpoint.port;
             }
-            LISTEN_TABLE.listen(bound_endpoint)?;
+            let listener = LISTEN_TABLE.listen(bound_endpoint, backlog, self.is_reuse_port())?;
+            self.listener.store(listener, Ordering::Release);
             debug!("TCP socket listening on {}", bound_endpoint);
             Ok(())
         })
@@ -298,10 +450,10 @@ impl TcpSocket {
             return Err(AxError::InvalidInput);
         }
 
-        // SAFETY: `self.local_addr` should be initialized after `bind()`.
-        let local_port = unsafe { self.local_addr.get().read().port };
+        let port = self.listen_endpoint().port;
+        let listener = self.listener();
         self.block_on(|| {
-            let (handle, (local_addr, peer_addr)) = LISTEN_TABLE.accept(local_port)?;
+            let (handle, (local_addr, peer_addr)) = LISTEN_TABLE.accept(port, listener)?;
             debug!("TCP socket accepted a new connection {}", peer_addr);
             Ok(TcpSocket::new_connected(handle, local_addr, peer_addr))
         })
@@ -326,11 +478,9 @@ impl TcpSocket {
 
         // listener
         self.update_state(STATE_LISTENING, STATE_CLOSED, || {
-            // SAFETY: `self.local_addr` should be initialized in a listening socket,
-            // and no other threads can read or write it.
-            let local_port = unsafe { self.local_addr.get().read().port };
+            let listen_endpoint = self.listen_endpoint();
             unsafe { self.local_addr.get().write(UNSPECIFIED_ENDPOINT) }; // clear bound address
-            LISTEN_TABLE.unlisten(local_port);
+            LISTEN_TABLE.unlisten(listen_endpoint.port, self.listener());
             SOCKET_SET.poll_interfaces();
             Ok(())
         })
@@ -366,7 +516,7 @@ impl TcpSocket {
 
         // SAFETY: `self.handle` should be initialized in a connected socket.
         let handle = unsafe { self.handle.get().read().unwrap() };
-        self.block_on(|| {
+        self.block_on_timeout(self.read_timeout(), || {
             SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
                 if socket.recv_queue() > 0 {
                     // data available
@@ -438,7 +588,7 @@ impl TcpSocket {
 
         // SAFETY: `self.handle` should be initialized in a connected socket.
         let handle = unsafe { self.handle.get().read().unwrap() };
-        self.block_on(|| {
+        self.block_on_timeout(self.write_timeout(), || {
             SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
                 if !socket.is_active() || !socket.may_send() {
                     // closed by remote
@@ -596,6 +746,12 @@ impl TcpSocket {
         self.get_state() == STATE_LISTENING
     }
 
+    /// The id of a listening socket in the [`ListenTable`](super::listen_table::ListenTable).
+    #[inline]
+    fn listener(&self) -> ListenerId {
+        self.listener.load(Ordering::Acquire)
+    }
+
     fn bound_endpoint(&self) -> AxResult<IpListenEndpoint> {
         // SAFETY: no other threads can read or write `self.local_addr`.
         let local_addr = unsafe { self.local_addr.get().read() };
@@ -613,6 +769,22 @@ impl TcpSocket {
         Ok(IpListenEndpoint { addr, port })
     }
 
+    /// The endpoint of a listening socket in the [`ListenTable`](super::listen_table::ListenTable).
+    fn listen_endpoint(&self) -> IpListenEndpoint {
+        // SAFETY: `self.local_addr` should be initialized in a listening socket,
+        // and no other threads can write it.
+        let local_addr = unsafe { self.local_addr.get().read() };
+        let addr = if !is_unspecified(local_addr.addr) {
+            Some(local_addr.addr)
+        } else {
+            None
+        };
+        IpListenEndpoint {
+            addr,
+            port: local_addr.port,
+        }
+    }
+
     fn poll_connect(&self) -> AxResult<PollState> {
         // SAFETY: `self.handle` should be initialized above.
         let handle = unsafe { self.handle.get().read().unwrap() };
@@ -633,6 +805,7 @@ impl TcpSocket {
                         self.local_addr.get().write(UNSPECIFIED_ENDPOINT);
                         self.peer_addr.get().write(UNSPECIFIED_ENDPOINT);
                     }
+                    *self.pending_error.lock() = Some(AxError::ConnectionRefused);
                     self.set_state(STATE_CLOSED); // connection failed
                     true
                 }
@@ -655,10 +828,8 @@ impl TcpSocket {
     }
 
     fn poll_listener(&self) -> AxResult<PollState> {
-        // SAFETY: `self.local_addr` should be initialized in a listening socket.
-        let local_addr = unsafe { self.local_addr.get().read() };
         Ok(PollState {
-            readable: LISTEN_TABLE.can_accept(local_addr.port)?,
+            readable: LISTEN_TABLE.can_accept(self.listen_endpoint().port, self.listener())?,
             writable: false,
         })
     }
@@ -668,21 +839,37 @@ impl TcpSocket {
     /// If the socket is non-blocking, it calls the function once and returns
     /// immediately. Otherwise, it may call the function multiple times if it
     /// returns [`Err(WouldBlock)`](AxError::WouldBlock).
-    fn block_on<F, T>(&self, mut f: F) -> AxResult<T>
+    fn block_on<F, T>(&self, f: F) -> AxResult<T>
+    where
+        F: FnMut() -> AxResult<T>,
+    {
+        self.block_on_timeout(None, f)
+    }
+
+    /// The same as [`block_on`](Self::block_on), but fails with
+    /// [`Err(WouldBlock)`](AxError::WouldBlock) after `timeout`, the same as
+    /// linux does for `SO_RCVTIMEO` and `SO_SNDTIMEO`.
+    fn block_on_timeout<F, T>(&self, timeout: Option<Duration>, mut f: F) -> AxResult<T>
     where
         F: FnMut() -> AxResult<T>,
     {
         debug!("TCP socket {}: blocking on", self.handle.get() as usize);
         if self.is_nonblocking() {
-            f()
-        } else {
-            loop {
-                SOCKET_SET.poll_interfaces();
-                match f() {
-                    Ok(t) => return Ok(t),
-                    Err(AxError::WouldBlock) => sel4::r#yield(),
-                    Err(e) => return Err(e),
+            return f();
+        }
+        let deadline =
+            timeout.map(|timeout| current_nanos().saturating_add(timeout.as_nanos() as u64));
+        loop {
+            SOCKET_SET.poll_interfaces();
+            match f() {
+                Ok(t) => return Ok(t),
+                Err(AxError::WouldBlock)
+                    if deadline.is_some_and(|deadline| current_nanos() >= deadline) =>
+                {
+                    return Err(AxError::WouldBlock)
                 }
+                Err(AxError::WouldBlock) => sel4::r#yield(),
+                Err(e) => return Err(e),
             }
         }
     }
@@ -706,7 +893,6 @@ fn get_ephemeral_port() -> AxResult<u16> {
     let mut curr = CURR.lock();
     let mut tries = 0;
     // TODO: more robust
-    debug_println!("Can listen: {}", LISTEN_TABLE.can_listen(6379));
     while tries <= PORT_END - PORT_START {
         let port = *curr;
         if *curr == PORT_END {
@@ -1,6 +1,7 @@
 use core::net::{IpAddr, Ipv4Addr, SocketAddr};
 use sel4::debug_println;
 
+use super::listen_table::MAX_LISTEN_BACKLOG;
 use super::TcpSocket;
 
 #[allow(unused)]
@@ -78,7 +79,7 @@ pub(crate) fn run_server() {
         .bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 6379))
         .unwrap();
     debug_println!("[Net thread] Finish binding!");
-    tcp_socket.listen().unwrap();
+    tcp_socket.listen(MAX_LISTEN_BACKLOG).unwrap();
     debug_println!("[Net thread] Start listening!");
     loop {
         match tcp_socket.accept() {
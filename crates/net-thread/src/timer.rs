@@ -0,0 +1,37 @@
+//! The client of the timer thread.
+//!
+//! The network stack has to be polled at the time asked by the interfaces for
+//! the retransmissions and the timeouts. A timer is armed for it, the timer
+//! thread signals the notification bound to the net thread with `TIMER_BADGE`
+//! when it expires.
+
+use common::TimerMessageLabel;
+use crate_consts::slots::net_thread::TIMER_EP;
+use sel4::cap::Endpoint;
+use spin::Mutex;
+
+/// The id of the timer polling the network stack.
+const POLL_TIMER_ID: u64 = 0;
+
+/// The deadline of the timer armed in the timer thread.
+static POLL_DEADLINE: Mutex<Option<u64>> = Mutex::new(None);
+
+/// Arm the timer to poll the network stack at the monotonic time `deadline`
+/// in nanoseconds, or cancel it if `None`.
+pub(crate) fn set_poll_timer(deadline: Option<u64>) {
+    let mut armed = POLL_DEADLINE.lock();
+    if *armed == deadline {
+        return;
+    }
+    let ep = Endpoint::from_bits(TIMER_EP);
+    match deadline {
+        Some(deadline) => ep.call(TimerMessageLabel::SetTimer(POLL_TIMER_ID, deadline).build()),
+        None => ep.call(TimerMessageLabel::CancelTimer(POLL_TIMER_ID).build()),
+    };
+    *armed = deadline;
+}
+
+/// Forget the timer expired, so the next deadline is armed again.
+pub(crate) fn poll_timer_expired() {
+    *POLL_DEADLINE.lock() = None;
+}
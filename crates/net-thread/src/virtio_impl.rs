@@ -1,4 +1,4 @@
-use axdriver_virtio::{BufferDirection, PhysAddr, VirtIoHal};
+use axdriver_virtio::{BufferDirection, MmioTransport, PhysAddr, VirtIoHal};
 use common::RootMessageLabel;
 use core::{
     ptr::NonNull,
@@ -6,6 +6,7 @@ use core::{
 };
 use crate_consts::{DEFAULT_THREAD_FAULT_EP, DMA_ADDR_START, PAGE_SIZE};
 use sel4::{cap::Endpoint, debug_println};
+use virtio_drivers::transport::{mmio::VirtIOHeader, Transport};
 static DMA_ADDR: AtomicUsize = AtomicUsize::new(DMA_ADDR_START + PAGE_SIZE);
 
 pub struct VirtIoHalImpl;
@@ -59,3 +60,20 @@ unsafe impl VirtIoHal for VirtIoHalImpl {
         // anywhere else.
     }
 }
+
+/// The virtual address of the MMIO registers of the VIRTIO net device.
+static NET_MMIO_VADDR: AtomicUsize = AtomicUsize::new(0);
+
+/// Record where the root task mapped the registers of the VIRTIO net device.
+pub(crate) fn set_net_mmio_vaddr(vaddr: usize) {
+    NET_MMIO_VADDR.store(vaddr, Ordering::Release);
+}
+
+/// Acknowledge the interrupt of the VIRTIO net device.
+///
+/// The device is owned by the interface, so a transport is created on the
+/// same MMIO registers only to acknowledge the interrupt status.
+pub(crate) fn ack_net_interrupt() -> bool {
+    let header = NonNull::new(NET_MMIO_VADDR.load(Ordering::Acquire) as *mut VirtIOHeader).unwrap();
+    unsafe { MmioTransport::new(header) }.is_ok_and(|mut transport| transport.ack_interrupt())
+}
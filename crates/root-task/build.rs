@@ -0,0 +1,28 @@
+//! Generate the table of the ELF files of the components in `system.toml`.
+
+#[path = "../crate-consts/system.rs"]
+mod system;
+
+use std::{env, fmt::Write, fs, path::PathBuf};
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let manifest = system::load();
+
+    let mut out = String::from("static ELF_FILES: &[(&str, &[u8])] = &[\n");
+    for component in &manifest.component {
+        let elf = manifest_dir.join("../../build").join(&component.elf);
+        println!("cargo:rerun-if-changed={}", elf.display());
+        writeln!(
+            out,
+            "    ({:?}, include_bytes_aligned!(16, {:?})),",
+            component.elf,
+            elf.display().to_string()
+        )
+        .unwrap();
+    }
+    out.push_str("];\n");
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("elf_files.rs");
+    fs::write(out_path, out).unwrap();
+}
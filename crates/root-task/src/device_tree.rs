@@ -0,0 +1,139 @@
+//! Discover the devices from the flattened device tree.
+//!
+//! The seL4 loader passes the device tree of the machine to the kernel, which
+//! hands it to the root task in the extra boot info. The root task looks up
+//! the registers and the interrupts of the devices there, instead of assuming
+//! the layout of one QEMU command line.
+
+use alloc::vec::Vec;
+use common::{IrqNum, NO_IRQ};
+use fdt::{node::FdtNode, Fdt};
+use sel4::BootInfoExtraId;
+use sel4_root_task::debug_println;
+
+use crate::irq::Trigger;
+
+/// The first shared peripheral interrupt of the GIC.
+const GIC_SPI_BASE: IrqNum = 32;
+/// The first private peripheral interrupt of the GIC.
+const GIC_PPI_BASE: IrqNum = 16;
+
+/// The index of the non-secure physical timer in the interrupts of the ARM
+/// generic timer, after the secure one.
+const ARCH_TIMER_PHYS_INDEX: usize = 1;
+
+/// A device with memory mapped registers.
+#[derive(Debug, Clone, Copy)]
+pub struct MmioDevice {
+    pub paddr: usize,
+    pub size: usize,
+    /// The interrupt number of the GIC, [NO_IRQ] if it has no interrupt.
+    pub irq: IrqNum,
+    pub trigger: Trigger,
+}
+
+/// The interrupt controller.
+#[derive(Debug, Clone, Copy)]
+pub struct Gic {
+    /// The version of the GIC architecture.
+    pub version: u8,
+    /// The physical address of the distributor.
+    pub distributor: usize,
+}
+
+/// The devices found in the device tree.
+#[derive(Debug, Default)]
+pub struct Platform {
+    pub uart: Option<MmioDevice>,
+    pub rtc: Option<MmioDevice>,
+    pub gic: Option<Gic>,
+    /// The interrupt of the non-secure physical timer of the ARM generic timer.
+    pub timer_irq: Option<(IrqNum, Trigger)>,
+    /// The virtio-mmio transports sorted by the address, the empty ones included.
+    pub virtio_mmio: Vec<MmioDevice>,
+}
+
+/// Get the interrupts of the `node` in the GIC numbering, with their trigger types.
+///
+/// The interrupt specifiers of the GIC are `<type number flags>`, the type is
+/// 0 for the SPIs and 1 for the PPIs, the low 4 bits of the flags are 1 or 2
+/// for the edge triggered interrupts and 4 or 8 for the level triggered ones.
+fn interrupts(node: &FdtNode) -> Vec<(IrqNum, Trigger)> {
+    let Some(property) = node.property("interrupts") else {
+        return Vec::new();
+    };
+    property
+        .value
+        .chunks_exact(12)
+        .filter_map(|cells| {
+            let cell = |i: usize| u32::from_be_bytes(cells[i * 4..i * 4 + 4].try_into().unwrap());
+            let trigger = match cell(2) & 0xf {
+                1 | 2 => Trigger::Edge,
+                _ => Trigger::Level,
+            };
+            match cell(0) {
+                0 => Some((GIC_SPI_BASE + cell(1) as IrqNum, trigger)),
+                1 => Some((GIC_PPI_BASE + cell(1) as IrqNum, trigger)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Get the first register region and the first interrupt of the `node`.
+fn mmio_device(node: &FdtNode) -> Option<MmioDevice> {
+    let region = node.reg()?.next()?;
+    let (irq, trigger) = interrupts(node)
+        .first()
+        .copied()
+        .unwrap_or((NO_IRQ, Trigger::Level));
+    Some(MmioDevice {
+        paddr: region.starting_address as usize,
+        size: region.size.unwrap_or(0),
+        irq,
+        trigger,
+    })
+}
+
+/// Parse the device tree passed in the extra boot info.
+pub fn parse(bootinfo: &sel4::BootInfo) -> Platform {
+    let extra = bootinfo
+        .extra()
+        .find(|extra| extra.id == BootInfoExtraId::Fdt)
+        .expect("[RootTask] no device tree in the boot info");
+    let fdt = Fdt::new(extra.content()).expect("[RootTask] invalid device tree");
+
+    let mut platform = Platform::default();
+    for node in fdt.all_nodes() {
+        let Some(compatible) = node.compatible() else {
+            continue;
+        };
+        let is = |name: &str| compatible.all().any(|c| c == name);
+        if is("virtio,mmio") {
+            platform.virtio_mmio.extend(mmio_device(&node));
+        } else if is("arm,pl011") {
+            platform.uart = platform.uart.or(mmio_device(&node));
+        } else if is("arm,pl031") {
+            platform.rtc = platform.rtc.or(mmio_device(&node));
+        } else if is("arm,armv8-timer") {
+            platform.timer_irq = interrupts(&node).get(ARCH_TIMER_PHYS_INDEX).copied();
+        } else if is("arm,gic-v3") || is("arm,cortex-a15-gic") || is("arm,gic-400") {
+            let version = if is("arm,gic-v3") { 3 } else { 2 };
+            platform.gic = mmio_device(&node).map(|device| Gic {
+                version,
+                distributor: device.paddr,
+            });
+        }
+    }
+    platform.virtio_mmio.sort_by_key(|device| device.paddr);
+
+    debug_println!(
+        "[RootTask] Device tree: uart {:x?}, rtc {:x?}, gic {:x?}, timer irq {:?}, {} virtio-mmio transports",
+        platform.uart,
+        platform.rtc,
+        platform.gic,
+        platform.timer_irq,
+        platform.virtio_mmio.len()
+    );
+    platform
+}
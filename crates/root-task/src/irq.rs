@@ -0,0 +1,128 @@
+//! Manage the interrupts claimed by the components.
+//!
+//! The root task owns the IRQControl cap and keeps the handlers it creates. A
+//! component can only claim the interrupt of the device assigned to it, once,
+//! with the trigger type and the core described by the root task.
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use common::{IrqError, IrqNum, NO_IRQ};
+use sel4::{cap::IrqHandler, init_thread, AbsoluteCPtr, CapRights};
+
+use crate::{utils::abs_cptr, OBJ_ALLOCATOR};
+
+/// The trigger type of an interrupt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Trigger {
+    #[default]
+    Level,
+    Edge,
+}
+
+/// An interrupt line granted to a task.
+#[derive(Debug, Clone, Copy)]
+pub struct IrqLine {
+    /// The index of the task allowed to claim the interrupt.
+    pub task: usize,
+    pub trigger: Trigger,
+    /// The core the interrupt is routed to.
+    pub core: usize,
+}
+
+#[derive(Default)]
+pub struct IrqManager {
+    /// The interrupt lines granted to the tasks.
+    lines: BTreeMap<IrqNum, IrqLine>,
+    /// The handlers of the claimed interrupts.
+    handlers: BTreeMap<IrqNum, IrqHandler>,
+}
+
+impl IrqManager {
+    /// Grant the interrupt `irq` to a task, [NO_IRQ] is ignored.
+    pub fn grant(&mut self, irq: IrqNum, line: IrqLine) {
+        if irq == NO_IRQ {
+            return;
+        }
+        if let Some(old) = self.lines.insert(irq, line) {
+            panic!(
+                "[RootTask] irq {} is granted to both task {} and task {}",
+                irq, old.task, line.task
+            );
+        }
+    }
+
+    /// Claim the interrupt `irq` for the `task`, returns the handler kept by the root task.
+    pub fn claim(&mut self, task: usize, irq: IrqNum) -> Result<IrqHandler, IrqError> {
+        let line = *self
+            .lines
+            .get(&irq)
+            .filter(|line| line.task == task)
+            .ok_or(IrqError::NotGranted)?;
+        if self.handlers.contains_key(&irq) {
+            return Err(IrqError::AlreadyClaimed);
+        }
+        if line.core >= sel4::sel4_cfg_usize!(MAX_NUM_NODES) {
+            return Err(IrqError::Kernel);
+        }
+        let (_, _, raw_index) = OBJ_ALLOCATOR.lock().allocate_slot();
+        let handler = IrqHandler::from_bits(raw_index as _);
+        if irq_control_get(irq, &line, &abs_cptr(handler)).is_err() {
+            OBJ_ALLOCATOR.lock().free_slot(raw_index);
+            return Err(IrqError::Kernel);
+        }
+        self.handlers.insert(irq, handler);
+        Ok(handler)
+    }
+
+    /// Claim the interrupt `irq` for the `task` and copy the handler to `dst`.
+    pub fn claim_into(
+        &mut self,
+        task: usize,
+        irq: IrqNum,
+        dst: &AbsoluteCPtr,
+    ) -> Result<(), IrqError> {
+        let handler = self.claim(task, irq)?;
+        dst.copy(&abs_cptr(handler), CapRights::all()).map_err(|_| {
+            self.release(irq);
+            IrqError::InvalidSlot
+        })
+    }
+
+    /// Release the interrupt `irq`, it can be claimed again.
+    ///
+    /// The copies of the handler held by the task are revoked, and its slot
+    /// is returned to the allocator.
+    pub fn release(&mut self, irq: IrqNum) {
+        if let Some(handler) = self.handlers.remove(&irq) {
+            abs_cptr(handler).revoke().unwrap();
+            abs_cptr(handler).delete().unwrap();
+            OBJ_ALLOCATOR.lock().free_slot(handler.bits() as _);
+        }
+    }
+
+    /// Release the interrupts claimed by the `task`, they stay granted to it.
+    pub fn release_task(&mut self, task: usize) {
+        let irqs: Vec<IrqNum> = self
+            .handlers
+            .keys()
+            .filter(|irq| self.lines[irq].task == task)
+            .copied()
+            .collect();
+        irqs.into_iter().for_each(|irq| self.release(irq));
+    }
+}
+
+/// Create the handler of the interrupt `irq` configured as `line` in `dst`.
+#[sel4::sel4_cfg(MAX_NUM_NODES = "1")]
+fn irq_control_get(irq: IrqNum, line: &IrqLine, dst: &AbsoluteCPtr) -> sel4::Result<()> {
+    init_thread::slot::IRQ_CONTROL
+        .cap()
+        .irq_control_get_trigger(irq, line.trigger == Trigger::Edge, dst)
+}
+
+/// Create the handler of the interrupt `irq` configured as `line` in `dst`.
+#[sel4::sel4_cfg(not(MAX_NUM_NODES = "1"))]
+fn irq_control_get(irq: IrqNum, line: &IrqLine, dst: &AbsoluteCPtr) -> sel4::Result<()> {
+    init_thread::slot::IRQ_CONTROL
+        .cap()
+        .irq_control_get_trigger_core(irq, line.trigger == Trigger::Edge, line.core as _, dst)
+}
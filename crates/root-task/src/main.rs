@@ -4,45 +4,37 @@
 
 extern crate alloc;
 
+mod device_tree;
+mod irq;
+mod sched;
+mod shutdown;
+mod supervisor;
+mod system;
 mod task;
 mod thread;
 mod utils;
+mod virtio;
 
 use alloc::vec::Vec;
 use common::*;
 use crate_consts::*;
-use include_bytes_aligned::include_bytes_aligned;
 use sel4::{
-    cap::LargePage,
-    cap_type::{Endpoint, Granule, IrqHandler, Notification, Untyped},
+    cap_type::{Endpoint, Untyped},
     init_thread::{self},
-    with_ipc_buffer, with_ipc_buffer_mut, CPtr, CapRights, Error, MessageInfo, ObjectBlueprintArm,
-    UntypedDesc, VmAttributes,
+    with_ipc_buffer, with_ipc_buffer_mut, CPtr, MessageInfo, UntypedDesc,
 };
 use sel4_root_task::{debug_println, root_task, Never};
 use spin::Mutex;
 use task::*;
-use task_helper::TaskHelperTrait;
 use utils::*;
 
-static TASK_FILES: &[(&str, &[u8])] = &[
-    (
-        "kernel-thread",
-        include_bytes_aligned!(16, "../../../build/kernel-thread.elf"),
-    ),
-    (
-        "block-thread",
-        include_bytes_aligned!(16, "../../../build/blk-thread.elf"),
-    ),
-    (
-        "net-thread",
-        include_bytes_aligned!(16, "../../../build/net-thread.elf"),
-    ),
-];
-
 /// The object allocator for the root task.
 pub(crate) static OBJ_ALLOCATOR: Mutex<ObjectAllocator> = Mutex::new(ObjectAllocator::empty());
 
+/// The object allocator for the task being started, with the untyped and the
+/// slots of its instance, see [task::destroy_task].
+pub(crate) static TASK_ALLOCATOR: Mutex<ObjectAllocator> = Mutex::new(ObjectAllocator::empty());
+
 /// free page placeholder
 pub(crate) static mut FREE_PAGE_PLACEHOLDER: FreePagePlaceHolder =
     FreePagePlaceHolder([0; GRANULE_SIZE]);
@@ -124,8 +116,6 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
     rebuild_cspace();
     // test_threads(&bootinfo);
 
-    let mut tasks = Vec::new();
-
     // Used for fault and normal IPC ( Reuse )
     let fault_ep = OBJ_ALLOCATOR
         .lock()
@@ -134,252 +124,87 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
     let irq_ep = OBJ_ALLOCATOR
         .lock()
         .allocate_and_retyped_fixed_sized::<Endpoint>();
-    let common_irq_handler = OBJ_ALLOCATOR.lock().allocate_normal_cap::<IrqHandler>();
-
-    for task in TASK_FILES.iter() {
-        tasks.push(build_kernel_thread(
-            (fault_ep, tasks.len() as _),
-            irq_ep,
-            task.0,
-            task.1,
-            unsafe { init_free_page_addr(bootinfo) },
-        )?);
-    }
-
-    // Prepare Kernel Thread
-    {
-        tasks[0]
-            .abs_cptr(DEFAULT_CUSTOM_SLOT)
-            .copy(
-                &init_thread::slot::CNODE.cap().relative(kernel_untyped),
-                CapRights::all(),
-            )
-            .unwrap();
-    }
-
-    // Prepare Block Thread
-    {
-        // Channel to send message to block thread
-        let blk_dev_ep = OBJ_ALLOCATOR
-            .lock()
-            .allocate_and_retyped_fixed_sized::<Endpoint>();
-        // Set Notification for Blk-Thread Task.
-        let blk_irq_not = OBJ_ALLOCATOR
-            .lock()
-            .allocate_and_retyped_fixed_sized::<Notification>();
-        tasks[0]
-            .abs_cptr(DEFAULT_CUSTOM_SLOT + 1)
-            .copy(&utils::abs_cptr(blk_dev_ep), CapRights::all())
-            .unwrap();
-        tasks[1]
-            .abs_cptr(DEFAULT_CUSTOM_SLOT)
-            .copy(
-                &init_thread::slot::CNODE.cap().relative(blk_irq_not),
-                CapRights::all(),
-            )
-            .unwrap();
-        tasks[1]
-            .abs_cptr(DEFAULT_CUSTOM_SLOT + 2)
-            .copy(
-                &init_thread::slot::CNODE.cap().relative(blk_dev_ep),
-                CapRights::all(),
-            )
-            .unwrap();
-        // Map device memory to blk-thread task
-        let (found_device_idx, found_device_desc) = bootinfo.untyped_list()
-            [bootinfo.device_untyped_range()]
-        .iter()
-        .enumerate()
-        .find(|(_i, desc)| {
-            (desc.paddr()..(desc.paddr() + (1 << desc.size_bits()))).contains(&VIRTIO_MMIO_ADDR)
-        })
-        .expect("[RootTask] can't find device memory");
-        assert!(found_device_desc.is_device());
-
-        let blk_device_untyped_cap = bootinfo.untyped().index(found_device_idx).cap();
 
-        let (blk_device_slot_index, blk_device_cnode_index, blk_device_index) =
-            OBJ_ALLOCATOR.lock().allocate_slot();
-        let blk_device_frame_slot = sel4::init_thread::Slot::from_index(blk_device_index)
-            .downcast::<sel4::cap_type::LargePage>();
+    let platform = device_tree::parse(bootinfo);
 
-        blk_device_untyped_cap
-            .untyped_retype(
-                &ObjectBlueprintArm::LargePage.into(),
-                &init_thread::slot::CNODE
-                    .cap()
-                    .relative_bits_with_depth(blk_device_cnode_index as u64, 52),
-                blk_device_slot_index,
-                1,
-            )
-            .unwrap();
-
-        let blk_device_frame_cap = blk_device_frame_slot.cap();
-        // let device_frame_addr = 0x10_2000_0000;
-        assert!(blk_device_frame_cap.frame_get_address().unwrap() < VIRTIO_MMIO_ADDR);
-        loop {
-            match blk_device_frame_cap.frame_map(
-                tasks[1].vspace,
-                VIRTIO_MMIO_VIRT_ADDR,
-                CapRights::all(),
-                VmAttributes::DEFAULT,
-            ) {
-                Ok(()) => {
-                    debug_println!("[RootTask] map device memory success");
-                    break;
-                }
-                Err(Error::FailedLookup) => {
-                    let pt = TaskImpl::allocate_pt(&mut tasks[1]);
-                    pt.pt_map(
-                        tasks[1].vspace,
-                        VIRTIO_MMIO_VIRT_ADDR,
-                        VmAttributes::DEFAULT,
-                    )
-                    .unwrap();
-                    tasks[1].mapped_pt.lock().push(pt);
-                }
-                Err(e) => {
-                    panic!("[RootTask] map device memory failed: {:?}", e);
-                }
-            }
-        }
-        // Map DMA frame.
-        let page_cap = OBJ_ALLOCATOR
-            .lock()
-            .allocate_and_retyped_fixed_sized::<Granule>();
-        tasks[1].map_page(DMA_ADDR_START, page_cap);
-        let page_cap = OBJ_ALLOCATOR
-            .lock()
-            .allocate_and_retyped_fixed_sized::<Granule>();
-        tasks[1].map_page(DMA_ADDR_START + PAGE_SIZE, page_cap);
-
-        // Channel to send message to net thread
-        let net_dev_ep = OBJ_ALLOCATOR
-            .lock()
-            .allocate_and_retyped_fixed_sized::<Endpoint>();
-        // Set Notification for Net-Thread Task.
-        let net_irq_not = OBJ_ALLOCATOR
-            .lock()
-            .allocate_and_retyped_fixed_sized::<Notification>();
-        tasks[0]
-            .abs_cptr(DEFAULT_CUSTOM_SLOT + 2)
-            .copy(&utils::abs_cptr(net_dev_ep), CapRights::all())
-            .unwrap();
-        tasks[2]
-            .abs_cptr(DEFAULT_CUSTOM_SLOT)
-            .copy(
-                &init_thread::slot::CNODE.cap().relative(net_irq_not),
-                CapRights::all(),
-            )
-            .unwrap();
-        tasks[2]
-            .abs_cptr(DEFAULT_CUSTOM_SLOT + 2)
-            .copy(
-                &init_thread::slot::CNODE.cap().relative(net_dev_ep),
-                CapRights::all(),
-            )
-            .unwrap();
-        // Map device memory to net-thread task
-
-        // The Net-thread and blk-thread both map the same MMIO memory.
-        // So we can copy the cap from blk-thread to net-thread.
-        let (_net_device_slot_index, _net_device_cnode_index, net_device_index) =
-            OBJ_ALLOCATOR.lock().allocate_slot();
-
-        abs_cptr(LargePage::from_bits(net_device_index as u64))
-            .copy(&abs_cptr(blk_device_frame_cap), CapRights::all())
-            .unwrap();
-
-        let net_device_frame_slot = sel4::init_thread::Slot::from_index(net_device_index)
-            .downcast::<sel4::cap_type::LargePage>();
-        let net_device_frame_cap = net_device_frame_slot.cap();
-
-        assert!(net_device_frame_cap.frame_get_address().unwrap() < VIRTIO_MMIO_ADDR);
-        loop {
-            match net_device_frame_cap.frame_map(
-                tasks[2].vspace,
-                VIRTIO_MMIO_VIRT_ADDR,
-                CapRights::all(),
-                VmAttributes::DEFAULT,
-            ) {
-                Ok(()) => {
-                    debug_println!("[RootTask] map device memory success");
-                    break;
-                }
-                Err(Error::FailedLookup) => {
-                    let pt = TaskImpl::allocate_pt(&mut tasks[2]);
-                    pt.pt_map(
-                        tasks[2].vspace,
-                        VIRTIO_MMIO_VIRT_ADDR,
-                        VmAttributes::DEFAULT,
-                    )
-                    .unwrap();
-                    tasks[2].mapped_pt.lock().push(pt);
-                }
-                Err(e) => {
-                    panic!("[RootTask] map device memory failed: {:?}", e);
-                }
-            }
-        }
-        // Map DMA frame.
-        for i in 0..32 {
-            let page_cap = OBJ_ALLOCATOR
-                .lock()
-                .allocate_and_retyped_fixed_sized::<Granule>();
-            tasks[2].map_page(DMA_ADDR_START + i * PAGE_SIZE, page_cap);
-        }
-    }
+    let mut system = system::build(bootinfo, &platform, fault_ep, irq_ep, kernel_untyped)?;
+    let mut supervisor = supervisor::Supervisor::new(&system);
+    let mut shutdown: Option<shutdown::Shutdown> = None;
+    let reply = allocate_reply(&mut OBJ_ALLOCATOR.lock());
 
     sys_null(-10);
 
     // Start tasks
-    run_tasks(&tasks);
+    run_tasks(&system.tasks);
 
     loop {
+        // Nothing wakes the root task at the deadline without a timer.
+        if let Some(shutdown) = shutdown.as_ref().filter(|shutdown| !shutdown.timer_armed()) {
+            shutdown.wait_timeout();
+            shutdown.finish(&system);
+        }
+
         // debug_println!("[RootTask]: Waiting for message...");
-        let (message, badge) = fault_ep.recv(());
+        let (message, badge) = fault_ep.recv(reply);
 
-        if let Some(info) = RootMessageLabel::try_from(&message) {
+        if badge >= NOTIFICATION_BADGE_START {
+            // The timer of a delayed restart or of the shutdown expired.
+            if shutdown.is_none() {
+                supervisor.restart_due(&mut system, bootinfo);
+            }
+        } else if let Some(info) = RootMessageLabel::try_from(&message) {
             match info {
                 RootMessageLabel::RegisterIRQ(irq_handler, irq_num) => {
-                    let slot = &tasks[badge as usize]
+                    let slot = &system.tasks[badge as usize]
                         .cnode
                         .relative(CPtr::from_bits(irq_handler));
-
-                    init_thread::slot::IRQ_CONTROL
-                        .cap()
-                        .irq_control_get(irq_num, slot)
-                        .unwrap();
+                    let result = system.irq_manager.claim_into(badge as usize, irq_num, slot);
+                    if let Err(err) = result {
+                        debug_println!(
+                            "[RootTask] task {} can't claim irq {}: {:?}",
+                            badge,
+                            irq_num,
+                            err
+                        );
+                    }
 
                     with_ipc_buffer_mut(|buffer| {
-                        sel4::reply(buffer, MessageInfo::new(0, 0, 0, 0));
+                        buffer.msg_regs_mut()[0] = IrqError::code(result);
+                        common::reply(buffer, reply, MessageInfo::new(0, 0, 0, 1));
                     });
                 }
                 RootMessageLabel::RegisterIRQWithCap(irq_num) => {
-                    with_ipc_buffer_mut(|buffer| {
-                        sel4::reply(buffer, MessageInfo::new(0, 0, 0, 0));
-                    });
-
-                    // send irq handler to kernel thread, TODO: use a common irq handler in constant.
-                    init_thread::slot::IRQ_CONTROL
-                        .cap()
-                        .irq_control_get(
+                    let result = system.irq_manager.claim(badge as usize, irq_num);
+                    if let Err(err) = result {
+                        debug_println!(
+                            "[RootTask] task {} can't claim irq {}: {:?}",
+                            badge,
                             irq_num,
-                            &init_thread::slot::CNODE.cap().relative(common_irq_handler),
-                        )
-                        .unwrap();
-                    // set cap
+                            err
+                        );
+                    }
+
                     with_ipc_buffer_mut(|buffer| {
-                        buffer.caps_or_badges_mut()[0] = common_irq_handler.bits() as _;
+                        buffer.msg_regs_mut()[0] = IrqError::code(result.map(|_| ()));
+                        common::reply(buffer, reply, MessageInfo::new(0, 0, 0, 1));
                     });
 
-                    // call
-                    let info = MessageInfo::new(0, 0, 1, 0);
-                    irq_ep.call(info);
-                    debug_println!("[RootTask] Sent IRQ to Kernel Thread");
+                    // Send the irq handler to the task through the IRQ endpoint.
+                    if let Ok(irq_handler) = result {
+                        with_ipc_buffer_mut(|buffer| {
+                            buffer.caps_or_badges_mut()[0] = irq_handler.bits() as _;
+                        });
+                        irq_ep.call(MessageInfo::new(0, 0, 1, 0));
+                        debug_println!("[RootTask] Sent IRQ to task {}", badge);
+                    }
+                }
+                RootMessageLabel::GetDevice(_, _) => {
+                    let (vaddr, irq_num) = system.devices[badge as usize];
+                    let message = RootMessageLabel::GetDevice(vaddr, irq_num).build();
+                    with_ipc_buffer_mut(|buffer| common::reply(buffer, reply, message));
                 }
                 RootMessageLabel::TranslateAddr(addr) => {
-                    let phys_addr = tasks[badge as usize]
+                    let phys_addr = system.tasks[badge as usize]
                         .mapped_page
                         .get(&(addr & !0xfff))
                         .map(|x| x.frame_get_address().unwrap())
@@ -387,16 +212,40 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
 
                     let message =
                         RootMessageLabel::TranslateAddr(phys_addr + addr % 0x1000).build();
-                    with_ipc_buffer_mut(|buffer| sel4::reply(buffer, message));
+                    with_ipc_buffer_mut(|buffer| common::reply(buffer, reply, message));
+                }
+                RootMessageLabel::Shutdown(mode) => {
+                    if shutdown.is_none() {
+                        shutdown = Some(shutdown::Shutdown::start(&mut system, mode));
+                    }
+                }
+                RootMessageLabel::Stopped => match shutdown.as_mut() {
+                    Some(shutdown) => shutdown.stopped(&system, badge as usize),
+                    None => debug_println!("[RootTask] task {} stops without a shutdown", badge),
+                },
+                RootMessageLabel::Ready => {
+                    if system.ready(badge as usize) {
+                        supervisor.rearm(&mut system);
+                        if let Some(shutdown) = shutdown.as_ref() {
+                            system.set_timer(shutdown.deadline());
+                        }
+                    }
                 }
             }
         } else {
             let fault = with_ipc_buffer(|buffer| sel4::Fault::new(buffer, &message));
-            debug_println!(
-                "[RootTask] received fault {:#x?} from budge: {}",
-                fault,
-                badge
-            )
+            match shutdown.as_mut() {
+                // The task isn't restarted while the system shuts down.
+                Some(shutdown) => {
+                    debug_println!("[RootTask] task {} faulted: {:#x?}", badge, fault);
+                    shutdown.stopped(&system, badge as usize);
+                }
+                None => supervisor.fault(&mut system, bootinfo, badge as usize, &fault),
+            }
+        }
+
+        if let Some(shutdown) = shutdown.as_ref().filter(|shutdown| shutdown.done()) {
+            shutdown.finish(&system);
         }
     }
 }
@@ -0,0 +1,115 @@
+//! Schedule the components.
+//!
+//! The tasks run with the priority of their components on their cores. On a
+//! MCS kernel each instance has a scheduling context, which gives it the
+//! budget of its component in each period. The context is kept when the
+//! instance is restarted, the reply object its server loop receives with
+//! isn't, it is retyped from the untyped of the instance and deleted with it.
+
+use alloc::vec::Vec;
+use common::SchedContext;
+use crate_consts::manifest::Component;
+use sel4::{cap::Endpoint, init_thread, CPtr};
+
+use crate::task::Sel4Task;
+
+/// The maximum controlled priority of the components.
+const COMPONENT_MCP: u64 = 255;
+
+/// The scheduling controls of the cores, given to the components asking for
+/// them.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub fn sched_controls(bootinfo: &sel4::BootInfo) -> Vec<CPtr> {
+    bootinfo
+        .sched_control()
+        .range()
+        .map(|index| CPtr::from_bits(index as _))
+        .collect()
+}
+
+/// The scheduling controls of the cores, only a MCS kernel has them.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub fn sched_controls(_bootinfo: &sel4::BootInfo) -> Vec<CPtr> {
+    Vec::new()
+}
+
+/// Schedule the `task` of the `component` on the `core`.
+///
+/// The fault endpoint and the reply object are given to it here on MCS,
+/// `fault_ep` is minted with the badge.
+#[sel4::sel4_cfg(KERNEL_MCS)]
+pub fn configure(
+    bootinfo: &sel4::BootInfo,
+    task: &Sel4Task,
+    component: &Component,
+    core: usize,
+    sched_context: SchedContext,
+    fault_ep: (Endpoint, u64),
+) -> sel4::Result<()> {
+    use crate::{utils::abs_cptr, TASK_ALLOCATOR};
+
+    bootinfo
+        .sched_control()
+        .index(core)
+        .cap()
+        .sched_control_configure_flags(
+            sched_context,
+            component.budget_us,
+            component.period_us,
+            0,
+            0,
+            0,
+        )?;
+
+    let reply = common::allocate_reply(&mut TASK_ALLOCATOR.lock());
+    task.abs_cptr(crate_consts::DEFAULT_THREAD_REPLY)
+        .move_(&abs_cptr(reply))?;
+    TASK_ALLOCATOR.lock().free_slot(reply.bits() as _);
+
+    // The fault endpoint is looked up in the CSpace of the root task, it is
+    // copied into the TCB.
+    let badged_ep = TASK_ALLOCATOR
+        .lock()
+        .allocate_normal_cap::<sel4::cap_type::Endpoint>();
+    abs_cptr(badged_ep).mint(&abs_cptr(fault_ep.0), sel4::CapRights::all(), fault_ep.1)?;
+    let result = task.tcb.tcb_set_sched_params(
+        init_thread::slot::TCB.cap(),
+        COMPONENT_MCP,
+        component.priority as _,
+        sched_context,
+        badged_ep,
+    );
+    abs_cptr(badged_ep).delete()?;
+    TASK_ALLOCATOR.lock().free_slot(badged_ep.bits() as _);
+    result
+}
+
+/// Schedule the `task` of the `component` on the `core`.
+#[sel4::sel4_cfg(not(KERNEL_MCS))]
+pub fn configure(
+    _bootinfo: &sel4::BootInfo,
+    task: &Sel4Task,
+    component: &Component,
+    core: usize,
+    _sched_context: SchedContext,
+    _fault_ep: (Endpoint, u64),
+) -> sel4::Result<()> {
+    task.tcb.tcb_set_sched_params(
+        init_thread::slot::TCB.cap(),
+        COMPONENT_MCP,
+        component.priority as _,
+    )?;
+    set_affinity(task, core)
+}
+
+/// Pin the task to the `core`.
+#[sel4::sel4_cfg(all(not(KERNEL_MCS), not(MAX_NUM_NODES = "1")))]
+fn set_affinity(task: &Sel4Task, core: usize) -> sel4::Result<()> {
+    task.tcb.tcb_set_affinity(core as _)
+}
+
+/// Pin the task to the `core`, the kernel is built with only one.
+#[sel4::sel4_cfg(all(not(KERNEL_MCS), MAX_NUM_NODES = "1"))]
+fn set_affinity(_task: &Sel4Task, _core: usize) -> sel4::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Shut the system down.
+//!
+//! The components with a `stop` notification in `system.toml` are told to
+//! stop, so they can finish their work, e.g. the block thread flushes the
+//! device and the net thread closes the connections. The root task waits for
+//! them to call `RootMessageLabel::Stopped`, at most [STOP_TIMEOUT_MS], then
+//! powers off or resets the machine through PSCI. Without a timer to wake it
+//! at the deadline, it waits the whole timeout.
+
+use alloc::collections::btree_set::BTreeSet;
+use common::{current_nanos, ShutdownMode, NANOS_PER_MILLIS};
+use sel4_root_task::debug_println;
+
+use crate::system::System;
+
+/// How long the components are waited for to stop.
+const STOP_TIMEOUT_MS: u64 = 3000;
+
+/// The function ID of PSCI `SYSTEM_OFF`.
+const PSCI_SYSTEM_OFF: u64 = 0x8400_0008;
+/// The function ID of PSCI `SYSTEM_RESET`.
+const PSCI_SYSTEM_RESET: u64 = 0x8400_0009;
+
+/// A shutdown waiting for the components to stop.
+pub struct Shutdown {
+    mode: ShutdownMode,
+    /// The tasks told to stop and not stopped yet.
+    pending: BTreeSet<usize>,
+    /// The monotonic time the tasks are waited for until.
+    deadline: u64,
+    /// Whether a timer wakes the root task at the deadline.
+    timer_armed: bool,
+}
+
+impl Shutdown {
+    /// Tell the tasks to stop.
+    pub fn start(system: &mut System, mode: ShutdownMode) -> Self {
+        debug_println!("[RootTask] shutdown: {:?}", mode);
+        let pending = (0..system.tasks.len())
+            .filter(|index| {
+                system.request_stop(*index).unwrap_or_else(|err| {
+                    debug_println!("[RootTask] can't stop {}: {:?}", system.name(*index), err);
+                    false
+                })
+            })
+            .collect();
+        let deadline = current_nanos() + STOP_TIMEOUT_MS * NANOS_PER_MILLIS;
+        Self {
+            mode,
+            pending,
+            deadline,
+            timer_armed: system.set_timer(deadline),
+        }
+    }
+
+    /// The monotonic time the tasks are waited for until.
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    /// Whether a timer wakes the root task at the deadline, it waits with
+    /// [Shutdown::wait_timeout] otherwise.
+    pub fn timer_armed(&self) -> bool {
+        self.timer_armed
+    }
+
+    /// Wait until the deadline without a timer, the other tasks run while
+    /// the root task yields.
+    pub fn wait_timeout(&self) {
+        while current_nanos() < self.deadline {
+            sel4::r#yield();
+        }
+    }
+
+    /// The task `index` is stopped, or it faulted while stopping.
+    pub fn stopped(&mut self, system: &System, index: usize) {
+        if self.pending.remove(&index) {
+            debug_println!("[RootTask] {} is stopped", system.name(index));
+        }
+    }
+
+    /// Whether all the tasks are stopped, or the timeout expired.
+    pub fn done(&self) -> bool {
+        self.pending.is_empty() || current_nanos() >= self.deadline
+    }
+
+    /// Suspend the tasks and power off or reset the machine.
+    ///
+    /// The root task halts if PSCI can't be called.
+    pub fn finish(&self, system: &System) -> ! {
+        for index in self.pending.iter() {
+            debug_println!("[RootTask] {} doesn't stop in time", system.name(*index));
+        }
+        system.suspend_all();
+
+        let function = match self.mode {
+            ShutdownMode::PowerOff => PSCI_SYSTEM_OFF,
+            ShutdownMode::Reboot => PSCI_SYSTEM_RESET,
+        };
+        let err = psci_call(function);
+        debug_println!("[RootTask] PSCI call failed: {:?}, halt", err);
+        loop {
+            sel4::init_thread::slot::TCB.cap().tcb_suspend().unwrap();
+        }
+    }
+}
+
+/// Call the PSCI `function` through the SMC capability.
+#[sel4::sel4_cfg(ALLOW_SMC_CALLS)]
+fn psci_call(function: u64) -> sel4::Result<()> {
+    let args = sel4::sys::seL4_ARM_SMCContext {
+        x0: function,
+        ..Default::default()
+    };
+    sel4::init_thread::slot::SMC
+        .cap()
+        .smc_call(&args)
+        .map(|_| ())
+}
+
+/// Call the PSCI `function`, the kernel doesn't provide the SMC capability.
+#[sel4::sel4_cfg(not(ALLOW_SMC_CALLS))]
+fn psci_call(_function: u64) -> sel4::Result<()> {
+    Err(sel4::Error::IllegalOperation)
+}
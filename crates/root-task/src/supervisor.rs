@@ -0,0 +1,142 @@
+//! Supervise the components started by the root task.
+//!
+//! A faulting task is destroyed, then restarted from its ELF file as the
+//! restart policy of its component says, until it faults `max_restarts`
+//! times in a row. The delays of the back-off are timers armed in the timer
+//! thread, which signals the notification bound to the root task when they
+//! expire.
+
+use alloc::vec::Vec;
+use common::{current_nanos, NANOS_PER_MILLIS};
+use crate_consts::manifest::RestartPolicy;
+use sel4::Fault;
+use sel4_root_task::debug_println;
+
+use crate::system::System;
+
+/// A task running longer than this after its restart, or the maximum delay
+/// of the back-off, doesn't fault in a row.
+const RESTART_WINDOW_MS: u64 = 1000;
+
+/// The restarts of a task.
+#[derive(Default)]
+struct Supervision {
+    /// The number of the faults in a row, each within the restart window of
+    /// the start of the task.
+    faults: u32,
+    /// The monotonic time the task was started at in nanoseconds.
+    started_at: u64,
+    /// The monotonic time the task is restarted at, if it is waiting for it.
+    restart_at: Option<u64>,
+}
+
+pub struct Supervisor {
+    /// The restarts of each task, indexed like [System::tasks].
+    tasks: Vec<Supervision>,
+}
+
+impl Supervisor {
+    /// Supervise the tasks of the `system` started just now.
+    pub fn new(system: &System) -> Self {
+        let now = current_nanos();
+        Self {
+            tasks: system
+                .tasks
+                .iter()
+                .map(|_| Supervision {
+                    started_at: now,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    /// Handle the `fault` of the task `index`, it is stopped and restarted.
+    pub fn fault(
+        &mut self,
+        system: &mut System,
+        bootinfo: &sel4::BootInfo,
+        index: usize,
+        fault: &Fault,
+    ) {
+        let name = system.name(index);
+        debug_println!(
+            "[RootTask] {} (task {}) faulted: {:#x?}",
+            name,
+            index,
+            fault
+        );
+        if let Err(err) = system.stop(index) {
+            debug_println!("[RootTask] failed to stop {}: {:?}", name, err);
+            return;
+        }
+
+        let now = current_nanos();
+        let supervision = &mut self.tasks[index];
+        let component = system.component(index);
+        let window_ms = match component.restart {
+            RestartPolicy::Never => {
+                debug_println!("[RootTask] {} is stopped", name);
+                return;
+            }
+            RestartPolicy::Always => RESTART_WINDOW_MS,
+            RestartPolicy::Backoff { max_ms, .. } => max_ms.max(RESTART_WINDOW_MS),
+        };
+        if now - supervision.started_at > window_ms * NANOS_PER_MILLIS {
+            supervision.faults = 0;
+        }
+        if supervision.faults >= component.max_restarts {
+            debug_println!(
+                "[RootTask] {} faulted {} times in a row, it is stopped",
+                name,
+                supervision.faults + 1
+            );
+            return;
+        }
+        let delay_ms = match component.restart {
+            RestartPolicy::Backoff { initial_ms, max_ms } => initial_ms
+                .saturating_mul(1 << supervision.faults.min(32))
+                .min(max_ms),
+            _ => 0,
+        };
+        supervision.faults += 1;
+
+        // The timer thread can't delay its own restart, it is stopped now.
+        let restart_at = now + delay_ms * NANOS_PER_MILLIS;
+        if delay_ms == 0 || !system.set_timer(restart_at) {
+            supervision.restart_at = Some(now);
+            self.restart_due(system, bootinfo);
+            return;
+        }
+        supervision.restart_at = Some(restart_at);
+        debug_println!("[RootTask] {} is restarted in {} ms", name, delay_ms);
+    }
+
+    /// Restart the tasks whose delays expired.
+    pub fn restart_due(&mut self, system: &mut System, bootinfo: &sel4::BootInfo) {
+        let now = current_nanos();
+        for index in 0..self.tasks.len() {
+            if !self.tasks[index].restart_at.is_some_and(|at| at <= now) {
+                continue;
+            }
+            self.tasks[index].restart_at = None;
+
+            let name = system.name(index);
+            debug_println!("[RootTask] restart {} (task {})", name, index);
+            if let Err(err) = system.restart(bootinfo, index) {
+                debug_println!("[RootTask] failed to restart {}: {:?}", name, err);
+                continue;
+            }
+            self.tasks[index].started_at = now;
+        }
+    }
+
+    /// Arm the timers of the delayed restarts again when the timer instance
+    /// is ready, they are lost with the old timer thread or left unarmed
+    /// while it started.
+    pub fn rearm(&self, system: &mut System) {
+        for at in self.tasks.iter().filter_map(|task| task.restart_at) {
+            system.set_timer(at);
+        }
+    }
+}
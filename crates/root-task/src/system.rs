@@ -0,0 +1,645 @@
+//! Start the components described by `system.toml`.
+//!
+//! The components are started in the order of the manifest, the ones with a
+//! virtio device once for each device found. The slots are filled after all
+//! the components are started, the objects of another component are taken
+//! from its first instance.
+//!
+//! An instance is restarted in place with the same index. Its objects are
+//! kept, so the capabilities held by the other components stay valid, and
+//! its slots are filled again. The objects of its task are retyped from an
+//! untyped of the instance, revoked when the task is destroyed.
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use common::{allocate_sched_context, IrqNum, SchedContext, TimerMessageLabel, NO_IRQ};
+use core::ops::Range;
+use crate_consts::{
+    manifest::{Component, DeviceKind, ObjectKind, SlotCap, COMPONENTS, ROOT_TIMER},
+    CNODE_RADIX_BITS, DEFAULT_CUSTOM_SLOT, DMA_ADDR_START, PAGE_SIZE, TIMER_BADGE,
+};
+use include_bytes_aligned::include_bytes_aligned;
+use sel4::{
+    cap::{Endpoint, Notification, Untyped},
+    cap_type, init_thread, CPtr, CPtrBits, CapRights,
+};
+use sel4_root_task::debug_println;
+
+use crate::{
+    device_tree::{MmioDevice, Platform},
+    irq::{IrqLine, IrqManager},
+    sched,
+    task::{build_kernel_thread, destroy_task, task_untyped_bits, Sel4Task},
+    utils::{abs_cptr, device_frame, init_free_page_addr},
+    virtio::{self, VirtioDevice},
+    OBJ_ALLOCATOR, TASK_ALLOCATOR,
+};
+
+include!(concat!(env!("OUT_DIR"), "/elf_files.rs"));
+
+/// The number of `seL4_Call` in the syscall register.
+const SYS_CALL: u64 = -1i64 as u64;
+
+/// The components started by the root task.
+pub struct System {
+    /// The tasks, indexed by the badges of their fault endpoints.
+    pub tasks: Vec<Sel4Task>,
+    /// The device of each task, replied to `RootMessageLabel::GetDevice`.
+    pub devices: Vec<(usize, IrqNum)>,
+    /// The interrupts of the devices, only claimed by the tasks they are assigned to.
+    pub irq_manager: IrqManager,
+    /// Whether each task is running, it is stopped after a fault.
+    running: Vec<bool>,
+    instances: Vec<Instance>,
+    links: Vec<Link>,
+    fault_ep: Endpoint,
+    irq_ep: Endpoint,
+    untyped: Untyped,
+    /// The scheduling controls of the cores, empty if the kernel isn't MCS.
+    sched_controls: Vec<CPtr>,
+    /// The timer instance and its endpoint minted for the root task.
+    timer: Option<(usize, Endpoint)>,
+    /// Whether the timer instance waits for the requests, the root task
+    /// calls it only then.
+    timer_ready: bool,
+    /// The id of the last timer armed for the root task.
+    timer_id: u64,
+    /// The notifications of the instances minted by the root task with the
+    /// badges it signals, indexed by the object and the badge.
+    badged_ntfns: BTreeMap<(CPtrBits, u64), Notification>,
+}
+
+/// An instance of a component, with the objects created for it.
+struct Instance {
+    component: &'static Component,
+    device: Option<MmioDevice>,
+    objects: BTreeMap<&'static str, CPtr>,
+    sched_context: SchedContext,
+    /// The untyped the objects of the task are retyped from.
+    untyped: Untyped,
+    /// The slots of the root task holding the capabilities of the task.
+    slots: Range<usize>,
+}
+
+/// The endpoint of a server held by a client, the server holds the
+/// notification of the client.
+struct Link {
+    /// The client instance, `None` for the root task.
+    client: Option<usize>,
+    /// The offset of the slot of the endpoint in the client.
+    offset: u64,
+    server: usize,
+    endpoint: CPtr,
+    /// The badge of the endpoint, the notification is at `badge - 1` in the
+    /// slots of the clients of the server.
+    badge: u64,
+    notify: CPtr,
+    notify_badge: u64,
+}
+
+/// Get the content of the ELF file `name` in the build directory.
+fn elf_file(name: &str) -> &'static [u8] {
+    ELF_FILES
+        .iter()
+        .find(|(elf, _)| *elf == name)
+        .map(|(_, data)| *data)
+        .unwrap()
+}
+
+/// Get the devices of the instances of the `component`, one instance is
+/// started for each of them.
+fn instance_devices(
+    component: &Component,
+    platform: &Platform,
+    virtio_devices: &[VirtioDevice],
+) -> Vec<Option<MmioDevice>> {
+    let Some(device) = component.device else {
+        return alloc::vec![None];
+    };
+    match device.kind {
+        DeviceKind::Pl011 => {
+            alloc::vec![Some(
+                platform.uart.expect("[RootTask] can't find the PL011 UART")
+            )]
+        }
+        DeviceKind::Pl031 => {
+            alloc::vec![Some(
+                platform.rtc.expect("[RootTask] can't find the PL031 RTC")
+            )]
+        }
+        DeviceKind::ArchTimer => {
+            let (irq, trigger) = platform
+                .timer_irq
+                .expect("[RootTask] can't find the ARM generic timer");
+            alloc::vec![Some(MmioDevice {
+                paddr: 0,
+                size: 0,
+                irq,
+                trigger,
+            })]
+        }
+        DeviceKind::Virtio(device_id) => virtio_devices
+            .iter()
+            .filter(|device| device.device_id == device_id)
+            .map(|device| Some(device.transport))
+            .collect(),
+    }
+}
+
+/// Start the components, `untyped` is given to the one asking for it.
+pub fn build(
+    bootinfo: &sel4::BootInfo,
+    platform: &Platform,
+    fault_ep: Endpoint,
+    irq_ep: Endpoint,
+    untyped: Untyped,
+) -> sel4::Result<System> {
+    let virtio_devices = virtio::probe(bootinfo, &platform.virtio_mmio);
+    for device in virtio_devices.iter() {
+        let supported = COMPONENTS.iter().any(|component| {
+            component.device.map(|desc| desc.kind) == Some(DeviceKind::Virtio(device.device_id))
+        });
+        if !supported {
+            debug_println!(
+                "[RootTask] no driver for the virtio {} device at {:#x}",
+                virtio::device_name(device.device_id),
+                device.transport.paddr
+            );
+        }
+    }
+
+    let mut system = System {
+        tasks: Vec::new(),
+        devices: Vec::new(),
+        irq_manager: IrqManager::default(),
+        running: Vec::new(),
+        instances: Vec::new(),
+        links: Vec::new(),
+        fault_ep,
+        irq_ep,
+        untyped,
+        sched_controls: sched::sched_controls(bootinfo),
+        timer: None,
+        timer_ready: false,
+        timer_id: 0,
+        badged_ntfns: BTreeMap::new(),
+    };
+    // Each instance has a CNode of the slots after the ones of the root task.
+    let slots_start = bootinfo.empty().range().end.div_ceil(1 << CNODE_RADIX_BITS);
+    for component in COMPONENTS {
+        let untyped_bits = task_untyped_bits(
+            elf_file(component.elf),
+            component.stack_pages,
+            component.dma_pages,
+        );
+        for device in instance_devices(component, platform, &virtio_devices) {
+            let index = system.instances.len();
+            let cnode = slots_start + index;
+            let objects = component
+                .objects
+                .iter()
+                .map(|(name, kind)| {
+                    let mut allocator = OBJ_ALLOCATOR.lock();
+                    let cptr = match kind {
+                        ObjectKind::Endpoint => allocator
+                            .allocate_and_retyped_fixed_sized::<cap_type::Endpoint>()
+                            .cptr(),
+                        ObjectKind::Notification => allocator
+                            .allocate_and_retyped_fixed_sized::<cap_type::Notification>()
+                            .cptr(),
+                    };
+                    (*name, cptr)
+                })
+                .collect();
+            system.instances.push(Instance {
+                component,
+                device,
+                objects,
+                sched_context: allocate_sched_context(&mut OBJ_ALLOCATOR.lock()),
+                untyped: OBJ_ALLOCATOR
+                    .lock()
+                    .allocate_and_retyped_variable_sized::<cap_type::Untyped>(untyped_bits),
+                slots: cnode << CNODE_RADIX_BITS..(cnode + 1) << CNODE_RADIX_BITS,
+            });
+
+            let (mut vaddr, mut irq) = (0, NO_IRQ);
+            if let Some(device) = device {
+                let seat = component.device.unwrap().vaddr;
+                if seat != 0 {
+                    vaddr = seat + device.paddr % PAGE_SIZE;
+                }
+                irq = device.irq;
+                system.irq_manager.grant(
+                    irq,
+                    IrqLine {
+                        task: index,
+                        trigger: device.trigger,
+                        core: component_core(component),
+                    },
+                );
+            }
+            system.devices.push((vaddr, irq));
+
+            let task = system.spawn(bootinfo, index)?;
+            system.tasks.push(task);
+            system.running.push(true);
+        }
+    }
+
+    let untyped_slots = system
+        .instances
+        .iter()
+        .flat_map(|instance| instance.component.slots)
+        .filter(|slot| matches!(slot.cap, SlotCap::Untyped))
+        .count();
+    assert!(untyped_slots <= 1, "[RootTask] the untyped is given twice");
+
+    for index in 0..system.instances.len() {
+        let instance = &system.instances[index];
+        for slot in instance.component.slots {
+            let SlotCap::Client {
+                component,
+                endpoint,
+                notify,
+                badge,
+            } = slot.cap
+            else {
+                continue;
+            };
+            let Some(server) = system.first_instance(component) else {
+                debug_println!(
+                    "[RootTask] {}: no {} for the slot {}",
+                    instance.component.name,
+                    component,
+                    slot.name
+                );
+                continue;
+            };
+            let link = Link {
+                client: Some(index),
+                offset: slot.offset,
+                server,
+                endpoint: system.instances[server].objects[endpoint],
+                badge: system.next_client_badge(server),
+                notify: instance.objects[notify],
+                notify_badge: badge,
+            };
+            system.links.push(link);
+        }
+    }
+
+    // The root task is the last client of the timer, the notification bound
+    // to its TCB is signalled when the delay of a restart expires.
+    if let Some((component, endpoint)) = ROOT_TIMER {
+        if let Some(server) = system.first_instance(component) {
+            let mut allocator = OBJ_ALLOCATOR.lock();
+            let ntfn = allocator.allocate_and_retyped_fixed_sized::<cap_type::Notification>();
+            let ep = allocator.allocate_normal_cap::<cap_type::Endpoint>();
+            drop(allocator);
+            init_thread::slot::TCB.cap().tcb_bind_notification(ntfn)?;
+
+            let link = Link {
+                client: None,
+                offset: 0,
+                server,
+                endpoint: system.instances[server].objects[endpoint],
+                badge: system.next_client_badge(server),
+                notify: ntfn.cptr(),
+                notify_badge: TIMER_BADGE,
+            };
+            abs_cptr(ep).mint(&abs_cptr(link.endpoint), CapRights::all(), link.badge)?;
+            system.links.push(link);
+            system.timer = Some((server, ep));
+        }
+    }
+
+    for index in 0..system.instances.len() {
+        system.fill_slots(index)?;
+    }
+
+    Ok(system)
+}
+
+impl System {
+    /// The index of the first instance of the `component`.
+    fn first_instance(&self, component: &str) -> Option<usize> {
+        self.instances
+            .iter()
+            .position(|instance| instance.component.name == component)
+    }
+
+    /// The badge of the next client of the `server`.
+    fn next_client_badge(&self, server: usize) -> u64 {
+        let component = self.instances[server].component;
+        let count = clients_slots(component).1;
+        let badge = self
+            .links
+            .iter()
+            .filter(|link| link.server == server)
+            .count() as u64
+            + 1;
+        assert!(
+            badge <= count,
+            "[RootTask] too many clients of {}",
+            component.name
+        );
+        badge
+    }
+
+    /// Create the task of the instance `index`, with its device and DMA pages
+    /// mapped. The slots are left empty.
+    fn spawn(&self, bootinfo: &sel4::BootInfo, index: usize) -> sel4::Result<Sel4Task> {
+        let instance = &self.instances[index];
+        let component = instance.component;
+        TASK_ALLOCATOR
+            .lock()
+            .init(instance.slots.clone(), instance.untyped);
+        let mut task = build_kernel_thread(
+            (self.fault_ep, index as _),
+            self.irq_ep,
+            component.name,
+            elf_file(component.elf),
+            component.stack_pages,
+            unsafe { init_free_page_addr(bootinfo) },
+        )?;
+        sched::configure(
+            bootinfo,
+            &task,
+            component,
+            component_core(component),
+            instance.sched_context,
+            (self.fault_ep, index as _),
+        )?;
+
+        // Map only the page of the registers, the neighbours in the same
+        // page are visible to the task too.
+        if let Some(device) = instance.device {
+            let seat = component.device.unwrap().vaddr;
+            if seat != 0 {
+                task.map_page(seat, device_frame(bootinfo, device.paddr, &TASK_ALLOCATOR));
+            }
+        }
+
+        // Map DMA frame.
+        for i in 0..component.dma_pages {
+            let page_cap = TASK_ALLOCATOR
+                .lock()
+                .allocate_and_retyped_fixed_sized::<cap_type::Granule>();
+            task.map_page(DMA_ADDR_START + i * PAGE_SIZE, page_cap);
+        }
+
+        Ok(task)
+    }
+
+    /// Fill the slots of the instance `index`, and its notifications in the
+    /// slots of its servers, or the notifications of its clients if it is a
+    /// server.
+    fn fill_slots(&self, index: usize) -> sel4::Result<()> {
+        let instance = &self.instances[index];
+        let task = &self.tasks[index];
+        let first = self.first_instance(instance.component.name) == Some(index);
+        for slot in instance.component.slots {
+            if slot.first_instance && !first {
+                continue;
+            }
+            let dst = task.abs_cptr(DEFAULT_CUSTOM_SLOT + slot.offset);
+            match slot.cap {
+                SlotCap::Empty | SlotCap::Client { .. } | SlotCap::Clients { .. } => {}
+                SlotCap::Untyped => dst.copy(&abs_cptr(self.untyped), CapRights::all())?,
+                SlotCap::SchedControl => {
+                    for (core, control) in self.sched_controls.iter().enumerate() {
+                        task.abs_cptr(DEFAULT_CUSTOM_SLOT + slot.offset + core as u64)
+                            .copy(&abs_cptr(*control), CapRights::all())?;
+                    }
+                }
+                SlotCap::Object {
+                    component,
+                    object,
+                    badge,
+                } => {
+                    let owner = component.map_or(Some(index), |name| self.first_instance(name));
+                    let Some(owner) = owner else {
+                        debug_println!(
+                            "[RootTask] {}: no {} for the slot {}",
+                            instance.component.name,
+                            component.unwrap(),
+                            slot.name
+                        );
+                        continue;
+                    };
+                    let object = self.instances[owner].objects[object];
+                    dst.mint(&abs_cptr(object), CapRights::all(), badge)?;
+                }
+            }
+        }
+
+        for link in self.links.iter() {
+            if link.client == Some(index) {
+                task.abs_cptr(DEFAULT_CUSTOM_SLOT + link.offset).mint(
+                    &abs_cptr(link.endpoint),
+                    CapRights::all(),
+                    link.badge,
+                )?;
+            }
+            if link.server == index {
+                let offset = clients_slots(instance.component).0;
+                task.abs_cptr(DEFAULT_CUSTOM_SLOT + offset + link.badge - 1)
+                    .mint(&abs_cptr(link.notify), CapRights::all(), link.notify_badge)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The name of the component of the task `index`.
+    pub fn name(&self, index: usize) -> &'static str {
+        self.instances[index].component.name
+    }
+
+    /// The component of the task `index`.
+    pub fn component(&self, index: usize) -> &'static Component {
+        self.instances[index].component
+    }
+
+    /// The index of the timer instance the root task is a client of.
+    pub fn timer(&self) -> Option<usize> {
+        self.timer.map(|(timer, _)| timer)
+    }
+
+    /// Stop the task `index` and destroy it, the interrupts it claimed are
+    /// released.
+    pub fn stop(&mut self, index: usize) -> sel4::Result<()> {
+        if !self.running[index] {
+            return Ok(());
+        }
+        self.running[index] = false;
+        if self.timer() == Some(index) {
+            self.timer_ready = false;
+        }
+        self.irq_manager.release_task(index);
+        destroy_task(&self.tasks[index], self.instances[index].untyped)
+    }
+
+    /// The task `index` waits for its requests, see `RootMessageLabel::Ready`.
+    ///
+    /// Returns whether it is the timer instance.
+    pub fn ready(&mut self, index: usize) -> bool {
+        let timer = self.timer() == Some(index) && self.running[index];
+        self.timer_ready |= timer;
+        timer
+    }
+
+    /// Start the task `index` stopped by [System::stop] again from its ELF
+    /// file, then tell the components holding its capabilities.
+    pub fn restart(&mut self, bootinfo: &sel4::BootInfo, index: usize) -> sel4::Result<()> {
+        assert!(!self.running[index]);
+        let component = self.instances[index].component;
+        // The objects retyped by the old task are deleted before the untyped
+        // is given again.
+        if component
+            .slots
+            .iter()
+            .any(|slot| matches!(slot.cap, SlotCap::Untyped))
+        {
+            abs_cptr(self.untyped).revoke()?;
+        }
+
+        self.tasks[index] = self.spawn(bootinfo, index)?;
+        self.fill_slots(index)?;
+        self.running[index] = true;
+        self.tasks[index].run();
+        self.notify_restart(index)
+    }
+
+    /// Tell the clients of the task `index` it is restarted.
+    ///
+    /// A client calling one of its endpoints waits for a reply which never
+    /// comes, so it is suspended and resumed to send the call again. The
+    /// notification of the slot is signalled, the client opens its sessions
+    /// again then.
+    fn notify_restart(&mut self, index: usize) -> sel4::Result<()> {
+        let server = self.instances[index].component;
+        if self.first_instance(server.name) != Some(index) {
+            return Ok(());
+        }
+        for client in 0..self.instances.len() {
+            if client == index || !self.running[client] {
+                continue;
+            }
+            let component = self.instances[client].component;
+            for slot in component.slots {
+                if slot.cap.component() != Some(server.name) {
+                    continue;
+                }
+                let endpoint = match slot.cap {
+                    SlotCap::Object { object, .. } => server
+                        .objects
+                        .iter()
+                        .any(|(name, kind)| *name == object && *kind == ObjectKind::Endpoint),
+                    _ => true,
+                };
+                let tcb = self.tasks[client].tcb;
+                if endpoint && calling(&self.tasks[client], DEFAULT_CUSTOM_SLOT + slot.offset)? {
+                    debug_println!("[RootTask] {} calls {} again", component.name, server.name);
+                    tcb.tcb_suspend()?;
+                    tcb.tcb_resume()?;
+                }
+                if let Some((notify, badge)) = slot.restart {
+                    let object = self.instances[client].objects[notify];
+                    self.badged_notification(object, badge)?.signal();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The notification `object` minted with the `badge` for the root task.
+    fn badged_notification(&mut self, object: CPtr, badge: u64) -> sel4::Result<Notification> {
+        if let Some(ntfn) = self.badged_ntfns.get(&(object.bits(), badge)) {
+            return Ok(*ntfn);
+        }
+        let ntfn = OBJ_ALLOCATOR
+            .lock()
+            .allocate_normal_cap::<cap_type::Notification>();
+        abs_cptr(ntfn).mint(&abs_cptr(object), CapRights::all(), badge)?;
+        self.badged_ntfns.insert((object.bits(), badge), ntfn);
+        Ok(ntfn)
+    }
+
+    /// Arm a timer of the root task expiring at the monotonic time `at`, the
+    /// notification bound to the root task is signalled then.
+    ///
+    /// Returns whether the timer is armed, it can't be without a running
+    /// timer instance. The timer instance started and not ready yet may call
+    /// the root task, so the timer is left for the caller to arm again when
+    /// it is ready, and `true` is returned.
+    pub fn set_timer(&mut self, at: u64) -> bool {
+        let Some((_, ep)) = self.timer.filter(|(timer, _)| self.running[*timer]) else {
+            return false;
+        };
+        if !self.timer_ready {
+            return true;
+        }
+        self.timer_id += 1;
+        ep.call(TimerMessageLabel::SetTimer(self.timer_id, at).build());
+        true
+    }
+
+    /// Signal the `stop` notification of the task `index` for the shutdown.
+    ///
+    /// Returns whether it is told, it calls `RootMessageLabel::Stopped`
+    /// when it finishes stopping then.
+    pub fn request_stop(&mut self, index: usize) -> sel4::Result<bool> {
+        let instance = &self.instances[index];
+        let Some((notify, badge)) = instance.component.stop else {
+            return Ok(false);
+        };
+        if !self.running[index] {
+            return Ok(false);
+        }
+        let object = instance.objects[notify];
+        self.badged_notification(object, badge)?.signal();
+        Ok(true)
+    }
+
+    /// Suspend all the running tasks, before the machine is powered off.
+    pub fn suspend_all(&self) {
+        for (index, task) in self.tasks.iter().enumerate() {
+            if self.running[index] {
+                let _ = task.tcb.tcb_suspend();
+            }
+        }
+    }
+}
+
+/// The core the instances of the `component` run on, 0 if the kernel isn't
+/// built with that many cores.
+fn component_core(component: &Component) -> usize {
+    let nodes = sel4::sel4_cfg_usize!(MAX_NUM_NODES);
+    if component.core >= nodes {
+        debug_println!(
+            "[RootTask] {} runs on core 0, core {} isn't one of the {} cores",
+            component.name,
+            component.core,
+            nodes
+        );
+        return 0;
+    }
+    component.core
+}
+
+/// The offset and the count of the slots of the clients of the `component`.
+fn clients_slots(component: &Component) -> (u64, u64) {
+    component
+        .slots
+        .iter()
+        .find_map(|slot| match slot.cap {
+            SlotCap::Clients { count } => Some((slot.offset, count)),
+            _ => None,
+        })
+        .expect("[RootTask] the server has no slots for the clients")
+}
+
+/// Whether the `task` is calling the endpoint in the slot `cptr`.
+fn calling(task: &Sel4Task, cptr: u64) -> sel4::Result<bool> {
+    let context = task.tcb.tcb_read_all_registers(false)?;
+    Ok(context.inner().x7 == SYS_CALL && context.inner().x0 == cptr)
+}
@@ -1,15 +1,15 @@
-use crate::{abs_cptr, GRANULE_SIZE, OBJ_ALLOCATOR};
+use crate::{abs_cptr, GRANULE_SIZE, OBJ_ALLOCATOR, TASK_ALLOCATOR};
 use alloc::{collections::btree_map::BTreeMap, vec::Vec};
 use common::{footprint, map_image, map_intermediate_translation_tables};
 use core::ops::DerefMut;
 use crate_consts::CNODE_RADIX_BITS;
 use object::{File, Object};
 use sel4::{
-    cap::{Endpoint, Null},
+    cap::{Endpoint, Null, Untyped},
     cap_type::{CNode, SmallPage, Tcb, PT},
     debug_println,
     init_thread::{self},
-    CNodeCapData, CapRights,
+    CNodeCapData, CapRights, CapTypeForObjectOfVariableSize,
 };
 use task_helper::{Sel4TaskHelper, TaskHelperTrait};
 use xmas_elf::ElfFile;
@@ -21,13 +21,13 @@ impl TaskHelperTrait<Sel4TaskHelper<Self>> for TaskImpl {
     const DEFAULT_STACK_TOP: usize = 0x1_0000_0000;
 
     fn allocate_pt(_task: &mut Self::Task) -> sel4::cap::PT {
-        OBJ_ALLOCATOR
+        TASK_ALLOCATOR
             .lock()
             .allocate_and_retyped_fixed_sized::<PT>()
     }
 
     fn allocate_page(_task: &mut Self::Task) -> sel4::cap::SmallPage {
-        OBJ_ALLOCATOR
+        TASK_ALLOCATOR
             .lock()
             .allocate_and_retyped_fixed_sized::<SmallPage>()
     }
@@ -91,10 +91,11 @@ pub fn build_kernel_thread(
     irq_ep: Endpoint,
     thread_name: &str,
     file_data: &[u8],
+    stack_pages: usize,
     free_page_addr: usize,
 ) -> sel4::Result<Sel4Task> {
     // make 新线程的虚拟地址空间
-    let cnode = OBJ_ALLOCATOR
+    let cnode = TASK_ALLOCATOR
         .lock()
         .allocate_and_retyped_variable_sized::<CNode>(CNODE_RADIX_BITS);
     let mut mapped_page = BTreeMap::new();
@@ -107,7 +108,7 @@ pub fn build_kernel_thread(
         sel4::init_thread::slot::ASID_POOL.cap(),
     );
 
-    let tcb = OBJ_ALLOCATOR
+    let tcb = TASK_ALLOCATOR
         .lock()
         .allocate_and_retyped_fixed_sized::<Tcb>();
 
@@ -125,11 +126,7 @@ pub fn build_kernel_thread(
     task.configure(2 * CNODE_RADIX_BITS, ipc_buffer_addr, ipc_buffer_cap)?;
 
     // Map stack for the task.
-    task.map_stack(10);
-
-    // set task priority and max control priority
-    task.tcb
-        .tcb_set_sched_params(init_thread::slot::TCB.cap(), 255, 255)?;
+    task.map_stack(stack_pages);
 
     task.tcb.debug_name(thread_name.as_bytes());
 
@@ -149,6 +146,33 @@ pub fn run_tasks(tasks: &Vec<Sel4Task>) {
     tasks.iter().for_each(Sel4Task::run)
 }
 
+/// The size bits of the untyped the objects of a task are retyped from.
+///
+/// It holds the CNodes of the task and of its slots in the root task, the
+/// TCB, the VSpace, the reply object on MCS and the page tables, the pages of
+/// the image, the IPC buffer, the stack and the `extra_pages`.
+pub fn task_untyped_bits(file_data: &[u8], stack_pages: usize, extra_pages: usize) -> usize {
+    let image_footprint = footprint(&File::parse(file_data).unwrap());
+    let cnodes = 3 << CNode::object_blueprint(CNODE_RADIX_BITS).physical_size_bits();
+    let pages = image_footprint.len() / GRANULE_SIZE + 1 + stack_pages + extra_pages;
+    // A page table for each 2 MiB of the image, and the tables of the other
+    // levels for the image, the stack, the device and the DMA pages.
+    let tables = image_footprint.len() / (1 << 21) + 16;
+    // The TCB, the VSpace and the reply object are at most a page each.
+    let size = cnodes + (pages + tables + 3) * GRANULE_SIZE;
+    size.next_power_of_two().trailing_zeros() as usize
+}
+
+/// Stop the task and delete its objects.
+///
+/// The objects are retyped from the `untyped` of the instance, revoking it
+/// deletes them and the capabilities to them, including the slots of the
+/// root task, which are in a CNode retyped from it too.
+pub fn destroy_task(task: &Sel4Task, untyped: Untyped) -> sel4::Result<()> {
+    task.tcb.tcb_suspend()?;
+    abs_cptr(untyped).revoke()
+}
+
 /// 创建一个新的虚拟地址空间
 /// # Parameters
 /// - `image`: ELF 文件
@@ -167,10 +191,10 @@ pub(crate) fn make_child_vspace<'a>(
     free_page_addr: usize,
     asid_pool: sel4::cap::AsidPool,
 ) -> (sel4::cap::VSpace, usize, sel4::cap::Granule) {
-    let inner_cnode = OBJ_ALLOCATOR
+    let inner_cnode = TASK_ALLOCATOR
         .lock()
         .allocate_and_retyped_variable_sized::<CNode>(CNODE_RADIX_BITS);
-    let mut allocator = OBJ_ALLOCATOR.lock();
+    let mut allocator = TASK_ALLOCATOR.lock();
     let allocator = allocator.deref_mut();
     let child_vspace = allocator.allocate_and_retyped_fixed_sized::<sel4::cap_type::VSpace>();
     // Build 2 level CSpace.
@@ -22,16 +22,16 @@ pub fn test_threads(bootinfo: &sel4::BootInfo) {
         .lock()
         .allocate_and_retyped_fixed_sized::<sel4::cap_type::Tcb>();
 
-    thread_tcb
-        .tcb_configure(
-            init_thread::slot::NULL.cptr(),
-            init_thread::slot::CNODE.cap(),
-            CNodeCapData::new(0, 0),
-            init_thread::slot::VSPACE.cap(),
-            SECONDARY_THREAD_IPC_BUFFER_FRAME.ptr() as sel4::Word,
-            SECONDARY_THREAD_IPC_BUFFER_FRAME.cap(bootinfo).into(),
-        )
-        .unwrap();
+    common::tcb_configure(
+        thread_tcb,
+        init_thread::slot::NULL.cptr(),
+        init_thread::slot::CNODE.cap(),
+        CNodeCapData::new(0, 0),
+        init_thread::slot::VSPACE.cap(),
+        SECONDARY_THREAD_IPC_BUFFER_FRAME.ptr() as sel4::Word,
+        SECONDARY_THREAD_IPC_BUFFER_FRAME.cap(bootinfo).into(),
+    )
+    .unwrap();
 
     let thread_fn = ThreadFn::new(move || {
         unsafe { set_ipc_buffer(SECONDARY_THREAD_IPC_BUFFER_FRAME.ptr().as_mut().unwrap()) }
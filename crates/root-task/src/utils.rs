@@ -1,7 +1,13 @@
-use crate_consts::GRANULE_SIZE;
-use sel4::{init_thread, AbsoluteCPtr, HasCPtrWithDepth};
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use common::ObjectAllocator;
+use crate_consts::{GRANULE_SIZE, PAGE_SIZE, PAGE_SIZE_BITS};
+use sel4::{
+    cap::{Granule, Untyped},
+    init_thread, AbsoluteCPtr, CapRights, HasCPtrWithDepth, ObjectBlueprint, ObjectBlueprintArm,
+};
+use spin::Mutex;
 
-use crate::FREE_PAGE_PLACEHOLDER;
+use crate::{FREE_PAGE_PLACEHOLDER, OBJ_ALLOCATOR};
 
 #[repr(C, align(4096))]
 pub struct FreePagePlaceHolder(#[allow(dead_code)] pub [u8; GRANULE_SIZE]);
@@ -44,3 +50,105 @@ fn get_user_image_frame_slot(
 pub fn abs_cptr<T: HasCPtrWithDepth>(path: T) -> AbsoluteCPtr {
     init_thread::slot::CNODE.cap().relative(path)
 }
+
+/// The device memory split off by [retype_device_frame] and left unused.
+///
+/// Each entry is `(paddr, size_bits, untyped)`, the next untyped of
+/// `size_bits` retyped from `untyped` covers the memory at `paddr`.
+static SPARE_DEVICE_UNTYPED: Mutex<Vec<(usize, usize, Untyped)>> = Mutex::new(Vec::new());
+
+/// The device frames retyped by [device_frame], indexed by the physical address.
+static DEVICE_FRAMES: Mutex<BTreeMap<usize, Granule>> = Mutex::new(BTreeMap::new());
+
+/// Get a new cap of the device frame containing the physical address `paddr`.
+///
+/// A frame cap can only be mapped once, so every call returns a copy of the
+/// frame retyped by the first one. The devices sharing a page, such as the
+/// virtio-mmio transports, can be mapped in several tasks this way. The copy
+/// is put in a slot of the `allocator`, the frame is always kept by the root
+/// task.
+pub fn device_frame(
+    bootinfo: &sel4::BootInfo,
+    paddr: usize,
+    allocator: &Mutex<ObjectAllocator>,
+) -> Granule {
+    let page = paddr & !(PAGE_SIZE - 1);
+    let frame = *DEVICE_FRAMES
+        .lock()
+        .entry(page)
+        .or_insert_with(|| retype_device_frame(bootinfo, page));
+    let (_, _, raw_index) = allocator.lock().allocate_slot();
+    let copy = Granule::from_bits(raw_index as u64);
+    abs_cptr(copy)
+        .copy(&abs_cptr(frame), CapRights::all())
+        .unwrap();
+    copy
+}
+
+/// Retype the frame at the physical address `paddr` from the device untyped containing it.
+///
+/// The untyped is split into halves until a page is left, the halves without
+/// the frame are kept for the frames retyped later, which may share the
+/// device untyped.
+fn retype_device_frame(bootinfo: &sel4::BootInfo, paddr: usize) -> Granule {
+    let retype = |untyped: Untyped, blueprint: ObjectBlueprint| {
+        let (slot_index, cnode_index, raw_index) = OBJ_ALLOCATOR.lock().allocate_slot();
+        untyped
+            .untyped_retype(
+                &blueprint,
+                &init_thread::slot::CNODE
+                    .cap()
+                    .relative_bits_with_depth(cnode_index as u64, 52),
+                slot_index,
+                1,
+            )
+            .unwrap();
+        init_thread::Slot::from_index(raw_index).cap()
+    };
+
+    let spare = {
+        let mut spares = SPARE_DEVICE_UNTYPED.lock();
+        spares
+            .iter()
+            .position(|(base, size_bits, _)| (*base..*base + (1 << size_bits)).contains(&paddr))
+            .map(|index| spares.swap_remove(index))
+    };
+    let (mut untyped, mut base, mut size_bits) = match spare {
+        Some((base, size_bits, parent)) => {
+            let untyped = retype(parent, ObjectBlueprint::Untyped { size_bits });
+            (untyped.cast(), base, size_bits)
+        }
+        None => {
+            let (device_idx, device_desc) = bootinfo.untyped_list()
+                [bootinfo.device_untyped_range()]
+            .iter()
+            .enumerate()
+            .find(|(_i, desc)| {
+                (desc.paddr()..(desc.paddr() + (1 << desc.size_bits()))).contains(&paddr)
+            })
+            .expect("[RootTask] can't find device memory");
+            assert!(device_desc.is_device());
+            let untyped = bootinfo
+                .untyped()
+                .index(bootinfo.device_untyped_range().start + device_idx)
+                .cap();
+            (untyped, device_desc.paddr(), device_desc.size_bits())
+        }
+    };
+    while size_bits > PAGE_SIZE_BITS {
+        size_bits -= 1;
+        // The objects are retyped from the start of the untyped, so the lower
+        // half is always retyped before the upper one.
+        let lower = retype(untyped, ObjectBlueprint::Untyped { size_bits });
+        let mut spares = SPARE_DEVICE_UNTYPED.lock();
+        if paddr >= base + (1 << size_bits) {
+            spares.push((base, size_bits, lower.cast()));
+            base += 1 << size_bits;
+            untyped = retype(untyped, ObjectBlueprint::Untyped { size_bits }).cast();
+        } else {
+            spares.push((base + (1 << size_bits), size_bits, untyped));
+            untyped = lower.cast();
+        }
+    }
+    retype(untyped, ObjectBlueprintArm::SmallPage.into()).cast()
+}
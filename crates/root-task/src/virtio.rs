@@ -0,0 +1,95 @@
+//! Probe the virtio-mmio transports found in the device tree.
+//!
+//! QEMU creates the transports whether or not a device is plugged into them,
+//! the device ID register tells the kind of the device and 0 for the empty
+//! ones. The root task reads it to start the matching driver.
+
+use alloc::vec::Vec;
+use crate_consts::PAGE_SIZE;
+use sel4::{init_thread, CapRights, VmAttributes};
+use sel4_root_task::debug_println;
+
+use crate::{
+    device_tree::MmioDevice,
+    utils::{device_frame, init_free_page_addr},
+    OBJ_ALLOCATOR,
+};
+
+/// The offset of the magic value register, it reads "virt".
+const VIRTIO_MMIO_MAGIC_VALUE: usize = 0x000;
+/// The offset of the device ID register.
+const VIRTIO_MMIO_DEVICE_ID: usize = 0x008;
+/// The little endian "virt".
+const VIRTIO_MMIO_MAGIC: u32 = 0x7472_6976;
+
+/// The device IDs in the virtio specification.
+pub const VIRTIO_ID_NET: u32 = 1;
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+pub const VIRTIO_ID_CONSOLE: u32 = 3;
+pub const VIRTIO_ID_RNG: u32 = 4;
+pub const VIRTIO_ID_9P: u32 = 9;
+
+/// A device plugged into a virtio-mmio transport.
+#[derive(Debug, Clone, Copy)]
+pub struct VirtioDevice {
+    pub device_id: u32,
+    pub transport: MmioDevice,
+}
+
+/// Get the name of the device with the `device_id`.
+pub fn device_name(device_id: u32) -> &'static str {
+    match device_id {
+        VIRTIO_ID_NET => "net",
+        VIRTIO_ID_BLOCK => "block",
+        VIRTIO_ID_CONSOLE => "console",
+        VIRTIO_ID_RNG => "rng",
+        VIRTIO_ID_9P => "9p",
+        _ => "unknown",
+    }
+}
+
+/// Read the device IDs of the `transports`, return the ones with a device.
+///
+/// The page of each transport is mapped to the free page of the root task
+/// while reading the registers.
+pub fn probe(bootinfo: &sel4::BootInfo, transports: &[MmioDevice]) -> Vec<VirtioDevice> {
+    let seat = unsafe { init_free_page_addr(bootinfo) };
+    let devices: Vec<_> = transports
+        .iter()
+        .filter_map(|transport| {
+            let frame = device_frame(bootinfo, transport.paddr, &OBJ_ALLOCATOR);
+            frame
+                .frame_map(
+                    init_thread::slot::VSPACE.cap(),
+                    seat,
+                    CapRights::read_only(),
+                    VmAttributes::DEFAULT,
+                )
+                .unwrap();
+            let register = |offset: usize| unsafe {
+                ((seat + transport.paddr % PAGE_SIZE + offset) as *const u32).read_volatile()
+            };
+            let magic = register(VIRTIO_MMIO_MAGIC_VALUE);
+            let device_id = register(VIRTIO_MMIO_DEVICE_ID);
+            frame.frame_unmap().unwrap();
+
+            if magic != VIRTIO_MMIO_MAGIC || device_id == 0 {
+                return None;
+            }
+            Some(VirtioDevice {
+                device_id,
+                transport: *transport,
+            })
+        })
+        .collect();
+
+    devices.iter().for_each(|device| {
+        debug_println!(
+            "[RootTask] virtio-mmio {:#x}: {} device, irq {}",
+            device.transport.paddr,
+            device_name(device.device_id),
+            device.transport.irq
+        )
+    });
+    devices
+}
@@ -8,12 +8,9 @@ sel4_panicking_env::register_debug_put_char!(sel4::sys::seL4_DebugPutChar);
 
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
-use common::{CloneArgs, CustomMessageLabel};
+use common::{CloneArgs, CloneFlags, CustomMessageLabel};
 use crate_consts::DEFAULT_THREAD_FAULT_EP;
-use sel4::{
-    cap::Endpoint, debug_println, set_ipc_buffer, with_ipc_buffer_mut, Cap,
-    CapTypeForFrameObjectOfFixedSize, MessageInfo,
-};
+use sel4::{cap::Endpoint, debug_println, set_ipc_buffer, Cap, IpcBuffer, MessageInfo};
 use sel4_dlmalloc::{StaticDlmallocGlobalAlloc, StaticHeap};
 use sel4_sync::PanickingRawMutex;
 use syscalls::Sysno;
@@ -33,6 +30,18 @@ pub(crate) fn set_tp_reg(tp: usize) {
         core::arch::asm!("msr tpidr_el0, {0}", in(reg) tp);
     }
 }
+
+/// The IPC buffer of the current thread.
+///
+/// The threads sharing the memory have their own IPC buffers, the kernel
+/// thread passes the address in `tpidrro_el0`.
+pub(crate) fn ipc_buffer() -> &'static mut IpcBuffer {
+    let addr: usize;
+    unsafe {
+        core::arch::asm!("mrs {0}, tpidrro_el0", out(reg) addr);
+        (addr as *mut IpcBuffer).as_mut().unwrap()
+    }
+}
 /// The entry of the test thread component.
 #[no_mangle]
 #[naked]
@@ -65,19 +74,16 @@ pub fn vsyscall_handler(
     #[inline(always)]
     fn get_tid() -> u64 {
         // Write syscall registers to ipc buffer.
-        with_ipc_buffer_mut(|buffer| {
-            let msgs: &mut [u64] = buffer.msg_regs_mut();
-            msgs[0] = Sysno::gettid.id() as _;
-        });
+        ipc_buffer().msg_regs_mut()[0] = Sysno::gettid.id() as _;
         // Load endpoint and send SysCall message.
-        let ep = Cap::from_bits(EP_CPTR.load(Ordering::SeqCst));
-        let _ = ep.call(MessageInfo::new(
+        let ep: Endpoint = Cap::from_bits(EP_CPTR.load(Ordering::SeqCst));
+        let _ = ep.with(ipc_buffer()).call(MessageInfo::new(
             CustomMessageLabel::SysCall.to_label(),
             0,
             0,
             7 * WORD_SIZE,
         ));
-        with_ipc_buffer_mut(|buffer| buffer.msg_regs()[0])
+        ipc_buffer().msg_regs()[0]
     }
 
     debug_println!("syscall id: {}", id);
@@ -92,19 +98,17 @@ pub fn vsyscall_handler(
     set_tp_reg(TP_REG.load(Ordering::SeqCst));
 
     // Write syscall registers to ipc buffer.
-    with_ipc_buffer_mut(|buffer| {
-        let msgs: &mut [u64] = buffer.msg_regs_mut();
-        msgs[0] = id as _;
-        msgs[1] = a as _;
-        msgs[2] = b as _;
-        msgs[3] = c as _;
-        msgs[4] = d as _;
-        msgs[5] = e as _;
-        msgs[6] = f as _;
-    });
+    let msgs: &mut [u64] = ipc_buffer().msg_regs_mut();
+    msgs[0] = id as _;
+    msgs[1] = a as _;
+    msgs[2] = b as _;
+    msgs[3] = c as _;
+    msgs[4] = d as _;
+    msgs[5] = e as _;
+    msgs[6] = f as _;
     // Load endpoint and send SysCall message.
-    let ep = Cap::from_bits(EP_CPTR.load(Ordering::SeqCst));
-    let message = ep.call(MessageInfo::new(
+    let ep: Endpoint = Cap::from_bits(EP_CPTR.load(Ordering::SeqCst));
+    let message = ep.with(ipc_buffer()).call(MessageInfo::new(
         CustomMessageLabel::SysCall.to_label(),
         0,
         0,
@@ -114,6 +118,11 @@ pub fn vsyscall_handler(
     if prev_id != 0 {
         set_tp_reg(tp);
         if get_tid() != prev_id {
+            // The child of `CLONE_SETTLS` runs with its own TLS.
+            let clone_args = unsafe { &*(a as *const CloneArgs) };
+            if CloneFlags::from_bits_truncate(clone_args.flags).contains(CloneFlags::CLONE_SETTLS) {
+                set_tp_reg(clone_args.tls as _);
+            }
             return 0;
         }
     }
@@ -122,7 +131,7 @@ pub fn vsyscall_handler(
     assert_eq!(message.length(), WORD_SIZE);
 
     // Get the result of the fake syscall
-    let ret = with_ipc_buffer_mut(|buffer| buffer.msg_regs()[0]);
+    let ret = ipc_buffer().msg_regs()[0];
 
     // Restore The TLS Register used by linux App
     set_tp_reg(tp);
@@ -187,7 +196,7 @@ fn main(_ep: Endpoint, busybox_entry: usize, vsyscall_section: usize) -> usize {
         vsyscall_handler as usize
     );
 
-    set_ipc_buffer_with_symbol();
+    set_ipc_buffer(ipc_buffer());
     let ep = Endpoint::from_bits(DEFAULT_THREAD_FAULT_EP);
     // Store Tls reg and endpoint cptr
     TP_REG.store(load_tp_reg(), Ordering::SeqCst);
@@ -247,18 +256,3 @@ pub fn sys_null(sys: isize) {
         );
     }
 }
-
-fn set_ipc_buffer_with_symbol() {
-    extern "C" {
-        static _end: usize;
-    }
-    let ipc_buffer = unsafe {
-        ((core::ptr::addr_of!(_end) as usize)
-            .next_multiple_of(sel4::cap_type::Granule::FRAME_OBJECT_TYPE.bytes())
-            as *mut sel4::IpcBuffer)
-            .as_mut()
-            .unwrap()
-    };
-
-    set_ipc_buffer(ipc_buffer);
-}
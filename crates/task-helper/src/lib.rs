@@ -159,10 +159,43 @@ impl<H: TaskHelperTrait<Self>> Sel4TaskHelper<H> {
             .copy(&cnode_relative(self.vspace), CapRights::all())
             .unwrap();
 
+        self.tcb_configure(
+            CNodeCapData::skip_high_bits(radix_bits),
+            ipc_buffer_addr,
+            ipc_buffer_cap,
+        )
+    }
+
+    /// Configure the TCB with the fault endpoint in [DEFAULT_THREAD_FAULT_EP].
+    #[sel4::sel4_cfg(not(KERNEL_MCS))]
+    fn tcb_configure(
+        &self,
+        cspace_root_data: CNodeCapData,
+        ipc_buffer_addr: usize,
+        ipc_buffer_cap: Granule,
+    ) -> Result<(), Error> {
         self.tcb.tcb_configure(
             CPtr::from_bits(DEFAULT_THREAD_FAULT_EP),
             self.cnode,
-            CNodeCapData::skip_high_bits(radix_bits),
+            cspace_root_data,
+            self.vspace,
+            ipc_buffer_addr as _,
+            ipc_buffer_cap,
+        )
+    }
+
+    /// Configure the TCB, the fault endpoint is set with the scheduling
+    /// context on MCS.
+    #[sel4::sel4_cfg(KERNEL_MCS)]
+    fn tcb_configure(
+        &self,
+        cspace_root_data: CNodeCapData,
+        ipc_buffer_addr: usize,
+        ipc_buffer_cap: Granule,
+    ) -> Result<(), Error> {
+        self.tcb.tcb_configure(
+            self.cnode,
+            cspace_root_data,
             self.vspace,
             ipc_buffer_addr as _,
             ipc_buffer_cap,
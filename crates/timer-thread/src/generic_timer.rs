@@ -0,0 +1,33 @@
+//! The non-secure physical timer of the ARM generic timer.
+//!
+//! The seL4 kernel takes its ticks from the virtual timer, or from the
+//! hypervisor timer when it is built with `KernelArmHypervisorSupport`, where
+//! the virtual timer (PPI 27) is left to the VCPUs. So the physical timer
+//! (PPI 30) is left to the timer thread. The kernel should be configured with
+//! `KernelArmExportPTMRUser` to allow accessing it from EL0.
+
+/// Enable the timer.
+const CTL_ENABLE: u64 = 1 << 0;
+
+/// Fire the interrupt at the count `ticks` of `CNTPCT_EL0`.
+///
+/// The interrupt is level triggered, it keeps asserted until the compare
+/// value is changed or the timer is disabled.
+pub fn set_deadline(ticks: u64) {
+    unsafe {
+        core::arch::asm!(
+            "msr cntp_cval_el0, {0}",
+            "msr cntp_ctl_el0, {1}",
+            "isb",
+            in(reg) ticks,
+            in(reg) CTL_ENABLE,
+        );
+    }
+}
+
+/// Disable the timer and its interrupt.
+pub fn disable() {
+    unsafe {
+        core::arch::asm!("msr cntp_ctl_el0, {0}", "isb", in(reg) 0u64);
+    }
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate sel4_panicking;
+
+mod generic_timer;
+mod queue;
+mod runtime;
+
+use common::{
+    current_nanos, get_device, nanos_to_ticks, ready, register_irq, TimerMessageLabel, SERVER_REPLY,
+};
+use crate_consts::{
+    slots::timer_thread::{CLIENT_NTFNS, EP, IRQ_HANDLER, IRQ_NTFN},
+    DEFAULT_THREAD_FAULT_EP, NOTIFICATION_BADGE_START, TIMER_MAX_CLIENTS,
+};
+use queue::TimerQueue;
+use sel4::{
+    cap::{Endpoint, IrqHandler, Notification},
+    debug_println, init_thread, with_ipc_buffer_mut, MessageInfo,
+};
+
+sel4_panicking_env::register_debug_put_char!(sel4::sys::seL4_DebugPutChar);
+
+/// Reply a message with empty message information
+#[inline]
+fn reply_with(regs: &[u64]) {
+    with_ipc_buffer_mut(|buffer| {
+        let msg_regs = buffer.msg_regs_mut();
+        regs.iter()
+            .enumerate()
+            .for_each(|(i, reg)| msg_regs[i] = *reg as _);
+        common::reply(buffer, SERVER_REPLY, MessageInfo::new(0, 0, 0, regs.len()))
+    });
+}
+
+/// The notification of the client with the endpoint badge `badge`.
+fn client_notification(badge: u64) -> Notification {
+    Notification::from_bits(CLIENT_NTFNS + badge - 1)
+}
+
+/// Signal the clients whose timers expired, then program the next deadline.
+fn expire_and_rearm(queue: &mut TimerQueue) {
+    loop {
+        let clients = queue.expire(current_nanos());
+        (1..TIMER_MAX_CLIENTS)
+            .filter(|badge| clients & (1 << badge) != 0)
+            .for_each(|badge| client_notification(badge).signal());
+        match queue.next_deadline() {
+            Some(deadline) => {
+                generic_timer::set_deadline(nanos_to_ticks(deadline));
+                // The deadline may pass while signalling, then the interrupt
+                // is pending already and nothing is missed.
+                if deadline > current_nanos() {
+                    break;
+                }
+            }
+            None => {
+                generic_timer::disable();
+                break;
+            }
+        }
+    }
+}
+
+fn main() -> ! {
+    debug_println!("[TimerThread] EntryPoint");
+    generic_timer::disable();
+
+    // Register interrupt handler and bind the notification to the TCB, so the
+    // interrupts are received from the same endpoint as the IPC requests.
+    let ntfn = Notification::from_bits(IRQ_NTFN);
+    let irq_handler = IrqHandler::from_bits(IRQ_HANDLER);
+    let ep = Endpoint::from_bits(DEFAULT_THREAD_FAULT_EP);
+
+    // The generic timer has no registers to map, only its interrupt is assigned.
+    let (_, irq_num) = get_device(ep);
+    register_irq(ep, irq_handler, irq_num).expect("[TimerThread] failed to register the irq");
+    irq_handler.irq_handler_set_notification(ntfn).unwrap();
+    irq_handler.irq_handler_ack().unwrap();
+    init_thread::slot::TCB
+        .cap()
+        .tcb_bind_notification(ntfn)
+        .unwrap();
+
+    let timer_ep = Endpoint::from_bits(EP);
+    let mut queue = TimerQueue::default();
+
+    debug_println!("[TimerThread] Waiting for timer requests");
+    // The root task arms its timers from now on.
+    ready(ep);
+    loop {
+        let (message, badge) = timer_ep.recv(SERVER_REPLY);
+
+        if badge >= NOTIFICATION_BADGE_START {
+            expire_and_rearm(&mut queue);
+            irq_handler.irq_handler_ack().unwrap();
+            continue;
+        }
+
+        match TimerMessageLabel::try_from(&message) {
+            Some(TimerMessageLabel::SetTimer(id, deadline)) => {
+                reply_with(&[]);
+                if !(1..TIMER_MAX_CLIENTS).contains(&badge) {
+                    debug_println!("[TimerThread] Unknown client {}", badge);
+                    continue;
+                }
+                queue.set(badge, id, deadline);
+                expire_and_rearm(&mut queue);
+            }
+            Some(TimerMessageLabel::CancelTimer(id)) => {
+                reply_with(&[]);
+                queue.cancel(badge, id);
+                expire_and_rearm(&mut queue);
+            }
+            None => {
+                debug_println!("[TimerThread] Recv unknown message {:#x?}", message);
+                reply_with(&[]);
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! The one-shot deadlines of the clients.
+
+use alloc::collections::{btree_map::BTreeMap, btree_set::BTreeSet};
+
+/// A timer is identified by the badge of the client and the id it chooses.
+type TimerKey = (u64, u64);
+
+/// The armed timers ordered by the deadline.
+#[derive(Default)]
+pub struct TimerQueue {
+    /// The deadline of each timer.
+    timers: BTreeMap<TimerKey, u64>,
+    /// The timers ordered by the deadline, then by the key.
+    deadlines: BTreeSet<(u64, TimerKey)>,
+}
+
+impl TimerQueue {
+    /// Arm the timer `id` of the client `badge`, the old deadline is replaced.
+    pub fn set(&mut self, badge: u64, id: u64, deadline: u64) {
+        self.cancel(badge, id);
+        self.timers.insert((badge, id), deadline);
+        self.deadlines.insert((deadline, (badge, id)));
+    }
+
+    pub fn cancel(&mut self, badge: u64, id: u64) {
+        if let Some(deadline) = self.timers.remove(&(badge, id)) {
+            self.deadlines.remove(&(deadline, (badge, id)));
+        }
+    }
+
+    /// The earliest deadline of all the timers.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.deadlines.first().map(|(deadline, _)| *deadline)
+    }
+
+    /// Remove the timers expired at `now`, returns the mask of the clients
+    /// owning them, bit `n` is the client with badge `n`.
+    pub fn expire(&mut self, now: u64) -> u64 {
+        let mut clients = 0;
+        while let Some(&(deadline, key)) = self.deadlines.first() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop_first();
+            self.timers.remove(&key);
+            clients |= 1 << key.0;
+        }
+        clients
+    }
+}
@@ -0,0 +1,136 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate sel4_panicking;
+
+mod pl011;
+mod runtime;
+mod tty;
+
+use common::*;
+use crate_consts::{
+    slots::uart_thread::{CONSOLE_EP, IRQ_HANDLER, IRQ_NTFN, READY_NTFN},
+    DEFAULT_THREAD_FAULT_EP, NOTIFICATION_BADGE_START,
+};
+use pl011::Pl011;
+use sel4::{
+    cap::{Endpoint, IrqHandler, Notification},
+    debug_println, init_thread, with_ipc_buffer, with_ipc_buffer_mut, MessageInfo,
+};
+use tty::Tty;
+
+sel4_panicking_env::register_debug_put_char!(sel4::sys::seL4_DebugPutChar);
+
+/// The error code replied by `ConsoleMessageLabel::Read` if nothing is readable,
+/// followed by the `VTIME` the reader should wait for.
+const EAGAIN: i64 = 11;
+
+/// Reply a message with empty message information
+#[inline]
+fn reply_with(regs: &[u64]) {
+    with_ipc_buffer_mut(|buffer| {
+        let msg_regs = buffer.msg_regs_mut();
+        regs.iter()
+            .enumerate()
+            .for_each(|(i, reg)| msg_regs[i] = *reg as _);
+        common::reply(buffer, SERVER_REPLY, MessageInfo::new(0, 0, 0, regs.len()))
+    });
+}
+
+fn main() -> ! {
+    debug_println!("[UartThread] EntryPoint");
+    let ep = Endpoint::from_bits(DEFAULT_THREAD_FAULT_EP);
+    let (uart_vaddr, irq_num) = get_device(ep);
+    let uart = unsafe { Pl011::new(uart_vaddr) };
+    uart.init();
+    let mut tty = Tty::new(uart);
+
+    // Register interrupt handler and bind the notification to the TCB, so the
+    // interrupts are received from the same endpoint as the IPC requests.
+    let ntfn = Notification::from_bits(IRQ_NTFN);
+    let irq_handler = IrqHandler::from_bits(IRQ_HANDLER);
+
+    register_irq(ep, irq_handler, irq_num).expect("[UartThread] failed to register the irq");
+    irq_handler.irq_handler_set_notification(ntfn).unwrap();
+    irq_handler.irq_handler_ack().unwrap();
+    init_thread::slot::TCB
+        .cap()
+        .tcb_bind_notification(ntfn)
+        .unwrap();
+
+    let console_ep = Endpoint::from_bits(CONSOLE_EP);
+    // Signalled to wake the kernel thread waiting for the input or the signals.
+    let ready_ntfn = Notification::from_bits(READY_NTFN);
+
+    debug_println!("[UartThread] Waiting for console requests");
+    loop {
+        let (message, badge) = console_ep.recv(SERVER_REPLY);
+
+        if badge >= NOTIFICATION_BADGE_START {
+            tty.receive();
+            irq_handler.irq_handler_ack().unwrap();
+            if tty.readable() || tty.has_signals() {
+                ready_ntfn.signal();
+            }
+            continue;
+        }
+
+        let Some(label) = ConsoleMessageLabel::try_from(&message) else {
+            debug_println!("[UartThread] Recv unknown message {:#x?}", message);
+            reply_with(&[]);
+            continue;
+        };
+        match label {
+            ConsoleMessageLabel::Read(len) => {
+                let mut regs = [0u64; 1 + bytes_regs(CONSOLE_MSG_BYTES)];
+                let mut buf = [0u8; CONSOLE_MSG_BYTES];
+                let len = (len as usize).min(CONSOLE_MSG_BYTES);
+                match tty.read(&mut buf[..len]) {
+                    Some(len) => {
+                        regs[0] = len as u64;
+                        bytes_to_regs(&buf[..len], &mut regs[1..]);
+                        reply_with(&regs[..1 + bytes_regs(len)]);
+                    }
+                    None => reply_with(&[-EAGAIN as u64, tty.read_timeout()]),
+                }
+            }
+            ConsoleMessageLabel::Write(len) => {
+                let mut buf = [0u8; CONSOLE_MSG_BYTES];
+                let len = (len as usize).min(CONSOLE_MSG_BYTES);
+                with_ipc_buffer(|buffer| regs_to_bytes(&buffer.msg_regs()[1..], &mut buf[..len]));
+                tty.write(&buf[..len]);
+                reply_with(&[len as u64]);
+            }
+            ConsoleMessageLabel::GetTermios => reply_with(&tty.termios().to_regs()),
+            ConsoleMessageLabel::SetTermios(regs, flush) => {
+                tty.set_termios(Termios::from_regs(&regs), flush != 0);
+                reply_with(&[]);
+                // Leaving the canonical mode may make the edited line readable.
+                if tty.readable() {
+                    ready_ntfn.signal();
+                }
+            }
+            ConsoleMessageLabel::GetWinSize => {
+                let winsize = tty.winsize();
+                reply_with(&[winsize.ws_row as u64, winsize.ws_col as u64]);
+            }
+            ConsoleMessageLabel::SetWinSize(rows, cols) => {
+                tty.set_winsize(WinSize {
+                    ws_row: rows as u16,
+                    ws_col: cols as u16,
+                    ..Default::default()
+                });
+                reply_with(&[]);
+            }
+            ConsoleMessageLabel::Poll => {
+                let events = match tty.readable() {
+                    true => CONSOLE_POLL_READABLE,
+                    false => 0,
+                };
+                reply_with(&[events]);
+            }
+            ConsoleMessageLabel::TakeSignals => reply_with(&[tty.take_signals()]),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! The driver of the PL011 UART.
+//!
+//! The baud rate and the line format are left as the firmware sets them,
+//! only the receive interrupts are enabled here.
+//!
+//! See <https://developer.arm.com/documentation/ddi0183/latest/>
+
+use core::ptr::{read_volatile, write_volatile};
+
+/// Data register.
+const UARTDR: usize = 0x00;
+/// Flag register.
+const UARTFR: usize = 0x18;
+/// Line control register.
+const UARTLCR_H: usize = 0x2c;
+/// Control register.
+const UARTCR: usize = 0x30;
+/// Interrupt mask set/clear register.
+const UARTIMSC: usize = 0x38;
+/// Interrupt clear register.
+const UARTICR: usize = 0x44;
+
+/// The receive FIFO is empty.
+const FR_RXFE: u32 = 1 << 4;
+/// The transmit FIFO is full.
+const FR_TXFF: u32 = 1 << 5;
+
+/// Enable the FIFOs.
+const LCR_H_FEN: u32 = 1 << 4;
+
+const CR_UARTEN: u32 = 1 << 0;
+const CR_TXE: u32 = 1 << 8;
+const CR_RXE: u32 = 1 << 9;
+
+/// The receive interrupt.
+const INT_RX: u32 = 1 << 4;
+/// The receive timeout interrupt, raised when the FIFO holds less than the trigger level.
+const INT_RT: u32 = 1 << 6;
+
+pub struct Pl011 {
+    base: usize,
+}
+
+impl Pl011 {
+    /// Create the driver of the UART mapped at `base`.
+    ///
+    /// # Safety
+    ///
+    /// The registers of a PL011 must be mapped at `base` and owned by the driver.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    #[inline]
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+
+    #[inline]
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.base + reg) as *mut u32, value) }
+    }
+
+    /// Enable the UART and its receive interrupts.
+    pub fn init(&self) {
+        self.write(UARTLCR_H, self.read(UARTLCR_H) | LCR_H_FEN);
+        self.write(UARTICR, INT_RX | INT_RT);
+        self.write(UARTIMSC, INT_RX | INT_RT);
+        self.write(UARTCR, CR_UARTEN | CR_TXE | CR_RXE);
+    }
+
+    /// Write a byte, waiting while the transmit FIFO is full.
+    pub fn putc(&self, c: u8) {
+        while self.read(UARTFR) & FR_TXFF != 0 {
+            core::hint::spin_loop();
+        }
+        self.write(UARTDR, c as u32);
+    }
+
+    /// Read a byte from the receive FIFO, `None` if it is empty.
+    pub fn getc(&self) -> Option<u8> {
+        if self.read(UARTFR) & FR_RXFE != 0 {
+            None
+        } else {
+            // The higher bits are the error flags of the byte.
+            Some(self.read(UARTDR) as u8)
+        }
+    }
+
+    /// Clear the receive interrupts after draining the FIFO.
+    pub fn ack_interrupt(&self) {
+        self.write(UARTICR, INT_RX | INT_RT);
+    }
+}
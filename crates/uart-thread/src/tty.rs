@@ -0,0 +1,313 @@
+//! The line discipline of the console.
+//!
+//! In the canonical mode the input is edited line by line and becomes
+//! readable at the end of the line, otherwise every byte is readable at once.
+//! The characters generating signals are recorded and reported to the kernel
+//! thread by `ConsoleMessageLabel::TakeSignals`.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use common::*;
+
+use crate::pl011::Pl011;
+
+/// The maximum length of a line, the same as `N_TTY_BUF_SIZE` in linux.
+const MAX_INPUT: usize = 4096;
+
+const SIGINT: u32 = 2;
+const SIGQUIT: u32 = 3;
+
+pub struct Tty {
+    uart: Pl011,
+    termios: Termios,
+    winsize: WinSize,
+    /// The line being edited in the canonical mode.
+    line: Vec<u8>,
+    /// The completed lines, an empty one is the end of file by `VEOF`.
+    lines: VecDeque<Vec<u8>>,
+    /// The bytes readable in the noncanonical mode.
+    raw: VecDeque<u8>,
+    /// The mask of the signals generated since the kernel thread took them.
+    signals: u64,
+    /// The next byte is taken literally, after `VLNEXT`.
+    literal_next: bool,
+}
+
+impl Tty {
+    pub fn new(uart: Pl011) -> Self {
+        Self {
+            uart,
+            termios: Termios::default(),
+            winsize: WinSize {
+                ws_row: 24,
+                ws_col: 80,
+                ..Default::default()
+            },
+            line: Vec::new(),
+            lines: VecDeque::new(),
+            raw: VecDeque::new(),
+            signals: 0,
+            literal_next: false,
+        }
+    }
+
+    #[inline]
+    fn lflag(&self, flag: u32) -> bool {
+        self.termios.c_lflag & flag != 0
+    }
+
+    #[inline]
+    fn is_cc(&self, c: u8, index: usize) -> bool {
+        // A disabled control character is `_POSIX_VDISABLE`.
+        self.termios.c_cc[index] != 0 && self.termios.c_cc[index] == c
+    }
+
+    /// Whether there is something to read, including the end of file.
+    pub fn readable(&self) -> bool {
+        if self.lflag(ICANON) {
+            !self.lines.is_empty()
+        } else {
+            !self.raw.is_empty() || self.polling()
+        }
+    }
+
+    /// Whether the non-canonical read returns at once with nothing readable,
+    /// it is the case of both `VMIN` and `VTIME` zero.
+    fn polling(&self) -> bool {
+        self.termios.c_cc[VMIN] == 0 && self.termios.c_cc[VTIME] == 0
+    }
+
+    /// The tenths of a second which the read waits for with nothing readable,
+    /// zero if it waits until some input arrives.
+    pub fn read_timeout(&self) -> u64 {
+        match self.lflag(ICANON) || self.termios.c_cc[VMIN] != 0 {
+            true => 0,
+            false => self.termios.c_cc[VTIME] as u64,
+        }
+    }
+
+    pub fn has_signals(&self) -> bool {
+        self.signals != 0
+    }
+
+    pub fn take_signals(&mut self) -> u64 {
+        core::mem::take(&mut self.signals)
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.termios
+    }
+
+    /// Change the settings, the pending input is kept unless `flush`.
+    pub fn set_termios(&mut self, termios: Termios, flush: bool) {
+        let was_canonical = self.lflag(ICANON);
+        self.termios = termios;
+        if flush {
+            self.flush_input();
+        } else if was_canonical && !self.lflag(ICANON) {
+            // The edited bytes become readable at once.
+            self.raw.extend(self.lines.drain(..).flatten());
+            self.raw.extend(self.line.drain(..));
+        } else if !was_canonical && self.lflag(ICANON) && !self.raw.is_empty() {
+            self.lines.push_back(self.raw.drain(..).collect());
+        }
+    }
+
+    pub fn winsize(&self) -> WinSize {
+        self.winsize
+    }
+
+    pub fn set_winsize(&mut self, winsize: WinSize) {
+        self.winsize = winsize;
+    }
+
+    fn flush_input(&mut self) {
+        self.line.clear();
+        self.lines.clear();
+        self.raw.clear();
+    }
+
+    /// Read at most `buf.len()` bytes, `None` if nothing is readable yet.
+    ///
+    /// A canonical read never returns more than one line.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.lflag(ICANON) {
+            let line = self.lines.front_mut()?;
+            let len = buf.len().min(line.len());
+            buf[..len].copy_from_slice(&line[..len]);
+            line.drain(..len);
+            // The end of file is consumed by the read returning 0.
+            if line.is_empty() {
+                self.lines.pop_front();
+            }
+            Some(len)
+        } else {
+            if self.raw.is_empty() {
+                // The reader waits for `VTIME` by itself, see [Tty::read_timeout].
+                return self.polling().then_some(0);
+            }
+            let len = buf.len().min(self.raw.len());
+            for (dst, src) in buf.iter_mut().zip(self.raw.drain(..len)) {
+                *dst = src;
+            }
+            Some(len)
+        }
+    }
+
+    /// Write the bytes with the output processing.
+    pub fn write(&self, data: &[u8]) {
+        for &c in data {
+            self.output(c);
+        }
+    }
+
+    fn output(&self, c: u8) {
+        let oflag = self.termios.c_oflag;
+        if oflag & OPOST != 0 && oflag & ONLCR != 0 && c == b'\n' {
+            self.uart.putc(b'\r');
+        }
+        self.uart.putc(c);
+    }
+
+    /// Echo the input byte, the control characters are echoed as `^X`.
+    fn echo(&self, c: u8) {
+        if !self.lflag(ECHO) {
+            if c == b'\n' && self.lflag(ECHONL) && self.lflag(ICANON) {
+                self.output(c);
+            }
+            return;
+        }
+        if self.lflag(ECHOCTL) && ((c < 0x20 && c != b'\t' && c != b'\n') || c == 0x7f) {
+            self.output(b'^');
+            self.output(c ^ 0x40);
+        } else {
+            self.output(c);
+        }
+    }
+
+    /// Erase the last character of the line on the screen.
+    fn echo_erase(&self, c: u8) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        if !self.lflag(ECHOE) {
+            self.echo(self.termios.c_cc[VERASE]);
+            return;
+        }
+        // The control characters take two columns.
+        let width = if self.lflag(ECHOCTL) && (c < 0x20 || c == 0x7f) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..width {
+            self.write(b"\x08 \x08");
+        }
+    }
+
+    fn erase_char(&mut self) -> bool {
+        match self.line.pop() {
+            Some(c) => {
+                self.echo_erase(c);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handle the bytes received by the UART, called on its interrupt.
+    pub fn receive(&mut self) {
+        while let Some(c) = self.uart.getc() {
+            self.input(c);
+        }
+        self.uart.ack_interrupt();
+    }
+
+    /// Handle a byte received from the UART.
+    fn input(&mut self, mut c: u8) {
+        let iflag = self.termios.c_iflag;
+        if iflag & ISTRIP != 0 {
+            c &= 0x7f;
+        }
+        if core::mem::take(&mut self.literal_next) {
+            self.push(c);
+            return;
+        }
+        match c {
+            b'\r' if iflag & IGNCR != 0 => return,
+            b'\r' if iflag & ICRNL != 0 => c = b'\n',
+            b'\n' if iflag & INLCR != 0 => c = b'\r',
+            _ => {}
+        }
+        if self.lflag(ISIG) {
+            let signal = if self.is_cc(c, VINTR) {
+                Some(SIGINT)
+            } else if self.is_cc(c, VQUIT) {
+                Some(SIGQUIT)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                self.signals |= 1 << signal;
+                if !self.lflag(NOFLSH) {
+                    self.flush_input();
+                }
+                self.echo(c);
+                if self.lflag(ECHO) {
+                    self.output(b'\n');
+                }
+                return;
+            }
+        }
+        if !self.lflag(ICANON) {
+            self.push(c);
+            return;
+        }
+        if self.lflag(IEXTEN) && self.is_cc(c, VLNEXT) {
+            self.literal_next = true;
+            return;
+        }
+        if self.is_cc(c, VERASE) || c == 0x08 {
+            self.erase_char();
+        } else if self.is_cc(c, VWERASE) && self.lflag(IEXTEN) {
+            while self.line.last() == Some(&b' ') && self.erase_char() {}
+            while self.line.last().is_some_and(|&c| c != b' ') && self.erase_char() {}
+        } else if self.is_cc(c, VKILL) {
+            if self.lflag(ECHOKE) {
+                while self.erase_char() {}
+            } else {
+                self.line.clear();
+                if self.lflag(ECHOK) {
+                    self.echo(b'\n');
+                }
+            }
+        } else if self.is_cc(c, VEOF) {
+            // The pending bytes become readable, or the read returns the end of file.
+            self.lines.push_back(core::mem::take(&mut self.line));
+        } else if c == b'\n' || self.is_cc(c, VEOL) || self.is_cc(c, VEOL2) {
+            self.line.push(c);
+            self.echo(c);
+            self.lines.push_back(core::mem::take(&mut self.line));
+        } else {
+            self.push(c);
+        }
+    }
+
+    /// Append a byte to the line, it is dropped if the line is full.
+    fn push(&mut self, c: u8) {
+        let full = if self.lflag(ICANON) {
+            // Leave a byte for the newline ending the line.
+            self.line.len() >= MAX_INPUT - 1
+        } else {
+            self.raw.len() >= MAX_INPUT
+        };
+        if full {
+            return;
+        }
+        if self.lflag(ICANON) {
+            self.line.push(c);
+        } else {
+            self.raw.push_back(c);
+        }
+        self.echo(c);
+    }
+}
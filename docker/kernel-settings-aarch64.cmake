@@ -9,6 +9,11 @@
 set(ARM_CPU cortex-a57 CACHE STRING "")
 set(KernelArch arm CACHE STRING "")
 set(KernelArmHypervisorSupport OFF CACHE BOOL "")
+# The timer thread reads the physical count and drives the physical timer.
+set(KernelArmExportPCNTUser ON CACHE BOOL "")
+set(KernelArmExportPTMRUser ON CACHE BOOL "")
+# The root task powers off and resets the machine through PSCI.
+set(KernelAllowSMCCalls ON CACHE BOOL "")
 # set(KernelMaxNumNodes 2 CACHE STRING "")
 set(KernelPlatform qemu-arm-virt CACHE STRING "")
 set(KernelSel4Arch aarch64 CACHE STRING "")